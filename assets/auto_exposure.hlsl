@@ -0,0 +1,74 @@
+#define HISTOGRAM_BINS 256
+#define GROUP_SIZE 16
+
+cbuffer AutoExposureSettings : register(b0)
+{
+    float min_log_luminance;
+    float log_luminance_range;
+    float adaptation;
+};
+
+Texture2D<float4> hdr_texture : register(t0);
+RWStructuredBuffer<uint> histogram : register(u0);
+RWStructuredBuffer<float> average_luminance : register(u1);
+
+groupshared uint local_histogram[HISTOGRAM_BINS];
+
+// Bin 0 holds (almost) black pixels, they are excluded from the average
+uint LuminanceToBin(float luminance)
+{
+    if (luminance < 0.00001f) {
+        return 0;
+    }
+
+    float log_luminance = saturate((log2(luminance) - min_log_luminance) / log_luminance_range);
+    return uint(log_luminance * float(HISTOGRAM_BINS - 2) + 1.0f);
+}
+
+[numthreads(GROUP_SIZE, GROUP_SIZE, 1)]
+void CSBuildHistogram(uint group_index : SV_GroupIndex, uint3 id : SV_DispatchThreadID)
+{
+    local_histogram[group_index] = 0;
+    GroupMemoryBarrierWithGroupSync();
+
+    uint width, height;
+    hdr_texture.GetDimensions(width, height);
+    if (id.x < width && id.y < height) {
+        float3 color = hdr_texture.Load(int3(id.xy, 0)).rgb;
+        float luminance = dot(color, float3(0.2126f, 0.7152f, 0.0722f));
+        InterlockedAdd(local_histogram[LuminanceToBin(luminance)], 1);
+    }
+    GroupMemoryBarrierWithGroupSync();
+
+    InterlockedAdd(histogram[group_index], local_histogram[group_index]);
+}
+
+[numthreads(HISTOGRAM_BINS, 1, 1)]
+void CSAverage(uint group_index : SV_GroupIndex)
+{
+    uint bin_count = histogram[group_index];
+    local_histogram[group_index] = bin_count * group_index;
+    // Cleared here so the next frame starts from an empty histogram
+    histogram[group_index] = 0;
+    GroupMemoryBarrierWithGroupSync();
+
+    for (uint cutoff = HISTOGRAM_BINS / 2; cutoff > 0; cutoff >>= 1) {
+        if (group_index < cutoff) {
+            local_histogram[group_index] += local_histogram[group_index + cutoff];
+        }
+        GroupMemoryBarrierWithGroupSync();
+    }
+
+    if (group_index == 0) {
+        uint width, height;
+        hdr_texture.GetDimensions(width, height);
+        float lit_pixel_count = max(float(width * height) - float(bin_count), 1.0f);
+
+        float average_bin = max(float(local_histogram[0]) / lit_pixel_count - 1.0f, 0.0f);
+        float log_average = average_bin / float(HISTOGRAM_BINS - 2) * log_luminance_range + min_log_luminance;
+        float current = exp2(log_average);
+
+        float previous = average_luminance[0];
+        average_luminance[0] = previous > 0.0f ? lerp(previous, current, adaptation) : current;
+    }
+}
@@ -0,0 +1,105 @@
+// G-buffer pass of the deferred pipeline, stores the surface seen through every pixel for
+// deferred_lighting.hlsl. Target order must match G_BUFFER_TARGETS in deferred.rs.
+
+cbuffer FrameData : register(b0)
+{
+    matrix view_projection;
+    float3 camera_position;
+};
+
+// IDs in the bindless heap, shared with deferred_lighting.hlsl
+cbuffer BindlessIds : register(b2)
+{
+    // vertices, indices, materials, uvs and lights
+    uint scene_srvs;
+    // of material texture slot 0, the other slots follow
+    uint first_texture;
+    uint g_buffer_srvs;
+};
+
+struct MaterialData
+{
+    float4 base_color;
+    float4 emissive;
+    float transmission;
+    float ior;
+    uint base_color_texture;
+    float metallic;
+    float perceptual_roughness;
+    float clearcoat;
+    float clearcoat_perceptual_roughness;
+    // of the instance, stochastic transparency below 1
+    float opacity;
+};
+
+static const uint NO_TEXTURE = 0xffffffff;
+
+// Every space views the whole bindless heap as one resource type, see bindless.rs. The
+// vertices, indices and uvs come through the input assembler.
+Texture2D<float4> textures[] : register(t0, space1);
+StructuredBuffer<MaterialData> material_buffers[] : register(t0, space6);
+#define material_buffer material_buffers[scene_srvs + 2]
+SamplerState texture_sampler : register(s0);
+
+struct PSInput
+{
+    float4 position : SV_POSITION;
+    float3 world_position : POSITION;
+    float2 uv : TEXCOORD;
+};
+
+struct GBufferOutput
+{
+    float4 albedo : SV_TARGET0;
+    float4 normal : SV_TARGET1;
+    float depth : SV_TARGET2;
+    float2 material : SV_TARGET3;
+    float4 emission : SV_TARGET4;
+};
+
+PSInput VSMain(float3 position : POSITION, float2 uv : TEXCOORD)
+{
+    PSInput result;
+    result.position = mul(view_projection, float4(position, 1.0f));
+    result.world_position = position;
+    result.uv = uv;
+    return result;
+}
+
+// Interleaved gradient noise, keeps the screen door pattern of faded instances fine grained
+float InterleavedGradientNoise(float2 pixel)
+{
+    return frac(52.9829189f * frac(dot(pixel, float2(0.06711056f, 0.00583715f))));
+}
+
+GBufferOutput PSMain(PSInput input, uint triangle_index : SV_PrimitiveID)
+{
+    MaterialData material = material_buffer[triangle_index];
+    if (InterleavedGradientNoise(input.position.xy) >= material.opacity)
+    {
+        discard;
+    }
+
+    float3 to_camera = camera_position - input.world_position;
+    // meshes have no vertex normals, faces are shaded flat like in the path tracer
+    float3 normal = normalize(cross(ddy(input.world_position), ddx(input.world_position)));
+    if (dot(normal, to_camera) < 0.0f)
+    {
+        normal = -normal;
+    }
+
+    float3 albedo = material.base_color.rgb;
+    if (material.base_color_texture != NO_TEXTURE)
+    {
+        uint texture_id = first_texture + material.base_color_texture;
+        albedo *= textures[NonUniformResourceIndex(texture_id)].Sample(texture_sampler, input.uv).rgb;
+    }
+
+    GBufferOutput output;
+    output.albedo = float4(albedo, 1.0f);
+    output.normal = float4(normal, 0.0f);
+    output.depth = length(to_camera);
+    output.material = float2(material.metallic, material.perceptual_roughness);
+    output.emission = float4(material.emissive.rgb, 1.0f);
+    return output;
+}
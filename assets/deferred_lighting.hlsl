@@ -0,0 +1,191 @@
+// Lighting pass of the deferred pipeline. Shades the surfaces deferred_gbuffer.hlsl stored like
+// raster_forward.hlsl does: punctual lights without shadows and a constant ambient term.
+
+struct PSInput
+{
+    float4 position : SV_POSITION;
+    float2 uv : TEXCOORD;
+};
+
+// must match CameraBuffer in demo.hlsl
+cbuffer CameraBuffer : register(b0) {
+    matrix inverse_view_matrix;
+    float aspect_ratio;
+    float fov;
+    uint background_mode;
+    float4 background_color;
+    float4 view_rect;
+};
+
+cbuffer LightingData : register(b1)
+{
+    uint light_count;
+    uint debug_view;
+};
+
+// IDs in the bindless heap, shared with deferred_gbuffer.hlsl
+cbuffer BindlessIds : register(b2)
+{
+    // vertices, indices, materials, uvs and lights
+    uint scene_srvs;
+    uint first_texture;
+    // in the order of G_BUFFER_TARGETS in deferred.rs
+    uint g_buffer_srvs;
+};
+
+static const uint BACKGROUND_ENVIRONMENT = 0;
+static const uint BACKGROUND_COLOR = 1;
+static const uint BACKGROUND_TRANSPARENT = 2;
+
+static const uint DEBUG_VIEW_NORMALS = 1;
+static const uint DEBUG_VIEW_DEPTH = 2;
+// distance shown as middle grey in the depth view
+static const float DEBUG_DEPTH_SCALE = 10.0f;
+
+static const float PI = 3.14159265359f;
+// must match GetEnvironmentLight in demo.hlsl
+static const float3 AMBIENT_LIGHT = float3(0.2f, 0.3f, 0.3f);
+
+static const uint LIGHT_KIND_POINT = 0;
+static const uint LIGHT_KIND_DIRECTIONAL = 1;
+static const uint LIGHT_KIND_SPOT = 2;
+
+struct Light
+{
+    float3 position;
+    uint kind;
+    float3 direction;
+    float range;
+    float3 color;
+    float radius;
+    float3 right;
+    float spot_cos_inner;
+    float3 up;
+    float spot_cos_outer;
+};
+
+// Every space views the whole bindless heap as one resource type, see bindless.rs
+Texture2D<float4> textures[] : register(t0, space1);
+StructuredBuffer<Light> light_buffers[] : register(t0, space7);
+#define light_buffer light_buffers[scene_srvs + 4]
+#define g_albedo textures[g_buffer_srvs]
+#define g_normal textures[g_buffer_srvs + 1]
+#define g_depth textures[g_buffer_srvs + 2]
+#define g_material textures[g_buffer_srvs + 3]
+#define g_emission textures[g_buffer_srvs + 4]
+
+PSInput VSMain(float4 position : POSITION, float2 uv : TEXCOORD) {
+    PSInput result;
+    result.position = position;
+    result.uv = uv;
+    return result;
+}
+
+float RangeAttenuation(float distance, float range)
+{
+    float factor = distance / range;
+    float window = saturate(1.0f - factor * factor * factor * factor);
+    return window * window;
+}
+
+// GGX distribution with the height correlated Smith visibility term and Schlick's Fresnel
+float3 Specular(float3 normal, float3 view, float3 to_light, float roughness, float3 f0)
+{
+    float3 half_vector = normalize(view + to_light);
+    float n_dot_h = saturate(dot(normal, half_vector));
+    float n_dot_v = max(dot(normal, view), 1e-4f);
+    float n_dot_l = max(dot(normal, to_light), 1e-4f);
+    float a2 = roughness * roughness;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0f) + 1.0f;
+    float distribution = a2 / (PI * d * d);
+    float visibility = 0.5f / (n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0f - a2) + a2)
+        + n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0f - a2) + a2));
+    float3 fresnel = f0 + (1.0f - f0) * pow(1.0f - saturate(dot(view, half_vector)), 5.0f);
+    return distribution * visibility * fresnel;
+}
+
+float4 Background()
+{
+    if (background_mode == BACKGROUND_COLOR)
+    {
+        return float4(background_color.rgb, 1.0f);
+    }
+    if (background_mode == BACKGROUND_TRANSPARENT)
+    {
+        return 0.0f;
+    }
+    return float4(AMBIENT_LIGHT, 1.0f);
+}
+
+float4 PSMain(PSInput input) : SV_TARGET
+{
+    int3 pixel = int3(input.position.xy, 0);
+    float distance = g_depth.Load(pixel).x;
+    float3 normal = g_normal.Load(pixel).xyz;
+
+    if (debug_view == DEBUG_VIEW_NORMALS)
+    {
+        return distance > 0.0f ? float4(normal * 0.5f + 0.5f, 1.0f) : float4(0.0f, 0.0f, 0.0f, 1.0f);
+    }
+    if (debug_view == DEBUG_VIEW_DEPTH)
+    {
+        // far away where nothing was drawn
+        return distance > 0.0f ? float4((distance / (distance + DEBUG_DEPTH_SCALE)).xxx, 1.0f) : 1.0f;
+    }
+    if (distance <= 0.0f)
+    {
+        return Background();
+    }
+
+    // Must match PSMain in demo.hlsl, the stored distance is along the primary ray
+    float2 uv = view_rect.xy + input.uv * view_rect.zw;
+    float2 ndc = float2(2.0f * uv.x - 1.0f, 1.0f - 2.0f * uv.y);
+    ndc.x *= aspect_ratio;
+    float scale = tan(fov * 0.5f);
+    float3 ray_direction_camera_space = normalize(float3(ndc.x * scale, ndc.y * scale, -1.0f));
+    float3 ray_direction = normalize(mul((float3x3)inverse_view_matrix, ray_direction_camera_space));
+    float3 position = inverse_view_matrix._m03_m13_m23 + ray_direction * distance;
+    float3 view = -ray_direction;
+
+    float3 albedo = g_albedo.Load(pixel).rgb;
+    float2 material = g_material.Load(pixel).xy;
+    float metallic = material.x;
+    float roughness = max(material.y * material.y, 1e-3f);
+    float3 f0 = lerp(0.04f, albedo, metallic);
+    float3 diffuse = albedo * (1.0f - metallic) / PI;
+
+    float3 color = AMBIENT_LIGHT * albedo + g_emission.Load(pixel).rgb;
+    for (uint i = 0; i < light_count; ++i)
+    {
+        Light light = light_buffer[i];
+        float3 radiance = light.color;
+        float3 to_light;
+        if (light.kind == LIGHT_KIND_DIRECTIONAL)
+        {
+            to_light = -light.direction;
+        }
+        else if (light.kind == LIGHT_KIND_POINT || light.kind == LIGHT_KIND_SPOT)
+        {
+            to_light = light.position - position;
+            float light_distance = length(to_light);
+            to_light /= light_distance;
+            radiance *= RangeAttenuation(light_distance, light.range) / max(light_distance * light_distance, 1e-4f);
+            if (light.kind == LIGHT_KIND_SPOT)
+            {
+                radiance *= smoothstep(light.spot_cos_outer, light.spot_cos_inner, dot(-to_light, light.direction));
+            }
+        }
+        else
+        {
+            // area lights are left to the path tracer
+            continue;
+        }
+
+        float n_dot_l = dot(normal, to_light);
+        if (n_dot_l > 0.0f)
+        {
+            color += radiance * n_dot_l * (diffuse + Specular(normal, view, to_light, roughness, f0));
+        }
+    }
+    return float4(color, 1.0f);
+}
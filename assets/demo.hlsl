@@ -11,19 +11,198 @@ cbuffer CameraBuffer : register(b0) {
     matrix inverse_view_matrix;
     float aspect_ratio;
     float fov;
+    uint background_mode;
+    float4 background_color;
+    // offset and size of the rendered part of the image in uv, tiles of an offline render
+    // cover only a part
+    float4 view_rect;
 };
 
-cbuffer MeshData : register(b1)
+static const uint BACKGROUND_ENVIRONMENT = 0;
+static const uint BACKGROUND_COLOR = 1;
+static const uint BACKGROUND_TRANSPARENT = 2;
+
+cbuffer SceneInfo : register(b1)
 {
     uint vertex_count;
+    uint light_count;
+    uint frame_index;
+    // 0 when every light is sampled
+    uint light_tree_size;
+    uint infinite_light_count;
+    uint primitive_count;
+};
+
+struct MaterialData
+{
+    float4 base_color;
+    float4 emissive;
+    float transmission;
+    float ior;
+    uint base_color_texture;
+    float metallic;
+    float perceptual_roughness;
+    float clearcoat;
+    float clearcoat_perceptual_roughness;
+    // of the instance, stochastic transparency below 1
+    float opacity;
+};
+
+cbuffer PathTracerSettings : register(b2)
+{
+    uint max_bounces;
+    uint samples_per_frame;
+    uint seed;
+    float max_indirect_radiance;
+    float regularization;
+    uint ambient_occlusion;
+    float ambient_occlusion_radius;
+    uint debug_view;
+    uint radiance_cache;
+    float radiance_cache_cell_size;
+    uint radiance_cache_frame;
+    // furnace test, a sphere under a uniform white environment replaces the scene
+    uint furnace_test;
+    // counts path lengths and ends into path_statistics_buffer
+    uint path_statistics;
+    // primary rays go through SobolSample positions of their pixel instead of its center
+    uint jitter;
+    // primary_triangles holds the depth prepass of this frame
+    uint depth_prepass;
+    // primary hits come from the G-buffer of hybrid_gbuffer.hlsl
+    uint hybrid;
+    // center and radius
+    float4 furnace_sphere;
+    MaterialData furnace_material;
+};
+
+// IDs in the bindless heap
+cbuffer BindlessIds : register(b3)
+{
+    // the scene buffers in the order they are declared below
+    uint scene_srvs;
+    // of material texture slot 0, the other slots follow
+    uint first_texture;
+    // the G-buffer of the hybrid integrator, moves when it grows
+    uint g_buffer_srvs;
+    // primary_triangles of the depth prepass, moves when it grows
+    uint depth_prepass_srv;
+};
+
+static const uint DEBUG_VIEW_NONE = 0;
+static const uint DEBUG_VIEW_NORMALS = 1;
+static const uint DEBUG_VIEW_DEPTH = 2;
+static const uint DEBUG_VIEW_UVS = 3;
+static const uint DEBUG_VIEW_BVH_HEATMAP = 4;
+static const uint DEBUG_VIEW_BOUNCE_COUNT = 5;
+static const uint DEBUG_VIEW_PATH_END = 6;
+// distance shown as middle grey in the depth view
+static const float DEBUG_DEPTH_SCALE = 10.0f;
+// crossed triangles shown as red in the heatmap
+static const float DEBUG_HEATMAP_MAX = 32.0f;
+
+static const uint NO_TEXTURE = 0xffffffff;
+
+// Every space views the whole bindless heap as one resource type, see bindless.rs
+Texture2D<float4> textures[] : register(t0, space1);
+Texture2D<uint> uint_textures[] : register(t0, space2);
+StructuredBuffer<float3> float3_buffers[] : register(t0, space3);
+StructuredBuffer<float2> float2_buffers[] : register(t0, space4);
+StructuredBuffer<uint> uint_buffers[] : register(t0, space5);
+StructuredBuffer<MaterialData> material_buffers[] : register(t0, space6);
+
+#define vertex_buffer float3_buffers[scene_srvs]
+#define index_buffer uint_buffers[scene_srvs + 1]
+// one material per triangle
+#define material_buffer material_buffers[scene_srvs + 2]
+#define uv_buffer float2_buffers[scene_srvs + 3]
+
+static const uint LIGHT_KIND_POINT = 0;
+static const uint LIGHT_KIND_DIRECTIONAL = 1;
+static const uint LIGHT_KIND_SPOT = 2;
+static const uint LIGHT_KIND_RECT = 3;
+static const uint LIGHT_KIND_DISK = 4;
+
+struct Light
+{
+    float3 position;
+    uint kind;
+    float3 direction;
+    float range;
+    float3 color;
+    float radius;
+    // half extents for rect lights, unit axes for disk lights
+    float3 right;
+    float spot_cos_inner;
+    float3 up;
+    float spot_cos_outer;
+};
+
+StructuredBuffer<Light> light_buffers[] : register(t0, space7);
+#define light_buffer light_buffers[scene_srvs + 4]
+
+// Light hierarchy node, the left child of an inner node is the next node. Directional lights
+// follow the tree as leaves.
+struct LightNode
+{
+    float3 bounds_min;
+    // 0 for leaves
+    uint right_child;
+    float3 bounds_max;
+    uint light;
+    float3 axis;
+    float cos_cone;
+    float power;
+    uint parent;
+    uint2 padding;
+};
+
+StructuredBuffer<LightNode> light_trees[] : register(t0, space8);
+#define light_tree light_trees[scene_srvs + 5]
+
+static const uint PRIMITIVE_KIND_SPHERE = 0;
+static const uint PRIMITIVE_KIND_PLANE = 1;
+static const uint PRIMITIVE_KIND_BOX = 2;
+
+struct Primitive
+{
+    float4x4 world_to_local;
+    // radius in x for spheres, half sizes otherwise
+    float3 size;
+    uint kind;
+    MaterialData material;
 };
 
-StructuredBuffer<float3> vertex_buffer : register(t0);
-StructuredBuffer<uint> index_buffer : register(t1);
+StructuredBuffer<Primitive> primitive_buffers[] : register(t0, space9);
+#define primitive_buffer primitive_buffers[scene_srvs + 6]
+// Triangle seen through every pixel center by the depth prepass plus one, 0 where it saw none
+#define primary_triangles uint_textures[depth_prepass_srv]
+// G-buffer of the hybrid integrator, in the order of G_BUFFER_TARGETS in hybrid_g_buffer.rs. The normal is the one of
+// the triangle and the depth the distance to the camera, the material ID is the triangle index plus one.
+#define g_albedo textures[g_buffer_srvs]
+#define g_normal textures[g_buffer_srvs + 1]
+#define g_depth textures[g_buffer_srvs + 2]
+#define g_material_id uint_textures[g_buffer_srvs + 3]
+SamplerState texture_sampler : register(s0);
+
+// Hash grid of the radiance leaving surfaces, resolved by radiance_cache.hlsl. An entry is the
+// checksum of its cell (0 when free), the last frame it was used, the sample count, the
+// radiance (float3), then the samples of this frame: count and fixed point sum (uint3).
+RWByteAddressBuffer radiance_cache_buffer : register(u0);
+static const uint RADIANCE_CACHE_ENTRIES = 1 << 18;
+static const uint RADIANCE_CACHE_STRIDE = 48;
+static const float RADIANCE_CACHE_SCALE = 128.0f;
+// Sample radiance is clamped to this, so a frame's sums don't overflow
+static const float RADIANCE_CACHE_MAX_RADIANCE = 256.0f;
+// Cells are read only once they hold this many samples
+static const uint RADIANCE_CACHE_MIN_SAMPLES = 16;
+// Entries tried after the one a cell hashes to
+static const uint RADIANCE_CACHE_PROBES = 8;
+// Fraction of paths that never read the cache, they keep it up to date
+static const float RADIANCE_CACHE_TRAINING_PATHS = 0.125f;
+static const uint RADIANCE_CACHE_INVALID = 0xffffffff;
 
 static const float SUPER_FAR = 10000.0f;
-static const uint MAX_BOUNCE_COUNT = 10;
-static const uint RENDERS_PER_FRAME = 2;
 static const float PI = 3.14159265359f;
 
 struct Ray
@@ -35,11 +214,14 @@ struct Ray
 struct RayTracingMaterial
 {
     float4 color;
-    float4 specular_color;
     float4 emission_color;
-    float specular_probability;
     float emission_strength;
-    float smoothness;
+    float metallic;
+    float perceptual_roughness;
+    float clearcoat;
+    float clearcoat_perceptual_roughness;
+    float transmission;
+    float ior;
 };
 
 struct Triangle
@@ -52,12 +234,41 @@ struct Triangle
 struct HitInfo
 {
     bool hit;
+    // whether the ray hit the side the normal points to
+    bool front_face;
+    // weights of the triangle corners a, b and c
+    float3 barycentrics;
     float distance;
     float3 hit_point;
     float3 normal;
+    float2 uv;
     RayTracingMaterial material;
 };
 
+static const uint NO_TRIANGLE = 0xffffffff;
+// Triangle the depth prepass saw through the pixel, the primary rays start their search from it
+static uint primary_triangle = NO_TRIANGLE;
+// Pixel the paths start from, the hybrid integrator reads their primary hit from the G-buffer there
+static int2 primary_pixel = 0;
+
+// Statistics of the ray being traced, read by the debug views
+static uint crossed_triangles = 0;
+static uint path_bounces = 0;
+static uint path_end = 0;
+
+// Why a path stopped, must match the counters of path_statistics.rs
+static const uint PATH_END_ESCAPED = 0;
+static const uint PATH_END_EMITTER = 1;
+static const uint PATH_END_RUSSIAN_ROULETTE = 2;
+static const uint PATH_END_MAX_BOUNCES = 3;
+static const uint PATH_END_RADIANCE_CACHE = 4;
+static const uint PATH_END_COUNT = 5;
+
+// Counters that are never cleared, the sum of the path lengths followed by the paths per PATH_END
+RWByteAddressBuffer path_statistics_buffer : register(u1);
+static const uint PATH_STATISTICS_LENGTH_OFFSET = 0;
+static const uint PATH_STATISTICS_END_OFFSET = 4;
+
 uint NextRandom(inout uint state)
 {
     state = state * 747796405 + 2891336453;
@@ -100,6 +311,10 @@ float2 mod2(float2 x, float2 y)
 
 float3 GetEnvironmentLight(Ray ray)
 {
+    if (furnace_test)
+    {
+        return 1.0f;
+    }
     return float3(0.2f, 0.3f, 0.3f);
 }
 
@@ -121,7 +336,9 @@ HitInfo IntersectTriangle(Ray ray, Triangle tri)
     float w = 1 - u - v;
 
     HitInfo hit_info;
-    hit_info.hit = determinant >= 1E-6 && distance >= 0 && u >= 0 && v >= 0 && w >= 0;
+    hit_info.hit = abs(determinant) >= 1E-6 && distance >= 0 && u >= 0 && v >= 0 && w >= 0;
+    hit_info.front_face = determinant > 0;
+    hit_info.barycentrics = float3(w, u, v);
     hit_info.hit_point = ray.origin + ray.direction * distance;
     hit_info.normal = normalize(normal_vector);
     hit_info.distance = distance;
@@ -129,72 +346,1149 @@ HitInfo IntersectTriangle(Ray ray, Triangle tri)
     return hit_info;
 }
 
-HitInfo GetCollision(Ray ray)
+// Intersects in local space. The local direction isn't normalized, so distances stay in world units
+HitInfo IntersectPrimitive(Ray ray, Primitive primitive)
 {
-    HitInfo closest_hit;
-    closest_hit.distance = SUPER_FAR;
+    float3 origin = mul(primitive.world_to_local, float4(ray.origin, 1.0f)).xyz;
+    float3 direction = mul(primitive.world_to_local, float4(ray.direction, 0.0f)).xyz;
+
+    HitInfo hit_info;
+    hit_info.hit = false;
+    hit_info.front_face = true;
+    hit_info.barycentrics = 0.0f;
+    hit_info.distance = SUPER_FAR;
+    hit_info.uv = 0.0f;
+    float3 local_normal = float3(0.0f, 1.0f, 0.0f);
+
+    if (primitive.kind == PRIMITIVE_KIND_SPHERE)
+    {
+        float radius = primitive.size.x;
+        float a = dot(direction, direction);
+        float b = dot(origin, direction);
+        float c = dot(origin, origin) - radius * radius;
+        float discriminant = b * b - a * c;
+        if (discriminant >= 0.0f)
+        {
+            float root = sqrt(discriminant);
+            float near = (-b - root) / a;
+            float far = (-b + root) / a;
+            hit_info.front_face = near >= 0.0f;
+            hit_info.distance = hit_info.front_face ? near : far;
+            hit_info.hit = hit_info.distance >= 0.0f;
+            local_normal = (origin + direction * hit_info.distance) / radius;
+            hit_info.uv = float2(atan2(local_normal.z, local_normal.x) / (2.0f * PI) + 0.5f, acos(clamp(local_normal.y, -1.0f, 1.0f)) / PI);
+        }
+    }
+    else if (primitive.kind == PRIMITIVE_KIND_PLANE)
+    {
+        hit_info.distance = -origin.y / direction.y;
+        float3 position = origin + direction * hit_info.distance;
+        hit_info.front_face = direction.y < 0.0f;
+        hit_info.hit = abs(direction.y) > 1e-8f && hit_info.distance >= 0.0f && all(abs(position.xz) <= primitive.size.xz);
+        hit_info.uv = any(isinf(primitive.size.xz)) ? position.xz : position.xz / (2.0f * primitive.size.xz) + 0.5f;
+    }
+    else if (primitive.kind == PRIMITIVE_KIND_BOX)
+    {
+        float3 t0 = (-primitive.size - origin) / direction;
+        float3 t1 = (primitive.size - origin) / direction;
+        float3 t_near = min(t0, t1);
+        float3 t_far = max(t0, t1);
+        float near = max(max(t_near.x, t_near.y), t_near.z);
+        float far = min(min(t_far.x, t_far.y), t_far.z);
+        hit_info.front_face = near >= 0.0f;
+        hit_info.distance = hit_info.front_face ? near : far;
+        hit_info.hit = near <= far && far >= 0.0f;
+
+        float3 relative = (origin + direction * hit_info.distance) / primitive.size;
+        float3 magnitude = abs(relative);
+        if (magnitude.x >= magnitude.y && magnitude.x >= magnitude.z)
+        {
+            local_normal = float3(sign(relative.x), 0.0f, 0.0f);
+            hit_info.uv = relative.zy;
+        }
+        else if (magnitude.y >= magnitude.z)
+        {
+            local_normal = float3(0.0f, sign(relative.y), 0.0f);
+            hit_info.uv = relative.xz;
+        }
+        else
+        {
+            local_normal = float3(0.0f, 0.0f, sign(relative.z));
+            hit_info.uv = relative.xy;
+        }
+        hit_info.uv = hit_info.uv * 0.5f + 0.5f;
+    }
+
+    hit_info.hit_point = ray.origin + ray.direction * hit_info.distance;
+    hit_info.normal = normalize(mul(transpose((float3x3)primitive.world_to_local), local_normal));
+    return hit_info;
+}
+
+uint HashUint(uint x)
+{
+    uint state = x * 747796405u + 2891336453u;
+    uint word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+// Stochastic transparency of faded instances: a ray passes through a surface with the probability of its missing
+// opacity. The same ray always decides the same way, the paths of a pixel differ so its average converges.
+bool HitsOpaquePart(float opacity, Ray ray, uint surface)
+{
+    if (opacity >= 1.0f)
+    {
+        return true;
+    }
+    uint3 origin = asuint(ray.origin);
+    uint3 direction = asuint(ray.direction);
+    uint hash = HashUint(origin.x ^ HashUint(origin.y ^ HashUint(origin.z ^ HashUint(surface))));
+    hash = HashUint(hash ^ HashUint(direction.x ^ HashUint(direction.y ^ HashUint(direction.z))));
+    return float(hash) * (1.0f / 4294967296.0f) < opacity;
+}
+
+// Whether the ray hits the triangle starting at index i closer than max_distance. Back faces are only hit from inside
+// transmissive meshes.
+bool HitTriangle(Ray ray, uint i, float max_distance, out HitInfo hit)
+{
+    Triangle tri;
+    tri.a = vertex_buffer[index_buffer[i]];
+    tri.b = vertex_buffer[index_buffer[i + 1]];
+    tri.c = vertex_buffer[index_buffer[i + 2]];
+
+    hit = IntersectTriangle(ray, tri);
+    crossed_triangles += hit.hit;
+    if (!hit.hit || hit.distance >= max_distance)
+    {
+        return false;
+    }
+    MaterialData material = material_buffer[i / 3];
+    if (!hit.front_face && material.transmission <= 0.0f)
+    {
+        return false;
+    }
+    return HitsOpaquePart(material.opacity, ray, i / 3);
+}
 
-    for (uint i = 0; i < vertex_count; i += 3)
+// Base color of a material at uv, the texture applied
+float4 BaseColor(MaterialData material, float2 uv)
+{
+    float4 color = material.base_color;
+    if (material.base_color_texture != NO_TEXTURE)
     {
-        Triangle tri;
-        tri.a = vertex_buffer[index_buffer[i]];
-        tri.b = vertex_buffer[index_buffer[i + 1]];
-        tri.c = vertex_buffer[index_buffer[i + 2]];
+        uint texture_id = first_texture + material.base_color_texture;
+        color *= textures[NonUniformResourceIndex(texture_id)].SampleLevel(texture_sampler, uv, 0);
+    }
+    return color;
+}
 
-        HitInfo hit = IntersectTriangle(ray, tri);
+void SetHitMaterial(inout HitInfo hit, MaterialData material, float4 color)
+{
+    hit.material.color = color;
+    hit.material.metallic = material.metallic;
+    hit.material.perceptual_roughness = material.perceptual_roughness;
+    hit.material.clearcoat = material.clearcoat;
+    hit.material.clearcoat_perceptual_roughness = material.clearcoat_perceptual_roughness;
+    hit.material.emission_color = material.emissive;
+    hit.material.emission_strength = 1.0f;
+    hit.material.transmission = material.transmission;
+    hit.material.ior = material.ior;
+}
+
+// Replaces closest_hit with the closest analytic primitive or furnace sphere in front of it, returns whether there was
+// one, with its material
+bool IntersectAnalytic(Ray ray, inout HitInfo closest_hit, out MaterialData material)
+{
+    int closest_primitive = -1;
+    for (uint p = 0; p < primitive_count; ++p)
+    {
+        Primitive primitive = primitive_buffer[p];
+        HitInfo hit = IntersectPrimitive(ray, primitive);
         if (hit.hit && hit.distance < closest_hit.distance)
+        {
+            if (!hit.front_face && primitive.material.transmission <= 0.0f)
+            {
+                continue;
+            }
+            if (!HitsOpaquePart(primitive.material.opacity, ray, 0x80000000u | p))
+            {
+                continue;
+            }
+            closest_hit = hit;
+            closest_primitive = p;
+        }
+    }
+
+    bool furnace_hit = false;
+    if (furnace_test)
+    {
+        Primitive sphere;
+        sphere.world_to_local = float4x4(
+            1.0f, 0.0f, 0.0f, -furnace_sphere.x,
+            0.0f, 1.0f, 0.0f, -furnace_sphere.y,
+            0.0f, 0.0f, 1.0f, -furnace_sphere.z,
+            0.0f, 0.0f, 0.0f, 1.0f);
+        sphere.size = furnace_sphere.w;
+        sphere.kind = PRIMITIVE_KIND_SPHERE;
+        sphere.material = furnace_material;
+        HitInfo hit = IntersectPrimitive(ray, sphere);
+        if (hit.hit && hit.distance < closest_hit.distance
+            && (hit.front_face || furnace_material.transmission > 0.0f))
         {
             closest_hit = hit;
-            closest_hit.material.color = float4(0.6f, 0.0f, 0.0f, 1.0f);
-            closest_hit.material.smoothness = 0.5f;
-            closest_hit.material.specular_color = float4(0.5f, 0.5f, 0.5f, 1.0f);
-            closest_hit.material.specular_probability = 0.5f;
-            closest_hit.material.emission_color = float4(0.0f, 0.0f, 0.0f, 1.0f);
-            closest_hit.material.emission_strength = 0.0f;
+            furnace_hit = true;
+        }
+    }
+
+    material = furnace_material;
+    if (!furnace_hit && closest_primitive >= 0)
+    {
+        material = primitive_buffer[closest_primitive].material;
+    }
+    return furnace_hit || closest_primitive >= 0;
+}
+
+// Closest hit along the ray. raster_triangle is the triangle the depth prepass saw through the pixel of a primary ray,
+// nothing rasterized is closer, so when the ray hits it the other triangles are skipped. NO_TRIANGLE tests them all.
+HitInfo GetCollision(Ray ray, uint raster_triangle)
+{
+    HitInfo closest_hit;
+    closest_hit.hit = false;
+    closest_hit.distance = SUPER_FAR;
+    uint closest_index = 0;
+
+    HitInfo triangle_hit;
+    if (raster_triangle < vertex_count / 3 && HitTriangle(ray, raster_triangle * 3, SUPER_FAR, triangle_hit))
+    {
+        closest_hit = triangle_hit;
+        closest_index = raster_triangle * 3;
+    }
+    else
+    {
+        for (uint i = 0; i < vertex_count; i += 3)
+        {
+            if (HitTriangle(ray, i, closest_hit.distance, triangle_hit))
+            {
+                closest_hit = triangle_hit;
+                closest_index = i;
+            }
+        }
+    }
+
+    MaterialData material;
+    bool analytic_hit = IntersectAnalytic(ray, closest_hit, material);
+    if (closest_hit.hit)
+    {
+        if (!analytic_hit)
+        {
+            material = material_buffer[closest_index / 3];
+            float3 weights = closest_hit.barycentrics;
+            closest_hit.uv = uv_buffer[index_buffer[closest_index]] * weights.x
+                + uv_buffer[index_buffer[closest_index + 1]] * weights.y
+                + uv_buffer[index_buffer[closest_index + 2]] * weights.z;
         }
+        SetHitMaterial(closest_hit, material, BaseColor(material, closest_hit.uv));
+    }
+    return closest_hit;
+}
+
+// Primary hit of the hybrid integrator, the surface the G-buffer stored for primary_pixel. Analytic primitives aren't
+// rasterized, the ray is still tested against them.
+HitInfo GBufferHit(Ray ray)
+{
+    HitInfo closest_hit;
+    closest_hit.hit = false;
+    closest_hit.distance = SUPER_FAR;
+
+    // 0 wraps around to NO_TRIANGLE
+    uint triangle_index = g_material_id.Load(int3(primary_pixel, 0)) - 1;
+    if (triangle_index < vertex_count / 3)
+    {
+        closest_hit.hit = true;
+        closest_hit.distance = g_depth.Load(int3(primary_pixel, 0)).x;
+        closest_hit.hit_point = ray.origin + ray.direction * closest_hit.distance;
+        closest_hit.normal = normalize(g_normal.Load(int3(primary_pixel, 0)).xyz);
+        closest_hit.front_face = dot(closest_hit.normal, ray.direction) < 0.0f;
+        closest_hit.barycentrics = 0.0f;
+        closest_hit.uv = 0.0f;
+    }
+
+    MaterialData material;
+    if (IntersectAnalytic(ray, closest_hit, material))
+    {
+        SetHitMaterial(closest_hit, material, BaseColor(material, closest_hit.uv));
+    }
+    else if (closest_hit.hit)
+    {
+        SetHitMaterial(closest_hit, material_buffer[triangle_index], g_albedo.Load(int3(primary_pixel, 0)));
     }
     return closest_hit;
 }
 
-float3 Trace(Ray ray, inout uint rng_state)
+HitInfo GetCollision(Ray ray)
+{
+    return GetCollision(ray, NO_TRIANGLE);
+}
+
+float RangeAttenuation(float distance, float range)
+{
+    float factor = distance / range;
+    float window = saturate(1.0f - factor * factor * factor * factor);
+    return window * window;
+}
+
+bool IsOccluded(float3 origin, float3 direction, float max_distance)
+{
+    Ray shadow_ray;
+    shadow_ray.origin = origin;
+    shadow_ray.direction = direction;
+    HitInfo hit = GetCollision(shadow_ray);
+    return hit.hit && hit.distance < max_distance;
+}
+
+float Luminance(float3 color)
+{
+    return dot(color, float3(0.2126f, 0.7152f, 0.0722f));
+}
+
+// Lowest GGX roughness, keeps the distribution finite on mirror-like surfaces
+static const float MIN_ALPHA = 0.002f;
+
+// Metallic-roughness parameters of a surface that doesn't transmit
+struct SurfaceBrdf
+{
+    // albedo of the Lambertian lobe
+    float3 diffuse;
+    // Fresnel reflectance at normal incidence
+    float3 f0;
+    // GGX roughness
+    float alpha;
+    // strength of the clear coat layer on top, 0 without one
+    float clearcoat;
+    // GGX roughness of the clear coat
+    float clearcoat_alpha;
+};
+
+// Fresnel reflectance at normal incidence of the clear coat, an index of refraction of 1.5
+static const float CLEARCOAT_F0 = 0.04f;
+
+SurfaceBrdf GetSurfaceBrdf(RayTracingMaterial material)
+{
+    SurfaceBrdf brdf;
+    brdf.diffuse = material.color.rgb * (1.0f - material.metallic);
+    brdf.f0 = lerp(0.04f, material.color.rgb, material.metallic);
+    brdf.alpha = max(material.perceptual_roughness * material.perceptual_roughness, MIN_ALPHA);
+    brdf.clearcoat = material.clearcoat;
+    float clearcoat_roughness = material.clearcoat_perceptual_roughness;
+    brdf.clearcoat_alpha = max(clearcoat_roughness * clearcoat_roughness, MIN_ALPHA);
+    return brdf;
+}
+
+float3 FresnelSchlick(float3 f0, float cos_theta)
+{
+    float x = 1.0f - saturate(cos_theta);
+    return f0 + (1.0f - f0) * x * x * x * x * x;
+}
+
+float GgxDistribution(float n_dot_h, float alpha)
+{
+    float alpha2 = alpha * alpha;
+    float d = n_dot_h * n_dot_h * (alpha2 - 1.0f) + 1.0f;
+    return alpha2 / (PI * d * d);
+}
+
+// Smith masking of the GGX distribution for one direction
+float SmithG1(float n_dot_x, float alpha)
+{
+    float alpha2 = alpha * alpha;
+    return 2.0f * n_dot_x / (n_dot_x + sqrt(alpha2 + (1.0f - alpha2) * n_dot_x * n_dot_x));
+}
+
+// GGX specular reflection, directions must be above the surface
+float3 EvaluateSpecular(float3 f0, float alpha, float3 normal, float3 view, float3 to_light)
+{
+    float n_dot_l = dot(normal, to_light);
+    float n_dot_v = dot(normal, view);
+    float3 half_vector = normalize(view + to_light);
+    float n_dot_h = saturate(dot(normal, half_vector));
+    float3 fresnel = FresnelSchlick(f0, dot(view, half_vector));
+    return fresnel * GgxDistribution(n_dot_h, alpha)
+        * SmithG1(n_dot_l, alpha) * SmithG1(n_dot_v, alpha) / (4.0f * n_dot_l * n_dot_v);
+}
+
+// Share of the light reflected by the clear coat, the layers below only get the rest. Like the
+// glTF clearcoat extension, this only depends on the view direction.
+float ClearcoatAttenuation(SurfaceBrdf brdf, float n_dot_v)
+{
+    return brdf.clearcoat * FresnelSchlick(CLEARCOAT_F0, n_dot_v).x;
+}
+
+// Lambertian diffuse plus GGX specular reflection from to_light towards view under an optional
+// clear coat, times the cosine of to_light
+float3 EvaluateBrdf(SurfaceBrdf brdf, float3 normal, float3 view, float3 to_light)
+{
+    float n_dot_l = dot(normal, to_light);
+    float n_dot_v = dot(normal, view);
+    if (n_dot_l <= 0 || n_dot_v <= 0)
+    {
+        return 0;
+    }
+    float3 half_vector = normalize(view + to_light);
+    float3 fresnel = FresnelSchlick(brdf.f0, dot(view, half_vector));
+    float3 specular = EvaluateSpecular(brdf.f0, brdf.alpha, normal, view, to_light);
+    float3 diffuse = brdf.diffuse * (1.0f - fresnel) / PI;
+    float3 base = diffuse + specular;
+    if (brdf.clearcoat > 0)
+    {
+        float3 clearcoat = EvaluateSpecular(CLEARCOAT_F0, brdf.clearcoat_alpha, normal, view, to_light);
+        base = base * (1.0f - ClearcoatAttenuation(brdf, n_dot_v)) + clearcoat * brdf.clearcoat;
+    }
+    return base * n_dot_l;
+}
+
+// Tangent and bitangent completing an orthonormal basis around the unit vector n
+void OrthonormalBasis(float3 n, out float3 tangent, out float3 bitangent)
+{
+    float z_sign = n.z >= 0 ? 1.0f : -1.0f;
+    float a = -1.0f / (z_sign + n.z);
+    float b = n.x * n.y * a;
+    tangent = float3(1.0f + z_sign * n.x * n.x * a, z_sign * b, -z_sign * n.x);
+    bitangent = float3(b, z_sign + n.y * n.y * a, -n.y);
+}
+
+// Samples a GGX microfacet normal visible from view (Heitz 2018), in tangent space with the normal along z
+float3 SampleGgxVisibleNormal(float3 view, float alpha, float2 u)
+{
+    float3 stretched = normalize(float3(alpha * view.x, alpha * view.y, view.z));
+    float length_squared = dot(stretched.xy, stretched.xy);
+    float3 t1 = length_squared > 0 ? float3(-stretched.y, stretched.x, 0) * rsqrt(length_squared) : float3(1, 0, 0);
+    float3 t2 = cross(stretched, t1);
+    float r = sqrt(u.x);
+    float phi = 2.0f * PI * u.y;
+    float p1 = r * cos(phi);
+    float p2 = r * sin(phi);
+    float s = 0.5f * (1.0f + stretched.z);
+    p2 = (1.0f - s) * sqrt(1.0f - p1 * p1) + s * p2;
+    float3 normal = p1 * t1 + p2 * t2 + sqrt(max(0.0f, 1.0f - p1 * p1 - p2 * p2)) * stretched;
+    return normalize(float3(alpha * normal.x, alpha * normal.y, max(0.0f, normal.z)));
+}
+
+// Reflects the ray off a GGX microfacet visible from it, returns the GGX reflectance over the
+// sampling pdf, which leaves only Fresnel and the masking of the reflected direction
+float3 SampleSpecular(inout Ray ray, float3 normal, float3 f0, float alpha, inout uint rng_state)
+{
+    float3 view = -ray.direction;
+    float3 tangent;
+    float3 bitangent;
+    OrthonormalBasis(normal, tangent, bitangent);
+    float3 local_view = float3(dot(view, tangent), dot(view, bitangent), dot(view, normal));
+    float2 u = float2(RandomValue(rng_state), RandomValue(rng_state));
+    float3 local_half = SampleGgxVisibleNormal(local_view, alpha, u);
+    float3 half_vector = local_half.x * tangent + local_half.y * bitangent + local_half.z * normal;
+    ray.direction = reflect(ray.direction, half_vector);
+    float n_dot_l = dot(normal, ray.direction);
+    if (n_dot_l <= 0)
+    {
+        return 0;
+    }
+    return FresnelSchlick(f0, dot(view, half_vector)) * SmithG1(n_dot_l, alpha);
+}
+
+// Probabilities of SampleBrdf picking the clear coat and the specular lobe by their estimated
+// reflectance, the diffuse lobe gets the rest
+float2 LobeProbabilities(SurfaceBrdf brdf, float n_dot_v)
+{
+    float clearcoat_weight = ClearcoatAttenuation(brdf, n_dot_v);
+    float base_weight = 1.0f - clearcoat_weight;
+    float specular_weight = base_weight * Luminance(FresnelSchlick(brdf.f0, n_dot_v));
+    float diffuse_weight = base_weight * Luminance(brdf.diffuse);
+    float total_weight = max(clearcoat_weight + specular_weight + diffuse_weight, 1e-4f);
+    return float2(clearcoat_weight, specular_weight) / total_weight;
+}
+
+// Solid angle pdf of reflecting view to to_light off a visible GGX microfacet
+float GgxReflectionPdf(float alpha, float n_dot_v, float n_dot_h)
+{
+    return SmithG1(n_dot_v, alpha) * GgxDistribution(n_dot_h, alpha) / (4.0f * n_dot_v);
+}
+
+// Solid angle pdf of SampleBrdf bouncing towards to_light
+float BrdfPdf(SurfaceBrdf brdf, float3 normal, float3 view, float3 to_light)
+{
+    float n_dot_l = dot(normal, to_light);
+    float n_dot_v = dot(normal, view);
+    if (n_dot_l <= 0 || n_dot_v <= 0)
+    {
+        return 0;
+    }
+    float n_dot_h = saturate(dot(normal, normalize(view + to_light)));
+    float2 probabilities = LobeProbabilities(brdf, n_dot_v);
+    return probabilities.x * GgxReflectionPdf(brdf.clearcoat_alpha, n_dot_v, n_dot_h)
+        + probabilities.y * GgxReflectionPdf(brdf.alpha, n_dot_v, n_dot_h)
+        + (1.0f - probabilities.x - probabilities.y) * n_dot_l / PI;
+}
+
+// Weight of a sample from the strategy with sample_pdf when other_pdf is the pdf of the other strategy
+float PowerHeuristic(float sample_pdf, float other_pdf)
+{
+    float sample_squared = sample_pdf * sample_pdf;
+    return sample_squared / max(sample_squared + other_pdf * other_pdf, 1e-8f);
+}
+
+// Picks a lobe with LobeProbabilities and bounces the ray off it, returns the path throughput.
+// pdf is the solid angle pdf of the new direction over every lobe, for weighting lights it hits.
+float3 SampleBrdf(inout Ray ray, HitInfo hit_info, SurfaceBrdf brdf, inout uint rng_state, out bool diffuse_bounce, out float pdf)
+{
+    float3 normal = hit_info.normal;
+    float3 view = -ray.direction;
+    float n_dot_v = dot(normal, view);
+    ray.origin = hit_info.hit_point;
+    diffuse_bounce = false;
+    pdf = 0;
+    if (n_dot_v <= 0)
+    {
+        return 0;
+    }
+
+    float2 probabilities = LobeProbabilities(brdf, n_dot_v);
+    float clearcoat_probability = probabilities.x;
+    float specular_probability = probabilities.y;
+    float base_weight = 1.0f - ClearcoatAttenuation(brdf, n_dot_v);
+
+    float3 throughput;
+    float lobe = RandomValue(rng_state);
+    if (lobe < clearcoat_probability)
+    {
+        float3 clearcoat = SampleSpecular(ray, normal, CLEARCOAT_F0, brdf.clearcoat_alpha, rng_state);
+        throughput = clearcoat * brdf.clearcoat / clearcoat_probability;
+    }
+    else if (lobe < clearcoat_probability + specular_probability)
+    {
+        float3 specular = SampleSpecular(ray, normal, brdf.f0, brdf.alpha, rng_state);
+        throughput = specular * base_weight / specular_probability;
+    }
+    else
+    {
+        diffuse_bounce = true;
+        ray.direction = normalize(normal + RandomDirection(rng_state));
+        float3 fresnel = FresnelSchlick(brdf.f0, dot(view, normalize(view + ray.direction)));
+        float diffuse_probability = max(1.0f - clearcoat_probability - specular_probability, 1e-4f);
+        throughput = brdf.diffuse * (1.0f - fresnel) * base_weight / diffuse_probability;
+    }
+    pdf = BrdfPdf(brdf, normal, view, ray.direction);
+    return throughput;
+}
+
+bool IsAreaLight(Light light)
+{
+    return light.kind == LIGHT_KIND_RECT || light.kind == LIGHT_KIND_DISK;
+}
+
+float AreaLightArea(Light light)
+{
+    if (light.kind == LIGHT_KIND_RECT)
+    {
+        return 4.0f * length(light.right) * length(light.up);
+    }
+    return PI * light.radius * light.radius;
+}
+
+// Distance to the emitting side of a rect or disk light, negative when the ray misses it
+float IntersectAreaLight(Ray ray, Light light)
+{
+    float cos_light = dot(ray.direction, light.direction);
+    if (!IsAreaLight(light) || cos_light >= 0)
+    {
+        return -1.0f;
+    }
+    float distance = dot(light.position - ray.origin, light.direction) / cos_light;
+    float3 offset = ray.origin + ray.direction * distance - light.position;
+    bool inside;
+    if (light.kind == LIGHT_KIND_RECT)
+    {
+        float2 uv = float2(dot(offset, light.right) / dot(light.right, light.right), dot(offset, light.up) / dot(light.up, light.up));
+        inside = all(abs(uv) <= 1.0f);
+    }
+    else
+    {
+        inside = dot(offset, offset) <= light.radius * light.radius;
+    }
+    return inside ? distance : -1.0f;
+}
+
+// Solid angle pdf of SampleLights picking a point on an area light at distance along to_light,
+// given the probability of picking the light itself
+float AreaLightPdf(Light light, float selection_pdf, float3 to_light, float distance)
+{
+    float cos_light = dot(light.direction, -to_light);
+    if (cos_light <= 0)
+    {
+        return 0;
+    }
+    return selection_pdf * distance * distance / (cos_light * AreaLightArea(light));
+}
+
+// Direct lighting from one light reflected towards view, picked with selection_pdf. Area lights
+// can also be hit by rays bouncing off the surface, their samples are weighted against those.
+float3 EvaluateLight(Light light, float selection_pdf, float3 position, float3 normal, float3 view, SurfaceBrdf brdf, inout uint rng_state)
+{
+    float mis_weight = 1.0f;
+    float3 to_light;
+    float distance;
+    float3 radiance = light.color;
+    if (light.kind == LIGHT_KIND_DIRECTIONAL)
+    {
+        to_light = -light.direction;
+        distance = SUPER_FAR;
+    }
+    else if (light.kind == LIGHT_KIND_RECT || light.kind == LIGHT_KIND_DISK)
+    {
+        float3 light_position;
+        float area;
+        if (light.kind == LIGHT_KIND_RECT)
+        {
+            float2 uv = float2(RandomValue(rng_state), RandomValue(rng_state)) * 2.0f - 1.0f;
+            light_position = light.position + light.right * uv.x + light.up * uv.y;
+            area = 4.0f * length(light.right) * length(light.up);
+        }
+        else
+        {
+            float2 disk = RandomPointInCircle(rng_state) * light.radius;
+            light_position = light.position + light.right * disk.x + light.up * disk.y;
+            area = PI * light.radius * light.radius;
+        }
+        to_light = light_position - position;
+        distance = length(to_light);
+        to_light /= distance;
+        float cos_light = dot(light.direction, -to_light);
+        radiance *= max(cos_light, 0.0f) * area / max(distance * distance, 1e-4f);
+        float light_pdf = AreaLightPdf(light, selection_pdf, to_light, distance);
+        mis_weight = PowerHeuristic(light_pdf, BrdfPdf(brdf, normal, view, to_light));
+    }
+    else
+    {
+        float3 light_position = light.position + RandomDirection(rng_state) * light.radius;
+        to_light = light_position - position;
+        distance = length(to_light);
+        to_light /= distance;
+        radiance *= RangeAttenuation(distance, light.range) / max(distance * distance, 1e-4f);
+        if (light.kind == LIGHT_KIND_SPOT)
+        {
+            float cos_angle = dot(-to_light, light.direction);
+            radiance *= smoothstep(light.spot_cos_outer, light.spot_cos_inner, cos_angle);
+        }
+    }
+
+    float3 reflected = radiance * EvaluateBrdf(brdf, normal, view, to_light) * mis_weight / selection_pdf;
+    if (all(reflected <= 0))
+    {
+        return 0;
+    }
+    if (IsOccluded(position + normal * 1e-4f, to_light, distance))
+    {
+        return 0;
+    }
+    return reflected;
+}
+
+// Estimated contribution of the lights under a tree node to a shading point
+float LightNodeImportance(LightNode node, float3 position, float3 normal)
+{
+    float3 center = (node.bounds_min + node.bounds_max) * 0.5f;
+    float radius = length(node.bounds_max - center);
+    float3 to_node = center - position;
+    float distance_squared = dot(to_node, to_node);
+    // inside the bounds every direction is possible
+    if (distance_squared <= radius * radius)
+    {
+        return node.power / max(radius * radius, 1e-4f);
+    }
+    float distance = sqrt(distance_squared);
+    to_node /= distance;
+    float uncertainty = asin(radius / distance);
+
+    // angle between the surface normal and the closest direction into the bounds
+    float angle_normal = max(acos(clamp(dot(normal, to_node), -1.0f, 1.0f)) - uncertainty, 0.0f);
+    if (angle_normal >= PI * 0.5f)
+    {
+        return 0;
+    }
+
+    // angle between the emission cone and the closest direction to the shading point
+    float angle_emission = acos(clamp(dot(node.axis, -to_node), -1.0f, 1.0f));
+    float angle_outside = max(angle_emission - acos(node.cos_cone) - uncertainty, 0.0f);
+    if (angle_outside >= PI * 0.5f)
+    {
+        return 0;
+    }
+
+    return node.power * cos(angle_normal) * cos(angle_outside) / distance_squared;
+}
+
+// Walks the light tree picking children by importance, returns the light index and its pdf
+bool SampleLightTree(float3 position, float3 normal, inout uint rng_state, out uint light, out float pdf)
+{
+    uint node_index = 0;
+    pdf = 1.0f;
+    while (light_tree[node_index].right_child != 0)
+    {
+        uint left = node_index + 1;
+        uint right = light_tree[node_index].right_child;
+        float left_importance = LightNodeImportance(light_tree[left], position, normal);
+        float right_importance = LightNodeImportance(light_tree[right], position, normal);
+        float total = left_importance + right_importance;
+        if (total <= 0)
+        {
+            light = 0;
+            return false;
+        }
+        float left_probability = left_importance / total;
+        if (RandomValue(rng_state) < left_probability)
+        {
+            node_index = left;
+            pdf *= left_probability;
+        }
+        else
+        {
+            node_index = right;
+            pdf *= 1.0f - left_probability;
+        }
+    }
+    light = light_tree[node_index].light;
+    return true;
+}
+
+// Probability of SampleLightTree picking the light of the leaf node_index
+float LightTreePdf(uint node_index, float3 position, float3 normal)
+{
+    float pdf = 1.0f;
+    while (node_index != 0)
+    {
+        uint parent = light_tree[node_index].parent;
+        uint left = parent + 1;
+        uint right = light_tree[parent].right_child;
+        float left_importance = LightNodeImportance(light_tree[left], position, normal);
+        float right_importance = LightNodeImportance(light_tree[right], position, normal);
+        float total = left_importance + right_importance;
+        if (total <= 0)
+        {
+            return 0;
+        }
+        pdf *= (node_index == left ? left_importance : right_importance) / total;
+        node_index = parent;
+    }
+    return pdf;
+}
+
+// Closest area light the ray hits before max_distance. Returns its index and, with a light tree,
+// its leaf.
+bool IntersectAreaLights(Ray ray, float max_distance, out uint light, out uint leaf, out float distance)
+{
+    light = 0;
+    leaf = 0;
+    distance = max_distance;
+    if (light_tree_size == 0)
+    {
+        for (uint i = 0; i < light_count; ++i)
+        {
+            float light_distance = IntersectAreaLight(ray, light_buffer[i]);
+            if (light_distance > 0 && light_distance < distance)
+            {
+                distance = light_distance;
+                light = i;
+            }
+        }
+        return distance < max_distance;
+    }
+
+    // lights without power aren't in the tree, they don't emit anything either
+    for (uint node = 0; node < light_tree_size; ++node)
+    {
+        if (light_tree[node].right_child != 0)
+        {
+            continue;
+        }
+        float light_distance = IntersectAreaLight(ray, light_buffer[light_tree[node].light]);
+        if (light_distance > 0 && light_distance < distance)
+        {
+            distance = light_distance;
+            light = light_tree[node].light;
+            leaf = node;
+        }
+    }
+    return distance < max_distance;
+}
+
+// Direct lighting from the lights reflected towards view. With many lights one of them is
+// picked through the light tree, directional lights are always sampled.
+float3 SampleLights(float3 position, float3 normal, float3 view, SurfaceBrdf brdf, inout uint rng_state)
+{
+    float3 light_sum = 0;
+    if (light_tree_size == 0)
+    {
+        for (uint i = 0; i < light_count; ++i)
+        {
+            light_sum += EvaluateLight(light_buffer[i], 1.0f, position, normal, view, brdf, rng_state);
+        }
+        return light_sum;
+    }
+
+    for (uint i = 0; i < infinite_light_count; ++i)
+    {
+        uint light = light_tree[light_tree_size + i].light;
+        light_sum += EvaluateLight(light_buffer[light], 1.0f, position, normal, view, brdf, rng_state);
+    }
+
+    uint light;
+    float pdf;
+    if (SampleLightTree(position, normal, rng_state, light, pdf))
+    {
+        light_sum += EvaluateLight(light_buffer[light], pdf, position, normal, view, brdf, rng_state);
+    }
+    return light_sum;
+}
+
+// Hash of the radiance cache cell around position and a checksum telling cells with the same entry apart. Surfaces
+// facing different ways get different cells, so light doesn't leak through thin walls.
+void RadianceCacheKey(float3 position, float3 normal, out uint hash, out uint checksum)
+{
+    int3 cell = int3(floor(position / radiance_cache_cell_size));
+    float3 axis = abs(normal);
+    uint dominant_axis = axis.x > axis.y && axis.x > axis.z ? 0 : (axis.y > axis.z ? 1 : 2);
+    uint normal_bin = dominant_axis * 2 + (normal[dominant_axis] < 0.0f ? 1 : 0);
+    hash = HashUint(asuint(cell.x) ^ HashUint(asuint(cell.y) ^ HashUint(asuint(cell.z) ^ HashUint(normal_bin))));
+    checksum = max(HashUint(hash ^ 0x68bc21ebu), 1u);
+}
+
+// Entry of a cell, inserted when insert is set and the cell isn't cached yet. RADIANCE_CACHE_INVALID when the cell
+// isn't cached or every probed entry is taken.
+uint RadianceCacheFind(uint hash, uint checksum, bool insert)
+{
+    for (uint probe = 0; probe < RADIANCE_CACHE_PROBES; probe++)
+    {
+        uint entry = (hash + probe) & (RADIANCE_CACHE_ENTRIES - 1);
+        uint address = entry * RADIANCE_CACHE_STRIDE;
+        uint stored;
+        if (insert)
+        {
+            radiance_cache_buffer.InterlockedCompareExchange(address, 0, checksum, stored);
+        }
+        else
+        {
+            stored = radiance_cache_buffer.Load(address);
+        }
+
+        if (stored == checksum || (insert && stored == 0))
+        {
+            radiance_cache_buffer.Store(address + 4, radiance_cache_frame);
+            return entry;
+        }
+        if (stored == 0)
+        {
+            break;
+        }
+    }
+    return RADIANCE_CACHE_INVALID;
+}
+
+// Cached radiance leaving the surface at position. The lookup is jittered in the tangent plane, so neighbouring cells
+// blend over the accumulation instead of showing their edges.
+bool QueryRadianceCache(float3 position, float3 normal, inout uint rng_state, out float3 radiance)
+{
+    radiance = 0;
+    float3 tangent;
+    float3 bitangent;
+    OrthonormalBasis(normal, tangent, bitangent);
+    float2 offset = (float2(RandomValue(rng_state), RandomValue(rng_state)) - 0.5f) * radiance_cache_cell_size;
+
+    uint hash;
+    uint checksum;
+    RadianceCacheKey(position + tangent * offset.x + bitangent * offset.y, normal, hash, checksum);
+    uint entry = RadianceCacheFind(hash, checksum, false);
+    if (entry == RADIANCE_CACHE_INVALID)
+    {
+        return false;
+    }
+
+    uint address = entry * RADIANCE_CACHE_STRIDE;
+    if (radiance_cache_buffer.Load(address + 8) < RADIANCE_CACHE_MIN_SAMPLES)
+    {
+        return false;
+    }
+    radiance = asfloat(radiance_cache_buffer.Load3(address + 12));
+    return true;
+}
+
+// Adds a sample of the radiance leaving a cell, blended in by the next resolve pass
+void UpdateRadianceCache(uint hash, uint checksum, float3 radiance)
+{
+    if (any(isnan(radiance)))
+    {
+        return;
+    }
+    uint entry = RadianceCacheFind(hash, checksum, true);
+    if (entry == RADIANCE_CACHE_INVALID)
+    {
+        return;
+    }
+
+    uint address = entry * RADIANCE_CACHE_STRIDE;
+    uint3 fixed_point = uint3(clamp(radiance, 0.0f, RADIANCE_CACHE_MAX_RADIANCE) * RADIANCE_CACHE_SCALE);
+    radiance_cache_buffer.InterlockedAdd(address + 24, 1);
+    radiance_cache_buffer.InterlockedAdd(address + 28, fixed_point.r);
+    radiance_cache_buffer.InterlockedAdd(address + 32, fixed_point.g);
+    radiance_cache_buffer.InterlockedAdd(address + 36, fixed_point.b);
+}
+
+// Scales radiance down so its luminance doesn't exceed max_indirect_radiance, primary hits are kept as is
+float3 ClampIndirect(float3 radiance, uint bounce_index)
+{
+    if (bounce_index == 0 || max_indirect_radiance <= 0.0f) {
+        return radiance;
+    }
+
+    float luminance = Luminance(radiance);
+    return luminance > max_indirect_radiance ? radiance * (max_indirect_radiance / luminance) : radiance;
+}
+
+// Schlick's approximation of the Fresnel reflectance, eta is the ratio of the indices of refraction
+float DielectricReflectance(float cos_incident, float eta)
+{
+    float cos_theta = cos_incident;
+    if (eta > 1.0f)
+    {
+        float sin2_transmitted = eta * eta * (1.0f - cos_incident * cos_incident);
+        if (sin2_transmitted >= 1.0f)
+        {
+            // total internal reflection
+            return 1.0f;
+        }
+        cos_theta = sqrt(1.0f - sin2_transmitted);
+    }
+    float r0 = (1.0f - eta) / (1.0f + eta);
+    r0 *= r0;
+    float x = 1.0f - cos_theta;
+    return r0 + (1.0f - r0) * x * x * x * x * x;
+}
+
+// Reflects or refracts the ray through a smooth dielectric surface, returns the path throughput
+float3 SampleTransmission(inout Ray ray, HitInfo hit_info, RayTracingMaterial material, inout uint rng_state)
+{
+    float3 normal = hit_info.front_face ? hit_info.normal : -hit_info.normal;
+    float eta = hit_info.front_face ? 1.0f / material.ior : material.ior;
+    float cos_incident = saturate(dot(-ray.direction, normal));
+
+    if (DielectricReflectance(cos_incident, eta) >= RandomValue(rng_state))
+    {
+        ray.origin = hit_info.hit_point + normal * 1e-4f;
+        ray.direction = reflect(ray.direction, normal);
+        return 1.0f;
+    }
+
+    ray.origin = hit_info.hit_point - normal * 1e-4f;
+    ray.direction = normalize(refract(ray.direction, normal, eta));
+    return material.color.rgb;
+}
+
+// Returns the radiance along the ray, alpha is 0 when the primary ray shows a transparent background
+float4 Trace(Ray ray, inout uint rng_state)
 {
     float3 incoming_light = 0;
     float3 ray_color = 1;
+    // Raised after diffuse bounces, so caustic-like paths through sharp reflections blur instead of producing fireflies
+    float min_roughness = 0.0f;
+    // Solid angle pdf of the last bounce off a surface, 0 when the lights weren't sampled there
+    float brdf_pdf = 0.0f;
+    float3 last_normal = 0;
+    // Radiance cache: whether the last surface bounce was diffuse, and the cell the path trains after missing the
+    // cache there, with the light gathered and the throughput on arrival
+    bool diffuse_bounced = false;
+    bool training = false;
+    if (radiance_cache)
+    {
+        training = RandomValue(rng_state) < RADIANCE_CACHE_TRAINING_PATHS;
+    }
+    bool cache_vertex = false;
+    uint cache_hash = 0;
+    uint cache_checksum = 0;
+    float3 cache_light = 0;
+    float3 cache_throughput = 0;
+    path_end = PATH_END_MAX_BOUNCES;
 
-    for (uint bounce_index = 0; bounce_index <= MAX_BOUNCE_COUNT; bounce_index++)
+    for (uint bounce_index = 0; bounce_index <= max_bounces; bounce_index++)
     {
-        HitInfo hit_info = GetCollision(ray);
+        path_bounces = bounce_index;
+        HitInfo hit_info;
+        if (bounce_index == 0 && hybrid)
+        {
+            hit_info = GBufferHit(ray);
+        }
+        else
+        {
+            hit_info = GetCollision(ray, bounce_index == 0 ? primary_triangle : NO_TRIANGLE);
+        }
+
+        // Area lights are only seen through bounces, the camera doesn't see them directly
+        uint light;
+        uint leaf;
+        float light_distance;
+        float max_distance = hit_info.hit ? hit_info.distance : SUPER_FAR;
+        if (bounce_index > 0 && IntersectAreaLights(ray, max_distance, light, leaf, light_distance))
+        {
+            Light area_light = light_buffer[light];
+            float mis_weight = 1.0f;
+            if (brdf_pdf > 0)
+            {
+                float selection_pdf = light_tree_size == 0 ? 1.0f : LightTreePdf(leaf, ray.origin, last_normal);
+                float light_pdf = AreaLightPdf(area_light, selection_pdf, ray.direction, light_distance);
+                mis_weight = PowerHeuristic(brdf_pdf, light_pdf);
+            }
+            incoming_light += ClampIndirect(area_light.color * ray_color * mis_weight, bounce_index);
+            path_end = PATH_END_EMITTER;
+            break;
+        }
 
         if (hit_info.hit)
         {
             RayTracingMaterial material = hit_info.material;
+            material.perceptual_roughness = max(material.perceptual_roughness, min_roughness);
+            material.clearcoat_perceptual_roughness = max(material.clearcoat_perceptual_roughness, min_roughness);
 
-            ray.origin = hit_info.hit_point;
-            bool is_specular_bounce = material.specular_probability >= RandomValue(rng_state);
-            float3 diffuse_direction = normalize(hit_info.normal + RandomDirection(rng_state));
-            float3 specular_direction = reflect(ray.direction, hit_info.normal);
-            ray.direction = normalize(lerp(diffuse_direction, specular_direction, material.smoothness * is_specular_bounce));
+            if (radiance_cache && diffuse_bounced && !cache_vertex && material.transmission == 0.0f)
+            {
+                float3 surface_normal = dot(hit_info.normal, ray.direction) > 0 ? -hit_info.normal : hit_info.normal;
+                float3 cached_light;
+                if (!training && QueryRadianceCache(hit_info.hit_point, surface_normal, rng_state, cached_light))
+                {
+                    incoming_light += ClampIndirect(cached_light * ray_color, bounce_index);
+                    path_end = PATH_END_RADIANCE_CACHE;
+                    break;
+                }
+                cache_vertex = true;
+                RadianceCacheKey(hit_info.hit_point, surface_normal, cache_hash, cache_checksum);
+                cache_light = incoming_light;
+                cache_throughput = ray_color;
+            }
 
-            // Update light calculations
             float3 emitted_light = material.emission_color.rgb * material.emission_strength;
-            incoming_light += emitted_light * ray_color;
-            ray_color *= lerp(material.color.rgb, material.specular_color.rgb, is_specular_bounce);
+            incoming_light += ClampIndirect(emitted_light * ray_color, bounce_index);
+
+            if (material.transmission > RandomValue(rng_state))
+            {
+                ray_color *= SampleTransmission(ray, hit_info, material, rng_state);
+                brdf_pdf = 0.0f;
+                diffuse_bounced = false;
+            }
+            else
+            {
+                SurfaceBrdf brdf = GetSurfaceBrdf(material);
+                float3 direct_light = SampleLights(hit_info.hit_point, hit_info.normal, -ray.direction, brdf, rng_state) * ray_color;
+                incoming_light += ClampIndirect(direct_light, bounce_index);
+
+                bool diffuse_bounce;
+                ray_color *= SampleBrdf(ray, hit_info, brdf, rng_state, diffuse_bounce, brdf_pdf);
+                last_normal = hit_info.normal;
+                diffuse_bounced = diffuse_bounce;
+                if (diffuse_bounce)
+                {
+                    min_roughness = regularization;
+                }
+            }
 
             // Random early exit if ray color is nearly 0 (can't contribute much to final result)
-            float p = max(ray_color.r, max(ray_color.g, ray_color.b));
+            float p = min(max(ray_color.r, max(ray_color.g, ray_color.b)), 1.0f);
             if (RandomValue(rng_state) >= p) {
+                path_end = PATH_END_RUSSIAN_ROULETTE;
                 break;
             }
             ray_color *= 1.0f / p;
         }
         else
         {
-            incoming_light += GetEnvironmentLight(ray) * ray_color;
+            path_end = PATH_END_ESCAPED;
+            if (bounce_index == 0 && background_mode == BACKGROUND_COLOR)
+            {
+                return float4(background_color.rgb, 1.0f);
+            }
+            if (bounce_index == 0 && background_mode == BACKGROUND_TRANSPARENT)
+            {
+                return 0.0f;
+            }
+            incoming_light += ClampIndirect(GetEnvironmentLight(ray) * ray_color, bounce_index);
             break;
         }
     }
 
-    return incoming_light;
+    if (cache_vertex)
+    {
+        // Light gathered after the cell, relative to the throughput on arrival
+        UpdateRadianceCache(cache_hash, cache_checksum, (incoming_light - cache_light) / max(cache_throughput, 1e-6f));
+    }
+
+    return float4(incoming_light, 1.0f);
+}
+
+// Fraction of a cosine weighted hemisphere around the primary hit that isn't blocked within ambient_occlusion_radius
+float3 TraceAmbientOcclusion(Ray ray, inout uint rng_state)
+{
+    HitInfo hit_info = GetCollision(ray, primary_triangle);
+    if (!hit_info.hit)
+    {
+        return 1.0f;
+    }
+
+    float3 normal = dot(hit_info.normal, ray.direction) > 0 ? -hit_info.normal : hit_info.normal;
+    float3 direction = normalize(normal + RandomDirection(rng_state));
+    bool occluded = IsOccluded(hit_info.hit_point + normal * 1e-4f, direction, ambient_occlusion_radius);
+    return occluded ? 0.0f : 1.0f;
+}
+
+// Blue for 0 over green to red for 1
+float3 HeatColor(float t)
+{
+    t = saturate(t);
+    return float3(saturate(2.0f * t - 1.0f), 1.0f - abs(2.0f * t - 1.0f), saturate(1.0f - 2.0f * t));
+}
+
+float4 TraceDebugView(Ray ray, inout uint rng_state)
+{
+    if (debug_view == DEBUG_VIEW_BOUNCE_COUNT)
+    {
+        Trace(ray, rng_state);
+        return float4(HeatColor(float(path_bounces) / float(max(max_bounces, 1))), 1.0f);
+    }
+    if (debug_view == DEBUG_VIEW_PATH_END)
+    {
+        static const float3 PATH_END_COLORS[PATH_END_COUNT] = {
+            float3(0.0f, 0.0f, 1.0f),
+            float3(1.0f, 1.0f, 0.0f),
+            float3(0.0f, 1.0f, 0.0f),
+            float3(1.0f, 0.0f, 0.0f),
+            float3(1.0f, 0.0f, 1.0f),
+        };
+        Trace(ray, rng_state);
+        return float4(PATH_END_COLORS[path_end], 1.0f);
+    }
+
+    crossed_triangles = 0;
+    HitInfo hit_info = GetCollision(ray, primary_triangle);
+    if (debug_view == DEBUG_VIEW_BVH_HEATMAP)
+    {
+        return float4(HeatColor(float(crossed_triangles) / DEBUG_HEATMAP_MAX), 1.0f);
+    }
+    if (debug_view == DEBUG_VIEW_DEPTH)
+    {
+        float distance = hit_info.hit ? hit_info.distance : SUPER_FAR;
+        return float4((distance / (distance + DEBUG_DEPTH_SCALE)).xxx, 1.0f);
+    }
+    if (!hit_info.hit)
+    {
+        return float4(0.0f, 0.0f, 0.0f, 1.0f);
+    }
+    if (debug_view == DEBUG_VIEW_NORMALS)
+    {
+        return float4(hit_info.normal * 0.5f + 0.5f, 1.0f);
+    }
+    return float4(frac(hit_info.uv), 0.0f, 1.0f);
 }
 
 PSInput VSMain(float4 position : POSITION, float2 uv : TEXCOORD) {
@@ -204,10 +1498,28 @@ PSInput VSMain(float4 position : POSITION, float2 uv : TEXCOORD) {
     return result;
 }
 
-float4 PSMain(PSInput input) : SV_TARGET
+// Sample index of a pixel from the first two dimensions of the Sobol sequence, in [0, 1)^2. Every power of two of
+// consecutive samples starting at 0 is stratified over the pixel, and XOR scrambling with scramble keeps that while
+// decorrelating the pixels.
+float2 SobolSample(uint index, uint2 scramble)
+{
+    uint x = reversebits(index);
+    uint y = 0;
+    for (uint direction = 1u << 31; index != 0; index >>= 1, direction ^= direction >> 1)
+    {
+        if (index & 1)
+        {
+            y ^= direction;
+        }
+    }
+    // 24 bits, so the conversion can't round up to 1
+    return float2((x ^ scramble.x) >> 8, (y ^ scramble.y) >> 8) / 16777216.0f;
+}
+
+// Must match View::ray on the CPU side
+Ray PrimaryRay(float2 uv)
 {
-    uint rng_state = (uint(floor(input.uv.x * 32767.0f)) * 1974u + uint(floor(input.uv.y * 32767.0f)) * 9277u) | 1u;
-    float2 ndc = float2(2.0f * input.uv.x - 1.0f, 2.0f * input.uv.y - 1.0f);
+    float2 ndc = float2(2.0f * uv.x - 1.0f, 1.0f - 2.0f * uv.y);
     ndc.x *= aspect_ratio;
     float scale = tan(fov * 0.5f);
 
@@ -216,11 +1528,63 @@ float4 PSMain(PSInput input) : SV_TARGET
     Ray ray;
     ray.direction = normalize(mul((float3x3)inverse_view_matrix, ray_direction_camera_space));
     ray.origin = inverse_view_matrix._m03_m13_m23;
+    return ray;
+}
 
-    float3 color = float3(0.0f, 0.0f, 0.0f);
-    for (uint index = 0; index < RENDERS_PER_FRAME; ++index) {
-        color += Trace(ray, rng_state);
+float4 PSMain(PSInput input) : SV_TARGET
+{
+    float2 pixel_size = float2(ddx(input.uv.x), ddy(input.uv.y));
+    float2 pixel_uv = view_rect.xy + input.uv * view_rect.zw;
+    uint pixel_hash = uint(floor(pixel_uv.x * 32767.0f)) * 1974u + uint(floor(pixel_uv.y * 32767.0f)) * 9277u + seed * 104729u;
+    // the same for every frame of the pixel, so its samples stay stratified across the accumulation
+    uint scramble_state = pixel_hash | 1u;
+    uint2 scramble = uint2(NextRandom(scramble_state), NextRandom(scramble_state));
+    primary_pixel = int2(input.position.xy);
+    if (depth_prepass)
+    {
+        // 0 wraps around to NO_TRIANGLE
+        primary_triangle = primary_triangles.Load(int3(primary_pixel, 0)) - 1;
+    }
+    if (hybrid)
+    {
+        // the debug views and ambient occlusion test it like a depth prepass triangle
+        primary_triangle = g_material_id.Load(int3(primary_pixel, 0)) - 1;
+    }
+
+    float4 color = 0.0f;
+    uint length_sum = 0;
+    uint path_ends[PATH_END_COUNT] = { 0, 0, 0, 0, 0 };
+    for (uint index = 0; index < samples_per_frame; ++index) {
+        // the samples of a frame continue the sequence where the previous frame of the accumulation stopped
+        uint sample_index = frame_index * samples_per_frame + index;
+        // the G-buffer holds the hits of the pixel centers
+        float2 offset = jitter && !hybrid ? SobolSample(sample_index, scramble) - 0.5f : 0.0f;
+        Ray ray = PrimaryRay(view_rect.xy + (input.uv + offset * pixel_size) * view_rect.zw);
+        uint rng_state = (pixel_hash + sample_index * 26699u) | 1u;
+        if (debug_view != DEBUG_VIEW_NONE)
+        {
+            color += TraceDebugView(ray, rng_state);
+        }
+        else
+        {
+            color += ambient_occlusion ? float4(TraceAmbientOcclusion(ray, rng_state), 1.0f) : Trace(ray, rng_state);
+            length_sum += path_bounces;
+            path_ends[path_end] += 1;
+        }
+    }
+
+    if (path_statistics)
+    {
+        // One atomic per counter and pixel instead of per path
+        path_statistics_buffer.InterlockedAdd(PATH_STATISTICS_LENGTH_OFFSET, length_sum);
+        for (uint end = 0; end < PATH_END_COUNT; ++end)
+        {
+            if (path_ends[end] > 0)
+            {
+                path_statistics_buffer.InterlockedAdd(PATH_STATISTICS_END_OFFSET + end * 4, path_ends[end]);
+            }
+        }
     }
 
-    return float4(color / float(RENDERS_PER_FRAME), 1.0f);
+    return color / float(max(samples_per_frame, 1));
 }
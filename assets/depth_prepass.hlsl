@@ -0,0 +1,18 @@
+// Depth prepass of the path tracer, stores the triangle seen through every pixel center for the
+// primary rays of demo.hlsl.
+
+cbuffer FrameData : register(b0)
+{
+    matrix view_projection;
+};
+
+float4 VSMain(float3 position : POSITION, float2 uv : TEXCOORD) : SV_POSITION
+{
+    return mul(view_projection, float4(position, 1.0f));
+}
+
+// plus one, the target is cleared to 0 for pixels without a triangle
+uint PSMain(uint triangle_index : SV_PrimitiveID) : SV_TARGET
+{
+    return triangle_index + 1;
+}
@@ -0,0 +1,36 @@
+// Lines of the gizmos, drawn over the tone mapped output without depth testing.
+
+cbuffer GizmoView : register(b0)
+{
+    matrix view_projection;
+    // scRGB back buffers take linear values
+    uint output_linear;
+};
+
+struct PSInput
+{
+    float4 position : SV_POSITION;
+    float3 color : COLOR;
+};
+
+PSInput VSMain(float3 position : POSITION, float3 color : COLOR)
+{
+    PSInput result;
+    result.position = mul(view_projection, float4(position, 1.0f));
+    result.color = color;
+    return result;
+}
+
+// same as in tonemap.hlsl
+float3 LinearToSrgb(float3 color)
+{
+    float3 low = color * 12.92f;
+    float3 high = 1.055f * pow(color, 1.0f / 2.4f) - 0.055f;
+    return lerp(high, low, color <= 0.0031308f);
+}
+
+float4 PSMain(PSInput input) : SV_TARGET
+{
+    float3 color = saturate(input.color);
+    return float4(output_linear ? color : LinearToSrgb(color), 1.0f);
+}
@@ -0,0 +1,115 @@
+// G-buffer pass of the hybrid integrator, stores the primary hit of every pixel center for demo.hlsl. Target order
+// must match G_BUFFER_TARGETS in hybrid_g_buffer.rs.
+
+cbuffer FrameData : register(b0)
+{
+    matrix view_projection;
+    float3 camera_position;
+    // of the accumulation, moves the stochastic transparency pattern every frame
+    uint frame_index;
+};
+
+// IDs in the bindless heap, the ones of the path tracer
+cbuffer BindlessIds : register(b1)
+{
+    // vertices, indices, materials
+    uint scene_srvs;
+    // of material texture slot 0, the other slots follow
+    uint first_texture;
+};
+
+struct MaterialData
+{
+    float4 base_color;
+    float4 emissive;
+    float transmission;
+    float ior;
+    uint base_color_texture;
+    float metallic;
+    float perceptual_roughness;
+    float clearcoat;
+    float clearcoat_perceptual_roughness;
+    // of the instance, stochastic transparency below 1
+    float opacity;
+};
+
+static const uint NO_TEXTURE = 0xffffffff;
+
+// Every space views the whole bindless heap as one resource type, see bindless.rs. The uvs come through the input
+// assembler.
+Texture2D<float4> textures[] : register(t0, space1);
+StructuredBuffer<float3> float3_buffers[] : register(t0, space3);
+StructuredBuffer<uint> uint_buffers[] : register(t0, space5);
+StructuredBuffer<MaterialData> material_buffers[] : register(t0, space6);
+#define vertex_buffer float3_buffers[scene_srvs]
+#define index_buffer uint_buffers[scene_srvs + 1]
+#define material_buffer material_buffers[scene_srvs + 2]
+SamplerState texture_sampler : register(s0);
+
+struct PSInput
+{
+    float4 position : SV_POSITION;
+    float3 world_position : POSITION;
+    float2 uv : TEXCOORD;
+};
+
+struct GBufferOutput
+{
+    float4 albedo : SV_TARGET0;
+    float4 normal : SV_TARGET1;
+    float depth : SV_TARGET2;
+    uint material_id : SV_TARGET3;
+};
+
+PSInput VSMain(float3 position : POSITION, float2 uv : TEXCOORD)
+{
+    PSInput result;
+    result.position = mul(view_projection, float4(position, 1.0f));
+    result.world_position = position;
+    result.uv = uv;
+    return result;
+}
+
+// Interleaved gradient noise, keeps the screen door pattern of faded instances fine grained
+float InterleavedGradientNoise(float2 pixel)
+{
+    return frac(52.9829189f * frac(dot(pixel, float2(0.06711056f, 0.00583715f))));
+}
+
+GBufferOutput PSMain(PSInput input, uint triangle_index : SV_PrimitiveID)
+{
+    // the normal of the triangle as the path tracer computes it, without facing the camera
+    uint first_index = triangle_index * 3;
+    float3 a = vertex_buffer[index_buffer[first_index]];
+    float3 b = vertex_buffer[index_buffer[first_index + 1]];
+    float3 c = vertex_buffer[index_buffer[first_index + 2]];
+    float3 normal = normalize(cross(b - a, c - a));
+
+    // rays pass through back faces of opaque surfaces, see HitTriangle in demo.hlsl
+    MaterialData material = material_buffer[triangle_index];
+    bool front_face = dot(normal, camera_position - input.world_position) > 0.0f;
+    if (!front_face && material.transmission <= 0.0f)
+    {
+        discard;
+    }
+    // the pattern moves every frame, so accumulation averages faded instances per pixel
+    if (InterleavedGradientNoise(input.position.xy + 5.588238f * float(frame_index)) >= material.opacity)
+    {
+        discard;
+    }
+
+    float4 albedo = material.base_color;
+    if (material.base_color_texture != NO_TEXTURE)
+    {
+        uint texture_id = first_texture + material.base_color_texture;
+        albedo *= textures[NonUniformResourceIndex(texture_id)].Sample(texture_sampler, input.uv);
+    }
+
+    GBufferOutput output;
+    output.albedo = albedo;
+    output.normal = float4(normal, 0.0f);
+    output.depth = length(camera_position - input.world_position);
+    // plus one, the target is cleared to 0 for pixels without a triangle
+    output.material_id = triangle_index + 1;
+    return output;
+}
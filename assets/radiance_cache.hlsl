@@ -0,0 +1,53 @@
+// Resolve pass of the radiance cache, a world space hash grid of the radiance leaving surfaces
+// that the path tracer in demo.hlsl fills and reads. The entry layout and constants must match
+// the RADIANCE_CACHE_* ones there.
+#define GROUP_SIZE 64
+
+// Entry: checksum (0 when free), last used frame, sample count, radiance (float3), then the
+// samples of the last frame: count and fixed point sum (uint3)
+static const uint RADIANCE_CACHE_STRIDE = 48;
+static const float RADIANCE_CACHE_SCALE = 128.0f;
+// Samples after which new ones get a constant weight, so the cache follows changes in lighting
+static const uint RADIANCE_CACHE_MAX_SAMPLES = 256;
+// Frames an unused cell is kept
+static const uint RADIANCE_CACHE_MAX_AGE = 64;
+
+cbuffer ResolveData : register(b0)
+{
+    uint frame;
+    uint reset;
+};
+
+RWByteAddressBuffer radiance_cache : register(u0);
+
+[numthreads(GROUP_SIZE, 1, 1)]
+void CSResolve(uint3 id : SV_DispatchThreadID)
+{
+    uint address = id.x * RADIANCE_CACHE_STRIDE;
+    uint checksum = radiance_cache.Load(address);
+    bool expired = checksum != 0 && frame - radiance_cache.Load(address + 4) > RADIANCE_CACHE_MAX_AGE;
+    if (reset || expired)
+    {
+        radiance_cache.Store4(address, 0);
+        radiance_cache.Store4(address + 16, 0);
+        radiance_cache.Store4(address + 32, 0);
+        return;
+    }
+
+    uint update_count = radiance_cache.Load(address + 24);
+    if (checksum == 0 || update_count == 0)
+    {
+        return;
+    }
+
+    uint sample_count = radiance_cache.Load(address + 8);
+    float3 radiance = asfloat(radiance_cache.Load3(address + 12));
+    float3 update = float3(radiance_cache.Load3(address + 28)) / (RADIANCE_CACHE_SCALE * float(update_count));
+    // Running average until the cell is full, exponential moving average after
+    uint blended_count = min(sample_count + update_count, RADIANCE_CACHE_MAX_SAMPLES);
+    float weight = min(float(update_count) / float(blended_count), 1.0f);
+
+    radiance_cache.Store(address + 8, blended_count);
+    radiance_cache.Store3(address + 12, asuint(lerp(radiance, update, weight)));
+    radiance_cache.Store4(address + 24, 0);
+}
@@ -0,0 +1,195 @@
+// Forward rasterizer for fast previews. Meshes are lit by the punctual lights without shadows,
+// indirect light is a constant ambient term of the environment color.
+
+cbuffer FrameData : register(b0)
+{
+    matrix view_projection;
+    float3 camera_position;
+    uint light_count;
+    uint debug_view;
+};
+
+// IDs in the bindless heap
+cbuffer BindlessIds : register(b1)
+{
+    // vertices, indices, materials, uvs and lights
+    uint scene_srvs;
+    // of material texture slot 0, the other slots follow
+    uint first_texture;
+};
+
+static const uint DEBUG_VIEW_NORMALS = 1;
+static const uint DEBUG_VIEW_DEPTH = 2;
+static const uint DEBUG_VIEW_UVS = 3;
+// distance shown as middle grey in the depth view
+static const float DEBUG_DEPTH_SCALE = 10.0f;
+
+static const float PI = 3.14159265359f;
+// must match GetEnvironmentLight in demo.hlsl
+static const float3 AMBIENT_LIGHT = float3(0.2f, 0.3f, 0.3f);
+
+struct MaterialData
+{
+    float4 base_color;
+    float4 emissive;
+    float transmission;
+    float ior;
+    uint base_color_texture;
+    float metallic;
+    float perceptual_roughness;
+    float clearcoat;
+    float clearcoat_perceptual_roughness;
+    // of the instance, stochastic transparency below 1
+    float opacity;
+};
+
+static const uint NO_TEXTURE = 0xffffffff;
+
+static const uint LIGHT_KIND_POINT = 0;
+static const uint LIGHT_KIND_DIRECTIONAL = 1;
+static const uint LIGHT_KIND_SPOT = 2;
+
+struct Light
+{
+    float3 position;
+    uint kind;
+    float3 direction;
+    float range;
+    float3 color;
+    float radius;
+    float3 right;
+    float spot_cos_inner;
+    float3 up;
+    float spot_cos_outer;
+};
+
+// Every space views the whole bindless heap as one resource type, see bindless.rs. The
+// vertices, indices and uvs come through the input assembler.
+Texture2D<float4> textures[] : register(t0, space1);
+StructuredBuffer<MaterialData> material_buffers[] : register(t0, space6);
+StructuredBuffer<Light> light_buffers[] : register(t0, space7);
+#define material_buffer material_buffers[scene_srvs + 2]
+#define light_buffer light_buffers[scene_srvs + 4]
+SamplerState texture_sampler : register(s0);
+
+struct PSInput
+{
+    float4 position : SV_POSITION;
+    float3 world_position : POSITION;
+    float2 uv : TEXCOORD;
+};
+
+PSInput VSMain(float3 position : POSITION, float2 uv : TEXCOORD)
+{
+    PSInput result;
+    result.position = mul(view_projection, float4(position, 1.0f));
+    result.world_position = position;
+    result.uv = uv;
+    return result;
+}
+
+float RangeAttenuation(float distance, float range)
+{
+    float factor = distance / range;
+    float window = saturate(1.0f - factor * factor * factor * factor);
+    return window * window;
+}
+
+// GGX distribution with the height correlated Smith visibility term and Schlick's Fresnel
+float3 Specular(float3 normal, float3 view, float3 to_light, float roughness, float3 f0)
+{
+    float3 half_vector = normalize(view + to_light);
+    float n_dot_h = saturate(dot(normal, half_vector));
+    float n_dot_v = max(dot(normal, view), 1e-4f);
+    float n_dot_l = max(dot(normal, to_light), 1e-4f);
+    float a2 = roughness * roughness;
+    float d = n_dot_h * n_dot_h * (a2 - 1.0f) + 1.0f;
+    float distribution = a2 / (PI * d * d);
+    float visibility = 0.5f / (n_dot_l * sqrt(n_dot_v * n_dot_v * (1.0f - a2) + a2)
+        + n_dot_v * sqrt(n_dot_l * n_dot_l * (1.0f - a2) + a2));
+    float3 fresnel = f0 + (1.0f - f0) * pow(1.0f - saturate(dot(view, half_vector)), 5.0f);
+    return distribution * visibility * fresnel;
+}
+
+// Interleaved gradient noise, keeps the screen door pattern of faded instances fine grained
+float InterleavedGradientNoise(float2 pixel)
+{
+    return frac(52.9829189f * frac(dot(pixel, float2(0.06711056f, 0.00583715f))));
+}
+
+float4 PSMain(PSInput input, uint triangle_index : SV_PrimitiveID) : SV_TARGET
+{
+    MaterialData material = material_buffer[triangle_index];
+    if (InterleavedGradientNoise(input.position.xy) >= material.opacity)
+    {
+        discard;
+    }
+
+    float3 to_camera = camera_position - input.world_position;
+    float distance = length(to_camera);
+    float3 view = to_camera / distance;
+    // meshes have no vertex normals, faces are shaded flat like in the path tracer
+    float3 normal = normalize(cross(ddy(input.world_position), ddx(input.world_position)));
+    if (dot(normal, view) < 0.0f)
+    {
+        normal = -normal;
+    }
+
+    if (debug_view == DEBUG_VIEW_NORMALS)
+    {
+        return float4(normal * 0.5f + 0.5f, 1.0f);
+    }
+    if (debug_view == DEBUG_VIEW_DEPTH)
+    {
+        return float4((distance / (distance + DEBUG_DEPTH_SCALE)).xxx, 1.0f);
+    }
+    if (debug_view == DEBUG_VIEW_UVS)
+    {
+        return float4(frac(input.uv), 0.0f, 1.0f);
+    }
+
+    float3 albedo = material.base_color.rgb;
+    if (material.base_color_texture != NO_TEXTURE)
+    {
+        uint texture_id = first_texture + material.base_color_texture;
+        albedo *= textures[NonUniformResourceIndex(texture_id)].Sample(texture_sampler, input.uv).rgb;
+    }
+    float roughness = max(material.perceptual_roughness * material.perceptual_roughness, 1e-3f);
+    float3 f0 = lerp(0.04f, albedo, material.metallic);
+    float3 diffuse = albedo * (1.0f - material.metallic) / PI;
+
+    float3 color = AMBIENT_LIGHT * albedo + material.emissive.rgb;
+    for (uint i = 0; i < light_count; ++i)
+    {
+        Light light = light_buffer[i];
+        float3 radiance = light.color;
+        float3 to_light;
+        if (light.kind == LIGHT_KIND_DIRECTIONAL)
+        {
+            to_light = -light.direction;
+        }
+        else if (light.kind == LIGHT_KIND_POINT || light.kind == LIGHT_KIND_SPOT)
+        {
+            to_light = light.position - input.world_position;
+            float light_distance = length(to_light);
+            to_light /= light_distance;
+            radiance *= RangeAttenuation(light_distance, light.range) / max(light_distance * light_distance, 1e-4f);
+            if (light.kind == LIGHT_KIND_SPOT)
+            {
+                radiance *= smoothstep(light.spot_cos_outer, light.spot_cos_inner, dot(-to_light, light.direction));
+            }
+        }
+        else
+        {
+            // area lights are left to the path tracer
+            continue;
+        }
+
+        float n_dot_l = dot(normal, to_light);
+        if (n_dot_l > 0.0f)
+        {
+            color += radiance * n_dot_l * (diffuse + Specular(normal, view, to_light, roughness, f0));
+        }
+    }
+    return float4(color, 1.0f);
+}
@@ -0,0 +1,156 @@
+struct PSInput
+{
+    float4 position : SV_POSITION;
+    float2 uv : TEXCOORD;
+};
+
+cbuffer TonemapSettings : register(b0)
+{
+    uint tonemapping;
+    float exposure;
+    uint auto_exposure;
+    uint dithering;
+};
+
+cbuffer TargetInfo : register(b1)
+{
+    // scRGB back buffers take linear values
+    uint output_linear;
+    // HDR target resolution relative to the output
+    float render_scale;
+    // largest channel value of unorm outputs, 0 for float ones
+    float unorm_max;
+};
+
+// part of the output the view covers, only set for the compute blit
+cbuffer BlitRect : register(b2)
+{
+    int4 output_rect;
+};
+
+Texture2D<float4> hdr_texture : register(t0);
+StructuredBuffer<float> average_luminance : register(t1);
+RWTexture2D<float4> output : register(u0);
+
+static const float MIDDLE_GREY = 0.18f;
+
+static const uint TONEMAPPING_NONE = 0;
+static const uint TONEMAPPING_REINHARD = 1;
+static const uint TONEMAPPING_ACES_FITTED = 2;
+
+static const uint DITHERING_NONE = 0;
+static const uint DITHERING_ORDERED = 1;
+static const uint DITHERING_NOISE = 2;
+
+float3 Reinhard(float3 color)
+{
+    return color / (1.0f + color);
+}
+
+// Krzysztof Narkowicz's fit of the ACES filmic curve
+float3 AcesFitted(float3 color)
+{
+    const float a = 2.51f;
+    const float b = 0.03f;
+    const float c = 2.43f;
+    const float d = 0.59f;
+    const float e = 0.14f;
+    return saturate((color * (a * color + b)) / (color * (c * color + d) + e));
+}
+
+float3 LinearToSrgb(float3 color)
+{
+    float3 low = color * 12.92f;
+    float3 high = 1.055f * pow(color, 1.0f / 2.4f) - 0.055f;
+    return lerp(high, low, color <= 0.0031308f);
+}
+
+// Threshold in [0, 1) of an 8x8 Bayer matrix
+float Bayer8x8(uint2 pixel)
+{
+    uint value = 0;
+    for (uint bit = 0; bit < 3; ++bit)
+    {
+        uint x = (pixel.x >> bit) & 1;
+        uint y = (pixel.y >> bit) & 1;
+        value |= ((x ^ y) << (5 - 2 * bit)) | (y << (4 - 2 * bit));
+    }
+    return (float(value) + 0.5f) / 64.0f;
+}
+
+// Jorge Jimenez's interleaved gradient noise
+float InterleavedGradientNoise(float2 pixel)
+{
+    return frac(52.9829189f * frac(dot(pixel, float2(0.06711056f, 0.00583715f))));
+}
+
+// Offsets the encoded color by up to half a quantization step, so rounding to the output format
+// turns gradients into noise instead of bands
+float3 Dither(float3 color, float2 pixel)
+{
+    if (dithering == DITHERING_NONE || unorm_max == 0.0f)
+    {
+        return color;
+    }
+    float threshold = dithering == DITHERING_ORDERED ? Bayer8x8(uint2(pixel)) : InterleavedGradientNoise(pixel);
+    return saturate(color + (threshold - 0.5f) / unorm_max);
+}
+
+PSInput VSMain(float4 position : POSITION, float2 uv : TEXCOORD)
+{
+    PSInput result;
+    result.position = position;
+    result.uv = uv;
+    return result;
+}
+
+// Tone maps the HDR target for the output pixel centered at `position`
+float4 Tonemap(float2 position)
+{
+    float4 hdr = hdr_texture.Load(int3(position * render_scale, 0));
+    // the scene is premultiplied by alpha, tone map the unpremultiplied color
+    float alpha = saturate(hdr.a);
+    float3 color = hdr.rgb / max(alpha, 0.0001f) * exposure;
+    if (auto_exposure != 0)
+    {
+        color *= MIDDLE_GREY / max(average_luminance[0], 0.0001f);
+    }
+
+    if (tonemapping == TONEMAPPING_REINHARD)
+    {
+        color = Reinhard(color);
+    }
+    else if (tonemapping == TONEMAPPING_ACES_FITTED)
+    {
+        color = AcesFitted(color);
+    }
+
+    color = saturate(color);
+    if (output_linear == 0)
+    {
+        color = LinearToSrgb(color);
+    }
+    color = Dither(color, floor(position));
+    return float4(color * alpha, alpha);
+}
+
+float4 PSMain(PSInput input) : SV_TARGET
+{
+    return Tonemap(input.position.xy);
+}
+
+// Writes the whole output, pixels outside of the view are cleared
+[numthreads(8, 8, 1)]
+void CSMain(uint3 id : SV_DispatchThreadID)
+{
+    uint width, height;
+    output.GetDimensions(width, height);
+    if (id.x >= width || id.y >= height)
+    {
+        return;
+    }
+
+    int2 pixel = int2(id.xy);
+    bool inside = all(pixel >= output_rect.xy) && all(pixel < output_rect.zw);
+    output[id.xy] = inside ? Tonemap(float2(pixel) + 0.5f) : 0.0f;
+}
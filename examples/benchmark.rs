@@ -0,0 +1,216 @@
+//! Renders a glTF scene along a fixed camera path and writes per frame timings, with the GPU
+//! time of every render graph pass in its own column.
+//!
+//! `cargo run --release --example benchmark -- <scene.glb> [frames] [output.csv|output.json]`
+//!
+//! The scene path is relative to `assets`. The camera orbits the scene during the first half of
+//! the frames and holds still during the second half, so the accumulation converges. Rendering
+//! still needs a window, keep it visible while the benchmark runs.
+
+use std::{f32::consts::TAU, fmt::Write as _, path::PathBuf};
+
+use bevy::{app::AppExit, prelude::*};
+use bevy_arca::core::{Camera, PointLight};
+use bevy_arca::gltf::{GltfAssetLabel, GltfPlugin};
+use bevy_arca::render::{GpuTimings, PathTracerSettings};
+use bevy_arca::ArcaPlugin;
+
+const ORBIT_RADIUS: f32 = 5.0;
+const ORBIT_HEIGHT: f32 = 1.0;
+
+#[derive(Resource)]
+struct BenchmarkConfig {
+    scene: String,
+    frames: u32,
+    output: PathBuf,
+}
+
+impl BenchmarkConfig {
+    fn from_args() -> Self {
+        let mut args = std::env::args().skip(1);
+        let scene = args.next().unwrap_or_else(|| "cube.glb".to_string());
+        let frames = args
+            .next()
+            .map(|frames| frames.parse().expect("frame count must be a number"))
+            .unwrap_or(200);
+        let output = args
+            .next()
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from("benchmark.csv"));
+        Self {
+            scene,
+            frames,
+            output,
+        }
+    }
+
+    fn orbit_frames(&self) -> u32 {
+        self.frames / 2
+    }
+}
+
+struct FrameSample {
+    frame: u32,
+    cpu_ms: f64,
+    timings: GpuTimings,
+    samples_per_pixel: u32,
+}
+
+#[derive(Resource, Default)]
+struct BenchmarkState {
+    frame: u32,
+    samples: Vec<FrameSample>,
+}
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>, config: Res<BenchmarkConfig>) {
+    commands.spawn((
+        Camera {
+            fov: std::f32::consts::PI / 4.0,
+            aspect_ratio: 16.0 / 9.0,
+        },
+        camera_transform(0, &config),
+        GlobalTransform::default(),
+    ));
+    commands.spawn((
+        PointLight::default(),
+        Transform::from_xyz(2.0, 3.0, 2.0),
+        GlobalTransform::default(),
+    ));
+    commands.spawn(SceneBundle {
+        scene: asset_server.load(GltfAssetLabel::Scene(0).from_asset(config.scene.clone())),
+        ..default()
+    });
+}
+
+fn camera_transform(frame: u32, config: &BenchmarkConfig) -> Transform {
+    let orbit_frames = config.orbit_frames().max(1);
+    let angle = frame.min(orbit_frames) as f32 / orbit_frames as f32 * TAU;
+    Transform::from_xyz(
+        angle.sin() * ORBIT_RADIUS,
+        ORBIT_HEIGHT,
+        angle.cos() * ORBIT_RADIUS,
+    )
+    .looking_at(Vec3::ZERO, Vec3::Y)
+}
+
+fn move_camera(
+    mut cameras: Query<&mut Transform, With<Camera>>,
+    config: Res<BenchmarkConfig>,
+    state: Res<BenchmarkState>,
+) {
+    let mut transform = cameras.single_mut();
+    let new_transform = camera_transform(state.frame, &config);
+    // keeps change detection quiet once the camera holds still, so accumulation isn't reset
+    transform.set_if_neq(new_transform);
+}
+
+fn record_frame(
+    time: Res<Time>,
+    timings: Res<GpuTimings>,
+    settings: Res<PathTracerSettings>,
+    config: Res<BenchmarkConfig>,
+    mut state: ResMut<BenchmarkState>,
+    mut exit: EventWriter<AppExit>,
+) {
+    // pipelines are created once their shaders load, nothing is rendered before that
+    if timings.views.is_empty() {
+        return;
+    }
+
+    let frame = state.frame;
+    let samples_per_pixel = if frame < config.orbit_frames() {
+        settings.samples_per_frame
+    } else {
+        (frame - config.orbit_frames() + 1) * settings.samples_per_frame
+    };
+    state.samples.push(FrameSample {
+        frame,
+        cpu_ms: time.delta_seconds_f64() * 1000.0,
+        timings: timings.clone(),
+        samples_per_pixel,
+    });
+    state.frame += 1;
+
+    if state.frame >= config.frames {
+        write_report(&config.output, &state.samples);
+        exit.send(AppExit::Success);
+    }
+}
+
+fn write_report(path: &PathBuf, samples: &[FrameSample]) {
+    // passes that didn't run in a frame, like auto exposure when it is disabled, report zero
+    let mut passes: Vec<&str> = Vec::new();
+    for pass in samples
+        .iter()
+        .flat_map(|sample| &sample.timings.views)
+        .flat_map(|view| &view.passes)
+    {
+        if !passes.contains(&pass.name.as_str()) {
+            passes.push(&pass.name);
+        }
+    }
+    let columns: Vec<_> = passes
+        .iter()
+        .map(|name| format!("{}_ms", name.to_lowercase().replace([' ', '-'], "_")))
+        .collect();
+
+    let is_json = path
+        .extension()
+        .is_some_and(|extension| extension == "json");
+    let mut report = String::new();
+    if is_json {
+        report.push_str("[\n");
+        for (index, sample) in samples.iter().enumerate() {
+            let separator = if index + 1 == samples.len() { "" } else { "," };
+            write!(
+                report,
+                "  {{\"frame\": {}, \"cpu_ms\": {:.4}, ",
+                sample.frame, sample.cpu_ms,
+            )
+            .unwrap();
+            for (pass, column) in passes.iter().zip(&columns) {
+                write!(report, "\"{column}\": {:.4}, ", sample.timings.pass(pass)).unwrap();
+            }
+            writeln!(
+                report,
+                "\"gpu_total_ms\": {:.4}, \"samples_per_pixel\": {}}}{separator}",
+                sample.timings.total(),
+                sample.samples_per_pixel,
+            )
+            .unwrap();
+        }
+        report.push_str("]\n");
+    } else {
+        report.push_str("frame,cpu_ms,");
+        for column in &columns {
+            write!(report, "{column},").unwrap();
+        }
+        report.push_str("gpu_total_ms,samples_per_pixel\n");
+        for sample in samples {
+            write!(report, "{},{:.4},", sample.frame, sample.cpu_ms).unwrap();
+            for pass in &passes {
+                write!(report, "{:.4},", sample.timings.pass(pass)).unwrap();
+            }
+            writeln!(
+                report,
+                "{:.4},{}",
+                sample.timings.total(),
+                sample.samples_per_pixel
+            )
+            .unwrap();
+        }
+    }
+
+    std::fs::write(path, report).expect("failed to write benchmark report");
+    info!("Benchmark report written to {}", path.display());
+}
+
+fn main() {
+    App::new()
+        .insert_resource(BenchmarkConfig::from_args())
+        .init_resource::<BenchmarkState>()
+        .add_plugins((DefaultPlugins, ArcaPlugin::default(), GltfPlugin))
+        .add_systems(Startup, setup)
+        .add_systems(Update, (move_camera, record_frame).chain())
+        .run();
+}
@@ -10,7 +10,7 @@ fn load_cube(mut commands: Commands, asset_server: Res<AssetServer>) {
 
 fn main() {
     App::new()
-        .add_plugins(ArcaPlugin)
+        .add_plugins(ArcaPlugin::default())
         .add_systems(Startup, load_cube)
         .run();
 }
@@ -1,12 +1,16 @@
 use std::f32::consts::PI;
 
 use bevy::prelude::*;
-use bevy_arca::core::Camera;
+use bevy_arca::core::{Camera, PointLight};
 use bevy_arca::gltf::{GltfAssetLabel, GltfPlugin};
 use bevy_arca::plugins::{CameraController, CameraControllerPlugin};
+use bevy_arca::render::RenderSettingsHandle;
 use bevy_arca::ArcaPlugin;
 
 fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.insert_resource(RenderSettingsHandle(
+        asset_server.load("default.render.ron"),
+    ));
     commands.spawn((
         Camera {
             fov: PI / 4.0,
@@ -16,6 +20,11 @@ fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
         GlobalTransform::default(),
         CameraController::default(),
     ));
+    commands.spawn((
+        PointLight::default(),
+        Transform::from_xyz(2.0, 3.0, -2.0),
+        GlobalTransform::default(),
+    ));
     commands.spawn(SceneBundle {
         scene: asset_server.load(GltfAssetLabel::Scene(0).from_asset("cube.glb")),
         transform: Transform::from_xyz(0.0, 0.0, -5.0),
@@ -27,7 +36,7 @@ fn main() {
     App::new()
         .add_plugins((
             DefaultPlugins,
-            ArcaPlugin,
+            ArcaPlugin::default(),
             GltfPlugin,
             CameraControllerPlugin,
         ))
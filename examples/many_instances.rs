@@ -0,0 +1,100 @@
+//! Stress test for scenes of many mesh instances: a grid of cubes sharing one mesh and a few
+//! materials, with every tenth cube spinning.
+//!
+//! The spinning cubes leave the static group of the `InstanceSchedule` while the rest stay
+//! baked, so only their triangles are uploaded again every frame.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_arca::core::{ArcaMeshBundle, Camera, Material, PointLight};
+use bevy_arca::gltf::{GltfAssetLabel, GltfPlugin};
+use bevy_arca::ArcaPlugin;
+
+const GRID_SIZE: i32 = 30;
+const SPACING: f32 = 1.5;
+
+#[derive(Component)]
+struct Spin;
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<Material>>,
+) {
+    let mesh = asset_server.load(
+        GltfAssetLabel::Primitive {
+            mesh: 0,
+            primitive: 0,
+        }
+        .from_asset("cube.glb"),
+    );
+    let materials: Vec<Handle<Material>> = [
+        Color::srgb(0.8, 0.2, 0.2),
+        Color::srgb(0.2, 0.8, 0.2),
+        Color::srgb(0.2, 0.2, 0.8),
+        Color::srgb(0.8, 0.8, 0.8),
+    ]
+    .into_iter()
+    .map(|base_color| {
+        materials.add(Material {
+            base_color,
+            perceptual_roughness: 0.5,
+            ..default()
+        })
+    })
+    .collect();
+
+    let offset = (GRID_SIZE - 1) as f32 * SPACING / 2.0;
+    for x in 0..GRID_SIZE {
+        for z in 0..GRID_SIZE {
+            let index = x * GRID_SIZE + z;
+            let mut cube = commands.spawn(ArcaMeshBundle {
+                mesh: mesh.clone(),
+                material: materials[index as usize % materials.len()].clone(),
+                transform: Transform::from_xyz(
+                    x as f32 * SPACING - offset,
+                    0.0,
+                    z as f32 * SPACING - offset,
+                )
+                .with_scale(Vec3::splat(0.5)),
+                ..default()
+            });
+            if index % 10 == 0 {
+                cube.insert(Spin);
+            }
+        }
+    }
+
+    commands.spawn((
+        Camera {
+            fov: PI / 4.0,
+            aspect_ratio: 16.0 / 9.0,
+        },
+        Transform::from_xyz(0.0, 30.0, 40.0).looking_at(Vec3::ZERO, Vec3::Y),
+        GlobalTransform::default(),
+    ));
+    commands.spawn((
+        PointLight {
+            intensity: 2000.0,
+            range: 100.0,
+            ..default()
+        },
+        Transform::from_xyz(0.0, 20.0, 0.0),
+        GlobalTransform::default(),
+    ));
+}
+
+fn spin(mut cubes: Query<&mut Transform, With<Spin>>, time: Res<Time>) {
+    for mut transform in &mut cubes {
+        transform.rotate_y(time.delta_seconds());
+    }
+}
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, ArcaPlugin::default(), GltfPlugin))
+        .add_systems(Startup, setup)
+        .add_systems(Update, spin)
+        .run();
+}
@@ -0,0 +1,134 @@
+//! Material showcase: a grid of spheres from rough to smooth and from dielectric to metal, a
+//! row of clear coat and glass spheres, and a cube with a generated base color texture.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_arca::core::{
+    ArcaMeshBundle, Camera, Image, Material, PlanePrimitive, PointLight, SpherePrimitive,
+    TextureColorSpace,
+};
+use bevy_arca::gltf::{GltfAssetLabel, GltfPlugin};
+use bevy_arca::ArcaPlugin;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+const STEPS: u32 = 5;
+
+fn spawn_sphere(commands: &mut Commands, material: Handle<Material>, translation: Vec3) {
+    commands.spawn((
+        SpherePrimitive { radius: 0.4 },
+        material,
+        Transform::from_translation(translation),
+        GlobalTransform::default(),
+    ));
+}
+
+fn checkerboard() -> Image {
+    let image = RgbaImage::from_fn(64, 64, |x, y| {
+        if (x / 8 + y / 8) % 2 == 0 {
+            Rgba([230, 190, 60, 255])
+        } else {
+            Rgba([40, 40, 40, 255])
+        }
+    });
+    let mut image = Image::from_dynamic(DynamicImage::ImageRgba8(image));
+    image.set_color_space(TextureColorSpace::Srgb);
+    image
+}
+
+fn setup(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut materials: ResMut<Assets<Material>>,
+    mut images: ResMut<Assets<Image>>,
+) {
+    let step = |i: u32| i as f32 / (STEPS - 1) as f32;
+    for row in 0..STEPS {
+        for column in 0..STEPS {
+            let material = materials.add(Material {
+                base_color: Color::srgb(0.9, 0.5, 0.3),
+                metallic: step(row),
+                perceptual_roughness: step(column),
+                ..default()
+            });
+            let translation = Vec3::new(column as f32 - 2.0, row as f32 + 0.5, 0.0);
+            spawn_sphere(&mut commands, material, translation);
+        }
+    }
+    for column in 0..STEPS {
+        let clearcoat = materials.add(Material {
+            base_color: Color::srgb(0.6, 0.05, 0.05),
+            perceptual_roughness: 0.6,
+            clearcoat: 1.0,
+            clearcoat_perceptual_roughness: step(column),
+            ..default()
+        });
+        spawn_sphere(
+            &mut commands,
+            clearcoat,
+            Vec3::new(column as f32 - 2.0, 0.5, 1.5),
+        );
+        let glass = materials.add(Material {
+            perceptual_roughness: step(column) * 0.5,
+            transmission: 1.0,
+            ior: 1.5,
+            ..default()
+        });
+        spawn_sphere(
+            &mut commands,
+            glass,
+            Vec3::new(column as f32 - 2.0, 0.5, 3.0),
+        );
+    }
+
+    commands.spawn(ArcaMeshBundle {
+        mesh: asset_server.load(
+            GltfAssetLabel::Primitive {
+                mesh: 0,
+                primitive: 0,
+            }
+            .from_asset("cube.glb"),
+        ),
+        material: materials.add(Material {
+            base_color_texture: Some(images.add(checkerboard())),
+            perceptual_roughness: 0.8,
+            ..default()
+        }),
+        transform: Transform::from_xyz(4.0, 0.5, 1.5).with_rotation(Quat::from_rotation_y(0.6)),
+        ..default()
+    });
+    commands.spawn((
+        PlanePrimitive::default(),
+        materials.add(Material {
+            base_color: Color::srgb(0.5, 0.5, 0.5),
+            perceptual_roughness: 0.9,
+            ..default()
+        }),
+        Transform::default(),
+        GlobalTransform::default(),
+    ));
+
+    commands.spawn((
+        Camera {
+            fov: PI / 4.0,
+            aspect_ratio: 16.0 / 9.0,
+        },
+        Transform::from_xyz(0.0, 4.0, 11.0).looking_at(Vec3::new(0.5, 2.0, 0.0), Vec3::Y),
+        GlobalTransform::default(),
+    ));
+    commands.spawn((
+        PointLight {
+            intensity: 300.0,
+            ..default()
+        },
+        Transform::from_xyz(-3.0, 7.0, 6.0),
+        GlobalTransform::default(),
+    ));
+}
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, ArcaPlugin::default(), GltfPlugin))
+        .add_systems(Startup, setup)
+        .run();
+}
@@ -0,0 +1,42 @@
+//! Opens a second window next to the primary one. Every window gets its own swapchain and
+//! render target and both show the scene through the same camera.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_arca::core::{Camera, PointLight};
+use bevy_arca::gltf::{GltfAssetLabel, GltfPlugin};
+use bevy_arca::ArcaPlugin;
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn(Window {
+        title: "Second window".to_string(),
+        resolution: (640.0, 480.0).into(),
+        ..default()
+    });
+
+    commands.spawn((
+        Camera {
+            fov: PI / 4.0,
+            aspect_ratio: 16.0 / 9.0,
+        },
+        Transform::from_xyz(3.0, 2.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+        GlobalTransform::default(),
+    ));
+    commands.spawn((
+        PointLight::default(),
+        Transform::from_xyz(2.0, 3.0, 2.0),
+        GlobalTransform::default(),
+    ));
+    commands.spawn(SceneBundle {
+        scene: asset_server.load(GltfAssetLabel::Scene(0).from_asset("cube.glb")),
+        ..default()
+    });
+}
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, ArcaPlugin::default(), GltfPlugin))
+        .add_systems(Startup, setup)
+        .run();
+}
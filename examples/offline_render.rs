@@ -0,0 +1,91 @@
+//! Renders a glTF scene to an image file without opening a window, then exits.
+//!
+//! `cargo run --release --example offline_render -- [scene.glb] [output.png|output.exr]`
+//!
+//! The scene path is relative to `assets`. The render starts once the scene is on the GPU.
+
+use std::{f32::consts::PI, path::PathBuf};
+
+use bevy::{app::AppExit, prelude::*, window::ExitCondition};
+use bevy_arca::core::{Camera, PointLight};
+use bevy_arca::gltf::{GltfAssetLabel, GltfPlugin};
+use bevy_arca::render::{RenderFinished, RenderRequest, SceneRenderReady};
+use bevy_arca::ArcaPlugin;
+
+const WIDTH: u32 = 1920;
+const HEIGHT: u32 = 1080;
+const SAMPLES: u32 = 256;
+
+#[derive(Resource)]
+struct OutputPath(PathBuf);
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    let scene = std::env::args()
+        .nth(1)
+        .unwrap_or_else(|| "cube.glb".to_string());
+    commands.spawn((
+        Camera {
+            fov: PI / 4.0,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        Transform::from_xyz(3.0, 2.0, 5.0).looking_at(Vec3::ZERO, Vec3::Y),
+        GlobalTransform::default(),
+    ));
+    commands.spawn((
+        PointLight::default(),
+        Transform::from_xyz(2.0, 3.0, 2.0),
+        GlobalTransform::default(),
+    ));
+    commands.spawn(SceneBundle {
+        scene: asset_server.load(GltfAssetLabel::Scene(0).from_asset(scene)),
+        ..default()
+    });
+}
+
+fn request_render(
+    mut ready_events: EventReader<SceneRenderReady>,
+    mut requests: EventWriter<RenderRequest>,
+    output: Res<OutputPath>,
+) {
+    if ready_events.read().last().is_none() {
+        return;
+    }
+    requests.send(RenderRequest {
+        width: WIDTH,
+        height: HEIGHT,
+        samples: SAMPLES,
+        output: output.0.clone(),
+        tile_size: None,
+    });
+}
+
+fn exit_when_finished(
+    mut finished_events: EventReader<RenderFinished>,
+    mut exit: EventWriter<AppExit>,
+) {
+    for finished in finished_events.read() {
+        info!("Wrote {}", finished.output.display());
+        exit.send(AppExit::Success);
+    }
+}
+
+fn main() {
+    let output = std::env::args()
+        .nth(2)
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("render.png"));
+    App::new()
+        .insert_resource(OutputPath(output))
+        .add_plugins((
+            DefaultPlugins.set(WindowPlugin {
+                primary_window: None,
+                exit_condition: ExitCondition::DontExit,
+                ..default()
+            }),
+            ArcaPlugin::default(),
+            GltfPlugin,
+        ))
+        .add_systems(Startup, setup)
+        .add_systems(Update, (request_render, exit_when_finished))
+        .run();
+}
@@ -0,0 +1,51 @@
+//! Animates the window size while the camera covers an inset viewport at a reduced render
+//! scale. The viewport, the aspect ratio and the HDR target have to follow every resize.
+
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+use bevy_arca::core::{Camera, CameraViewport, PointLight};
+use bevy_arca::gltf::{GltfAssetLabel, GltfPlugin};
+use bevy_arca::ArcaPlugin;
+
+fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
+    commands.spawn((
+        Camera {
+            fov: PI / 4.0,
+            aspect_ratio: 16.0 / 9.0,
+        },
+        CameraViewport {
+            rect: Rect::new(0.1, 0.1, 0.9, 0.9),
+            render_scale: 0.75,
+        },
+        Transform::from_xyz(0.0, 0.0, 0.0).looking_at(Vec3::new(0.0, 0.0, -1.0), Vec3::Y),
+        GlobalTransform::default(),
+    ));
+    commands.spawn((
+        PointLight::default(),
+        Transform::from_xyz(2.0, 3.0, -2.0),
+        GlobalTransform::default(),
+    ));
+    commands.spawn(SceneBundle {
+        scene: asset_server.load(GltfAssetLabel::Scene(0).from_asset("cube.glb")),
+        transform: Transform::from_xyz(0.0, 0.0, -5.0),
+        ..default()
+    });
+}
+
+fn animate_window_size(mut windows: Query<&mut Window>, time: Res<Time>) {
+    let t = time.elapsed_seconds();
+    for mut window in &mut windows {
+        let width = 800.0 + 300.0 * (t * 0.7).sin();
+        let height = 600.0 + 200.0 * (t * 1.1).cos();
+        window.resolution.set(width, height);
+    }
+}
+
+fn main() {
+    App::new()
+        .add_plugins((DefaultPlugins, ArcaPlugin::default(), GltfPlugin))
+        .add_systems(Startup, setup)
+        .add_systems(Update, animate_window_size)
+        .run();
+}
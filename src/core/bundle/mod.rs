@@ -0,0 +1,38 @@
+use bevy::prelude::*;
+
+use super::{Material, Mesh};
+
+/// Whether a mesh entity is rendered. Only the entity's own value is taken into account,
+/// it is not inherited from parents.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Component, Default)]
+pub enum Visibility {
+    #[default]
+    Visible,
+    Hidden,
+}
+
+/// How much of a mesh or analytic primitive entity is there, from 0 for invisible to 1 for
+/// fully opaque, for fading objects in and out without swapping their materials. The path
+/// tracer lets the other rays pass, rasterizing pipelines dither it. Entities without it are
+/// opaque. Entities whose opacity keeps changing are moved to the dynamic group of the
+/// `InstanceSchedule` like moving ones.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component, Default)]
+pub struct InstanceOpacity(pub f32);
+
+impl Default for InstanceOpacity {
+    fn default() -> Self {
+        Self(1.0)
+    }
+}
+
+/// Components required for an entity to be picked up by the renderer.
+#[derive(Bundle, Clone, Default)]
+pub struct ArcaMeshBundle {
+    pub mesh: Handle<Mesh>,
+    pub material: Handle<Material>,
+    pub transform: Transform,
+    pub global_transform: GlobalTransform,
+    pub visibility: Visibility,
+}
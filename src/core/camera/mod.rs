@@ -1,8 +1,9 @@
 use bevy::prelude::*;
 
-use crate::render::ResizeEvent;
+use crate::render::{ResizeEvent, View};
 
-#[derive(Component)]
+#[derive(Component, Reflect, Debug, Clone)]
+#[reflect(Component)]
 pub struct Camera {
     pub fov: f32,
     pub aspect_ratio: f32,
@@ -12,26 +13,159 @@ impl Camera {
     pub fn projection_matrix(&self) -> Mat4 {
         Mat4::perspective_lh(self.fov, self.aspect_ratio, 0.1, 100.0)
     }
+
+    /// View matrix as used by the path tracer, see [`crate::render::View`] for the conventions.
+    pub fn view_matrix(&self, transform: &GlobalTransform) -> Mat4 {
+        View::new(transform, self).view_matrix()
+    }
+
+    /// Ray going through `viewport_position` into the scene.
+    ///
+    /// `viewport_position` is normalized: `(0, 0)` is the top left corner of the viewport and
+    /// `(1, 1)` is the bottom right one. Matches primary ray generation in the shader exactly.
+    pub fn viewport_to_world_ray(
+        &self,
+        transform: &GlobalTransform,
+        viewport_position: Vec2,
+    ) -> Ray3d {
+        View::new(transform, self).ray(viewport_position)
+    }
+
+    /// Normalized viewport position `world_position` is seen at, inverse of
+    /// [`Camera::viewport_to_world_ray`].
+    ///
+    /// Returns `None` if the point is behind the camera. Points outside of the viewport give
+    /// coordinates outside of `[0, 1]`.
+    pub fn world_to_viewport(
+        &self,
+        transform: &GlobalTransform,
+        world_position: Vec3,
+    ) -> Option<Vec2> {
+        View::new(transform, self).project(world_position)
+    }
+}
+
+/// Part of the window a [`Camera`] is drawn into and the resolution it is path traced at.
+///
+/// The rectangle is normalized, so it keeps its place when the window is resized. The aspect
+/// ratio of the camera follows its size in pixels. Without the component a camera covers the
+/// whole window at full resolution.
+#[derive(Component, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Component, Default)]
+pub struct CameraViewport {
+    /// `(0, 0)` is the top left corner of the window and `(1, 1)` the bottom right one.
+    pub rect: Rect,
+    /// Resolution of the path traced image relative to the viewport, upscaled when it is tone
+    /// mapped into the window. Clamped to `[0.1, 1]`.
+    pub render_scale: f32,
+}
+
+impl Default for CameraViewport {
+    fn default() -> Self {
+        Self {
+            rect: Rect::new(0.0, 0.0, 1.0, 1.0),
+            render_scale: 1.0,
+        }
+    }
+}
+
+/// Exposure compensation of a [`Camera`], applied to the scene radiance before tone mapping.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default)]
+pub struct Exposure {
+    /// Compensation in stops, radiance is scaled by `2^ev`.
+    pub ev: f32,
+}
+
+impl Default for Exposure {
+    fn default() -> Self {
+        Self { ev: 0.0 }
+    }
+}
+
+impl Exposure {
+    pub fn multiplier(&self) -> f32 {
+        self.ev.exp2()
+    }
+}
+
+/// Enables histogram based auto-exposure for a [`Camera`].
+///
+/// The average scene luminance is measured on the GPU every frame and mapped to middle grey.
+/// [`Exposure`] is still applied on top as compensation.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default)]
+pub struct AutoExposure {
+    /// Lowest luminance taken into account, in log2 units.
+    pub min_log_luminance: f32,
+    /// Highest luminance taken into account, in log2 units.
+    pub max_log_luminance: f32,
+    /// How fast the exposure adapts to the measured luminance, per second.
+    pub speed: f32,
+}
+
+impl Default for AutoExposure {
+    fn default() -> Self {
+        Self {
+            min_log_luminance: -8.0,
+            max_log_luminance: 4.0,
+            speed: 1.5,
+        }
+    }
+}
+
+/// What a [`Camera`] shows where its rays don't hit any geometry.
+///
+/// Only changes what is seen directly, the environment keeps lighting the scene either way.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq)]
+#[reflect(Component, Default)]
+pub enum Background {
+    /// The environment lighting the scene.
+    #[default]
+    Environment,
+    /// A solid color, exposure and tone mapping apply to it like to the rest of the image.
+    Color(Color),
+    /// Alpha 0, the output is premultiplied by coverage. Only useful on windows created with
+    /// `Window::transparent`, whose render is composited over the desktop.
+    Transparent,
 }
 
 pub struct CameraPlugin;
 
 impl Plugin for CameraPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(Update, update_aspect_ratio);
+        app.register_type::<Camera>()
+            .register_type::<CameraViewport>()
+            .register_type::<Exposure>()
+            .register_type::<AutoExposure>()
+            .register_type::<Background>()
+            .add_systems(Update, update_aspect_ratio);
     }
 }
 
 fn update_aspect_ratio(
-    mut cameras: Query<&mut Camera>,
+    mut cameras: Query<(&mut Camera, Option<Ref<CameraViewport>>)>,
     mut resize_event: EventReader<ResizeEvent>,
+    mut window_size: Local<Option<Vec2>>,
 ) {
-    let mut camera = cameras
+    let (mut camera, viewport) = cameras
         .get_single_mut()
         .expect("only 1 camera is supported right now");
 
-    for resize_event in resize_event.read() {
-        camera.aspect_ratio = resize_event.width / resize_event.height;
-        info!("Aspect ratio of camera is {}", camera.aspect_ratio);
+    let resized = resize_event.read().last().map(|resize_event| {
+        *window_size = Some(Vec2::new(resize_event.width, resize_event.height));
+    });
+    let viewport_changed = viewport
+        .as_ref()
+        .is_some_and(|viewport| viewport.is_changed());
+    let Some(window_size) = *window_size else {
+        return;
+    };
+    if resized.is_none() && !viewport_changed {
+        return;
     }
+
+    let size = viewport.map_or(window_size, |viewport| viewport.rect.size() * window_size);
+    camera.aspect_ratio = size.x / size.y.max(f32::EPSILON);
+    info!("Aspect ratio of camera is {}", camera.aspect_ratio);
 }
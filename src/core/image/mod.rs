@@ -4,6 +4,7 @@ use bevy::prelude::*;
 
 use image::DynamicImage;
 use sampler::Sampler;
+use serde::{Deserialize, Serialize};
 use windows::Win32::Graphics::{
     Direct3D12::{
         D3D12_MIP_REGION, D3D12_RESOURCE_DESC1, D3D12_RESOURCE_DIMENSION,
@@ -15,6 +16,15 @@ use windows::Win32::Graphics::{
 
 use crate::win_types::WinHandle;
 
+/// How the texels of an [`Image`] are encoded.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TextureColorSpace {
+    /// sRGB encoded colors, decoded to linear when sampled. Base color and emissive textures.
+    Srgb,
+    /// Sampled as stored. Normal, metallic-roughness and occlusion textures.
+    Linear,
+}
+
 #[derive(Asset, Reflect, Debug, Clone, Default)]
 #[reflect_value(Default)]
 pub struct Image {
@@ -97,6 +107,22 @@ impl Image {
         }
     }
 
+    /// Images created from pixels are [`TextureColorSpace::Linear`] until set otherwise.
+    pub fn color_space(&self) -> TextureColorSpace {
+        if self.texture_descriptor.Format == DXGI_FORMAT_R8G8B8A8_UNORM_SRGB {
+            TextureColorSpace::Srgb
+        } else {
+            TextureColorSpace::Linear
+        }
+    }
+
+    pub fn set_color_space(&mut self, color_space: TextureColorSpace) {
+        self.texture_descriptor.Format = match color_space {
+            TextureColorSpace::Srgb => DXGI_FORMAT_R8G8B8A8_UNORM_SRGB,
+            TextureColorSpace::Linear => DXGI_FORMAT_R8G8B8A8_UNORM,
+        };
+    }
+
     #[inline]
     pub fn width(&self) -> u32 {
         self.texture_descriptor.Width as u32
@@ -0,0 +1,140 @@
+mod placeholder_rig;
+
+use bevy::prelude::*;
+
+pub use placeholder_rig::{PlaceholderLight, PlaceholderLights};
+
+pub struct LightPlugin {
+    pub placeholder_lights: bool,
+}
+
+impl Plugin for LightPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<PointLight>()
+            .register_type::<DirectionalLight>()
+            .register_type::<SpotLight>()
+            .register_type::<RectLight>()
+            .register_type::<DiskLight>()
+            .register_type::<PlaceholderLights>()
+            .register_type::<PlaceholderLight>()
+            .insert_resource(PlaceholderLights {
+                enabled: self.placeholder_lights,
+                ..default()
+            })
+            .add_systems(Update, placeholder_rig::update_placeholder_lights);
+    }
+}
+
+/// Light emitted from a single point in all directions.
+///
+/// `intensity` is radiant intensity, the light falls off with the squared distance
+/// and is cut off smoothly at `range`.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default)]
+pub struct PointLight {
+    pub color: Color,
+    pub intensity: f32,
+    pub range: f32,
+    pub radius: f32,
+}
+
+impl Default for PointLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 10.0,
+            range: 20.0,
+            radius: 0.0,
+        }
+    }
+}
+
+/// Light coming from infinitely far away along the entity's forward direction.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default)]
+pub struct DirectionalLight {
+    pub color: Color,
+    pub illuminance: f32,
+}
+
+impl Default for DirectionalLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            illuminance: 1.0,
+        }
+    }
+}
+
+/// Point light restricted to a cone around the entity's forward direction.
+///
+/// Angles are in radians, measured from the cone axis. The light fades out between
+/// `inner_angle` and `outer_angle`.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default)]
+pub struct SpotLight {
+    pub color: Color,
+    pub intensity: f32,
+    pub range: f32,
+    pub radius: f32,
+    pub inner_angle: f32,
+    pub outer_angle: f32,
+}
+
+impl Default for SpotLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 10.0,
+            range: 20.0,
+            radius: 0.0,
+            inner_angle: 0.0,
+            outer_angle: std::f32::consts::FRAC_PI_4,
+        }
+    }
+}
+
+/// One-sided rectangular area light emitting along the entity's forward direction.
+///
+/// `intensity` is the emitted radiance. The rectangle spans `width` along the entity's
+/// right axis and `height` along its up axis, larger lights cast softer shadows.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default)]
+pub struct RectLight {
+    pub color: Color,
+    pub intensity: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Default for RectLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 10.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+}
+
+/// One-sided disk area light emitting along the entity's forward direction.
+///
+/// `intensity` is the emitted radiance.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default)]
+pub struct DiskLight {
+    pub color: Color,
+    pub intensity: f32,
+    pub radius: f32,
+}
+
+impl Default for DiskLight {
+    fn default() -> Self {
+        Self {
+            color: Color::WHITE,
+            intensity: 10.0,
+            radius: 0.5,
+        }
+    }
+}
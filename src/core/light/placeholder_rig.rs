@@ -0,0 +1,80 @@
+use bevy::prelude::*;
+
+use super::{DirectionalLight, DiskLight, PointLight, RectLight, SpotLight};
+
+/// Three point light rig lighting scenes without lights of their own, so a model dropped into
+/// an empty scene isn't lit by the dim environment alone. The rig is despawned as soon as the
+/// scene gets a light and spawned again once it has none.
+///
+/// Set through [`crate::ArcaPlugin::placeholder_lights`].
+#[derive(Resource, Reflect, Debug, Clone, Copy)]
+#[reflect(Resource, Default)]
+pub struct PlaceholderLights {
+    pub enabled: bool,
+    /// Illuminance of the key light, the fill and rim lights are dimmer.
+    pub illuminance: f32,
+}
+
+impl Default for PlaceholderLights {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            illuminance: 2.0,
+        }
+    }
+}
+
+/// Marks the lights spawned by [`PlaceholderLights`].
+#[derive(Component, Reflect, Debug, Clone, Copy, Default)]
+#[reflect(Component, Default)]
+pub struct PlaceholderLight;
+
+type AnyLight = Or<(
+    With<PointLight>,
+    With<DirectionalLight>,
+    With<SpotLight>,
+    With<RectLight>,
+    With<DiskLight>,
+)>;
+
+// name, direction the light comes from and illuminance relative to the key light
+const RIG: [(&str, Vec3, f32); 3] = [
+    ("placeholder key light", Vec3::new(-1.0, 1.5, 1.0), 1.0),
+    ("placeholder fill light", Vec3::new(1.5, 0.5, 1.0), 0.4),
+    ("placeholder rim light", Vec3::new(0.0, 1.0, -1.5), 0.6),
+];
+
+pub(super) fn update_placeholder_lights(
+    mut commands: Commands,
+    settings: Res<PlaceholderLights>,
+    scene_lights: Query<(), (AnyLight, Without<PlaceholderLight>)>,
+    rig: Query<Entity, With<PlaceholderLight>>,
+) {
+    let wanted = settings.enabled && scene_lights.is_empty();
+    if !wanted {
+        for entity in &rig {
+            commands.entity(entity).despawn();
+        }
+        return;
+    }
+    if !rig.is_empty() && !settings.is_changed() {
+        return;
+    }
+
+    for entity in &rig {
+        commands.entity(entity).despawn();
+    }
+    for (name, from, share) in RIG {
+        let transform = Transform::from_translation(from).looking_at(Vec3::ZERO, Vec3::Y);
+        commands.spawn((
+            Name::new(name),
+            DirectionalLight {
+                illuminance: settings.illuminance * share,
+                ..default()
+            },
+            transform,
+            GlobalTransform::from(transform),
+            PlaceholderLight,
+        ));
+    }
+}
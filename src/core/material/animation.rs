@@ -0,0 +1,122 @@
+use bevy::prelude::*;
+
+use super::Material;
+
+pub struct MaterialAnimationPlugin;
+
+impl Plugin for MaterialAnimationPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<MaterialAnimation>()
+            .add_systems(Update, animate_materials);
+    }
+}
+
+/// Keyframes of one [`Material`] field, as `(time in seconds, value)` pairs sorted by time.
+/// Values are interpolated linearly and held before the first and after the last keyframe.
+#[derive(Reflect, Debug, Clone)]
+pub enum MaterialTrack {
+    BaseColor(Vec<(f32, LinearRgba)>),
+    Emissive(Vec<(f32, LinearRgba)>),
+    Transmission(Vec<(f32, f32)>),
+    Ior(Vec<(f32, f32)>),
+}
+
+impl MaterialTrack {
+    fn duration(&self) -> f32 {
+        let last = match self {
+            MaterialTrack::BaseColor(keyframes) | MaterialTrack::Emissive(keyframes) => {
+                keyframes.last().map(|(time, _)| *time)
+            }
+            MaterialTrack::Transmission(keyframes) | MaterialTrack::Ior(keyframes) => {
+                keyframes.last().map(|(time, _)| *time)
+            }
+        };
+        last.unwrap_or(0.0)
+    }
+
+    fn apply(&self, material: &mut Material, time: f32) {
+        match self {
+            MaterialTrack::BaseColor(keyframes) => {
+                if let Some(color) = sample(keyframes, time, |from, to, t| from.mix(&to, t)) {
+                    material.base_color = color.into();
+                }
+            }
+            MaterialTrack::Emissive(keyframes) => {
+                if let Some(color) = sample(keyframes, time, |from, to, t| from.mix(&to, t)) {
+                    material.emissive = color;
+                }
+            }
+            MaterialTrack::Transmission(keyframes) => {
+                if let Some(transmission) = sample(keyframes, time, f32::lerp) {
+                    material.transmission = transmission;
+                }
+            }
+            MaterialTrack::Ior(keyframes) => {
+                if let Some(ior) = sample(keyframes, time, f32::lerp) {
+                    material.ior = ior;
+                }
+            }
+        }
+    }
+}
+
+fn sample<T: Copy>(keyframes: &[(f32, T)], time: f32, lerp: impl Fn(T, T, f32) -> T) -> Option<T> {
+    let next = keyframes.partition_point(|(keyframe_time, _)| *keyframe_time <= time);
+    match (
+        next.checked_sub(1).map(|i| &keyframes[i]),
+        keyframes.get(next),
+    ) {
+        (Some((start, from)), Some((end, to))) => {
+            Some(lerp(*from, *to, (time - start) / (end - start)))
+        }
+        (Some((_, value)), None) | (None, Some((_, value))) => Some(*value),
+        (None, None) => None,
+    }
+}
+
+/// Plays [`MaterialTrack`]s on the `Handle<Material>` of the entity. The material asset itself
+/// is changed, so every mesh sharing it is animated, like glTF's `KHR_animation_pointer` does.
+/// Every changed frame restarts accumulation.
+#[derive(Component, Reflect, Debug, Clone, Default)]
+#[reflect(Component, Default)]
+pub struct MaterialAnimation {
+    pub tracks: Vec<MaterialTrack>,
+    /// Starts over after the last keyframe of the longest track.
+    pub repeat: bool,
+    pub paused: bool,
+    /// Playback position in seconds.
+    pub elapsed: f32,
+}
+
+fn animate_materials(
+    mut animations: Query<(&mut MaterialAnimation, &Handle<Material>)>,
+    mut materials: ResMut<Assets<Material>>,
+    time: Res<Time>,
+) {
+    for (mut animation, handle) in &mut animations {
+        if animation.paused {
+            continue;
+        }
+        animation.elapsed += time.delta_seconds();
+        let duration = animation
+            .tracks
+            .iter()
+            .map(MaterialTrack::duration)
+            .fold(0.0, f32::max);
+        if animation.repeat && duration > 0.0 {
+            animation.elapsed %= duration;
+        }
+
+        let Some(material) = materials.get(handle) else {
+            continue;
+        };
+        let mut animated = material.clone();
+        for track in &animation.tracks {
+            track.apply(&mut animated, animation.elapsed);
+        }
+        // only touch the asset when something changed, every change rebuilds the scene data
+        if animated != *material {
+            *materials.get_mut(handle).unwrap() = animated;
+        }
+    }
+}
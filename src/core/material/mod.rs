@@ -1,27 +1,45 @@
+mod animation;
+
 use bevy::{math::Affine2, prelude::*};
+use serde::{Deserialize, Serialize};
 
 use super::Image;
 
-pub struct MaterialPlugin;
-
-impl Plugin for MaterialPlugin {
-    fn build(&self, app: &mut App) {
-        app.init_asset::<Material>()
-            .register_asset_reflect::<Material>();
-
-        let mut material_assets = app.world_mut().resource_mut::<Assets<Material>>();
-
-        material_assets.insert(&Handle::default(), Material::default());
-    }
-}
+pub use animation::{MaterialAnimation, MaterialAnimationPlugin, MaterialTrack};
 
-#[derive(Asset, Debug, Reflect, Clone)]
+/// Textures are asset handles and aren't serialized, a deserialized material is untextured
+/// until they are set again.
+#[derive(Asset, Debug, Reflect, Serialize, Deserialize, Clone, PartialEq)]
+#[reflect(Default, Serialize, Deserialize)]
+#[serde(default)]
 pub struct Material {
     pub base_color: Color,
+    #[serde(skip)]
     pub base_color_texture: Option<Handle<Image>>,
+    /// Light emitted by the surface, in linear space. Anything non-black turns the
+    /// mesh into an area light for the path tracer.
+    pub emissive: LinearRgba,
+    /// How metallic the surface is, in `[0, 1]`. Metals tint their reflections with
+    /// `base_color` and don't scatter diffuse light.
+    pub metallic: f32,
+    /// Roughness of the surface as artists perceive it, in `[0, 1]`, from mirror-like to fully
+    /// rough. The microfacet roughness is its square.
+    pub perceptual_roughness: f32,
+    /// Strength of a clear coat layer on top of the surface, in `[0, 1]`, like varnish or car
+    /// paint. The coat is a dielectric with an index of refraction of 1.5.
+    pub clearcoat: f32,
+    /// Perceptual roughness of the clear coat, in `[0, 1]`.
+    pub clearcoat_perceptual_roughness: f32,
+    #[serde(skip)]
     pub normal_map_texture: Option<Handle<Image>>,
+    #[serde(skip)]
     pub occlusion_texture: Option<Handle<Image>>,
     pub uv_transform: Affine2,
+    /// Fraction of light refracted through the surface instead of being scattered, in
+    /// `[0, 1]`. Transmitted light is tinted by `base_color`. Meshes using it should be closed.
+    pub transmission: f32,
+    /// Index of refraction of the inside of the mesh, used when `transmission` is non-zero.
+    pub ior: f32,
 }
 
 impl Default for Material {
@@ -29,9 +47,16 @@ impl Default for Material {
         Self {
             base_color: Color::WHITE,
             base_color_texture: None,
+            emissive: LinearRgba::BLACK,
+            metallic: 0.0,
+            perceptual_roughness: 0.5,
+            clearcoat: 0.0,
+            clearcoat_perceptual_roughness: 0.5,
             normal_map_texture: None,
             occlusion_texture: None,
             uv_transform: Affine2::IDENTITY,
+            transmission: 0.0,
+            ior: 1.5,
         }
     }
 }
@@ -1,21 +1,149 @@
-use bevy::{asset::Asset, reflect::TypePath};
-use windows::Win32::Graphics::Direct3D12::D3D12_PRIMITIVE_TOPOLOGY_TYPE;
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+use windows::Win32::Graphics::Direct3D12::{
+    D3D12_PRIMITIVE_TOPOLOGY_TYPE, D3D12_PRIMITIVE_TOPOLOGY_TYPE_LINE,
+    D3D12_PRIMITIVE_TOPOLOGY_TYPE_POINT, D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
+};
 
-#[derive(Asset, TypePath)]
+#[derive(Asset, Reflect, Serialize, Deserialize, Debug, Clone)]
+#[reflect(Serialize, Deserialize)]
 pub struct Mesh {
-    pub primitive_topology: D3D12_PRIMITIVE_TOPOLOGY_TYPE,
+    pub primitive_topology: PrimitiveTopology,
     pub positions: Vec<[f32; 3]>,
     pub normals: Option<Vec<[f32; 3]>>,
+    /// First set of texture coordinates.
+    pub uvs: Option<Vec<[f32; 2]>>,
     pub indices: Option<Vec<u32>>,
+    /// Extra per-vertex data for custom pipelines, ignored by the built-in path tracer. Only
+    /// attributes registered in `CustomVertexAttributes` reach the GPU.
+    pub custom_attributes: Vec<CustomAttribute>,
 }
 
 impl Mesh {
-    pub fn new(primitive_topology: D3D12_PRIMITIVE_TOPOLOGY_TYPE) -> Self {
+    pub fn new(primitive_topology: PrimitiveTopology) -> Self {
         Self {
             primitive_topology,
             positions: Vec::new(),
             normals: None,
+            uvs: None,
             indices: None,
+            custom_attributes: Vec::new(),
+        }
+    }
+
+    /// Sets the custom attribute `name`, replacing a previous one with the same name. Needs one
+    /// value per position.
+    pub fn insert_custom_attribute(
+        &mut self,
+        name: impl Into<String>,
+        values: impl Into<VertexAttributeValues>,
+    ) {
+        let name = name.into();
+        let values = values.into();
+        match self
+            .custom_attributes
+            .iter_mut()
+            .find(|attribute| attribute.name == name)
+        {
+            Some(attribute) => attribute.values = values,
+            None => self
+                .custom_attributes
+                .push(CustomAttribute { name, values }),
+        }
+    }
+
+    pub fn custom_attribute(&self, name: &str) -> Option<&VertexAttributeValues> {
+        self.custom_attributes
+            .iter()
+            .find(|attribute| attribute.name == name)
+            .map(|attribute| &attribute.values)
+    }
+}
+
+/// Named per-vertex data of a [`Mesh`].
+#[derive(Reflect, Serialize, Deserialize, Debug, Clone)]
+#[reflect(Serialize, Deserialize)]
+pub struct CustomAttribute {
+    pub name: String,
+    pub values: VertexAttributeValues,
+}
+
+/// Per-vertex values with one to four `f32` components.
+#[derive(Reflect, Serialize, Deserialize, Debug, Clone)]
+#[reflect(Serialize, Deserialize)]
+pub enum VertexAttributeValues {
+    Float32(Vec<f32>),
+    Float32x2(Vec<[f32; 2]>),
+    Float32x3(Vec<[f32; 3]>),
+    Float32x4(Vec<[f32; 4]>),
+}
+
+impl VertexAttributeValues {
+    pub fn len(&self) -> usize {
+        match self {
+            Self::Float32(values) => values.len(),
+            Self::Float32x2(values) => values.len(),
+            Self::Float32x3(values) => values.len(),
+            Self::Float32x4(values) => values.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Value of vertex `index`, missing components are zero.
+    pub fn get_padded(&self, index: usize) -> [f32; 4] {
+        let mut padded = [0.0; 4];
+        let components: &[f32] = match self {
+            Self::Float32(values) => std::slice::from_ref(&values[index]),
+            Self::Float32x2(values) => &values[index],
+            Self::Float32x3(values) => &values[index],
+            Self::Float32x4(values) => &values[index],
+        };
+        padded[..components.len()].copy_from_slice(components);
+        padded
+    }
+}
+
+impl From<Vec<f32>> for VertexAttributeValues {
+    fn from(values: Vec<f32>) -> Self {
+        Self::Float32(values)
+    }
+}
+
+impl From<Vec<[f32; 2]>> for VertexAttributeValues {
+    fn from(values: Vec<[f32; 2]>) -> Self {
+        Self::Float32x2(values)
+    }
+}
+
+impl From<Vec<[f32; 3]>> for VertexAttributeValues {
+    fn from(values: Vec<[f32; 3]>) -> Self {
+        Self::Float32x3(values)
+    }
+}
+
+impl From<Vec<[f32; 4]>> for VertexAttributeValues {
+    fn from(values: Vec<[f32; 4]>) -> Self {
+        Self::Float32x4(values)
+    }
+}
+
+#[derive(Reflect, Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[reflect(Serialize, Deserialize)]
+pub enum PrimitiveTopology {
+    PointList,
+    LineList,
+    TriangleList,
+}
+
+impl From<PrimitiveTopology> for D3D12_PRIMITIVE_TOPOLOGY_TYPE {
+    fn from(topology: PrimitiveTopology) -> Self {
+        match topology {
+            PrimitiveTopology::PointList => D3D12_PRIMITIVE_TOPOLOGY_TYPE_POINT,
+            PrimitiveTopology::LineList => D3D12_PRIMITIVE_TOPOLOGY_TYPE_LINE,
+            PrimitiveTopology::TriangleList => D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
         }
     }
 }
@@ -1,23 +1,41 @@
+mod bundle;
 mod camera;
 mod image;
+mod light;
 mod material;
 mod mesh;
+mod placeholder;
+mod primitive;
+mod scene;
 mod shader;
-mod vertex_buffer;
 
 use bevy::prelude::*;
 use camera::CameraPlugin;
+use light::LightPlugin;
+use material::MaterialAnimationPlugin;
+use primitive::PrimitivePlugin;
+use scene::SceneDespawnPlugin;
 
-pub use camera::Camera;
-pub use image::Image;
-pub use material::Material;
-pub use mesh::Mesh;
+pub use bundle::{ArcaMeshBundle, InstanceOpacity, Visibility};
+pub use camera::{AutoExposure, Background, Camera, CameraViewport, Exposure};
+pub use image::{Image, TextureColorSpace};
+pub use light::{
+    DirectionalLight, DiskLight, PlaceholderLight, PlaceholderLights, PointLight, RectLight,
+    SpotLight,
+};
+pub use material::{Material, MaterialAnimation, MaterialTrack};
+pub use mesh::{CustomAttribute, Mesh, PrimitiveTopology, VertexAttributeValues};
+pub use placeholder::PlaceholderAssets;
+pub use primitive::{BoxPrimitive, PlanePrimitive, SpherePrimitive};
+pub use scene::DespawnSceneExt;
 pub use shader::Shader;
-pub use vertex_buffer::VertexBuffer;
 
 use shader::ShaderLoader;
 
-pub struct CorePlugin;
+pub struct CorePlugin {
+    pub placeholders: bool,
+    pub placeholder_lights: bool,
+}
 
 impl Plugin for CorePlugin {
     fn build(&self, app: &mut App) {
@@ -27,11 +45,25 @@ impl Plugin for CorePlugin {
             .init_asset::<Shader>()
             .register_type::<Image>()
             .register_type::<Material>()
+            .register_type::<Mesh>()
+            .register_type::<PrimitiveTopology>()
+            .register_type::<VertexAttributeValues>()
+            .register_type::<Visibility>()
+            .register_type::<InstanceOpacity>()
             .register_asset_reflect::<Image>()
             .register_asset_reflect::<Material>()
+            .register_asset_reflect::<Mesh>()
             .register_asset_loader(ShaderLoader);
 
-        app.add_plugins(CameraPlugin);
+        app.add_plugins((
+            CameraPlugin,
+            LightPlugin {
+                placeholder_lights: self.placeholder_lights,
+            },
+            MaterialAnimationPlugin,
+            PrimitivePlugin,
+            SceneDespawnPlugin,
+        ));
 
         app.world_mut()
             .resource_mut::<Assets<Image>>()
@@ -40,5 +72,116 @@ impl Plugin for CorePlugin {
         app.world_mut()
             .resource_mut::<Assets<Material>>()
             .insert(&Handle::default(), Material::default());
+
+        let placeholders = PlaceholderAssets::new(self.placeholders, app.world_mut());
+        app.insert_resource(placeholders);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bevy::{
+        reflect::serde::{ReflectDeserializer, ReflectSerializer},
+        scene::serde::SceneDeserializer,
+    };
+    use serde::de::DeserializeSeed;
+
+    use super::*;
+
+    fn registry() -> AppTypeRegistry {
+        let registry = AppTypeRegistry::default();
+        {
+            let mut registry = registry.write();
+            registry.register::<Transform>();
+            registry.register::<Camera>();
+            registry.register::<Exposure>();
+            registry.register::<PointLight>();
+            registry.register::<DirectionalLight>();
+            registry.register::<Mesh>();
+            registry.register::<Material>();
+            registry.register::<Handle<Mesh>>();
+            registry.register::<Handle<Material>>();
+        }
+        registry
+    }
+
+    #[test]
+    fn scenes_round_trip_through_ron() {
+        let mut world = World::new();
+        world.insert_resource(registry());
+        world.spawn((
+            Transform::from_xyz(0.0, 1.0, 5.0),
+            Camera {
+                fov: 0.8,
+                aspect_ratio: 1.5,
+            },
+            Exposure::default(),
+        ));
+        world.spawn((
+            Transform::from_xyz(2.0, 3.0, 0.0),
+            PointLight {
+                intensity: 25.0,
+                ..default()
+            },
+        ));
+        world.spawn(DirectionalLight::default());
+        world.spawn((
+            Transform::default(),
+            Handle::<Mesh>::weak_from_u128(1),
+            Handle::<Material>::weak_from_u128(2),
+        ));
+
+        let registry = world.resource::<AppTypeRegistry>().clone();
+        let registry = registry.read();
+        let ron = DynamicScene::from_world(&world)
+            .serialize(&registry)
+            .unwrap();
+        let mut deserializer = ron::de::Deserializer::from_str(&ron).unwrap();
+        let scene = SceneDeserializer {
+            type_registry: &registry,
+        }
+        .deserialize(&mut deserializer)
+        .unwrap();
+
+        assert_eq!(scene.entities.len(), 4);
+        assert_eq!(scene.serialize(&registry).unwrap(), ron);
+    }
+
+    #[test]
+    fn meshes_and_materials_round_trip_through_ron() {
+        let registry = registry();
+        let registry = registry.read();
+
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.positions = vec![[0.0, 0.0, 0.0], [1.0, 0.0, 0.0], [0.0, 1.0, 0.0]];
+        mesh.indices = Some(vec![0, 1, 2]);
+        mesh.insert_custom_attribute("weight", vec![0.25, 0.5, 0.75]);
+        let material = Material {
+            base_color: Color::srgb(0.8, 0.2, 0.1),
+            emissive: LinearRgba::rgb(1.0, 0.5, 0.0),
+            metallic: 1.0,
+            transmission: 0.5,
+            ..default()
+        };
+
+        let ron = ron::to_string(&ReflectSerializer::new(&mesh, &registry)).unwrap();
+        let mut deserializer = ron::de::Deserializer::from_str(&ron).unwrap();
+        let value = ReflectDeserializer::new(&registry)
+            .deserialize(&mut deserializer)
+            .unwrap();
+        let deserialized = Mesh::from_reflect(value.as_ref()).unwrap();
+        assert_eq!(deserialized.positions, mesh.positions);
+        assert_eq!(deserialized.indices, mesh.indices);
+        assert_eq!(
+            deserialized.custom_attribute("weight").unwrap().len(),
+            mesh.positions.len()
+        );
+
+        let ron = ron::to_string(&ReflectSerializer::new(&material, &registry)).unwrap();
+        let mut deserializer = ron::de::Deserializer::from_str(&ron).unwrap();
+        let value = ReflectDeserializer::new(&registry)
+            .deserialize(&mut deserializer)
+            .unwrap();
+        assert_eq!(Material::from_reflect(value.as_ref()).unwrap(), material);
     }
 }
@@ -0,0 +1,78 @@
+use bevy::prelude::*;
+use image::{DynamicImage, Rgba, RgbaImage};
+
+use super::{Image, Material, Mesh, PrimitiveTopology, TextureColorSpace};
+
+const CHECKERBOARD_SIZE: u32 = 8;
+
+// corner `i` is at ((i & 1), (i >> 1) & 1, (i >> 2) & 1) - 0.5, faces wound counter-clockwise
+// when seen from outside
+const CUBE_INDICES: [u32; 36] = [
+    4, 6, 2, 4, 2, 0, // -X
+    1, 3, 7, 1, 7, 5, // +X
+    0, 1, 5, 0, 5, 4, // -Y
+    6, 7, 3, 6, 3, 2, // +Y
+    2, 3, 1, 2, 1, 0, // -Z
+    4, 5, 7, 4, 7, 6, // +Z
+];
+
+/// Assets rendered in place of ones that aren't loaded yet.
+///
+/// With [`PlaceholderAssets::enabled`] unset, meshes that aren't loaded are skipped and missing
+/// materials fall back to the default one. Set through [`crate::ArcaPlugin::placeholders`].
+#[derive(Resource, Debug, Clone)]
+pub struct PlaceholderAssets {
+    pub enabled: bool,
+    /// Unit cube centered on the entity.
+    pub mesh: Handle<Mesh>,
+    /// Plain gray material.
+    pub material: Handle<Material>,
+    /// Gray checkerboard, for materials whose textures aren't loaded.
+    pub texture: Handle<Image>,
+}
+
+impl PlaceholderAssets {
+    pub(super) fn new(enabled: bool, world: &mut World) -> Self {
+        let mesh = world.resource_mut::<Assets<Mesh>>().add(unit_cube());
+        let material = world.resource_mut::<Assets<Material>>().add(Material {
+            base_color: Color::srgb(0.5, 0.5, 0.5),
+            ..default()
+        });
+        let texture = world.resource_mut::<Assets<Image>>().add(checkerboard());
+        Self {
+            enabled,
+            mesh,
+            material,
+            texture,
+        }
+    }
+}
+
+fn unit_cube() -> Mesh {
+    let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+    mesh.positions = (0..8)
+        .map(|corner| {
+            [
+                (corner & 1) as f32 - 0.5,
+                ((corner >> 1) & 1) as f32 - 0.5,
+                ((corner >> 2) & 1) as f32 - 0.5,
+            ]
+        })
+        .collect();
+    mesh.indices = Some(CUBE_INDICES.to_vec());
+    mesh
+}
+
+fn checkerboard() -> Image {
+    let image = RgbaImage::from_fn(CHECKERBOARD_SIZE, CHECKERBOARD_SIZE, |x, y| {
+        if (x + y) % 2 == 0 {
+            Rgba([96, 96, 96, 255])
+        } else {
+            Rgba([160, 160, 160, 255])
+        }
+    });
+    let mut image = Image::from_dynamic(DynamicImage::ImageRgba8(image));
+    // stands in for base color textures
+    image.set_color_space(TextureColorSpace::Srgb);
+    image
+}
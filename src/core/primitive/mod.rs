@@ -0,0 +1,59 @@
+use bevy::prelude::*;
+
+pub struct PrimitivePlugin;
+
+impl Plugin for PrimitivePlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<SpherePrimitive>()
+            .register_type::<PlanePrimitive>()
+            .register_type::<BoxPrimitive>();
+    }
+}
+
+/// Sphere around the entity's origin, intersected exactly by the path tracer instead of being
+/// tessellated.
+///
+/// Analytic primitives are shaded with the `Handle<Material>` of their entity and placed by its
+/// `GlobalTransform`, scale included. Base color textures are ignored.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default)]
+pub struct SpherePrimitive {
+    pub radius: f32,
+}
+
+impl Default for SpherePrimitive {
+    fn default() -> Self {
+        Self { radius: 0.5 }
+    }
+}
+
+/// Rectangle in the entity's local XZ plane facing +Y, only hit from behind by transmissive
+/// materials. Infinite half sizes give an infinite plane, the default.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default)]
+pub struct PlanePrimitive {
+    pub half_size: Vec2,
+}
+
+impl Default for PlanePrimitive {
+    fn default() -> Self {
+        Self {
+            half_size: Vec2::INFINITY,
+        }
+    }
+}
+
+/// Box around the entity's origin, aligned to its local axes.
+#[derive(Component, Reflect, Debug, Clone, Copy)]
+#[reflect(Component, Default)]
+pub struct BoxPrimitive {
+    pub half_size: Vec3,
+}
+
+impl Default for BoxPrimitive {
+    fn default() -> Self {
+        Self {
+            half_size: Vec3::splat(0.5),
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use bevy::{
+    ecs::system::EntityCommands,
+    prelude::*,
+    scene::{SceneInstance, SceneSpawner},
+};
+
+pub struct SceneDespawnPlugin;
+
+impl Plugin for SceneDespawnPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Last, despawn_scenes);
+    }
+}
+
+/// Marks a scene root for despawning at the end of the frame.
+#[derive(Component)]
+struct DespawnScene;
+
+pub trait DespawnSceneExt {
+    /// Despawns a spawned scene instance together with its root entity.
+    ///
+    /// Dropping the entities releases their mesh and material handles, and the renderer
+    /// rebuilds its GPU mesh data without them. Assets are only unloaded once no other
+    /// entity holds a handle to them.
+    fn despawn_scene(&mut self);
+}
+
+impl DespawnSceneExt for EntityCommands<'_> {
+    fn despawn_scene(&mut self) {
+        self.insert(DespawnScene);
+    }
+}
+
+fn despawn_scenes(
+    mut commands: Commands,
+    scenes: Query<(Entity, Option<&SceneInstance>), With<DespawnScene>>,
+    mut scene_spawner: ResMut<SceneSpawner>,
+) {
+    for (entity, instance) in &scenes {
+        if let Some(instance) = instance {
+            scene_spawner.despawn_instance(**instance);
+        }
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -13,6 +13,13 @@ pub struct Shader {
 }
 
 impl Shader {
+    /// Shader of HLSL `source` that doesn't come from a file.
+    pub fn from_source(source: &str) -> Self {
+        Self {
+            source: CString::new(source).expect("shader source contains a nul byte"),
+        }
+    }
+
     pub fn pcstr(&self) -> PCSTR {
         PCSTR::from_raw(self.source.as_ptr() as *const u8)
     }
@@ -1,4 +1,4 @@
-use std::mem;
+use std::{collections::BTreeMap, mem};
 
 use bevy::{
     asset::{io::Reader, AssetLoader, LoadContext},
@@ -17,21 +17,30 @@ use gltf::{
 };
 
 use image::ImageError;
+use serde::{Deserialize, Serialize};
 use thiserror::Error;
-use windows::Win32::Graphics::Direct3D12::{
-    D3D12_PRIMITIVE_TOPOLOGY_TYPE, D3D12_PRIMITIVE_TOPOLOGY_TYPE_LINE,
-    D3D12_PRIMITIVE_TOPOLOGY_TYPE_POINT, D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
-};
 
 use crate::{
-    core::{Image, Material, Mesh},
+    core::{Image, Material, Mesh, PrimitiveTopology, TextureColorSpace, VertexAttributeValues},
     gltf::Gltf,
 };
 
-use super::{tree_iterator::GltfTreeIterator, GltfAssetLabel, GltfMesh, GltfNode, GltfPrimitive};
+use super::{
+    tree_iterator::GltfTreeIterator, GltfAssetLabel, GltfMesh, GltfNode, GltfPrimitive, GltfStats,
+    GltfWorldTransform,
+};
 
 pub struct GltfLoader;
 
+/// Settings of the glTF loader, pass them with `AssetServer::load_with_settings`.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct GltfLoaderSettings {
+    /// Color space of textures by glTF texture index, replacing the one derived from how the
+    /// materials use them: sRGB for base color and emissive textures, linear for normal,
+    /// metallic-roughness and occlusion ones.
+    pub texture_color_spaces: BTreeMap<usize, TextureColorSpace>,
+}
+
 #[derive(Error, Debug)]
 pub enum GltfError {
     #[error("invalid glTF file: {0}")]
@@ -54,17 +63,17 @@ pub enum GltfError {
 
 impl AssetLoader for GltfLoader {
     type Asset = Gltf;
-    type Settings = ();
+    type Settings = GltfLoaderSettings;
     type Error = GltfError;
     async fn load<'a>(
         &'a self,
         reader: &'a mut dyn Reader,
-        _settings: &'a (),
+        settings: &'a GltfLoaderSettings,
         load_context: &'a mut LoadContext<'_>,
     ) -> Result<Gltf, Self::Error> {
         let mut bytes = Vec::new();
         reader.read_to_end(&mut bytes).await?;
-        load_gltf(&bytes, load_context).await
+        load_gltf(&bytes, settings, load_context).await
     }
 
     fn extensions(&self) -> &[&str] {
@@ -95,6 +104,9 @@ fn load_material(material: &gltf::Material, load_context: &mut LoadContext) -> H
         })
         .unwrap_or_default();
 
+    let emissive = material.emissive_factor();
+    let emissive_strength = material.emissive_strength().unwrap_or(1.0);
+
     let normal_map_texture: Option<Handle<Image>> = material
         .normal_texture()
         .map(|normal_texture| image_handle(load_context, &normal_texture.texture()));
@@ -103,14 +115,35 @@ fn load_material(material: &gltf::Material, load_context: &mut LoadContext) -> H
         .occlusion_texture()
         .map(|occlusion_texture| image_handle(load_context, &occlusion_texture.texture()));
 
+    let transmission = material
+        .transmission()
+        .map_or(0.0, |transmission| transmission.transmission_factor());
+    let ior = material.ior().unwrap_or(1.5);
+
+    // not parsed by the gltf crate, read from the raw extension instead
+    let clearcoat = material.extension_value("KHR_materials_clearcoat");
+    let clearcoat_factor = |name| {
+        clearcoat
+            .and_then(|clearcoat| clearcoat.get(name))
+            .and_then(|factor| factor.as_f64())
+            .unwrap_or(0.0) as f32
+    };
+
     load_context.add_labeled_asset(
         material_label.to_string(),
         Material {
             base_color: Color::srgba(color[0], color[1], color[2], color[3]),
             base_color_texture,
+            emissive: LinearRgba::rgb(emissive[0], emissive[1], emissive[2]) * emissive_strength,
+            metallic: pbr.metallic_factor(),
+            perceptual_roughness: pbr.roughness_factor(),
+            clearcoat: clearcoat_factor("clearcoatFactor"),
+            clearcoat_perceptual_roughness: clearcoat_factor("clearcoatRoughnessFactor"),
             normal_map_texture,
             occlusion_texture,
             uv_transform,
+            transmission,
+            ior,
         },
     )
 }
@@ -132,23 +165,85 @@ fn convert_texture_transform_to_affine2(texture_transform: TextureTransform) ->
     )
 }
 
+/// Color space of every texture by how the materials use it, with the overrides of `settings`.
+/// Textures no material uses are taken for colors.
+fn texture_color_spaces(
+    gltf: &gltf::Gltf,
+    settings: &GltfLoaderSettings,
+) -> Vec<TextureColorSpace> {
+    let mut color_spaces = vec![None; gltf.textures().len()];
+    let mut classify = |texture: gltf::Texture, color_space| {
+        let classified = color_spaces[texture.index()].get_or_insert(color_space);
+        if *classified != color_space {
+            warn!(
+                "glTF texture {} holds both colors and data, sampling it as sRGB",
+                texture.index()
+            );
+            *classified = TextureColorSpace::Srgb;
+        }
+    };
+    for material in gltf.materials() {
+        let pbr = material.pbr_metallic_roughness();
+        if let Some(info) = pbr.base_color_texture() {
+            classify(info.texture(), TextureColorSpace::Srgb);
+        }
+        if let Some(info) = material.emissive_texture() {
+            classify(info.texture(), TextureColorSpace::Srgb);
+        }
+        if let Some(info) = pbr.metallic_roughness_texture() {
+            classify(info.texture(), TextureColorSpace::Linear);
+        }
+        if let Some(info) = material.normal_texture() {
+            classify(info.texture(), TextureColorSpace::Linear);
+        }
+        if let Some(info) = material.occlusion_texture() {
+            classify(info.texture(), TextureColorSpace::Linear);
+        }
+    }
+
+    for (&index, &color_space) in &settings.texture_color_spaces {
+        match color_spaces.get_mut(index) {
+            Some(classified) => *classified = Some(color_space),
+            None => warn!("Color space set for glTF texture {index}, which doesn't exist"),
+        }
+    }
+    color_spaces
+        .into_iter()
+        .map(|color_space| color_space.unwrap_or(TextureColorSpace::Srgb))
+        .collect()
+}
+
 async fn load_gltf<'a, 'b, 'c>(
     bytes: &'a [u8],
+    settings: &GltfLoaderSettings,
     load_context: &'b mut LoadContext<'c>,
 ) -> Result<Gltf, GltfError> {
     let gltf = gltf::Gltf::from_slice(bytes)?;
     let buffer_data = load_buffers(&gltf).await?;
+    let color_spaces = texture_color_spaces(&gltf, settings);
+    let mut stats = GltfStats {
+        nodes: gltf.nodes().len(),
+        meshes: gltf.meshes().len(),
+        materials: gltf.materials().len(),
+        textures: gltf.textures().len(),
+        extensions_used: gltf.extensions_used().map(String::from).collect(),
+        extensions_required: gltf.extensions_required().map(String::from).collect(),
+        ..default()
+    };
 
     IoTaskPool::get()
         .scope(|scope| {
             gltf.textures().for_each(|gltf_texture| {
                 let buffer_data = &buffer_data;
-                scope.spawn(async move { load_image(gltf_texture, buffer_data).await });
+                let color_space = color_spaces[gltf_texture.index()];
+                scope
+                    .spawn(async move { load_image(gltf_texture, color_space, buffer_data).await });
             });
         })
         .into_iter()
         .for_each(|result| match result {
             Ok((image, label)) => {
+                stats.texture_memory += image.data.len();
                 load_context.add_labeled_asset(label, image);
             }
             Err(err) => {
@@ -201,10 +296,40 @@ async fn load_gltf<'a, 'b, 'c>(
                     );
                     mesh.normals = Some(read_attributes(&accessor, &buffer_data));
                 }
+                if let Semantic::Extras(name) = &semantic {
+                    assert_eq!(
+                        accessor.data_type(),
+                        DataType::F32,
+                        "Only f32 custom attributes are supported"
+                    );
+                    let values: VertexAttributeValues = match accessor.dimensions() {
+                        Dimensions::Scalar => read_attributes::<f32, 1>(&accessor, &buffer_data)
+                            .into_iter()
+                            .map(|[value]| value)
+                            .collect::<Vec<_>>()
+                            .into(),
+                        Dimensions::Vec2 => {
+                            read_attributes::<f32, 2>(&accessor, &buffer_data).into()
+                        }
+                        Dimensions::Vec3 => {
+                            read_attributes::<f32, 3>(&accessor, &buffer_data).into()
+                        }
+                        Dimensions::Vec4 => {
+                            read_attributes::<f32, 4>(&accessor, &buffer_data).into()
+                        }
+                        dimensions => panic!("{dimensions:?} custom attributes aren't supported"),
+                    };
+                    // glTF prefixes application specific attributes with an underscore
+                    mesh.insert_custom_attribute(format!("_{name}"), values);
+                }
             }
 
-            // Read vertex indices
             let reader = primitive.reader(|buffer| Some(buffer_data[buffer.index()].as_slice()));
+            mesh.uvs = reader
+                .read_tex_coords(0)
+                .map(|uvs| uvs.into_f32().collect());
+
+            // Read vertex indices
             if let Some(indices) = reader.read_indices() {
                 mesh.indices = Some(match indices {
                     ReadIndices::U8(is) => is.map(|x| x as u32).collect(),
@@ -213,6 +338,12 @@ async fn load_gltf<'a, 'b, 'c>(
                 });
             };
 
+            stats.vertices += mesh.positions.len();
+            if mesh.primitive_topology == PrimitiveTopology::TriangleList {
+                let corners = mesh.indices.as_ref().map_or(mesh.positions.len(), Vec::len);
+                stats.triangles += corners / 3;
+            }
+
             let mesh_handle = load_context.add_labeled_asset(primitive_label.to_string(), mesh);
             primitives.push(GltfPrimitive {
                 index: primitive.index(),
@@ -300,6 +431,7 @@ async fn load_gltf<'a, 'b, 'c>(
         meshes,
         materials,
         nodes,
+        stats,
     })
 }
 
@@ -325,6 +457,7 @@ async fn load_buffers(gltf: &gltf::Gltf) -> Result<Vec<Vec<u8>>, GltfError> {
 
 async fn load_image<'a, 'b>(
     gltf_texture: gltf::Texture<'a>,
+    color_space: TextureColorSpace,
     buffer_data: &[Vec<u8>],
 ) -> Result<(Image, String), GltfError> {
     match gltf_texture.source().source() {
@@ -340,7 +473,13 @@ async fn load_image<'a, 'b>(
             reader.set_format(image_crate_format);
             reader.no_limits();
             match reader.decode() {
-                Ok(image) => Ok((Image::from_dynamic(image), String::from("asd"))),
+                Ok(image) => {
+                    let mut image = Image::from_dynamic(image);
+                    image.set_color_space(color_space);
+                    // the label materials look their textures up by
+                    let label = GltfAssetLabel::Texture(gltf_texture.index()).to_string();
+                    Ok((image, label))
+                }
                 Err(error) => Err(GltfError::ImageCrateError(error)),
             }
         }
@@ -351,11 +490,11 @@ async fn load_image<'a, 'b>(
 }
 
 #[allow(clippy::result_large_err)]
-fn get_primitive_topology(mode: Mode) -> Result<D3D12_PRIMITIVE_TOPOLOGY_TYPE, GltfError> {
+fn get_primitive_topology(mode: Mode) -> Result<PrimitiveTopology, GltfError> {
     match mode {
-        Mode::Points => Ok(D3D12_PRIMITIVE_TOPOLOGY_TYPE_POINT),
-        Mode::Lines => Ok(D3D12_PRIMITIVE_TOPOLOGY_TYPE_LINE),
-        Mode::Triangles => Ok(D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE),
+        Mode::Points => Ok(PrimitiveTopology::PointList),
+        Mode::Lines => Ok(PrimitiveTopology::LineList),
+        Mode::Triangles => Ok(PrimitiveTopology::TriangleList),
         mode => Err(GltfError::UnsupportedPrimitive { mode }),
     }
 }
@@ -375,7 +514,7 @@ where
     let end = start + view.length();
 
     let data = &buffer[start..end];
-    let stride = view.stride().unwrap_or(12); // Vec3: 3 * 4 bytes = 12 bytes
+    let stride = view.stride().unwrap_or(N * mem::size_of::<T>());
     let count = accessor.count();
 
     let mut attributes = Vec::with_capacity(count);
@@ -452,7 +591,13 @@ fn load_node(
                 let material_handle = material_label.map_or(Handle::default(), |label| {
                     load_context.get_label_handle::<Material>(label.to_string())
                 });
-                parent.spawn((mesh_handle, material_handle, GlobalTransform::default()));
+                parent.spawn((
+                    mesh_handle,
+                    material_handle,
+                    Transform::IDENTITY,
+                    GlobalTransform::default(),
+                    GltfWorldTransform(world_transform),
+                ));
             }
         }
 
@@ -1,4 +1,5 @@
 mod loader;
+mod transform_audit;
 mod tree_iterator;
 
 use bevy::{asset::AssetPath, prelude::*};
@@ -6,6 +7,10 @@ use bevy::{asset::AssetPath, prelude::*};
 use crate::core::{Material, Mesh};
 
 use self::loader::GltfLoader;
+use self::transform_audit::TransformAuditPlugin;
+
+pub use self::loader::GltfLoaderSettings;
+pub use self::transform_audit::{GltfWorldTransform, TransformAudit};
 
 pub struct GltfPlugin;
 
@@ -15,7 +20,8 @@ impl Plugin for GltfPlugin {
             .init_asset::<GltfNode>()
             .init_asset::<GltfPrimitive>()
             .init_asset::<GltfMesh>()
-            .preregister_asset_loader::<GltfLoader>(&["gltf", "glb"]);
+            .preregister_asset_loader::<GltfLoader>(&["gltf", "glb"])
+            .add_plugins(TransformAuditPlugin);
     }
 
     fn finish(&self, app: &mut App) {
@@ -30,6 +36,24 @@ pub struct Gltf {
     pub materials: Vec<Handle<Material>>,
     pub nodes: Vec<Handle<GltfNode>>,
     pub default_scene: Option<Handle<Scene>>,
+    pub stats: GltfStats,
+}
+
+/// Size of a loaded glTF, to warn about oversized assets before spawning them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GltfStats {
+    /// Triangles of every mesh, counted once however many nodes use the mesh.
+    pub triangles: usize,
+    pub vertices: usize,
+    /// Bytes of the decoded textures, without mips.
+    pub texture_memory: usize,
+    pub nodes: usize,
+    pub meshes: usize,
+    pub materials: usize,
+    pub textures: usize,
+    pub extensions_used: Vec<String>,
+    /// Extensions a loader has to support to load the file correctly.
+    pub extensions_required: Vec<String>,
 }
 
 #[derive(Asset, Debug, Clone, TypePath)]
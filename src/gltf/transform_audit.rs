@@ -0,0 +1,94 @@
+//! Checks that spawned glTF scenes end up where their node hierarchy places them.
+
+use bevy::{prelude::*, transform::TransformSystem, utils::HashSet};
+
+use crate::core::Mesh;
+
+/// Transform of the node a glTF primitive belongs to relative to the root of its scene, as the
+/// loader accumulates it along the node hierarchy.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq)]
+#[reflect(Component, Default)]
+pub struct GltfWorldTransform(pub Transform);
+
+/// Insert to compare the `GlobalTransform` of every glTF primitive each frame with its
+/// [`GltfWorldTransform`] placed by the entity that spawned the scene. Mismatches point at
+/// propagation bugs, like entities spawned without a `Transform` or in the wrong order.
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Resource, Default)]
+pub struct TransformAudit {
+    /// Largest difference of any element of the two matrices that still matches.
+    pub tolerance: f32,
+    /// Primitives that didn't match in the last frame.
+    pub mismatches: usize,
+}
+
+impl Default for TransformAudit {
+    fn default() -> Self {
+        Self {
+            tolerance: 1e-4,
+            mismatches: 0,
+        }
+    }
+}
+
+pub(super) struct TransformAuditPlugin;
+
+impl Plugin for TransformAuditPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<GltfWorldTransform>()
+            .register_type::<TransformAudit>()
+            .add_systems(
+                PostUpdate,
+                audit_transforms
+                    .after(TransformSystem::TransformPropagate)
+                    .run_if(resource_exists::<TransformAudit>),
+            );
+    }
+}
+
+fn audit_transforms(
+    primitives: Query<(Entity, &GlobalTransform, &GltfWorldTransform), With<Handle<Mesh>>>,
+    parents: Query<&Parent>,
+    scene_roots: Query<&GlobalTransform, With<Handle<Scene>>>,
+    names: Query<&Name>,
+    mut audit: ResMut<TransformAudit>,
+    // warned about already, until they match again
+    mut mismatched: Local<HashSet<Entity>>,
+) {
+    let mut mismatches = HashSet::new();
+    for (entity, global_transform, world_transform) in &primitives {
+        let root = parents
+            .iter_ancestors(entity)
+            .find_map(|ancestor| scene_roots.get(ancestor).ok())
+            .copied()
+            .unwrap_or_default();
+        let expected = root * world_transform.0;
+        if expected
+            .affine()
+            .abs_diff_eq(global_transform.affine(), audit.tolerance)
+        {
+            continue;
+        }
+
+        mismatches.insert(entity);
+        if !mismatched.contains(&entity) {
+            // the name is on the node, the primitive is its child
+            let name = parents
+                .get(entity)
+                .and_then(|parent| names.get(parent.get()))
+                .map_or("unnamed node", Name::as_str);
+            warn!(
+                "glTF primitive {entity} of {name} is at {:?}, its node hierarchy places it at \
+                 {:?}",
+                global_transform.translation(),
+                expected.translation()
+            );
+        }
+    }
+
+    let count = mismatches.len();
+    *mismatched = mismatches;
+    if audit.mismatches != count {
+        audit.mismatches = count;
+    }
+}
@@ -5,14 +5,54 @@ pub mod render;
 mod win_types;
 
 use bevy::prelude::*;
+use windows::Win32::Graphics::Dxgi::DXGI_SWAP_CHAIN_DESC1;
 
 use core::CorePlugin;
-use render::RenderPlugin;
+use render::{ConfigureSwapchain, GpuSettings, RenderPlugin};
 
-pub struct ArcaPlugin;
+pub struct ArcaPlugin {
+    /// Render placeholders in place of meshes and materials that are still loading, see
+    /// [`core::PlaceholderAssets`].
+    pub placeholders: bool,
+    /// Light scenes without lights with a three point rig, see [`core::PlaceholderLights`].
+    pub placeholder_lights: bool,
+    /// Changes the description of every swapchain before it is created or resized, see
+    /// [`render::ConfigureSwapchain`].
+    pub configure_swapchain: Option<fn(&mut DXGI_SWAP_CHAIN_DESC1)>,
+    /// Renders on WARP, the software rasterizer, even where a hardware adapter is available.
+    /// Without it WARP is only used when creating the hardware device fails.
+    pub force_warp: bool,
+}
+
+impl Default for ArcaPlugin {
+    fn default() -> Self {
+        Self {
+            placeholders: true,
+            placeholder_lights: true,
+            configure_swapchain: None,
+            force_warp: false,
+        }
+    }
+}
 
 impl Plugin for ArcaPlugin {
     fn build(&self, app: &mut App) {
-        app.add_plugins((CorePlugin, RenderPlugin));
+        if let Some(configure_swapchain) = self.configure_swapchain {
+            app.insert_resource(ConfigureSwapchain(configure_swapchain));
+        }
+        if self.force_warp {
+            let settings = app.world().get_resource::<GpuSettings>().copied();
+            app.insert_resource(GpuSettings {
+                force_warp: true,
+                ..settings.unwrap_or_default()
+            });
+        }
+        app.add_plugins((
+            CorePlugin {
+                placeholders: self.placeholders,
+                placeholder_lights: self.placeholder_lights,
+            },
+            RenderPlugin,
+        ));
     }
 }
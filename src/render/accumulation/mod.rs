@@ -0,0 +1,88 @@
+use bevy::prelude::*;
+
+use super::{
+    headless::HeadlessTarget, material_textures::MaterialTextures, offline::OfflineRender,
+    render_target::WindowRenderTarget, DebugView, LightData, MeshData, PathTracerSettings,
+    PrimitiveData, RenderSchedule, RenderSet, ResizeEvent, ScenePipeline, UploadQueue,
+};
+use crate::core::{Background, Camera};
+
+/// Restarts progressive accumulation of every window, the [`Headless`](super::Headless) target
+/// and the running offline render.
+///
+/// Sent automatically when the camera moves, lights, materials, meshes, primitives,
+/// [`PathTracerSettings`], the [`DebugView`] or the [`ScenePipeline`] change, while scene data is
+/// still being uploaded and when a window is resized. User systems can send it too, for example
+/// after changing something the renderer can't detect on its own.
+#[derive(Event, Debug, Clone, Copy, Default)]
+pub struct ResetAccumulation;
+
+pub struct AccumulationPlugin;
+
+impl Plugin for AccumulationPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<ResetAccumulation>().add_systems(
+            RenderSchedule,
+            (detect_accumulation_reset, reset_accumulation)
+                .chain()
+                .in_set(RenderSet::Prepare),
+        );
+    }
+}
+
+type ChangedCamera = Or<(
+    Changed<Camera>,
+    Changed<GlobalTransform>,
+    Changed<Background>,
+)>;
+
+#[allow(clippy::too_many_arguments)]
+fn detect_accumulation_reset(
+    cameras: Query<(), (With<Camera>, ChangedCamera)>,
+    mesh_data: Res<MeshData>,
+    light_data: Res<LightData>,
+    primitive_data: Res<PrimitiveData>,
+    material_textures: Res<MaterialTextures>,
+    settings: Res<PathTracerSettings>,
+    debug_view: Res<DebugView>,
+    scene_pipeline: Res<ScenePipeline>,
+    uploads: Res<UploadQueue>,
+    mut resize_events: EventReader<ResizeEvent>,
+    mut reset_events: EventWriter<ResetAccumulation>,
+) {
+    let resized = resize_events.read().count() > 0;
+    if resized
+        || !cameras.is_empty()
+        || mesh_data.updated()
+        || light_data.updated()
+        || primitive_data.updated()
+        || material_textures.updated()
+        || settings.is_changed()
+        || debug_view.is_changed()
+        || scene_pipeline.is_changed()
+        || !uploads.is_empty()
+    {
+        reset_events.send(ResetAccumulation);
+    }
+}
+
+fn reset_accumulation(
+    mut reset_events: EventReader<ResetAccumulation>,
+    mut render_targets: Query<&mut WindowRenderTarget>,
+    offline_render: Option<ResMut<OfflineRender>>,
+    headless: Option<ResMut<HeadlessTarget>>,
+) {
+    if reset_events.read().count() == 0 {
+        return;
+    }
+
+    for mut render_target in &mut render_targets {
+        render_target.reset_accumulation();
+    }
+    if let Some(mut offline_render) = offline_render {
+        offline_render.reset_accumulation();
+    }
+    if let Some(mut headless) = headless {
+        headless.reset_accumulation();
+    }
+}
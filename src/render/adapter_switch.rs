@@ -0,0 +1,124 @@
+use bevy::prelude::*;
+
+use super::{
+    capture::FrameCapture,
+    headless::HeadlessTarget,
+    leak_report::wait_for_idle,
+    material_textures::MaterialTextures,
+    offline::OfflineRender,
+    pipelines::{AutoExposurePipeline, GizmoPipeline, PipelineStorage, TonemapPipeline},
+    render_target::WindowRenderTarget,
+    Drawer, Gpu, GpuFeatures, GpuSettings, LightData, MeshData, PrimitiveData, RenderSchedule,
+    RenderSet, UploadQueue,
+};
+
+/// Recreates the renderer on WARP, the reference software rasterizer, or back on the hardware
+/// adapter, to tell driver specific artifacts from renderer bugs.
+///
+/// The GPU is flushed and every GPU object released at the end of the frame, the next frame
+/// builds them again from the assets. Accumulation and running offline renders start over.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct SwitchAdapter {
+    pub warp: bool,
+}
+
+impl SwitchAdapter {
+    /// Switches to the adapter type `gpu` doesn't run on.
+    pub fn other(gpu: &Gpu) -> Self {
+        Self { warp: !gpu.warp }
+    }
+}
+
+pub struct AdapterSwitchPlugin;
+
+impl Plugin for AdapterSwitchPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<SwitchAdapter>().add_systems(
+            RenderSchedule,
+            switch_adapter
+                .run_if(on_event::<SwitchAdapter>())
+                .after(RenderSet::Present),
+        );
+    }
+}
+
+fn switch_adapter(world: &mut World) {
+    let Some(&SwitchAdapter { warp }) = world
+        .resource::<Events<SwitchAdapter>>()
+        .iter_current_update_events()
+        .last()
+    else {
+        return;
+    };
+    if world.resource::<Gpu>().warp == warp {
+        return;
+    }
+    info!(
+        "Switching the renderer to the {} adapter",
+        adapter_name(warp)
+    );
+
+    wait_for_idle(world.resource::<Gpu>());
+    recreate_renderer(world, warp);
+}
+
+/// Releases every GPU object and creates a new device on the `warp` adapter, falling back to
+/// the other one. The old device has to be idle or removed.
+pub(super) fn recreate_renderer(world: &mut World, warp: bool) {
+    release_gpu_objects(world);
+    // enabling the debug layer for the new device removes devices that are still alive
+    world.remove_resource::<Gpu>();
+
+    let settings = *world.resource::<GpuSettings>();
+    let gpu = unsafe { Gpu::new(warp, settings) }
+        .or_else(|error| {
+            error!(
+                "Failed to create the {} device, staying on the {} one: {error}",
+                adapter_name(warp),
+                adapter_name(!warp)
+            );
+            unsafe { Gpu::new(!warp, settings) }
+        })
+        .expect("Failed to initialize renderer");
+
+    world.insert_resource(GpuFeatures::new(&gpu));
+    world.insert_resource(Drawer::new(&gpu));
+    world.insert_resource(MaterialTextures::new(&gpu));
+    world.insert_resource(gpu);
+    world.resource_mut::<MeshData>().reupload();
+    world.resource_mut::<LightData>().reupload();
+    world.resource_mut::<PrimitiveData>().reupload();
+}
+
+// pipelines and window render targets are created again by their systems once they're missing
+fn release_gpu_objects(world: &mut World) {
+    world.insert_resource(PipelineStorage::new());
+    world.remove_resource::<TonemapPipeline>();
+    world.remove_resource::<AutoExposurePipeline>();
+    world.remove_resource::<GizmoPipeline>();
+    world.remove_resource::<Drawer>();
+    world.remove_resource::<MaterialTextures>();
+    world.remove_resource::<HeadlessTarget>();
+    world.insert_resource(UploadQueue::default());
+    world.resource_mut::<FrameCapture>().release_readbacks();
+    if let Some(render) = world.remove_resource::<OfflineRender>() {
+        info!("Restarting the offline render on the new adapter");
+        world.send_event(render.into_request());
+    }
+
+    let render_targets: Vec<Entity> = world
+        .query_filtered::<Entity, With<WindowRenderTarget>>()
+        .iter(world)
+        .collect();
+    for entity in render_targets {
+        world.entity_mut(entity).remove::<WindowRenderTarget>();
+    }
+}
+
+fn adapter_name(warp: bool) -> &'static str {
+    if warp {
+        "WARP"
+    } else {
+        "hardware"
+    }
+}
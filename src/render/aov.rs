@@ -0,0 +1,232 @@
+//! Arbitrary output variables: extra render targets the scene pass writes next to the HDR
+//! target, like albedo, normals or object ids for denoisers and compositing.
+
+use bevy::prelude::*;
+use windows::Win32::Graphics::{
+    Direct3D12::*,
+    Dxgi::Common::{DXGI_FORMAT, DXGI_SAMPLE_DESC},
+};
+
+use super::{set_debug_name, Descriptors, Gpu};
+
+/// Most AOVs that can be registered.
+pub const MAX_AOVS: usize = 8;
+
+/// When an AOV is cleared, always before the scene pass.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AovClear {
+    /// Every frame, for values written anew each frame.
+    EveryFrame([f32; 4]),
+    /// When accumulation restarts, for values averaged over the frames like the HDR target.
+    Accumulation([f32; 4]),
+    /// Never, the pipeline writes every pixel itself.
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AovDesc {
+    pub format: DXGI_FORMAT,
+    pub clear: AovClear,
+}
+
+/// AOVs every window render target has, by name.
+///
+/// Registered AOVs are allocated in the size of the HDR target of every window and recreated
+/// with it. Every view imports them into its render graph, where the scene pass writes them:
+/// pipelines find them in [`super::SceneTarget::aov`]. [`super::DebugView::Aov`]
+/// shows one in place of the image and [`super::FrameCapture::request_aov`] reads one back.
+/// Offline renders and headless targets have none.
+#[derive(Resource, Debug, Clone, Default)]
+pub struct AovRegistry {
+    aovs: Vec<(&'static str, AovDesc)>,
+}
+
+impl AovRegistry {
+    /// Adds the AOV `name`, or changes it when it is registered already. The targets follow at
+    /// the end of the frame, restarting accumulation.
+    pub fn register(&mut self, name: &'static str, desc: AovDesc) {
+        match self
+            .aovs
+            .iter_mut()
+            .find(|(registered, _)| *registered == name)
+        {
+            Some((_, registered)) => *registered = desc,
+            None => {
+                assert!(
+                    self.aovs.len() < MAX_AOVS,
+                    "more than {MAX_AOVS} AOVs registered"
+                );
+                self.aovs.push((name, desc));
+            }
+        }
+    }
+
+    pub fn unregister(&mut self, name: &str) {
+        self.aovs.retain(|(registered, _)| *registered != name);
+    }
+
+    pub fn get(&self, name: &str) -> Option<AovDesc> {
+        self.iter()
+            .find(|(registered, _)| *registered == name)
+            .map(|(_, desc)| desc)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&'static str, AovDesc)> + '_ {
+        self.aovs.iter().copied()
+    }
+}
+
+/// AOV of a render target, resting in `ALL_SHADER_RESOURCE` outside of the scene pass.
+pub struct AovTarget {
+    name: &'static str,
+    desc: AovDesc,
+    texture: ID3D12Resource,
+    rtv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    // for showing it in place of the HDR target
+    srv: Descriptors,
+}
+
+impl AovTarget {
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn desc(&self) -> AovDesc {
+        self.desc
+    }
+
+    pub fn texture(&self) -> &ID3D12Resource {
+        &self.texture
+    }
+
+    pub fn rtv_handle(&self) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+        self.rtv_handle
+    }
+
+    pub(crate) fn srv(&self) -> &Descriptors {
+        &self.srv
+    }
+
+    /// Value the AOV is cleared to before the scene pass of the `frame_index`th frame of an
+    /// accumulation, if any.
+    pub(crate) fn clear_value(&self, frame_index: u32) -> Option<[f32; 4]> {
+        match self.desc.clear {
+            AovClear::EveryFrame(value) => Some(value),
+            AovClear::Accumulation(value) if frame_index == 0 => Some(value),
+            AovClear::Accumulation(_) | AovClear::Never => None,
+        }
+    }
+}
+
+impl std::fmt::Debug for AovTarget {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AovTarget")
+            .field("name", &self.name)
+            .field("desc", &self.desc)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The registered AOVs of one render target.
+pub(crate) struct AovTargets {
+    // MAX_AOVS RTVs, one per registry slot
+    rtvs: Descriptors,
+    targets: Vec<AovTarget>,
+    size: UVec2,
+}
+
+impl AovTargets {
+    pub(crate) fn new(gpu: &Gpu) -> Self {
+        Self {
+            rtvs: gpu.descriptors.rtv.allocate(MAX_AOVS),
+            targets: Vec::new(),
+            size: UVec2::ZERO,
+        }
+    }
+
+    pub(crate) fn targets(&self) -> &[AovTarget] {
+        &self.targets
+    }
+
+    /// Recreates the targets in `size` when it or the registered AOVs changed, the old ones are
+    /// retired for the frames in flight. Returns whether they were recreated.
+    pub(crate) fn update(&mut self, gpu: &Gpu, registry: &AovRegistry, size: UVec2) -> bool {
+        let unchanged = size == self.size
+            && self.targets.len() == registry.aovs.len()
+            && self
+                .targets
+                .iter()
+                .zip(registry.iter())
+                .all(|(target, (name, desc))| target.name == name && target.desc == desc);
+        if unchanged {
+            return false;
+        }
+
+        self.size = size;
+        let targets = registry
+            .iter()
+            .enumerate()
+            .map(|(slot, (name, desc))| {
+                create_aov(gpu, name, desc, size, self.rtvs.cpu_handle(slot))
+            })
+            .collect();
+        gpu.retired
+            .retire(std::mem::replace(&mut self.targets, targets));
+        true
+    }
+}
+
+fn create_aov(
+    gpu: &Gpu,
+    name: &'static str,
+    desc: AovDesc,
+    size: UVec2,
+    rtv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+) -> AovTarget {
+    let mut texture: Option<ID3D12Resource> = None;
+    unsafe {
+        gpu.device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_DEFAULT,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                Width: size.x as u64,
+                Height: size.y,
+                DepthOrArraySize: 1,
+                MipLevels: 1,
+                Format: desc.format,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+                Flags: D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+                ..Default::default()
+            },
+            D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+            None,
+            &mut texture,
+        )
+    }
+    .unwrap_or_else(|error| panic!("failed to create AOV {name}: {error}"));
+    let texture = texture.unwrap();
+    set_debug_name(&texture, &format!("AOV {name}"));
+
+    let srv = gpu.descriptors.cbv_srv_uav.allocate(1);
+    unsafe {
+        gpu.device
+            .CreateRenderTargetView(&texture, None, rtv_handle);
+        gpu.device
+            .CreateShaderResourceView(&texture, None, srv.cpu_handle(0));
+    }
+    AovTarget {
+        name,
+        desc,
+        texture,
+        rtv_handle,
+        srv,
+    }
+}
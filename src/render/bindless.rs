@@ -0,0 +1,179 @@
+//! Shader visible heap of the views shaders index by ID, instead of binding tables of them.
+
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use windows::Win32::Graphics::Direct3D12::{
+    ID3D12Device9, D3D12_CPU_DESCRIPTOR_HANDLE, D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
+    D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV, D3D12_DESCRIPTOR_RANGE,
+    D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+};
+
+use super::{descriptor_allocator::FreeList, render_target::FRAME_COUNT, DescriptorHeap};
+
+/// Size of the bindless heap.
+pub const BINDLESS_DESCRIPTORS: usize = 1 << 16;
+
+/// Register spaces of the bindless table, from space 1 on.
+pub const BINDLESS_SPACES: usize = 16;
+
+/// Lowest resource binding tier that can bind [`bindless_ranges`], tier 1 limits a stage to
+/// 128 SRVs. Creating the [`super::Gpu`] fails below it.
+pub const MIN_RESOURCE_BINDING_TIER: u32 = 2;
+
+/// The CBV/SRV/UAV heap the scene pipelines read the scene through.
+///
+/// Views get an ID, their index in the heap, which shaders find them by. The root signatures
+/// bind the whole heap as one table of [`bindless_ranges`], so any number of textures and
+/// buffers can be read without tables built per pipeline. Freed IDs are reused once the frames
+/// in flight when they were freed are finished. Clones share the heap.
+#[derive(Clone)]
+pub struct BindlessHeap(Arc<Bindless>);
+
+struct Bindless {
+    heap: DescriptorHeap,
+    slots: Mutex<BindlessSlots>,
+}
+
+impl BindlessHeap {
+    pub fn new(device: &ID3D12Device9) -> Self {
+        let heap = DescriptorHeap::with_device(
+            device,
+            D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+            BINDLESS_DESCRIPTORS,
+            D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
+        );
+        Self(Arc::new(Bindless {
+            heap,
+            slots: Mutex::new(BindlessSlots::new()),
+        }))
+    }
+
+    /// Allocates `count` consecutive IDs, for views read as one range.
+    pub fn allocate(&self, count: usize) -> BindlessDescriptors {
+        assert!(count > 0, "allocated no bindless descriptors");
+        let first = self
+            .0
+            .slots
+            .lock()
+            .unwrap()
+            .allocate(count)
+            .unwrap_or_else(|| {
+                panic!("bindless heap of {BINDLESS_DESCRIPTORS} descriptors is full")
+            });
+        BindlessDescriptors {
+            heap: self.clone(),
+            range: first..first + count,
+        }
+    }
+
+    /// Reuses the IDs freed the last time `frame_slot` was recorded. Called when a frame
+    /// begins, after waiting for the GPU to finish the frame of the same slot.
+    pub fn begin_frame(&self, frame_slot: usize) {
+        self.0.slots.lock().unwrap().begin_frame(frame_slot);
+    }
+
+    /// Set on the command list and bound as the table of [`bindless_ranges`].
+    pub fn heap(&self) -> &DescriptorHeap {
+        &self.0.heap
+    }
+}
+
+/// Consecutive IDs of the [`BindlessHeap`], freed when dropped.
+pub struct BindlessDescriptors {
+    heap: BindlessHeap,
+    range: Range<usize>,
+}
+
+impl BindlessDescriptors {
+    /// ID of the first descriptor, the others follow it.
+    pub fn first_id(&self) -> u32 {
+        self.range.start as u32
+    }
+
+    pub fn count(&self) -> usize {
+        self.range.len()
+    }
+
+    pub fn cpu_handle(&self, index: usize) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+        assert!(
+            index < self.count(),
+            "descriptor {index} is outside of {} bindless descriptors",
+            self.count()
+        );
+        self.heap.0.heap.cpu_handle_at(self.range.start + index)
+    }
+}
+
+impl Drop for BindlessDescriptors {
+    fn drop(&mut self) {
+        let mut slots = self.heap.0.slots.lock().unwrap();
+        slots.retire(self.range.clone());
+    }
+}
+
+/// Ranges of the descriptor table bound to the start of the [`BindlessHeap`]. Every register
+/// space from 1 to [`BINDLESS_SPACES`] views the whole heap as an unbounded SRV array, shaders
+/// declare one array per resource type they read and index it by ID.
+pub(crate) fn bindless_ranges() -> [D3D12_DESCRIPTOR_RANGE; BINDLESS_SPACES] {
+    std::array::from_fn(|space| D3D12_DESCRIPTOR_RANGE {
+        RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+        NumDescriptors: u32::MAX,
+        BaseShaderRegister: 0,
+        RegisterSpace: space as u32 + 1,
+        OffsetInDescriptorsFromTableStart: 0,
+    })
+}
+
+#[derive(Debug)]
+struct BindlessSlots {
+    free: FreeList,
+    // freed while the frame of each slot was recorded, the GPU may still read them
+    retired: [Vec<Range<usize>>; FRAME_COUNT],
+    frame_slot: usize,
+}
+
+impl BindlessSlots {
+    fn new() -> Self {
+        Self {
+            free: FreeList::new(BINDLESS_DESCRIPTORS),
+            retired: Default::default(),
+            frame_slot: 0,
+        }
+    }
+
+    fn allocate(&mut self, count: usize) -> Option<usize> {
+        self.free.allocate(count)
+    }
+
+    fn retire(&mut self, range: Range<usize>) {
+        self.retired[self.frame_slot].push(range);
+    }
+
+    fn begin_frame(&mut self, frame_slot: usize) {
+        for range in std::mem::take(&mut self.retired[frame_slot]) {
+            self.free.free(range);
+        }
+        self.frame_slot = frame_slot;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BindlessSlots;
+
+    #[test]
+    fn ids_are_reused_once_their_frame_slot_begins_again() {
+        let mut slots = BindlessSlots::new();
+        slots.begin_frame(0);
+        assert_eq!(slots.allocate(4), Some(0));
+        slots.retire(0..4);
+        slots.begin_frame(1);
+        // the frame of slot 0 may still read them
+        assert_eq!(slots.allocate(4), Some(4));
+        slots.begin_frame(0);
+        assert_eq!(slots.allocate(4), Some(0));
+    }
+}
@@ -0,0 +1,506 @@
+mod screenshot;
+mod sequence;
+
+use bevy::{prelude::*, utils::HashMap};
+use image::RgbaImage;
+use windows::Win32::Graphics::{
+    Direct3D12::*,
+    Dxgi::Common::{
+        DXGI_FORMAT, DXGI_FORMAT_R10G10B10A2_UNORM, DXGI_FORMAT_R16G16B16A16_FLOAT,
+        DXGI_FORMAT_R32G32B32A32_FLOAT, DXGI_FORMAT_UNKNOWN, DXGI_SAMPLE_DESC,
+    },
+};
+
+use super::{
+    d3d::{footprint_location, read_buffer, subresource_location, transition_barrier},
+    fence_timeout::FenceTimeout,
+    render_target::FRAME_COUNT,
+    set_debug_name, Drawer, Gpu,
+};
+
+pub use screenshot::{Screenshot, ScreenshotImage, ScreenshotPlugin, ScreenshotTaken};
+pub use sequence::{
+    FrameSequence, FrameSequenceCallback, FrameSequencePlugin, FrameSequenceSettings,
+};
+
+/// Tone mapped frame copied back from the GPU, sRGB encoded RGBA with 8 bits per channel
+/// whatever the format of the back buffer.
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u8>,
+}
+
+/// HDR target copied back from the GPU: the linear scene radiance before exposure and tone
+/// mapping, premultiplied RGBA floats at render resolution.
+#[derive(Debug, Clone)]
+pub struct CapturedHdrFrame {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<f32>,
+}
+
+/// AOV copied back from the GPU as stored, rows of `width` pixels in `format` without padding.
+#[derive(Debug, Clone)]
+pub struct CapturedAov {
+    pub width: u32,
+    pub height: u32,
+    pub format: DXGI_FORMAT,
+    pub data: Vec<u8>,
+}
+
+/// Copies the next presented frame back to the CPU.
+///
+/// Call [`FrameCapture::request`], the frame shows up in [`FrameCapture::take`] once the GPU
+/// finished it, at the end of the frame after the one it was drawn in.
+/// [`FrameCapture::request_hdr`] and [`FrameCapture::take_hdr`] do the same for the HDR target,
+/// [`FrameCapture::request_aov`] and [`FrameCapture::take_aov`] for the AOVs of the
+/// [`super::AovRegistry`].
+#[derive(Resource, Default)]
+pub struct FrameCapture {
+    back_buffer: CaptureSlot,
+    hdr: CaptureSlot,
+    aovs: HashMap<&'static str, CaptureSlot>,
+    captured: Option<CapturedFrame>,
+    captured_hdr: Option<CapturedHdrFrame>,
+    captured_aovs: HashMap<&'static str, CapturedAov>,
+    // whether the captured frames were read back this frame
+    fresh: bool,
+    fresh_hdr: bool,
+}
+
+impl FrameCapture {
+    pub fn request(&mut self) {
+        self.back_buffer.requested = true;
+    }
+
+    pub fn request_hdr(&mut self) {
+        self.hdr.requested = true;
+    }
+
+    /// Requests the AOV `name` of the next window drawn with it, it stays pending until then.
+    pub fn request_aov(&mut self, name: &'static str) {
+        self.aovs.entry(name).or_default().requested = true;
+    }
+
+    /// Whether a requested frame hasn't been read back yet.
+    pub fn is_pending(&self) -> bool {
+        self.back_buffer.is_pending()
+            || self.hdr.is_pending()
+            || self.aovs.values().any(CaptureSlot::is_pending)
+    }
+
+    pub fn take(&mut self) -> Option<CapturedFrame> {
+        self.captured.take()
+    }
+
+    pub fn take_hdr(&mut self) -> Option<CapturedHdrFrame> {
+        self.captured_hdr.take()
+    }
+
+    pub fn take_aov(&mut self, name: &str) -> Option<CapturedAov> {
+        self.captured_aovs.remove(name)
+    }
+
+    /// Frame read back at the end of this frame if it wasn't taken yet. Unlike
+    /// [`Self::take`] it leaves the frame to others that requested it.
+    pub fn latest(&self) -> Option<&CapturedFrame> {
+        self.captured.as_ref().filter(|_| self.fresh)
+    }
+
+    pub fn latest_hdr(&self) -> Option<&CapturedHdrFrame> {
+        self.captured_hdr.as_ref().filter(|_| self.fresh_hdr)
+    }
+
+    /// Drops the readback buffers, captures in flight are requested again.
+    pub(crate) fn release_readbacks(&mut self) {
+        self.back_buffer.release_readback();
+        self.hdr.release_readback();
+        self.aovs
+            .values_mut()
+            .for_each(CaptureSlot::release_readback);
+    }
+
+    /// Records the copy of the back buffer `texture` into the readback of `frame_slot` when a
+    /// capture is requested, `texture` must be in `state`.
+    pub(crate) fn record(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &ID3D12GraphicsCommandList,
+        texture: &ID3D12Resource,
+        state: D3D12_RESOURCE_STATES,
+        frame_slot: usize,
+    ) {
+        self.back_buffer
+            .record(gpu, command_list, texture, state, frame_slot);
+    }
+
+    /// Like [`Self::record`], for the HDR target.
+    pub(crate) fn record_hdr(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &ID3D12GraphicsCommandList,
+        texture: &ID3D12Resource,
+        state: D3D12_RESOURCE_STATES,
+        frame_slot: usize,
+    ) {
+        self.hdr
+            .record(gpu, command_list, texture, state, frame_slot);
+    }
+
+    /// Like [`Self::record`], for the AOV `name`.
+    pub(crate) fn record_aov(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &ID3D12GraphicsCommandList,
+        name: &str,
+        texture: &ID3D12Resource,
+        state: D3D12_RESOURCE_STATES,
+        frame_slot: usize,
+    ) {
+        if let Some(slot) = self.aovs.get_mut(name) {
+            slot.record(gpu, command_list, texture, state, frame_slot);
+        }
+    }
+
+    /// Whether copies were recorded in `frame_slot` and not read yet.
+    fn is_in_flight(&self, frame_slot: usize) -> bool {
+        self.back_buffer.in_flight[frame_slot]
+            || self.hdr.in_flight[frame_slot]
+            || self.aovs.values().any(|slot| slot.in_flight[frame_slot])
+    }
+}
+
+/// A kind of capture, with a readback per frame slot. A copy is read at the end of the frame
+/// after the one it was recorded in, before the slot is recorded again.
+#[derive(Default)]
+struct CaptureSlot {
+    requested: bool,
+    readbacks: [Option<TextureReadback>; FRAME_COUNT],
+    in_flight: [bool; FRAME_COUNT],
+}
+
+impl CaptureSlot {
+    fn is_pending(&self) -> bool {
+        self.requested || self.in_flight.contains(&true)
+    }
+
+    fn record(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &ID3D12GraphicsCommandList,
+        texture: &ID3D12Resource,
+        state: D3D12_RESOURCE_STATES,
+        frame_slot: usize,
+    ) {
+        if !self.requested {
+            return;
+        }
+        self.requested = false;
+
+        // the GPU finished the last copy of the slot before the frame began
+        let readback = match self.readbacks[frame_slot].take() {
+            Some(readback) if readback.fits(texture) => readback,
+            _ => TextureReadback::new(gpu, texture),
+        };
+        readback.record(command_list, texture, state);
+        self.readbacks[frame_slot] = Some(readback);
+        self.in_flight[frame_slot] = true;
+    }
+
+    fn release_readback(&mut self) {
+        self.requested |= self.in_flight.contains(&true);
+        self.in_flight = [false; FRAME_COUNT];
+        self.readbacks = Default::default();
+    }
+
+    /// Readback of the copy recorded in `frame_slot`, once.
+    fn take_finished(&mut self, frame_slot: usize) -> Option<&TextureReadback> {
+        if !std::mem::take(&mut self.in_flight[frame_slot]) {
+            return None;
+        }
+        let readback = self.readbacks[frame_slot]
+            .as_ref()
+            .expect("capture in flight without a readback buffer");
+        Some(readback)
+    }
+}
+
+/// Runs at the end of the frame, reads the copies recorded in the frame before it.
+pub fn read_frame_capture(
+    gpu: Res<Gpu>,
+    fence_timeout: Res<FenceTimeout>,
+    drawer: Res<Drawer>,
+    mut capture: ResMut<FrameCapture>,
+) {
+    capture.fresh = false;
+    capture.fresh_hdr = false;
+    let frame_slot = drawer.finish_previous_frame(&gpu, *fence_timeout);
+    if !capture.is_in_flight(frame_slot) {
+        return;
+    }
+    if let Some(frame) = capture
+        .back_buffer
+        .take_finished(frame_slot)
+        .map(TextureReadback::read)
+    {
+        capture.captured = Some(frame);
+        capture.fresh = true;
+    }
+    if let Some(readback) = capture.hdr.take_finished(frame_slot) {
+        let desc = readback.texture_desc;
+        let frame = CapturedHdrFrame {
+            width: desc.Width as u32,
+            height: desc.Height,
+            pixels: readback.read_float(),
+        };
+        capture.captured_hdr = Some(frame);
+        capture.fresh_hdr = true;
+    }
+    let capture = &mut *capture;
+    for (name, slot) in &mut capture.aovs {
+        if let Some(readback) = slot.take_finished(frame_slot) {
+            let desc = readback.texture_desc;
+            let aov = CapturedAov {
+                width: desc.Width as u32,
+                height: desc.Height,
+                format: desc.Format,
+                data: readback.read_bytes(),
+            };
+            capture.captured_aovs.insert(name, aov);
+        }
+    }
+}
+
+/// Divides the color of premultiplied pixels by their alpha, for formats like PNG that store
+/// straight alpha.
+pub(super) fn unpremultiply(image: &mut RgbaImage) {
+    for pixel in image.pixels_mut() {
+        let alpha = pixel[3] as u32;
+        if alpha > 0 && alpha < 255 {
+            for channel in &mut pixel.0[..3] {
+                *channel = (*channel as u32 * 255 / alpha).min(255) as u8;
+            }
+        }
+    }
+}
+
+/// Readback buffer a texture is copied into.
+pub(super) struct TextureReadback {
+    buffer: ID3D12Resource,
+    footprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT,
+    // bytes of the pixels of a row, without the padding up to the row pitch
+    row_size: usize,
+    size: usize,
+    texture_desc: D3D12_RESOURCE_DESC,
+}
+
+impl TextureReadback {
+    pub(super) fn new(gpu: &Gpu, texture: &ID3D12Resource) -> Self {
+        let texture_desc = unsafe { texture.GetDesc() };
+        let mut footprint = D3D12_PLACED_SUBRESOURCE_FOOTPRINT::default();
+        let mut row_size = 0;
+        let mut size = 0;
+        unsafe {
+            gpu.device.GetCopyableFootprints(
+                &texture_desc,
+                0,
+                1,
+                0,
+                Some(&mut footprint),
+                None,
+                Some(&mut row_size),
+                Some(&mut size),
+            )
+        };
+
+        let desc = D3D12_RESOURCE_DESC {
+            Alignment: 0,
+            Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+            Width: size,
+            Height: 1,
+            DepthOrArraySize: 1,
+            MipLevels: 1,
+            Format: DXGI_FORMAT_UNKNOWN,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                ..Default::default()
+            },
+            Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+            Flags: D3D12_RESOURCE_FLAG_NONE,
+        };
+        let mut buffer: Option<ID3D12Resource> = None;
+        unsafe {
+            gpu.device.CreateCommittedResource(
+                &D3D12_HEAP_PROPERTIES {
+                    Type: D3D12_HEAP_TYPE_READBACK,
+                    ..Default::default()
+                },
+                D3D12_HEAP_FLAG_NONE,
+                &desc,
+                D3D12_RESOURCE_STATE_COPY_DEST,
+                None,
+                &mut buffer,
+            )
+        }
+        .expect("Failed to create capture readback buffer");
+        let buffer = buffer.expect("CreateCommittedResource was successful but buffer is None");
+        set_debug_name(&buffer, "texture readback buffer");
+
+        Self {
+            buffer,
+            footprint,
+            row_size: row_size as usize,
+            size: size as usize,
+            texture_desc,
+        }
+    }
+
+    pub(super) fn fits(&self, texture: &ID3D12Resource) -> bool {
+        let desc = unsafe { texture.GetDesc() };
+        desc.Width == self.texture_desc.Width
+            && desc.Height == self.texture_desc.Height
+            && desc.Format == self.texture_desc.Format
+    }
+
+    pub(super) fn record(
+        &self,
+        command_list: &ID3D12GraphicsCommandList,
+        texture: &ID3D12Resource,
+        state: D3D12_RESOURCE_STATES,
+    ) {
+        let destination = footprint_location(&self.buffer, self.footprint);
+        let source = subresource_location(texture, 0);
+        unsafe {
+            command_list.ResourceBarrier(&[transition_barrier(
+                texture,
+                state,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+            )]);
+            command_list.CopyTextureRegion(&destination, 0, 0, 0, &source, None);
+            command_list.ResourceBarrier(&[transition_barrier(
+                texture,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+                state,
+            )]);
+        }
+    }
+
+    /// Copies the rows out of the readback buffer, dropping the row padding and converting the
+    /// pixels to 8 bit sRGB.
+    pub(super) fn read(&self) -> CapturedFrame {
+        let format = self.texture_desc.Format;
+        let mut pixels = Vec::new();
+        self.read_pixels(|pixel| pixels.extend_from_slice(&to_rgba8(format, pixel)));
+        CapturedFrame {
+            width: self.footprint.Footprint.Width,
+            height: self.footprint.Footprint.Height,
+            pixels,
+        }
+    }
+
+    /// Pixels of a `R16G16B16A16_FLOAT` or `R32G32B32A32_FLOAT` texture as RGBA floats.
+    pub(super) fn read_float(&self) -> Vec<f32> {
+        let mut pixels = Vec::new();
+        match self.texture_desc.Format {
+            DXGI_FORMAT_R16G16B16A16_FLOAT => self.read_pixels(|pixel| {
+                pixels.extend(
+                    pixel
+                        .chunks_exact(2)
+                        .map(|bits| f16_to_f32(u16::from_le_bytes([bits[0], bits[1]]))),
+                )
+            }),
+            DXGI_FORMAT_R32G32B32A32_FLOAT => self.read_pixels(|pixel| {
+                pixels.extend(
+                    pixel
+                        .chunks_exact(4)
+                        .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap())),
+                )
+            }),
+            format => panic!("{format:?} isn't a float format"),
+        }
+        pixels
+    }
+
+    /// The rows of the texture without their padding, in its format.
+    pub(super) fn read_bytes(&self) -> Vec<u8> {
+        let row_pitch = self.footprint.Footprint.RowPitch as usize;
+        let mut bytes = Vec::new();
+        read_buffer(&self.buffer, 0..self.size, |data| {
+            for row in data.chunks(row_pitch) {
+                bytes.extend_from_slice(&row[..self.row_size]);
+            }
+        });
+        bytes
+    }
+
+    /// Calls `f` with the bytes of every pixel, row by row, skipping the row padding.
+    fn read_pixels(&self, mut f: impl FnMut(&[u8])) {
+        let width = self.footprint.Footprint.Width as usize;
+        let row_pitch = self.footprint.Footprint.RowPitch as usize;
+        let pixel_size = match self.texture_desc.Format {
+            DXGI_FORMAT_R32G32B32A32_FLOAT => 16,
+            DXGI_FORMAT_R16G16B16A16_FLOAT => 8,
+            _ => 4,
+        };
+        read_buffer(&self.buffer, 0..self.size, |data| {
+            for row in data.chunks(row_pitch) {
+                row[..width * pixel_size]
+                    .chunks_exact(pixel_size)
+                    .for_each(&mut f);
+            }
+        });
+    }
+}
+
+fn to_rgba8(format: DXGI_FORMAT, pixel: &[u8]) -> [u8; 4] {
+    match format {
+        DXGI_FORMAT_R10G10B10A2_UNORM => {
+            let value = u32::from_le_bytes(pixel.try_into().unwrap());
+            let channel = |shift: u32| ((value >> shift) & 0x3ff) >> 2;
+            [
+                channel(0) as u8,
+                channel(10) as u8,
+                channel(20) as u8,
+                ((value >> 30) * 85) as u8,
+            ]
+        }
+        DXGI_FORMAT_R16G16B16A16_FLOAT => {
+            let channel = |index: usize| {
+                let bits = u16::from_le_bytes([pixel[index * 2], pixel[index * 2 + 1]]);
+                f16_to_f32(bits).clamp(0.0, 1.0)
+            };
+            let alpha = channel(3);
+            // scRGB is linear and premultiplied like the other formats, encode the color alone
+            let encode = |linear: f32| {
+                let color = linear / alpha.max(0.0001);
+                let srgb = if color <= 0.0031308 {
+                    color * 12.92
+                } else {
+                    1.055 * color.powf(1.0 / 2.4) - 0.055
+                };
+                (srgb * alpha * 255.0 + 0.5) as u8
+            };
+            [
+                encode(channel(0)),
+                encode(channel(1)),
+                encode(channel(2)),
+                (alpha * 255.0 + 0.5) as u8,
+            ]
+        }
+        _ => pixel.try_into().unwrap(),
+    }
+}
+
+fn f16_to_f32(bits: u16) -> f32 {
+    let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent = ((bits >> 10) & 0x1f) as i32;
+    let mantissa = (bits & 0x3ff) as f32;
+    match exponent {
+        0 => sign * mantissa * 2f32.powi(-24),
+        0x1f if mantissa == 0.0 => sign * f32::INFINITY,
+        0x1f => f32::NAN,
+        _ => sign * (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+    }
+}
@@ -0,0 +1,126 @@
+use std::path::{Path, PathBuf};
+
+use bevy::prelude::*;
+use image::{Rgba32FImage, RgbaImage};
+
+use super::{unpremultiply, CapturedFrame, CapturedHdrFrame, FrameCapture};
+use crate::render::{RenderSchedule, RenderSet};
+
+/// Captures the next presented frame. The pixels are sent with [`ScreenshotTaken`] and written
+/// to `path` if it is set.
+#[derive(Event, Debug, Clone, Default)]
+pub struct Screenshot {
+    /// Captures the HDR target instead of the tone mapped back buffer, see
+    /// [`CapturedHdrFrame`]. Write it to an `.exr`.
+    pub hdr: bool,
+    /// File the image is written to, its extension picks the format.
+    pub path: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone)]
+pub enum ScreenshotImage {
+    Tonemapped(CapturedFrame),
+    Hdr(CapturedHdrFrame),
+}
+
+/// Sent for every [`Screenshot`] once its frame is read back and written.
+#[derive(Event, Debug, Clone)]
+pub struct ScreenshotTaken {
+    pub image: ScreenshotImage,
+    pub path: Option<PathBuf>,
+}
+
+pub struct ScreenshotPlugin;
+
+impl Plugin for ScreenshotPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<Screenshot>()
+            .add_event::<ScreenshotTaken>()
+            .init_resource::<PendingScreenshots>()
+            .add_systems(Update, request_screenshots)
+            .add_systems(
+                RenderSchedule,
+                finish_screenshots
+                    .after(super::read_frame_capture)
+                    .in_set(RenderSet::Present),
+            );
+    }
+}
+
+// screenshots waiting for their frame, all requested in the same frame share one capture
+#[derive(Resource, Default)]
+pub(super) struct PendingScreenshots(Vec<Screenshot>);
+
+fn request_screenshots(
+    mut requests: EventReader<Screenshot>,
+    mut pending: ResMut<PendingScreenshots>,
+    mut capture: ResMut<FrameCapture>,
+) {
+    for request in requests.read() {
+        if request.hdr {
+            capture.request_hdr();
+        } else {
+            capture.request();
+        }
+        pending.0.push(request.clone());
+    }
+}
+
+pub(super) fn finish_screenshots(
+    mut pending: ResMut<PendingScreenshots>,
+    mut capture: ResMut<FrameCapture>,
+    mut taken_events: EventWriter<ScreenshotTaken>,
+) {
+    if pending.0.is_empty() {
+        return;
+    }
+    let wants = |hdr: bool| pending.0.iter().any(|request| request.hdr == hdr);
+    let tonemapped = wants(false).then(|| capture.take()).flatten();
+    let hdr = wants(true).then(|| capture.take_hdr()).flatten();
+    if tonemapped.is_none() && hdr.is_none() {
+        return;
+    }
+
+    let (finished, waiting) = pending.0.drain(..).partition(|request| {
+        if request.hdr {
+            hdr.is_some()
+        } else {
+            tonemapped.is_some()
+        }
+    });
+    pending.0 = waiting;
+    for request in finished {
+        let image = match (request.hdr, &tonemapped, &hdr) {
+            (false, Some(frame), _) => ScreenshotImage::Tonemapped(frame.clone()),
+            (true, _, Some(frame)) => ScreenshotImage::Hdr(frame.clone()),
+            _ => unreachable!(),
+        };
+        if let Some(path) = &request.path {
+            match save(&image, path) {
+                Ok(()) => info!("Screenshot written to {}", path.display()),
+                Err(error) => error!("failed to write screenshot to {}: {error}", path.display()),
+            }
+        }
+        taken_events.send(ScreenshotTaken {
+            image,
+            path: request.path,
+        });
+    }
+}
+
+pub(super) fn save(image: &ScreenshotImage, path: &Path) -> image::ImageResult<()> {
+    match image {
+        ScreenshotImage::Tonemapped(frame) => {
+            let mut image = RgbaImage::from_raw(frame.width, frame.height, frame.pixels.clone())
+                .expect("captured frame size doesn't match its pixels");
+            // the frame is premultiplied, most formats aren't
+            unpremultiply(&mut image);
+            image.save(path)
+        }
+        ScreenshotImage::Hdr(frame) => {
+            Rgba32FImage::from_raw(frame.width, frame.height, frame.pixels.clone())
+                .expect("captured frame size doesn't match its pixels")
+                .save(path)
+        }
+    }
+}
@@ -0,0 +1,150 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use super::{
+    screenshot::{finish_screenshots, save},
+    FrameCapture, ScreenshotImage,
+};
+use crate::render::{RenderSchedule, RenderSet};
+
+/// Called with the index of every recorded frame and its pixels.
+pub type FrameSequenceCallback = Box<dyn FnMut(u32, &ScreenshotImage) + Send + Sync>;
+
+pub struct FrameSequenceSettings {
+    /// Records one out of every `every_nth` frames, 1 records all of them.
+    pub every_nth: u32,
+    /// Records the HDR target instead of the tone mapped back buffer, write it as `exr`.
+    pub hdr: bool,
+    /// Directory the frames are written to as `frame_00000.<extension>`, nothing is written
+    /// without it.
+    pub directory: Option<PathBuf>,
+    pub extension: String,
+    pub on_frame: Option<FrameSequenceCallback>,
+}
+
+impl Default for FrameSequenceSettings {
+    fn default() -> Self {
+        Self {
+            every_nth: 1,
+            hdr: false,
+            directory: None,
+            extension: "png".to_string(),
+            on_frame: None,
+        }
+    }
+}
+
+/// Records presented frames to numbered images or a callback, for videos of the path tracer
+/// converging or of camera flythroughs.
+///
+/// Every recorded frame is read back and written before the next one starts, so recording
+/// slows the app down.
+#[derive(Resource, Default)]
+pub struct FrameSequence {
+    recording: Option<Recording>,
+}
+
+struct Recording {
+    settings: FrameSequenceSettings,
+    // frames since the recording started
+    frame: u64,
+    // frames recorded so far, the index of the next one
+    recorded: u32,
+    awaiting_capture: bool,
+}
+
+impl FrameSequence {
+    /// Starts recording from the next frame on, replacing the running recording.
+    pub fn start(&mut self, settings: FrameSequenceSettings) {
+        if let Some(directory) = &settings.directory {
+            if let Err(error) = std::fs::create_dir_all(directory) {
+                error!(
+                    "failed to create frame sequence directory {}: {error}",
+                    directory.display()
+                );
+            }
+        }
+        self.recording = Some(Recording {
+            settings,
+            frame: 0,
+            recorded: 0,
+            awaiting_capture: false,
+        });
+    }
+
+    /// Stops recording and returns how many frames were recorded.
+    pub fn stop(&mut self) -> u32 {
+        self.recording
+            .take()
+            .map_or(0, |recording| recording.recorded)
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+}
+
+pub struct FrameSequencePlugin;
+
+impl Plugin for FrameSequencePlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<FrameSequence>()
+            .add_systems(Update, request_sequence_frames)
+            .add_systems(
+                RenderSchedule,
+                record_sequence_frames
+                    .after(super::read_frame_capture)
+                    .before(finish_screenshots)
+                    .in_set(RenderSet::Present),
+            );
+    }
+}
+
+fn request_sequence_frames(mut sequence: ResMut<FrameSequence>, mut capture: ResMut<FrameCapture>) {
+    let Some(recording) = &mut sequence.recording else {
+        return;
+    };
+    if recording.frame % recording.settings.every_nth.max(1) as u64 == 0 {
+        if recording.settings.hdr {
+            capture.request_hdr();
+        } else {
+            capture.request();
+        }
+        recording.awaiting_capture = true;
+    }
+    recording.frame += 1;
+}
+
+// runs before the screenshots take the frames, so both can share a capture
+fn record_sequence_frames(mut sequence: ResMut<FrameSequence>, capture: Res<FrameCapture>) {
+    let Some(recording) = &mut sequence.recording else {
+        return;
+    };
+    if !recording.awaiting_capture {
+        return;
+    }
+    let image = if recording.settings.hdr {
+        capture.latest_hdr().cloned().map(ScreenshotImage::Hdr)
+    } else {
+        capture.latest().cloned().map(ScreenshotImage::Tonemapped)
+    };
+    // not drawn yet, an occluded window for example
+    let Some(image) = image else {
+        return;
+    };
+    recording.awaiting_capture = false;
+
+    let index = recording.recorded;
+    recording.recorded += 1;
+    let settings = &mut recording.settings;
+    if let Some(directory) = &settings.directory {
+        let path = directory.join(format!("frame_{index:05}.{}", settings.extension));
+        if let Err(error) = save(&image, &path) {
+            error!("failed to write frame to {}: {error}", path.display());
+        }
+    }
+    if let Some(on_frame) = &mut settings.on_frame {
+        on_frame(index, &image);
+    }
+}
@@ -0,0 +1,89 @@
+use std::sync::{
+    mpsc::{self, Receiver, Sender},
+    Mutex,
+};
+
+use bevy::prelude::*;
+use windows::Win32::Graphics::Direct3D12::{ID3D12GraphicsCommandList, ID3D12Resource};
+
+use super::{render_target::FRAME_COUNT, Gpu};
+
+/// What GPU work queued through [`GpuCommandQueue`] is recorded with.
+pub struct GpuCommandContext<'a> {
+    pub gpu: &'a Gpu,
+    /// Command list of the frame, open and without any pass recorded yet.
+    pub command_list: &'a ID3D12GraphicsCommandList,
+    keep_alive: &'a mut Vec<ID3D12Resource>,
+}
+
+impl GpuCommandContext<'_> {
+    /// Keeps `resource` alive until the GPU finished the frame, for upload buffers and other
+    /// sources nothing else holds on to.
+    pub fn keep_alive(&mut self, resource: &ID3D12Resource) {
+        self.keep_alive.push(resource.clone());
+    }
+}
+
+type GpuCommand = Box<dyn FnOnce(&mut GpuCommandContext) + Send>;
+
+/// Queues GPU work from anywhere, including systems outside of [`super::RenderSchedule`] and
+/// async tasks. Clone it to hand it to other threads.
+///
+/// Queued commands are recorded in order at the start of the next drawn frame, after the
+/// scene uploads and before any render pass. Resources they touch have to be left in the state
+/// they were found in.
+#[derive(Resource, Clone)]
+pub struct GpuCommandQueue {
+    sender: Sender<GpuCommand>,
+}
+
+impl GpuCommandQueue {
+    pub fn push(&self, command: impl FnOnce(&mut GpuCommandContext) + Send + 'static) {
+        self.sender
+            .send(Box::new(command))
+            .expect("GPU command receiver was dropped");
+    }
+}
+
+/// Receiving end of [`GpuCommandQueue`], drained by the drawer.
+#[derive(Resource)]
+pub struct GpuCommands {
+    receiver: Mutex<Receiver<GpuCommand>>,
+    // resources of the commands of every frame in flight, kept alive until the GPU finished them
+    in_flight: [Vec<ID3D12Resource>; FRAME_COUNT],
+}
+
+/// Creates the sending and receiving end of the queue.
+pub(super) fn gpu_command_queue() -> (GpuCommandQueue, GpuCommands) {
+    let (sender, receiver) = mpsc::channel();
+    (
+        GpuCommandQueue { sender },
+        GpuCommands {
+            receiver: Mutex::new(receiver),
+            in_flight: Default::default(),
+        },
+    )
+}
+
+impl GpuCommands {
+    /// Records every queued command. The frame last recorded in `frame_slot` must be finished
+    /// on the GPU.
+    pub(crate) fn record(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &ID3D12GraphicsCommandList,
+        frame_slot: usize,
+    ) {
+        let in_flight = &mut self.in_flight[frame_slot];
+        in_flight.clear();
+        let mut context = GpuCommandContext {
+            gpu,
+            command_list,
+            keep_alive: in_flight,
+        };
+        let receiver = self.receiver.get_mut().unwrap();
+        for command in receiver.try_iter() {
+            command(&mut context);
+        }
+    }
+}
@@ -0,0 +1,205 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use image::RgbaImage;
+
+use super::{
+    capture::{CapturedFrame, FrameCapture},
+    render_target::WindowRenderTarget,
+    PathTracerSettings, RenderSchedule, RenderSet, RenderSettings, Tonemapping,
+};
+
+/// Renders the scene once with each of two [`RenderSettings`] and writes both results into one
+/// PNG image.
+///
+/// Each render accumulates `frames` frames before it's captured. The settings in use before the
+/// comparison are restored afterwards and [`ComparisonFinished`] is sent. Keep the camera still
+/// while the comparison runs, a moving camera restarts accumulation.
+#[derive(Event, Debug, Clone)]
+pub struct CompareRenders {
+    pub first: RenderSettings,
+    pub second: RenderSettings,
+    pub frames: u32,
+    pub mode: ComparisonMode,
+    pub output: PathBuf,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ComparisonMode {
+    /// Both renders next to each other, the first one on the left.
+    #[default]
+    SideBySide,
+    /// Absolute per channel difference of the two renders.
+    Difference,
+}
+
+/// Sent when the image of a [`CompareRenders`] is written.
+#[derive(Event, Debug, Clone)]
+pub struct ComparisonFinished {
+    pub output: PathBuf,
+}
+
+pub struct ComparisonPlugin;
+
+impl Plugin for ComparisonPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<CompareRenders>()
+            .add_event::<ComparisonFinished>()
+            .add_systems(Update, start_comparison)
+            .add_systems(RenderSchedule, update_comparison.after(RenderSet::Present));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ComparisonStage {
+    RenderingFirst,
+    CapturingFirst,
+    RenderingSecond,
+    CapturingSecond,
+}
+
+#[derive(Resource)]
+struct ActiveComparison {
+    request: CompareRenders,
+    stage: ComparisonStage,
+    first: Option<CapturedFrame>,
+    restore: (PathTracerSettings, Tonemapping),
+}
+
+fn start_comparison(
+    mut commands: Commands,
+    mut requests: EventReader<CompareRenders>,
+    active: Option<Res<ActiveComparison>>,
+    mut path_tracer_settings: ResMut<PathTracerSettings>,
+    mut tonemapping: ResMut<Tonemapping>,
+) {
+    let Some(request) = requests.read().last() else {
+        return;
+    };
+    if active.is_some() {
+        warn!("A render comparison is already running, ignoring the new one");
+        return;
+    }
+
+    let restore = (*path_tracer_settings, *tonemapping);
+    apply_settings(&request.first, &mut path_tracer_settings, &mut tonemapping);
+    commands.insert_resource(ActiveComparison {
+        request: request.clone(),
+        stage: ComparisonStage::RenderingFirst,
+        first: None,
+        restore,
+    });
+}
+
+fn apply_settings(
+    settings: &RenderSettings,
+    path_tracer_settings: &mut PathTracerSettings,
+    tonemapping: &mut Tonemapping,
+) {
+    *path_tracer_settings = settings.path_tracer;
+    *tonemapping = settings.tonemapping;
+}
+
+/// Runs after the frame is drawn, so `accumulated_frames` includes it.
+fn update_comparison(
+    mut commands: Commands,
+    active: Option<ResMut<ActiveComparison>>,
+    render_targets: Query<&WindowRenderTarget>,
+    mut capture: ResMut<FrameCapture>,
+    mut path_tracer_settings: ResMut<PathTracerSettings>,
+    mut tonemapping: ResMut<Tonemapping>,
+    mut finished_events: EventWriter<ComparisonFinished>,
+) {
+    let Some(mut active) = active else {
+        return;
+    };
+    let Some(render_target) = render_targets.iter().next() else {
+        return;
+    };
+
+    match active.stage {
+        ComparisonStage::RenderingFirst | ComparisonStage::RenderingSecond => {
+            if render_target.accumulated_frames() >= active.request.frames {
+                capture.request();
+                active.stage = if active.stage == ComparisonStage::RenderingFirst {
+                    ComparisonStage::CapturingFirst
+                } else {
+                    ComparisonStage::CapturingSecond
+                };
+            }
+        }
+        ComparisonStage::CapturingFirst => {
+            if let Some(frame) = capture.take() {
+                active.first = Some(frame);
+                apply_settings(
+                    &active.request.second,
+                    &mut path_tracer_settings,
+                    &mut tonemapping,
+                );
+                active.stage = ComparisonStage::RenderingSecond;
+            }
+        }
+        ComparisonStage::CapturingSecond => {
+            let Some(second) = capture.take() else {
+                return;
+            };
+            let first = active.first.take().expect("first render wasn't captured");
+            (*path_tracer_settings, *tonemapping) = active.restore;
+            commands.remove_resource::<ActiveComparison>();
+
+            let Some(image) = compose(&first, &second, active.request.mode) else {
+                error!("Render comparison failed, the window was resized while it was running");
+                return;
+            };
+            image
+                .save(&active.request.output)
+                .expect("failed to write render comparison");
+            info!(
+                "Render comparison written to {}",
+                active.request.output.display()
+            );
+            finished_events.send(ComparisonFinished {
+                output: active.request.output.clone(),
+            });
+        }
+    }
+}
+
+fn compose(
+    first: &CapturedFrame,
+    second: &CapturedFrame,
+    mode: ComparisonMode,
+) -> Option<RgbaImage> {
+    if first.width != second.width || first.height != second.height {
+        return None;
+    }
+    let width = first.width;
+    let pixel = |frame: &CapturedFrame, x: u32, y: u32| {
+        let index = ((y * frame.width + x) * 4) as usize;
+        let mut pixel: [u8; 4] = frame.pixels[index..index + 4].try_into().unwrap();
+        // the swapchain alpha isn't meaningful
+        pixel[3] = 255;
+        image::Rgba(pixel)
+    };
+
+    let image = match mode {
+        ComparisonMode::SideBySide => RgbaImage::from_fn(width * 2, first.height, |x, y| {
+            if x < width {
+                pixel(first, x, y)
+            } else {
+                pixel(second, x - width, y)
+            }
+        }),
+        ComparisonMode::Difference => RgbaImage::from_fn(width, first.height, |x, y| {
+            let a = pixel(first, x, y).0;
+            let b = pixel(second, x, y).0;
+            image::Rgba([
+                a[0].abs_diff(b[0]),
+                a[1].abs_diff(b[1]),
+                a[2].abs_diff(b[2]),
+                255,
+            ])
+        }),
+    };
+    Some(image)
+}
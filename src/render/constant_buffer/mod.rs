@@ -1,25 +1,36 @@
-use std::ptr;
+use std::sync::{Arc, Mutex};
 
 use windows::Win32::Graphics::{
     Direct3D12::*,
     Dxgi::Common::{DXGI_FORMAT_UNKNOWN, DXGI_SAMPLE_DESC},
 };
 
-use super::Gpu;
+use super::{d3d::write_buffer, descriptor_ring::RingPositions, set_debug_name, Gpu};
 
-pub struct ConstantBuffer<T> {
-    pub buffer: ID3D12Resource,
-    _type: std::marker::PhantomData<T>,
+/// Size of the [`ConstantRing`] in bytes, shared by all frames in flight.
+pub const CONSTANT_RING_SIZE: usize = 4 * 1024 * 1024;
+
+const BLOCK_SIZE: usize = D3D12_CONSTANT_BUFFER_DATA_PLACEMENT_ALIGNMENT as usize;
+
+/// Upload heap every [`ConstantBuffer`] writes to, in aligned blocks.
+///
+/// Like the [`super::DescriptorRing`], every frame takes the blocks after the ones of the frame
+/// before, and they are given back once the frame slot they were written in begins again.
+/// Clones share the ring.
+#[derive(Clone)]
+pub struct ConstantRing(Arc<Ring>);
+
+struct Ring {
+    buffer: ID3D12Resource,
+    positions: Mutex<RingPositions>,
 }
 
-impl<T> ConstantBuffer<T> {
-    pub fn create(gpu: &Gpu) -> Self {
-        let size_of = std::mem::size_of::<T>();
-        let constant_buffer_size = size_of as u64;
-        let constant_buffer_desc = D3D12_RESOURCE_DESC {
+impl ConstantRing {
+    pub fn new(device: &ID3D12Device9) -> Self {
+        let desc = D3D12_RESOURCE_DESC {
             Alignment: 0,
             Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
-            Width: constant_buffer_size,
+            Width: CONSTANT_RING_SIZE as u64,
             Height: 1,
             DepthOrArraySize: 1,
             MipLevels: 1,
@@ -31,9 +42,6 @@ impl<T> ConstantBuffer<T> {
             Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
             Flags: D3D12_RESOURCE_FLAG_NONE,
         };
-
-        let mut constant_buffer: Option<ID3D12Resource> = None;
-
         let heap_properties = D3D12_HEAP_PROPERTIES {
             Type: D3D12_HEAP_TYPE_UPLOAD,
             CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
@@ -42,41 +50,138 @@ impl<T> ConstantBuffer<T> {
             VisibleNodeMask: 1,
         };
 
+        let mut buffer: Option<ID3D12Resource> = None;
         unsafe {
-            gpu.device
+            device
                 .CreateCommittedResource(
                     &heap_properties,
                     D3D12_HEAP_FLAG_NONE,
-                    &constant_buffer_desc,
+                    &desc,
                     D3D12_RESOURCE_STATE_GENERIC_READ,
                     None,
-                    &mut constant_buffer,
+                    &mut buffer,
                 )
-                .expect("Failed to create constant buffer");
+                .expect("Failed to create constant ring");
         }
+        let buffer = buffer.expect("Failed to create constant ring");
+        set_debug_name(&buffer, "constant ring");
+        Self(Arc::new(Ring {
+            buffer,
+            positions: Mutex::new(RingPositions::new(CONSTANT_RING_SIZE / BLOCK_SIZE)),
+        }))
+    }
+
+    /// Gives back the blocks written the last time `frame_slot` was recorded. Called when a
+    /// frame begins, after waiting for the GPU to finish the frame of the same slot.
+    pub fn begin_frame(&self, frame_slot: usize) {
+        self.0.positions.lock().unwrap().begin_frame(frame_slot);
+    }
+
+    /// Writes `data` to the next free blocks and returns their GPU address.
+    fn write<T>(&self, data: &T) -> u64 {
+        let blocks = std::mem::size_of::<T>().div_ceil(BLOCK_SIZE).max(1);
+        let first = self
+            .0
+            .positions
+            .lock()
+            .unwrap()
+            .allocate(blocks)
+            .unwrap_or_else(|| {
+                panic!(
+                    "constant ring of {CONSTANT_RING_SIZE} bytes is full, the frames in flight \
+                     wrote too many constants"
+                )
+            });
+        let offset = first * BLOCK_SIZE;
+        write_buffer(&self.0.buffer, offset, std::slice::from_ref(data));
+        unsafe { self.0.buffer.GetGPUVirtualAddress() + offset as u64 }
+    }
+}
+
+/// Constants of type `T` bound as a root CBV, written to the [`ConstantRing`] of the [`Gpu`].
+///
+/// Every [`ConstantBuffer::write`] takes new blocks of the frame being recorded, so data the GPU
+/// may still be reading is never overwritten, even when a frame writes once per view. Write
+/// at least once per frame before binding and bind [`ConstantBuffer::gpu_adress`] after
+/// writing.
+pub struct ConstantBuffer<T> {
+    ring: ConstantRing,
+    gpu_address: u64,
+    _type: std::marker::PhantomData<T>,
+}
+
+impl<T> ConstantBuffer<T> {
+    pub fn create(gpu: &Gpu) -> Self {
         Self {
-            buffer: constant_buffer.expect("Failed to create constant buffer"),
+            ring: gpu.constant_ring.clone(),
+            gpu_address: 0,
             _type: std::marker::PhantomData,
         }
     }
 
     pub fn write(&mut self, data: &T) {
-        let mut data_begin: *mut std::ffi::c_void = ptr::null_mut();
-        unsafe {
-            self.buffer
-                .Map(0, None, Some(&mut data_begin))
-                .expect("Failed to map constant buffer");
-
-            ptr::copy_nonoverlapping(
-                data as *const _ as *const u8,
-                data_begin as *mut u8,
-                std::mem::size_of::<T>(),
-            );
-            self.buffer.Unmap(0, None);
-        }
+        self.gpu_address = self.ring.write(data);
     }
 
+    /// Address of the most recent write.
     pub fn gpu_adress(&self) -> u64 {
-        unsafe { self.buffer.GetGPUVirtualAddress() }
+        self.gpu_address
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{
+        render_target::FRAME_COUNT,
+        test_utils::{as_bytes, read_back, warp_gpu},
+    };
+
+    #[repr(C)]
+    #[derive(Clone, Copy)]
+    struct Data {
+        vector: [f32; 3],
+        index: u32,
+        // not a multiple of 16 bytes, so the slot padding is exercised too
+        scalars: [f32; 5],
+    }
+
+    #[test]
+    fn writes_of_one_frame_dont_overwrite_each_other() {
+        let gpu = warp_gpu();
+        gpu.constant_ring.begin_frame(0);
+        let mut buffer = ConstantBuffer::<Data>::create(&gpu);
+        // one write per view, more views than frames in flight
+        let views: Vec<Data> = (0..FRAME_COUNT + 2)
+            .map(|view| Data {
+                vector: [view as f32, 1.0, 2.0],
+                index: view as u32,
+                scalars: [view as f32 * 0.5; 5],
+            })
+            .collect();
+
+        let mut addresses = Vec::new();
+        for data in &views {
+            buffer.write(data);
+            addresses.push(buffer.gpu_adress());
+        }
+
+        let ring = &gpu.constant_ring.0.buffer;
+        let base_address = unsafe { ring.GetGPUVirtualAddress() };
+        let end = (addresses.iter().max().unwrap() - base_address) as usize + BLOCK_SIZE;
+        let bytes = read_back(&gpu, ring, end);
+        for (data, address) in views.iter().zip(&addresses) {
+            assert_eq!(
+                address % D3D12_CONSTANT_BUFFER_DATA_PLACEMENT_ALIGNMENT as u64,
+                0
+            );
+            assert_eq!(
+                addresses.iter().filter(|other| *other == address).count(),
+                1
+            );
+            let offset = (address - base_address) as usize;
+            let expected = unsafe { as_bytes(std::slice::from_ref(data)) };
+            assert_eq!(&bytes[offset..offset + expected.len()], expected);
+        }
     }
 }
@@ -0,0 +1,201 @@
+//! Safe wrappers around the Direct3D 12 interop that needs more than calling a COM method:
+//! interface pointers borrowed into descriptor structs, mapped memory and shader blobs. The rest
+//! of the renderer goes through these instead of repeating the unsafe code, so this file is
+//! where it has to be audited.
+//!
+//! Bounds of mapped memory are always checked, misuse that is only wasteful, like redundant
+//! barriers, is caught by debug assertions.
+
+use std::{mem::ManuallyDrop, ops::Range};
+
+use windows::{
+    core::Interface,
+    Win32::Graphics::{Direct3D::ID3DBlob, Direct3D12::*},
+};
+
+/// Borrows `object` into a descriptor struct without touching its reference count. The
+/// descriptor must not be used after `object` is dropped.
+pub(crate) fn borrow_interface<T: Interface>(object: &T) -> ManuallyDrop<Option<T>> {
+    // SAFETY: `Option<T>` of an interface has the layout of a non-null pointer, like `T` itself.
+    // `ManuallyDrop` keeps the copy from releasing the reference it doesn't own.
+    unsafe { std::mem::transmute_copy(object) }
+}
+
+pub(crate) fn transition_barrier(
+    resource: &ID3D12Resource,
+    state_before: D3D12_RESOURCE_STATES,
+    state_after: D3D12_RESOURCE_STATES,
+) -> D3D12_RESOURCE_BARRIER {
+    debug_assert_ne!(
+        state_before, state_after,
+        "transition barrier without a state change"
+    );
+    D3D12_RESOURCE_BARRIER {
+        Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
+        Flags: D3D12_RESOURCE_BARRIER_FLAG_NONE,
+        Anonymous: D3D12_RESOURCE_BARRIER_0 {
+            Transition: ManuallyDrop::new(D3D12_RESOURCE_TRANSITION_BARRIER {
+                pResource: borrow_interface(resource),
+                StateBefore: state_before,
+                StateAfter: state_after,
+                Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
+            }),
+        },
+    }
+}
+
+/// UAV barrier covering every resource.
+pub(crate) fn global_uav_barrier() -> D3D12_RESOURCE_BARRIER {
+    D3D12_RESOURCE_BARRIER {
+        Type: D3D12_RESOURCE_BARRIER_TYPE_UAV,
+        Flags: D3D12_RESOURCE_BARRIER_FLAG_NONE,
+        Anonymous: D3D12_RESOURCE_BARRIER_0 {
+            UAV: ManuallyDrop::new(D3D12_RESOURCE_UAV_BARRIER {
+                pResource: ManuallyDrop::new(None),
+            }),
+        },
+    }
+}
+
+pub(crate) fn uav_barrier(resource: &ID3D12Resource) -> D3D12_RESOURCE_BARRIER {
+    D3D12_RESOURCE_BARRIER {
+        Type: D3D12_RESOURCE_BARRIER_TYPE_UAV,
+        Flags: D3D12_RESOURCE_BARRIER_FLAG_NONE,
+        Anonymous: D3D12_RESOURCE_BARRIER_0 {
+            UAV: ManuallyDrop::new(D3D12_RESOURCE_UAV_BARRIER {
+                pResource: borrow_interface(resource),
+            }),
+        },
+    }
+}
+
+/// Copy location of a subresource of a texture.
+pub(crate) fn subresource_location(
+    texture: &ID3D12Resource,
+    subresource: u32,
+) -> D3D12_TEXTURE_COPY_LOCATION {
+    D3D12_TEXTURE_COPY_LOCATION {
+        pResource: borrow_interface(texture),
+        Type: D3D12_TEXTURE_COPY_TYPE_SUBRESOURCE_INDEX,
+        Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
+            SubresourceIndex: subresource,
+        },
+    }
+}
+
+/// Copy location of texture data laid out in a buffer.
+pub(crate) fn footprint_location(
+    buffer: &ID3D12Resource,
+    footprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT,
+) -> D3D12_TEXTURE_COPY_LOCATION {
+    D3D12_TEXTURE_COPY_LOCATION {
+        pResource: borrow_interface(buffer),
+        Type: D3D12_TEXTURE_COPY_TYPE_PLACED_FOOTPRINT,
+        Anonymous: D3D12_TEXTURE_COPY_LOCATION_0 {
+            PlacedFootprint: footprint,
+        },
+    }
+}
+
+fn buffer_size(buffer: &ID3D12Resource) -> usize {
+    let desc = unsafe { buffer.GetDesc() };
+    debug_assert_eq!(desc.Dimension, D3D12_RESOURCE_DIMENSION_BUFFER);
+    desc.Width as usize
+}
+
+/// Copies `data` into an upload heap `buffer`, starting `offset` bytes in. Panics if it doesn't
+/// fit.
+pub(crate) fn write_buffer<T>(buffer: &ID3D12Resource, offset: usize, data: &[T]) {
+    let written = offset..offset + std::mem::size_of_val(data);
+    assert!(
+        written.end <= buffer_size(buffer),
+        "writing bytes {written:?} of a {} byte buffer",
+        buffer_size(buffer)
+    );
+    unsafe {
+        let mut memory = std::ptr::null_mut();
+        buffer
+            .Map(0, Some(&D3D12_RANGE::default()), Some(&mut memory))
+            .expect("failed to map buffer");
+        // SAFETY: the destination was checked to be inside of the buffer, and mapped memory
+        // never overlaps with `data`. The copy is untyped, so padding bytes are fine.
+        std::ptr::copy_nonoverlapping(
+            data.as_ptr() as *const u8,
+            (memory as *mut u8).add(written.start),
+            written.len(),
+        );
+        buffer.Unmap(
+            0,
+            Some(&D3D12_RANGE {
+                Begin: written.start,
+                End: written.end,
+            }),
+        );
+    }
+}
+
+/// Passes the whole mapped memory of an upload heap `buffer` to `f`, for writes that don't
+/// come from a single slice. The memory is write-combined, reading it back is slow.
+pub(crate) fn write_mapped(buffer: &ID3D12Resource, f: impl FnOnce(&mut [u8])) {
+    let size = buffer_size(buffer);
+    unsafe {
+        let mut memory = std::ptr::null_mut();
+        buffer
+            .Map(0, Some(&D3D12_RANGE::default()), Some(&mut memory))
+            .expect("failed to map buffer");
+        // SAFETY: the mapping covers the whole buffer and stays valid until `Unmap`, the upload
+        // heap is initialized memory
+        f(std::slice::from_raw_parts_mut(memory as *mut u8, size));
+        buffer.Unmap(0, None);
+    }
+}
+
+/// Passes the bytes `range` of a readback heap `buffer` to `f`. Panics if the range isn't
+/// inside of the buffer.
+pub(crate) fn read_buffer<R>(
+    buffer: &ID3D12Resource,
+    range: Range<usize>,
+    f: impl FnOnce(&[u8]) -> R,
+) -> R {
+    assert!(
+        range.start <= range.end && range.end <= buffer_size(buffer),
+        "reading bytes {range:?} of a {} byte buffer",
+        buffer_size(buffer)
+    );
+    unsafe {
+        let mut memory = std::ptr::null_mut();
+        buffer
+            .Map(
+                0,
+                Some(&D3D12_RANGE {
+                    Begin: range.start,
+                    End: range.end,
+                }),
+                Some(&mut memory),
+            )
+            .expect("failed to map readback buffer");
+        // SAFETY: the range was checked to be inside of the buffer, which stays mapped until
+        // `f` returns
+        let bytes = std::slice::from_raw_parts((memory as *const u8).add(range.start), range.len());
+        let result = f(bytes);
+        buffer.Unmap(0, Some(&D3D12_RANGE::default()));
+        result
+    }
+}
+
+/// Contents of a blob, like compiled shaders, serialized root signatures and error messages.
+pub(crate) fn blob_bytes(blob: &ID3DBlob) -> &[u8] {
+    // SAFETY: the blob owns the memory and lives at least as long as the returned slice
+    unsafe {
+        std::slice::from_raw_parts(blob.GetBufferPointer() as *const u8, blob.GetBufferSize())
+    }
+}
+
+/// Bytecode of a compiled shader, valid as long as `blob` lives.
+pub(crate) fn shader_bytecode(blob: &ID3DBlob) -> D3D12_SHADER_BYTECODE {
+    let bytes = blob_bytes(blob);
+    D3D12_SHADER_BYTECODE {
+        pShaderBytecode: bytes.as_ptr() as *const _,
+        BytecodeLength: bytes.len(),
+    }
+}
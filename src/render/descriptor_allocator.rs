@@ -0,0 +1,208 @@
+//! Shader invisible descriptors, allocated and freed as the objects they view come and go.
+
+use std::{
+    ops::Range,
+    sync::{Arc, Mutex},
+};
+
+use windows::Win32::Graphics::Direct3D12::{
+    ID3D12Device9, D3D12_CPU_DESCRIPTOR_HANDLE, D3D12_DESCRIPTOR_HEAP_FLAG_NONE,
+    D3D12_DESCRIPTOR_HEAP_TYPE, D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+    D3D12_DESCRIPTOR_HEAP_TYPE_DSV, D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
+};
+
+use super::DescriptorHeap;
+
+/// Size of the heaps an allocator adds, larger allocations get a heap of their own size.
+pub const DESCRIPTORS_PER_HEAP: usize = 256;
+
+/// The allocators of the [`super::Gpu`], one per type of shader invisible descriptor.
+pub struct DescriptorAllocators {
+    pub rtv: DescriptorAllocator,
+    pub dsv: DescriptorAllocator,
+    /// Views copied into shader visible heaps, like the SRVs of the HDR targets.
+    pub cbv_srv_uav: DescriptorAllocator,
+}
+
+impl DescriptorAllocators {
+    pub fn new(device: &ID3D12Device9) -> Self {
+        Self {
+            rtv: DescriptorAllocator::new(device, D3D12_DESCRIPTOR_HEAP_TYPE_RTV),
+            dsv: DescriptorAllocator::new(device, D3D12_DESCRIPTOR_HEAP_TYPE_DSV),
+            cbv_srv_uav: DescriptorAllocator::new(device, D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV),
+        }
+    }
+}
+
+/// Allocates ranges of shader invisible descriptors of one type. Freed ranges are reused and
+/// another heap is added once no free range is large enough, so handles stay where they are
+/// until their [`Descriptors`] are dropped. Clones allocate from the same heaps.
+#[derive(Clone)]
+pub struct DescriptorAllocator(Arc<Mutex<Heaps>>);
+
+struct Heaps {
+    device: ID3D12Device9,
+    heap_type: D3D12_DESCRIPTOR_HEAP_TYPE,
+    heaps: Vec<(DescriptorHeap, FreeList)>,
+}
+
+impl DescriptorAllocator {
+    pub fn new(device: &ID3D12Device9, heap_type: D3D12_DESCRIPTOR_HEAP_TYPE) -> Self {
+        Self(Arc::new(Mutex::new(Heaps {
+            device: device.clone(),
+            heap_type,
+            heaps: Vec::new(),
+        })))
+    }
+
+    /// Allocates `count` consecutive descriptors, for views bound as one range.
+    pub fn allocate(&self, count: usize) -> Descriptors {
+        assert!(count > 0, "allocated no descriptors");
+        let mut heaps = self.0.lock().unwrap();
+        let found = heaps
+            .heaps
+            .iter_mut()
+            .enumerate()
+            .find_map(|(index, (_, free))| free.allocate(count).map(|first| (index, first)));
+        let (heap_index, first) = found.unwrap_or_else(|| {
+            let size = count.max(DESCRIPTORS_PER_HEAP);
+            let heap = DescriptorHeap::with_device(
+                &heaps.device,
+                heaps.heap_type,
+                size,
+                D3D12_DESCRIPTOR_HEAP_FLAG_NONE,
+            );
+            let mut free = FreeList::new(size);
+            let first = free.allocate(count).unwrap();
+            heaps.heaps.push((heap, free));
+            (heaps.heaps.len() - 1, first)
+        });
+        let first_handle = heaps.heaps[heap_index].0.cpu_handle_at(first);
+        Descriptors {
+            allocator: self.clone(),
+            heap_index,
+            range: first..first + count,
+            first_handle,
+            increment: heaps.heaps[heap_index].0.increment(),
+        }
+    }
+
+    /// Descriptors allocated now, over all heaps.
+    pub fn allocated(&self) -> usize {
+        let heaps = self.0.lock().unwrap();
+        heaps
+            .heaps
+            .iter()
+            .map(|(heap, free)| heap.descriptor_count() - free.free_count())
+            .sum()
+    }
+}
+
+/// Consecutive descriptors of a [`DescriptorAllocator`], freed when dropped. The views written
+/// into them must not be used by the GPU anymore by then.
+pub struct Descriptors {
+    allocator: DescriptorAllocator,
+    heap_index: usize,
+    range: Range<usize>,
+    first_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    increment: usize,
+}
+
+impl Descriptors {
+    pub fn count(&self) -> usize {
+        self.range.len()
+    }
+
+    pub fn cpu_handle(&self, index: usize) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+        assert!(
+            index < self.count(),
+            "descriptor {index} is outside of an allocation of {}",
+            self.count()
+        );
+        D3D12_CPU_DESCRIPTOR_HANDLE {
+            ptr: self.first_handle.ptr + index * self.increment,
+        }
+    }
+}
+
+impl Drop for Descriptors {
+    fn drop(&mut self) {
+        let mut heaps = self.allocator.0.lock().unwrap();
+        heaps.heaps[self.heap_index].1.free(self.range.clone());
+    }
+}
+
+/// Free ranges of a heap, sorted and merged with their neighbors.
+#[derive(Debug, PartialEq)]
+pub(super) struct FreeList {
+    ranges: Vec<Range<usize>>,
+}
+
+impl FreeList {
+    // one free range of all descriptors, not the descriptors of the range
+    #[allow(clippy::single_range_in_vec_init)]
+    pub(super) fn new(count: usize) -> Self {
+        Self {
+            ranges: vec![0..count],
+        }
+    }
+
+    // first fit, large allocations are rare and come first
+    pub(super) fn allocate(&mut self, count: usize) -> Option<usize> {
+        let index = self.ranges.iter().position(|range| range.len() >= count)?;
+        let range = &mut self.ranges[index];
+        let first = range.start;
+        range.start += count;
+        if range.start == range.end {
+            self.ranges.remove(index);
+        }
+        Some(first)
+    }
+
+    pub(super) fn free(&mut self, freed: Range<usize>) {
+        let index = self
+            .ranges
+            .partition_point(|range| range.start < freed.start);
+        let merges_previous = index > 0 && self.ranges[index - 1].end == freed.start;
+        let merges_next = self
+            .ranges
+            .get(index)
+            .is_some_and(|next| next.start == freed.end);
+        match (merges_previous, merges_next) {
+            (true, true) => {
+                self.ranges[index - 1].end = self.ranges[index].end;
+                self.ranges.remove(index);
+            }
+            (true, false) => self.ranges[index - 1].end = freed.end,
+            (false, true) => self.ranges[index].start = freed.start,
+            (false, false) => self.ranges.insert(index, freed),
+        }
+    }
+
+    fn free_count(&self) -> usize {
+        self.ranges.iter().map(Range::len).sum()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::FreeList;
+
+    #[test]
+    fn freed_ranges_are_merged_and_reused() {
+        let mut free = FreeList::new(8);
+        assert_eq!(free.allocate(2), Some(0));
+        assert_eq!(free.allocate(3), Some(2));
+        assert_eq!(free.allocate(2), Some(5));
+        assert_eq!(free.allocate(2), None);
+
+        free.free(0..2);
+        free.free(5..7);
+        assert_eq!(free.ranges, vec![0..2, 5..8]);
+        assert_eq!(free.allocate(3), Some(5));
+
+        free.free(5..8);
+        free.free(2..5);
+        assert_eq!(free, FreeList::new(8));
+    }
+}
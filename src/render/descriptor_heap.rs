@@ -1,50 +1,81 @@
 use windows::Win32::Graphics::Direct3D12::{
-    ID3D12DescriptorHeap, D3D12_CPU_DESCRIPTOR_HANDLE, D3D12_DESCRIPTOR_HEAP_DESC,
-    D3D12_DESCRIPTOR_HEAP_FLAGS, D3D12_DESCRIPTOR_HEAP_TYPE, D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
+    ID3D12DescriptorHeap, ID3D12Device9, D3D12_CPU_DESCRIPTOR_HANDLE, D3D12_DESCRIPTOR_HEAP_DESC,
+    D3D12_DESCRIPTOR_HEAP_FLAGS, D3D12_DESCRIPTOR_HEAP_TYPE,
+    D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV, D3D12_DESCRIPTOR_HEAP_TYPE_DSV,
+    D3D12_DESCRIPTOR_HEAP_TYPE_RTV, D3D12_DESCRIPTOR_HEAP_TYPE_SAMPLER,
     D3D12_GPU_DESCRIPTOR_HANDLE,
 };
 
-use super::Gpu;
+use super::{set_debug_name, Gpu};
 
 pub struct DescriptorHeap {
     heap: ID3D12DescriptorHeap,
-    current_ptr: D3D12_CPU_DESCRIPTOR_HANDLE,
+    heap_start: D3D12_CPU_DESCRIPTOR_HANDLE,
+    descriptor_count: usize,
     heap_increment: usize,
 }
 
 impl DescriptorHeap {
+    #[track_caller]
     pub fn new(
         gpu: &Gpu,
         heap_type: D3D12_DESCRIPTOR_HEAP_TYPE,
         descriptor_count: usize,
         flags: D3D12_DESCRIPTOR_HEAP_FLAGS,
+    ) -> Self {
+        Self::with_device(&gpu.device, heap_type, descriptor_count, flags)
+    }
+
+    #[track_caller]
+    pub(crate) fn with_device(
+        device: &ID3D12Device9,
+        heap_type: D3D12_DESCRIPTOR_HEAP_TYPE,
+        descriptor_count: usize,
+        flags: D3D12_DESCRIPTOR_HEAP_FLAGS,
     ) -> Self {
         let heap: ID3D12DescriptorHeap = unsafe {
-            gpu.device
+            device
                 .CreateDescriptorHeap(&D3D12_DESCRIPTOR_HEAP_DESC {
                     Type: heap_type,
                     NumDescriptors: descriptor_count as u32,
                     Flags: flags,
                     ..Default::default()
                 })
-                .expect("Failed to create Render Target View Descriptor heap")
+                .expect("Failed to create descriptor heap")
         };
-        let heap_increment = unsafe {
-            gpu.device
-                .GetDescriptorHandleIncrementSize(D3D12_DESCRIPTOR_HEAP_TYPE_RTV)
-        } as usize;
+        set_debug_name(
+            &heap,
+            &format!("{} descriptor heap", heap_type_name(heap_type)),
+        );
+        let heap_increment = unsafe { device.GetDescriptorHandleIncrementSize(heap_type) } as usize;
         let heap_start = unsafe { heap.GetCPUDescriptorHandleForHeapStart() };
         Self {
             heap,
-            current_ptr: heap_start,
+            heap_start,
+            descriptor_count,
             heap_increment,
         }
     }
 
-    pub fn cpu_handle(&mut self) -> D3D12_CPU_DESCRIPTOR_HANDLE {
-        let result = self.current_ptr;
-        self.current_ptr.ptr += self.heap_increment;
-        result
+    /// Handle of the descriptor at `index`, for rewriting it in place or copying into it.
+    pub fn cpu_handle_at(&self, index: usize) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+        assert!(
+            index < self.descriptor_count,
+            "descriptor {index} is outside of a heap of {} descriptors",
+            self.descriptor_count
+        );
+        D3D12_CPU_DESCRIPTOR_HANDLE {
+            ptr: self.heap_start.ptr + index * self.heap_increment,
+        }
+    }
+
+    pub fn descriptor_count(&self) -> usize {
+        self.descriptor_count
+    }
+
+    /// Distance between the handles of neighboring descriptors.
+    pub fn increment(&self) -> usize {
+        self.heap_increment
     }
 
     pub fn heap(&self) -> ID3D12DescriptorHeap {
@@ -55,3 +86,13 @@ impl DescriptorHeap {
         unsafe { self.heap.GetGPUDescriptorHandleForHeapStart() }
     }
 }
+
+fn heap_type_name(heap_type: D3D12_DESCRIPTOR_HEAP_TYPE) -> &'static str {
+    match heap_type {
+        D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV => "CBV/SRV/UAV",
+        D3D12_DESCRIPTOR_HEAP_TYPE_SAMPLER => "sampler",
+        D3D12_DESCRIPTOR_HEAP_TYPE_RTV => "RTV",
+        D3D12_DESCRIPTOR_HEAP_TYPE_DSV => "DSV",
+        _ => "unknown",
+    }
+}
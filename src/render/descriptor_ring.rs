@@ -0,0 +1,205 @@
+//! Shader visible descriptors staged for the frame being recorded.
+
+use std::sync::Mutex;
+
+use windows::Win32::Graphics::Direct3D12::{
+    ID3D12DescriptorHeap, ID3D12Device9, D3D12_CPU_DESCRIPTOR_HANDLE,
+    D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE, D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+    D3D12_GPU_DESCRIPTOR_HANDLE,
+};
+
+use super::{render_target::FRAME_COUNT, DescriptorHeap, Descriptors};
+
+/// Size of the ring, shared by all frames in flight.
+pub const RING_DESCRIPTORS: usize = 4096;
+
+/// Shader visible CBV/SRV/UAV heap passes stage their descriptors in while a frame is
+/// recorded, instead of keeping a table of their own from pipeline creation on.
+///
+/// Every frame takes the descriptors after the ones of the frame before, wrapping around at
+/// the end of the heap. They are given back once the frame slot they were staged in begins
+/// again, the GPU finished that frame by then.
+pub struct DescriptorRing {
+    device: ID3D12Device9,
+    ring: Mutex<Ring>,
+}
+
+struct Ring {
+    heap: DescriptorHeap,
+    positions: RingPositions,
+}
+
+impl DescriptorRing {
+    pub fn new(device: &ID3D12Device9) -> Self {
+        let heap = DescriptorHeap::with_device(
+            device,
+            D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+            RING_DESCRIPTORS,
+            D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
+        );
+        Self {
+            device: device.clone(),
+            ring: Mutex::new(Ring {
+                heap,
+                positions: RingPositions::new(RING_DESCRIPTORS),
+            }),
+        }
+    }
+
+    /// Gives back the descriptors staged the last time `frame_slot` was recorded. Called when
+    /// a frame begins, after waiting for the GPU to finish the frame of the same slot.
+    pub fn begin_frame(&self, frame_slot: usize) {
+        self.ring.lock().unwrap().positions.begin_frame(frame_slot);
+    }
+
+    /// `count` consecutive descriptors for the frame being recorded, to write views into.
+    pub fn allocate(&self, count: usize) -> TransientDescriptors {
+        let mut ring = self.ring.lock().unwrap();
+        let first = ring.positions.allocate(count).unwrap_or_else(|| {
+            panic!(
+                "descriptor ring of {RING_DESCRIPTORS} descriptors is full, the frames in flight \
+                 staged too many descriptors"
+            )
+        });
+        let increment = ring.heap.increment();
+        TransientDescriptors {
+            heap: ring.heap.heap(),
+            first_cpu_handle: ring.heap.cpu_handle_at(first),
+            first_gpu_handle: D3D12_GPU_DESCRIPTOR_HANDLE {
+                ptr: ring.heap.gpu_handle().ptr + (first * increment) as u64,
+            },
+            count,
+            increment,
+        }
+    }
+
+    /// Copies shader invisible `descriptors` into the ring, for binding them as one table in
+    /// the frame being recorded.
+    pub fn stage(&self, descriptors: &Descriptors) -> TransientDescriptors {
+        let staged = self.allocate(descriptors.count());
+        unsafe {
+            self.device.CopyDescriptorsSimple(
+                descriptors.count() as u32,
+                staged.cpu_handle(0),
+                descriptors.cpu_handle(0),
+                D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
+            )
+        };
+        staged
+    }
+}
+
+/// Consecutive descriptors of the [`DescriptorRing`], valid until the GPU finished the frame
+/// they were allocated in.
+pub struct TransientDescriptors {
+    heap: ID3D12DescriptorHeap,
+    first_cpu_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    first_gpu_handle: D3D12_GPU_DESCRIPTOR_HANDLE,
+    count: usize,
+    increment: usize,
+}
+
+impl TransientDescriptors {
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    pub fn cpu_handle(&self, index: usize) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+        assert!(
+            index < self.count,
+            "descriptor {index} is outside of {} transient descriptors",
+            self.count
+        );
+        D3D12_CPU_DESCRIPTOR_HANDLE {
+            ptr: self.first_cpu_handle.ptr + index * self.increment,
+        }
+    }
+
+    /// Start of the table, the ring heap has to be set on the command list.
+    pub fn gpu_handle(&self) -> D3D12_GPU_DESCRIPTOR_HANDLE {
+        self.first_gpu_handle
+    }
+
+    pub fn heap(&self) -> ID3D12DescriptorHeap {
+        self.heap.clone()
+    }
+}
+
+/// Positions in a ring of `capacity` elements that frames in flight allocate from in turn.
+/// Positions count on over the wraps, the element of a position is at `position % capacity`.
+#[derive(Debug)]
+pub(super) struct RingPositions {
+    capacity: u64,
+    head: u64,
+    // first position the GPU may still read
+    tail: u64,
+    // head when the frame of each slot began
+    frame_starts: [u64; FRAME_COUNT],
+}
+
+impl RingPositions {
+    pub(super) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity as u64,
+            head: 0,
+            tail: 0,
+            frame_starts: [0; FRAME_COUNT],
+        }
+    }
+
+    /// Gives back the elements allocated the last time `frame_slot` was recorded.
+    pub(super) fn begin_frame(&mut self, frame_slot: usize) {
+        // frames take the slots in turn, the next slot holds the oldest frame still in flight
+        self.tail = self.frame_starts[(frame_slot + 1) % FRAME_COUNT];
+        self.frame_starts[frame_slot] = self.head;
+    }
+
+    /// First of `count` consecutive elements, `None` when the frames in flight hold too many.
+    pub(super) fn allocate(&mut self, count: usize) -> Option<usize> {
+        let capacity = self.capacity;
+        // allocations can't wrap around, the rest of the ring is skipped
+        let offset = self.head % capacity;
+        let skipped = if offset + count as u64 > capacity {
+            capacity - offset
+        } else {
+            0
+        };
+        if self.head + skipped + count as u64 - self.tail > capacity {
+            return None;
+        }
+        self.head += skipped;
+        let first = (self.head % capacity) as usize;
+        self.head += count as u64;
+        Some(first)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RingPositions, RING_DESCRIPTORS};
+
+    #[test]
+    fn descriptors_are_reused_once_their_frame_slot_begins_again() {
+        let mut ring = RingPositions::new(RING_DESCRIPTORS);
+        ring.begin_frame(0);
+        assert_eq!(ring.allocate(RING_DESCRIPTORS / 2), Some(0));
+        ring.begin_frame(1);
+        assert_eq!(
+            ring.allocate(RING_DESCRIPTORS / 4),
+            Some(RING_DESCRIPTORS / 2)
+        );
+        // frame 0 is finished, the table that doesn't fit the end starts over at 0
+        ring.begin_frame(0);
+        assert_eq!(ring.allocate(RING_DESCRIPTORS / 2), Some(0));
+    }
+
+    #[test]
+    fn frames_in_flight_cant_overflow_the_ring() {
+        let mut ring = RingPositions::new(RING_DESCRIPTORS);
+        ring.begin_frame(0);
+        ring.allocate(RING_DESCRIPTORS / 2);
+        ring.begin_frame(1);
+        ring.allocate(RING_DESCRIPTORS / 2);
+        assert_eq!(ring.allocate(1), None);
+    }
+}
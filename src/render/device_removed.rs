@@ -0,0 +1,195 @@
+//! Device removal: DRED data in the logs and recreating the renderer on a new device.
+
+use bevy::prelude::*;
+use windows::{
+    core::{Interface, HRESULT, PCWSTR},
+    Win32::Graphics::{
+        Direct3D12::*,
+        Dxgi::{DXGI_ERROR_DEVICE_REMOVED, DXGI_ERROR_DEVICE_RESET},
+    },
+};
+
+use super::{
+    adapter_switch::recreate_renderer, drawer::present, render_target::switch_frame, Gpu,
+    RenderSchedule, RenderSet,
+};
+
+/// Sent after the device was removed, a driver crash, a GPU hang or a driver update for
+/// example, and the renderer was recreated on a new one. Accumulation and running offline
+/// renders start over.
+#[derive(Event, Debug, Clone)]
+pub struct DeviceRecovered {
+    /// Why the old device was removed.
+    pub reason: String,
+}
+
+// the device is given up on after this many removals, it doesn't get better
+const MAX_RECOVERIES: u32 = 3;
+
+pub struct DeviceRecoveryPlugin;
+
+impl Plugin for DeviceRecoveryPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<DeviceRecovered>().add_systems(
+            RenderSchedule,
+            recover_removed_device
+                .after(present)
+                .before(switch_frame)
+                .in_set(RenderSet::Present),
+        );
+    }
+}
+
+/// Whether `result` of a DXGI call tells that the device is gone.
+pub(crate) fn is_device_removed(result: HRESULT) -> bool {
+    result == DXGI_ERROR_DEVICE_REMOVED || result == DXGI_ERROR_DEVICE_RESET
+}
+
+/// Records breadcrumbs and page faults of devices created from now on, read by
+/// [`log_removal_data`]. Has to be called before the device is created.
+pub(super) unsafe fn enable_removal_data() -> windows::core::Result<()> {
+    let mut settings: Option<ID3D12DeviceRemovedExtendedDataSettings> = None;
+    D3D12GetDebugInterface(&mut settings)?;
+    let settings = settings.unwrap();
+    settings.SetAutoBreadcrumbsEnablement(D3D12_DRED_ENABLEMENT_FORCED_ON);
+    settings.SetPageFaultEnablement(D3D12_DRED_ENABLEMENT_FORCED_ON);
+    Ok(())
+}
+
+// runs right after present, before anything waits for or reads back from the removed device
+fn recover_removed_device(world: &mut World, mut recoveries: Local<u32>) {
+    let gpu = world.resource::<Gpu>();
+    let Err(reason) = (unsafe { gpu.device.GetDeviceRemovedReason() }) else {
+        return;
+    };
+    error!("The GPU device was removed: {reason}");
+    log_removal_data(gpu);
+
+    *recoveries += 1;
+    if *recoveries > MAX_RECOVERIES {
+        panic!("The GPU device was removed {MAX_RECOVERIES} times, giving up: {reason}");
+    }
+    let warp = gpu.warp;
+    recreate_renderer(world, warp);
+    info!("Recreated the renderer after the device was removed");
+    world.send_event(DeviceRecovered {
+        reason: reason.to_string(),
+    });
+}
+
+/// Logs the commands that didn't finish and the page fault that removed the device of `gpu`,
+/// when the debug layer recorded them.
+pub(crate) fn log_removal_data(gpu: &Gpu) {
+    let Ok(dred) = gpu.device.cast::<ID3D12DeviceRemovedExtendedData1>() else {
+        return;
+    };
+    unsafe {
+        if let Ok(breadcrumbs) = dred.GetAutoBreadcrumbsOutput1() {
+            log_breadcrumbs(&breadcrumbs);
+        }
+        if let Ok(page_fault) = dred.GetPageFaultAllocationOutput1() {
+            log_page_fault(&page_fault);
+        }
+    }
+}
+
+unsafe fn log_breadcrumbs(output: &D3D12_DRED_AUTO_BREADCRUMBS_OUTPUT1) {
+    let mut node = output.pHeadAutoBreadcrumbNode;
+    while let Some(current) = node.as_ref() {
+        node = current.pNext;
+        let count = current.BreadcrumbCount;
+        let completed = current.pLastBreadcrumbValue.as_ref().copied().unwrap_or(0);
+        if completed >= count || current.pCommandHistory.is_null() {
+            continue;
+        }
+
+        let history = std::slice::from_raw_parts(current.pCommandHistory, count as usize);
+        let contexts = if current.pBreadcrumbContexts.is_null() {
+            &[][..]
+        } else {
+            std::slice::from_raw_parts(
+                current.pBreadcrumbContexts,
+                current.BreadcrumbContextsCount as usize,
+            )
+        };
+        let mut commands = String::new();
+        for (index, op) in history.iter().enumerate() {
+            let state = match (index as u32).cmp(&completed) {
+                std::cmp::Ordering::Less => "done",
+                std::cmp::Ordering::Equal => "FAILED",
+                std::cmp::Ordering::Greater => "pending",
+            };
+            let context = contexts
+                .iter()
+                .find(|context| context.BreadcrumbIndex == index as u32)
+                .map(|context| format!(" \"{}\"", wide_string(context.pContextString)))
+                .unwrap_or_default();
+            commands.push_str(&format!("\n  {state:>7} {}{context}", op_name(*op)));
+        }
+        error!(
+            "Command list \"{}\" on queue \"{}\" finished {completed} of {count} commands:\
+             {commands}",
+            wide_string(current.pCommandListDebugNameW),
+            wide_string(current.pCommandQueueDebugNameW),
+        );
+    }
+}
+
+unsafe fn log_page_fault(output: &D3D12_DRED_PAGE_FAULT_OUTPUT1) {
+    if output.PageFaultVA == 0 {
+        return;
+    }
+    let allocations = |mut node: *const D3D12_DRED_ALLOCATION_NODE1| {
+        let mut names = Vec::new();
+        while let Some(current) = node.as_ref() {
+            names.push(format!(
+                "\"{}\" (type {})",
+                wide_string(current.ObjectNameW),
+                current.AllocationType.0
+            ));
+            node = current.pNext;
+        }
+        names.join(", ")
+    };
+    error!(
+        "GPU page fault at {:#x}\nallocations there: {}\nrecently freed there: {}",
+        output.PageFaultVA,
+        allocations(output.pHeadExistingAllocationNode),
+        allocations(output.pHeadRecentFreedAllocationNode),
+    );
+}
+
+unsafe fn wide_string(string: PCWSTR) -> String {
+    if string.is_null() {
+        return "unnamed".to_string();
+    }
+    string.to_string().unwrap_or_default()
+}
+
+fn op_name(op: D3D12_AUTO_BREADCRUMB_OP) -> String {
+    let name = match op {
+        D3D12_AUTO_BREADCRUMB_OP_SETMARKER => "SetMarker",
+        D3D12_AUTO_BREADCRUMB_OP_BEGINEVENT => "BeginEvent",
+        D3D12_AUTO_BREADCRUMB_OP_ENDEVENT => "EndEvent",
+        D3D12_AUTO_BREADCRUMB_OP_DRAWINSTANCED => "DrawInstanced",
+        D3D12_AUTO_BREADCRUMB_OP_DRAWINDEXEDINSTANCED => "DrawIndexedInstanced",
+        D3D12_AUTO_BREADCRUMB_OP_EXECUTEINDIRECT => "ExecuteIndirect",
+        D3D12_AUTO_BREADCRUMB_OP_DISPATCH => "Dispatch",
+        D3D12_AUTO_BREADCRUMB_OP_COPYBUFFERREGION => "CopyBufferRegion",
+        D3D12_AUTO_BREADCRUMB_OP_COPYTEXTUREREGION => "CopyTextureRegion",
+        D3D12_AUTO_BREADCRUMB_OP_COPYRESOURCE => "CopyResource",
+        D3D12_AUTO_BREADCRUMB_OP_RESOLVESUBRESOURCE => "ResolveSubresource",
+        D3D12_AUTO_BREADCRUMB_OP_CLEARRENDERTARGETVIEW => "ClearRenderTargetView",
+        D3D12_AUTO_BREADCRUMB_OP_CLEARUNORDEREDACCESSVIEW => "ClearUnorderedAccessView",
+        D3D12_AUTO_BREADCRUMB_OP_CLEARDEPTHSTENCILVIEW => "ClearDepthStencilView",
+        D3D12_AUTO_BREADCRUMB_OP_RESOURCEBARRIER => "ResourceBarrier",
+        D3D12_AUTO_BREADCRUMB_OP_PRESENT => "Present",
+        D3D12_AUTO_BREADCRUMB_OP_RESOLVEQUERYDATA => "ResolveQueryData",
+        D3D12_AUTO_BREADCRUMB_OP_BEGINSUBMISSION => "BeginSubmission",
+        D3D12_AUTO_BREADCRUMB_OP_ENDSUBMISSION => "EndSubmission",
+        D3D12_AUTO_BREADCRUMB_OP_BARRIER => "Barrier",
+        D3D12_AUTO_BREADCRUMB_OP_BEGIN_COMMAND_LIST => "BeginCommandList",
+        _ => return format!("operation {}", op.0),
+    };
+    name.to_string()
+}
@@ -1,153 +1,896 @@
-use bevy::prelude::*;
+use std::cell::RefCell;
+
+use bevy::{ecs::system::SystemParam, prelude::*};
 use windows::{
     core::Interface,
+    Win32::Foundation::RECT,
     Win32::Graphics::{
         Direct3D12::{
-            ID3D12GraphicsCommandList, ID3D12Resource, D3D12_COMMAND_LIST_TYPE_DIRECT,
-            D3D12_RESOURCE_BARRIER, D3D12_RESOURCE_BARRIER_0,
-            D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES, D3D12_RESOURCE_BARRIER_FLAG_NONE,
-            D3D12_RESOURCE_BARRIER_TYPE_TRANSITION, D3D12_RESOURCE_STATES,
+            ID3D12CommandAllocator, ID3D12GraphicsCommandList, ID3D12Resource,
+            D3D12_COMMAND_LIST_TYPE_DIRECT, D3D12_CPU_DESCRIPTOR_HANDLE, D3D12_RESOURCE_STATES,
+            D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE, D3D12_RESOURCE_STATE_COPY_SOURCE,
             D3D12_RESOURCE_STATE_PRESENT, D3D12_RESOURCE_STATE_RENDER_TARGET,
-            D3D12_RESOURCE_TRANSITION_BARRIER,
+            D3D12_RESOURCE_STATE_RESOLVE_DEST, D3D12_RESOURCE_STATE_RESOLVE_SOURCE,
+            D3D12_RESOURCE_STATE_UNORDERED_ACCESS, D3D12_VIEWPORT,
         },
-        Dxgi::DXGI_PRESENT,
+        Dxgi::Common::DXGI_FORMAT,
     },
 };
 
-use super::{gpu::Gpu, pipelines::PipelineStorage, render_target::WindowRenderTarget, MeshData};
-use crate::core::Camera;
+use super::{
+    aov::AovTarget,
+    capture::FrameCapture,
+    command_queue::GpuCommands,
+    d3d::transition_barrier,
+    fence_timeout::FenceTimeout,
+    frame_events::{FrameRenderStarted, FrameRendered, FrameTimestamp},
+    frame_graph::FrameGraph,
+    gpu::Gpu,
+    gpu_fence::GpuFence,
+    gpu_timings::TimestampQueries,
+    headless::HeadlessTarget,
+    late_latch::CameraLateLatch,
+    material_textures::MaterialTextures,
+    offline::OfflineRender,
+    pipelines::{
+        AutoExposurePipeline, DebugView, GizmoPipeline, PathTracerSettings, Pipeline,
+        PipelineStorage, ScenePipeline, SceneTarget, TargetDesc, TonemapPipeline,
+    },
+    pix,
+    render_graph::{RenderGraph, RenderNode},
+    render_target::{BackBufferFormat, PresentMode, WindowRenderTarget, FRAME_COUNT},
+    set_debug_name,
+    upload::{UploadBudget, UploadQueue},
+    Descriptors, LightData, MeshData, PrimitiveData,
+};
+use crate::core::{Background, Camera};
+
+/// Command allocator of one of the [`FRAME_COUNT`] frames in flight.
+struct FrameContext {
+    command_allocator: ID3D12CommandAllocator,
+    // the frame fence reaches it once the GPU finished the last frame recorded with the
+    // allocator, 0 before the first one
+    fence_value: u64,
+}
 
 #[derive(Resource)]
 pub struct Drawer {
     command_list: ID3D12GraphicsCommandList,
+    frames: [FrameContext; FRAME_COUNT],
+    // signaled after every submit
+    frame_fence: GpuFence,
+    timestamps: TimestampQueries,
+    frame_count: u64,
+    // frame drawn but not presented yet
+    pending_frame: Option<u64>,
+    frame_graph: FrameGraph,
 }
 
 impl Drawer {
     pub fn new(gpu: &Gpu) -> Self {
+        let frames = std::array::from_fn(|index| {
+            let command_allocator: ID3D12CommandAllocator = unsafe {
+                gpu.device
+                    .CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT)
+            }
+            .expect("CreateCommandAllocator failed");
+            set_debug_name(
+                &command_allocator,
+                &format!("command allocator of frame {index}"),
+            );
+            FrameContext {
+                command_allocator,
+                fence_value: 0,
+            }
+        });
         let command_list: ID3D12GraphicsCommandList = unsafe {
             gpu.device.CreateCommandList(
                 0,
                 D3D12_COMMAND_LIST_TYPE_DIRECT,
-                &gpu.command_allocator,
+                &frames[0].command_allocator,
                 None,
             )
         }
         .expect("CreateCommandList failed");
+        set_debug_name(&command_list, "draw command list");
         unsafe {
             command_list.Close().expect("Failed to close command list");
         };
+        Self {
+            command_list,
+            frames,
+            frame_fence: GpuFence::new(gpu, "frame fence"),
+            timestamps: TimestampQueries::new(gpu),
+            frame_count: 0,
+            pending_frame: None,
+            frame_graph: FrameGraph::default(),
+        }
+    }
+
+    /// Starts recording the next frame into the command list, once the GPU finished the frame
+    /// that used its command allocator, its staged descriptors and constants, freed bindless IDs,
+    /// retired resources and readbacks before.
+    fn begin_frame(&mut self, gpu: &Gpu, timeout: FenceTimeout) -> u64 {
+        let frame = self.frame_count;
+        self.frame_count += 1;
+        self.pending_frame = Some(frame);
+        self.frame_graph.clear();
+
+        let context = &self.frames[self.frame_slot()];
+        self.frame_fence
+            .wait(gpu, context.fence_value, timeout, "a frame in flight");
+        gpu.descriptor_ring.begin_frame(self.frame_slot());
+        gpu.constant_ring.begin_frame(self.frame_slot());
+        gpu.bindless.begin_frame(self.frame_slot());
+        gpu.retired.begin_frame(self.frame_slot());
+        self.timestamps.begin_frame(self.frame_slot());
+        unsafe {
+            context.command_allocator.Reset().unwrap();
+            self.command_list
+                .Reset(&context.command_allocator, None)
+                .unwrap();
+        }
+        frame
+    }
+
+    /// Opens the command list again after it was submitted for a view, for the next one.
+    fn reopen(&mut self) {
+        let context = &self.frames[self.frame_slot()];
+        unsafe {
+            self.command_list
+                .Reset(&context.command_allocator, None)
+                .unwrap()
+        };
+    }
+
+    /// Which of the [`FRAME_COUNT`] frames in flight is recorded, resources the GPU reads in
+    /// a frame can be reused in the frame with the same slot.
+    pub(crate) fn frame_slot(&self) -> usize {
+        (self.frame_count.wrapping_sub(1) % FRAME_COUNT as u64) as usize
+    }
+
+    /// Blocks until the GPU finished the frame recorded before the current one and returns its
+    /// slot. Readbacks are read one frame late, so the GPU works on the current frame in the
+    /// meantime, until the slot is recorded again.
+    pub(crate) fn finish_previous_frame(&self, gpu: &Gpu, timeout: FenceTimeout) -> usize {
+        let slot = (self.frame_slot() + FRAME_COUNT - 1) % FRAME_COUNT;
+        self.frame_fence.wait(
+            gpu,
+            self.frames[slot].fence_value,
+            timeout,
+            "the previous frame",
+        );
+        slot
+    }
+
+    /// Whether the GPU finished every submitted frame, so nothing in flight reads what the CPU
+    /// rewrites in place.
+    pub(crate) fn frames_finished(&self) -> bool {
+        self.frames
+            .iter()
+            .all(|frame| self.frame_fence.is_complete(frame.fence_value))
+    }
+
+    pub(crate) fn timestamps_mut(&mut self) -> &mut TimestampQueries {
+        &mut self.timestamps
+    }
+
+    /// Passes and barriers of the last drawn frame.
+    pub fn frame_graph(&self) -> &FrameGraph {
+        &self.frame_graph
+    }
+
+    /// Records a transition of `resource`, named `name` in the frame graph.
+    pub(super) fn transition(
+        &mut self,
+        name: &'static str,
+        resource: &ID3D12Resource,
+        state_before: D3D12_RESOURCE_STATES,
+        state_after: D3D12_RESOURCE_STATES,
+    ) {
+        self.frame_graph.barrier(name, state_before, state_after);
+        unsafe {
+            self.command_list.ResourceBarrier(&[transition_barrier(
+                resource,
+                state_before,
+                state_after,
+            )])
+        };
+    }
+
+    /// Opens an event named `name` in the command list, see [`super::pix`].
+    pub(super) fn begin_event(&self, name: &str) {
+        pix::begin_event(&self.command_list, name);
+    }
+
+    pub(super) fn end_event(&self) {
+        pix::end_event(&self.command_list);
+    }
+
+    /// Starts timing a pass of the view, see [`super::GpuTimings`].
+    pub(super) fn begin_timed_pass(&mut self, name: &'static str) {
+        self.timestamps.begin_pass(&self.command_list, name);
+    }
 
-        Self { command_list }
+    pub(super) fn end_timed_pass(&mut self) {
+        self.timestamps.end_pass(&self.command_list);
     }
+
+    /// Records a pass in the frame graph.
+    pub(super) fn record_pass(
+        &mut self,
+        name: &'static str,
+        reads: &[&'static str],
+        writes: &[&'static str],
+    ) {
+        self.frame_graph.pass(name, reads, writes);
+    }
+}
+
+/// Everything a camera view is drawn into.
+pub(crate) struct ViewTarget<'a> {
+    /// Part of `output` the view covers.
+    pub viewport: D3D12_VIEWPORT,
+    pub rect: RECT,
+    /// Part of `hdr_target` that is path traced, `viewport` scaled by `render_scale`.
+    pub hdr_viewport: D3D12_VIEWPORT,
+    pub hdr_rect: RECT,
+    pub render_scale: f32,
+    pub hdr_format: DXGI_FORMAT,
+    pub hdr_target: &'a ID3D12Resource,
+    pub hdr_rtv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    /// Shader invisible, staged in the [`super::DescriptorRing`] by the passes reading it.
+    pub hdr_srv: &'a Descriptors,
+    /// The SRV of `hdr_target` and a UAV of `output`, for
+    /// [`FinalBlit::Compute`](super::FinalBlit::Compute). None if `output` doesn't allow
+    /// unordered access.
+    pub blit_descriptors: Option<&'a Descriptors>,
+    /// Depth target of the size of `hdr_target`, in `DEPTH_WRITE`.
+    pub dsv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    /// Registered AOVs of the size of `hdr_target`, in `ALL_SHADER_RESOURCE`.
+    pub aovs: &'a [AovTarget],
+    pub accumulated_frames: u32,
+    /// Tone mapped result, left in `output_state` before and after the view is drawn.
+    pub output: &'a ID3D12Resource,
+    pub output_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    pub output_state: D3D12_RESOURCE_STATES,
+    pub output_format: BackBufferFormat,
+    /// Multisampled target the passes after path tracing draw into before it is resolved to
+    /// `output`.
+    pub msaa: Option<MsaaTarget<'a>>,
+}
+
+/// Multisampled color target in the format of [`ViewTarget::output`], resting in
+/// `RESOLVE_SOURCE`.
+pub(crate) struct MsaaTarget<'a> {
+    pub texture: &'a ID3D12Resource,
+    pub rtv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    pub samples: u32,
+}
+
+/// Scene data and queued GPU work, recorded at the start of every drawn frame.
+#[derive(SystemParam)]
+pub struct FrameUploads<'w> {
+    mesh_data: ResMut<'w, MeshData>,
+    light_data: ResMut<'w, LightData>,
+    primitive_data: ResMut<'w, PrimitiveData>,
+    material_textures: ResMut<'w, MaterialTextures>,
+    uploads: ResMut<'w, UploadQueue>,
+    upload_budget: Res<'w, UploadBudget>,
+    gpu_commands: ResMut<'w, GpuCommands>,
 }
 
+impl FrameUploads<'_> {
+    /// Hands changed scene data to every pipeline, not only the drawn one, so switching the
+    /// [`ScenePipeline`] doesn't have to upload the scene again.
+    fn record(
+        &mut self,
+        gpu: &Gpu,
+        pipelines: &mut PipelineStorage,
+        drawer: &Drawer,
+        timeout: FenceTimeout,
+    ) {
+        // the scene data is written to upload buffers the frame before may still copy from, and
+        // the texture SRVs are rewritten in place
+        if self.mesh_data.updated()
+            || self.light_data.updated()
+            || self.primitive_data.updated()
+            || self.material_textures.updated()
+        {
+            drawer.finish_previous_frame(gpu, timeout);
+            debug_assert!(
+                drawer.frames_finished(),
+                "scene data written while a frame in flight may read it"
+            );
+        }
+        if self.material_textures.updated() {
+            self.material_textures.bind(gpu, drawer);
+        }
+        for pipeline in pipelines.values_mut() {
+            if self.mesh_data.updated() {
+                pipeline.set_mesh_data(&self.mesh_data, &mut self.uploads);
+            }
+            if self.light_data.updated() {
+                pipeline.set_light_data(&self.light_data, &mut self.uploads);
+            }
+            if self.primitive_data.updated() {
+                pipeline.set_primitive_data(&self.primitive_data, &mut self.uploads);
+            }
+        }
+        self.mesh_data.set_used();
+        self.light_data.set_used();
+        self.primitive_data.set_used();
+        self.material_textures.set_used();
+        self.uploads.record(
+            &drawer.command_list,
+            self.upload_budget.bytes_per_frame,
+            drawer.frame_slot(),
+        );
+        self.gpu_commands
+            .record(gpu, &drawer.command_list, drawer.frame_slot());
+    }
+}
+
+/// Pipelines of the passes drawn after the scene in every view, created once their shaders
+/// are loaded.
+#[derive(SystemParam)]
+pub struct ViewPipelines<'w> {
+    tonemap: Option<ResMut<'w, TonemapPipeline>>,
+    auto_exposure: Option<Res<'w, AutoExposurePipeline>>,
+    gizmos: Option<ResMut<'w, GizmoPipeline>>,
+}
+
+/// Camera a view is rendered from.
+pub(crate) struct ViewCamera<'a> {
+    pub camera: &'a Camera,
+    pub transform: &'a GlobalTransform,
+    pub background: &'a Background,
+    /// Part of the camera image the view covers, in normalized viewport positions.
+    pub view_rect: Rect,
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn draw<const PIPELINE_ID: usize>(
     mut pipelines: ResMut<PipelineStorage>,
+    view_pipelines: ViewPipelines,
+    path_tracer_settings: Res<PathTracerSettings>,
     gpu: Res<Gpu>,
-    cameras: Query<(&Camera, &GlobalTransform)>,
-    mut mesh_data: ResMut<MeshData>,
+    cameras: Query<(&Camera, &GlobalTransform, Option<&Background>)>,
+    mut frame_uploads: FrameUploads,
     mut render_targets: Query<&mut WindowRenderTarget>,
     mut drawer: ResMut<Drawer>,
+    mut capture: ResMut<FrameCapture>,
+    offline_render: Option<ResMut<OfflineRender>>,
+    headless: Option<ResMut<HeadlessTarget>>,
+    mut frame_started: EventWriter<FrameRenderStarted>,
+    late_latch: Option<Res<CameraLateLatch>>,
+    fence_timeout: Res<FenceTimeout>,
+    debug_view: Res<DebugView>,
+    mut latched_transform: Local<Option<GlobalTransform>>,
 ) {
-    if render_targets.is_empty() {
+    if render_targets.is_empty() && offline_render.is_none() && headless.is_none() {
         return;
     }
 
-    let pipeline = pipelines.get_mut(&PIPELINE_ID);
-    if pipeline.is_none() {
+    // scene data goes to every scene pipeline, none may miss it
+    if !ScenePipeline::ALL
+        .iter()
+        .all(|scene_pipeline| pipelines.contains_key(&scene_pipeline.id()))
+    {
         return;
     }
-    let pipeline = pipeline.unwrap();
+    let ViewPipelines {
+        tonemap: Some(mut tonemap_pipeline),
+        auto_exposure: Some(auto_exposure_pipeline),
+        gizmos: mut gizmo_pipeline,
+    } = view_pipelines
+    else {
+        return;
+    };
 
-    unsafe {
-        gpu.command_allocator.Reset().unwrap();
-        drawer
-            .command_list
-            .Reset(&gpu.command_allocator, pipeline.state())
-            .unwrap();
-    }
+    let frame = drawer.begin_frame(&gpu, *fence_timeout);
+    frame_started.send(FrameRenderStarted {
+        frame,
+        timestamp: FrameTimestamp::now(&gpu),
+    });
+
+    pix::set_marker(&drawer.command_list, &format!("frame {frame}"));
 
-    let (camera_settings, camera_global_transform) = cameras
+    drawer.begin_event("uploads");
+    frame_uploads.record(&gpu, &mut pipelines, &drawer, *fence_timeout);
+    drawer.end_event();
+    let pipeline = pipelines.get_mut(&PIPELINE_ID).unwrap();
+    drawer.record_pass("uploads", &[], &["scene buffers"]);
+
+    let (camera, camera_global_transform, background) = cameras
         .get_single()
         .expect("only 1 camera is supported right now");
+    let background = background.unwrap_or(&Background::Environment);
+
+    // windows aren't drawn while an offline render runs, it owns the per frame constants
+    if let Some(mut offline_render) = offline_render {
+        // once every tile is rendered only the uploads are submitted until the last copy is read
+        if !offline_render.is_rendered() {
+            let camera = offline_render.camera(camera);
+            let view_camera = ViewCamera {
+                camera: &camera,
+                transform: camera_global_transform,
+                background,
+                view_rect: offline_render.view_rect(),
+            };
+            record_view(
+                &gpu,
+                &mut drawer,
+                pipeline.as_mut(),
+                &mut tonemap_pipeline,
+                &auto_exposure_pipeline,
+                // offline renders are final images, gizmos are for the interactive views
+                None,
+                &path_tracer_settings,
+                *debug_view,
+                &view_camera,
+                &offline_render.view_target(),
+            );
+            offline_render.record_readback(&gpu, &drawer.command_list, drawer.frame_slot());
+            offline_render.finish_frame();
+        }
+        submit(&gpu, &mut drawer);
+        return;
+    }
+
+    let latched = late_latch.and_then(|late_latch| late_latch.latch(camera_global_transform));
+    let relatched = latched != *latched_transform;
+    *latched_transform = latched;
+    let view_camera = ViewCamera {
+        camera,
+        transform: latched.as_ref().unwrap_or(camera_global_transform),
+        background,
+        view_rect: Rect::new(0.0, 0.0, 1.0, 1.0),
+    };
+    let mut submitted = false;
     for mut render_target in render_targets.iter_mut() {
-        unsafe {
-            drawer
-                .command_list
-                .RSSetViewports(&[render_target.viewport]);
-            drawer.command_list.RSSetScissorRects(&[render_target.rect]);
+        if relatched {
+            render_target.reset_accumulation();
         }
+        // minimized and fully covered windows aren't drawn until they show again
+        if render_target.is_occluded() {
+            continue;
+        }
+        if submitted {
+            drawer.reopen();
+        }
+        record_view(
+            &gpu,
+            &mut drawer,
+            pipeline.as_mut(),
+            &mut tonemap_pipeline,
+            &auto_exposure_pipeline,
+            gizmo_pipeline.as_deref_mut(),
+            &path_tracer_settings,
+            *debug_view,
+            &view_camera,
+            &render_target.view_target(),
+        );
 
-        let back_buffer = render_target.back_buffer();
-        let barrier = transition_barrier(
-            back_buffer,
+        capture.record(
+            &gpu,
+            &drawer.command_list,
+            render_target.back_buffer(),
             D3D12_RESOURCE_STATE_PRESENT,
-            D3D12_RESOURCE_STATE_RENDER_TARGET,
+            drawer.frame_slot(),
         );
-        unsafe { drawer.command_list.ResourceBarrier(&[barrier]) };
-
-        let rtv_handle = render_target.back_buffer_handle();
-        unsafe {
-            drawer
-                .command_list
-                .OMSetRenderTargets(1, Some(&rtv_handle), false, None)
-        };
-
-        unsafe {
-            drawer.command_list.ClearRenderTargetView(
-                render_target.back_buffer_handle(),
-                &[0.0_f32, 0.2_f32, 0.4_f32, 1.0_f32],
-                None,
+        capture.record_hdr(
+            &gpu,
+            &drawer.command_list,
+            render_target.hdr_target(),
+            D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+            drawer.frame_slot(),
+        );
+        for aov in render_target.aovs() {
+            capture.record_aov(
+                &gpu,
+                &drawer.command_list,
+                aov.name(),
+                aov.texture(),
+                D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+                drawer.frame_slot(),
             );
         }
 
-        if mesh_data.updated() {
-            pipeline.set_mesh_data(&mesh_data, &mut drawer.command_list);
-            mesh_data.set_used();
+        submit(&gpu, &mut drawer);
+        submitted = true;
+        render_target.set_drawn();
+    }
+    if let Some(mut headless) = headless {
+        if relatched {
+            headless.reset_accumulation();
         }
-        pipeline.write_camera_data(camera_global_transform, camera_settings);
-        pipeline.populate_command_list(&mut drawer.command_list);
+        if submitted {
+            drawer.reopen();
+        }
+        let camera = headless.camera(view_camera.camera);
+        record_view(
+            &gpu,
+            &mut drawer,
+            pipeline.as_mut(),
+            &mut tonemap_pipeline,
+            &auto_exposure_pipeline,
+            gizmo_pipeline.as_deref_mut(),
+            &path_tracer_settings,
+            *debug_view,
+            &ViewCamera {
+                camera: &camera,
+                ..view_camera
+            },
+            &headless.view_target(),
+        );
 
-        unsafe {
-            drawer.command_list.ResourceBarrier(&[transition_barrier(
-                back_buffer,
-                D3D12_RESOURCE_STATE_RENDER_TARGET,
-                D3D12_RESOURCE_STATE_PRESENT,
-            )]);
+        capture.record(
+            &gpu,
+            &drawer.command_list,
+            headless.output(),
+            D3D12_RESOURCE_STATE_COPY_SOURCE,
+            drawer.frame_slot(),
+        );
+        capture.record_hdr(
+            &gpu,
+            &drawer.command_list,
+            headless.hdr_target(),
+            D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+            drawer.frame_slot(),
+        );
+
+        submit(&gpu, &mut drawer);
+        headless.finish_frame(&gpu, *fence_timeout);
+        submitted = true;
+    }
+    // the uploads are recorded even when no window is drawn
+    if !submitted {
+        submit(&gpu, &mut drawer);
+    }
+}
+
+/// Presents the windows drawn this frame, the first system of [`super::RenderSet::Present`].
+pub fn present(
+    gpu: Res<Gpu>,
+    mut drawer: ResMut<Drawer>,
+    mut render_targets: Query<(&mut WindowRenderTarget, Option<&PresentMode>)>,
+    mut frame_rendered: EventWriter<FrameRendered>,
+) {
+    let Some(frame) = drawer.pending_frame.take() else {
+        return;
+    };
+
+    for (mut render_target, present_mode) in &mut render_targets {
+        if !render_target.take_drawn() {
+            if render_target.is_occluded() {
+                render_target.test_occlusion();
+            }
+            continue;
         }
+        render_target.present(present_mode.copied().unwrap_or_default());
+        render_target.signal_end_present(&gpu.queue);
+        render_target.advance_accumulation();
+    }
+
+    frame_rendered.send(FrameRendered {
+        frame,
+        timestamp: FrameTimestamp::now(&gpu),
+    });
+}
+
+/// Records the prepass, path tracing, auto exposure, tone mapping and gizmo passes of one view.
+#[allow(clippy::too_many_arguments)]
+fn record_view(
+    gpu: &Gpu,
+    drawer: &mut Drawer,
+    pipeline: &mut dyn Pipeline,
+    tonemap_pipeline: &mut TonemapPipeline,
+    auto_exposure_pipeline: &AutoExposurePipeline,
+    gizmo_pipeline: Option<&mut GizmoPipeline>,
+    path_tracer_settings: &PathTracerSettings,
+    debug_view: DebugView,
+    camera: &ViewCamera,
+    target: &ViewTarget,
+) {
+    drawer.frame_graph.begin_view();
+    drawer.begin_event("view");
+    drawer.timestamps.begin_view(&drawer.command_list);
 
+    let mut graph = RenderGraph::default();
+    let scene_buffers = graph.import_untracked("scene buffers");
+    let luminance = graph.import_untracked("luminance");
+    let hdr = graph.import(
+        "HDR target",
+        target.hdr_target,
+        D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+    );
+    let output = graph.import("output", target.output, target.output_state);
+    // the tone mapping pass draws into the MSAA target that is resolved to the output
+    let (color, color_handle, samples) = match &target.msaa {
+        Some(msaa) => (
+            graph.import(
+                "MSAA target",
+                msaa.texture,
+                D3D12_RESOURCE_STATE_RESOLVE_SOURCE,
+            ),
+            msaa.rtv_handle,
+            msaa.samples,
+        ),
+        None => (output, target.output_handle, 1),
+    };
+    let aovs: Vec<_> = target
+        .aovs
+        .iter()
+        .map(|aov| {
+            graph.import(
+                aov.name(),
+                aov.texture(),
+                D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+            )
+        })
+        .collect();
+    // DebugView::Aov tone maps the AOV in place of the HDR target, which is passed through
+    let shown_aov = match debug_view {
+        DebugView::Aov(name) => target.aovs.iter().position(|aov| aov.name() == name),
+        _ => None,
+    };
+    let (tonemap_source, tonemap_srv) = match shown_aov {
+        Some(index) => (aovs[index], target.aovs[index].srv()),
+        None => (hdr, target.hdr_srv),
+    };
+
+    // Progressive accumulation: the target keeps the running average of all frames
+    // since the last reset, the new frame is blended in with weight 1 / (n + 1)
+    let frame_index = target.accumulated_frames;
+    pipeline.write_frame_data(
+        camera.transform,
+        camera.camera,
+        camera.background,
+        path_tracer_settings,
+        frame_index,
+        camera.view_rect,
+    );
+    let scene_target = SceneTarget {
+        desc: TargetDesc::new(target.hdr_format, 1),
+        rtv_handle: target.hdr_rtv_handle,
+        dsv_handle: target.dsv_handle,
+        size: UVec2::new(target.hdr_rect.right as u32, target.hdr_rect.bottom as u32),
+        aovs: target.aovs,
+        frame_slot: drawer.frame_slot(),
+    };
+    let prepass = pipeline.prepass();
+    // the prepass and the main pass are recorded by different nodes
+    let pipeline = RefCell::new(pipeline);
+
+    let prepass_targets = graph.import_untracked("prepass targets");
+    if let Some(name) = prepass {
+        graph.add_node(
+            RenderNode::new(name, |drawer| {
+                unsafe {
+                    drawer.command_list.RSSetViewports(&[target.hdr_viewport]);
+                    drawer.command_list.RSSetScissorRects(&[target.hdr_rect]);
+                }
+                pipeline.borrow_mut().populate_prepass(
+                    gpu,
+                    &mut drawer.command_list,
+                    &scene_target,
+                );
+            })
+            .reads(scene_buffers, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE)
+            .writes(prepass_targets, D3D12_RESOURCE_STATE_RENDER_TARGET),
+        );
+    }
+
+    let mut path_trace = RenderNode::new("path trace", |drawer| {
         unsafe {
+            drawer.command_list.RSSetViewports(&[target.hdr_viewport]);
+            drawer.command_list.RSSetScissorRects(&[target.hdr_rect]);
             drawer
                 .command_list
-                .Close()
-                .expect("Failed to close command list");
+                .OMSetRenderTargets(1, Some(&target.hdr_rtv_handle), false, None);
         }
 
-        let command_list = drawer.command_list.cast().ok();
-        unsafe { gpu.queue.ExecuteCommandLists(&[command_list]) };
+        if frame_index == 0 {
+            unsafe {
+                drawer.command_list.ClearRenderTargetView(
+                    target.hdr_rtv_handle,
+                    &[0.0_f32, 0.0_f32, 0.0_f32, 1.0_f32],
+                    None,
+                );
+            }
+        }
+        for aov in target.aovs {
+            if let Some(value) = aov.clear_value(frame_index) {
+                unsafe {
+                    drawer
+                        .command_list
+                        .ClearRenderTargetView(aov.rtv_handle(), &value, None)
+                };
+            }
+        }
+        let weight = 1.0 / (frame_index + 1) as f32;
+        unsafe {
+            drawer
+                .command_list
+                .OMSetBlendFactor(Some(&[weight, weight, weight, weight]))
+        };
 
-        unsafe { render_target.swapchain.Present(1, DXGI_PRESENT(0)) }
-            .ok()
-            .unwrap();
-        render_target.signal_end_present(&gpu.queue);
+        pipeline
+            .borrow_mut()
+            .populate_command_list(gpu, &mut drawer.command_list, &scene_target);
+    })
+    .reads(scene_buffers, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE)
+    .writes(hdr, D3D12_RESOURCE_STATE_RENDER_TARGET);
+    if prepass.is_some() {
+        path_trace = path_trace.reads(prepass_targets, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE);
+    }
+    for &aov in &aovs {
+        path_trace = path_trace.writes(aov, D3D12_RESOURCE_STATE_RENDER_TARGET);
+    }
+    graph.add_node(path_trace);
+
+    if auto_exposure_pipeline.enabled() {
+        graph.add_node(
+            RenderNode::new("auto exposure", |drawer| {
+                auto_exposure_pipeline.populate_command_list(
+                    &mut drawer.command_list,
+                    &gpu.descriptor_ring.stage(target.hdr_srv),
+                    target.hdr_rect.right as u32,
+                    target.hdr_rect.bottom as u32,
+                );
+            })
+            .reads(hdr, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE)
+            .writes(luminance, D3D12_RESOURCE_STATE_UNORDERED_ACCESS),
+        );
+    }
+
+    match target
+        .blit_descriptors
+        .filter(|_| target.msaa.is_none() && shown_aov.is_none())
+    {
+        Some(blit_descriptors) => graph.add_node(
+            RenderNode::new("tonemap", |drawer| {
+                let output_desc = unsafe { target.output.GetDesc() };
+                tonemap_pipeline.populate_blit_command_list(
+                    &mut drawer.command_list,
+                    &gpu.descriptor_ring.stage(blit_descriptors),
+                    auto_exposure_pipeline.luminance_address(),
+                    target.output_format,
+                    target.render_scale,
+                    target.rect,
+                    output_desc.Width as u32,
+                    output_desc.Height,
+                );
+            })
+            .reads(hdr, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE)
+            .reads(luminance, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE)
+            .writes(output, D3D12_RESOURCE_STATE_UNORDERED_ACCESS),
+        ),
+        None => graph.add_node(
+            RenderNode::new("tonemap", |drawer| {
+                unsafe {
+                    drawer
+                        .command_list
+                        .OMSetRenderTargets(1, Some(&color_handle), false, None);
+                    // nothing else draws the parts of the output outside of the viewport
+                    drawer
+                        .command_list
+                        .ClearRenderTargetView(color_handle, &[0.0; 4], None);
+                    drawer.command_list.RSSetViewports(&[target.viewport]);
+                    drawer.command_list.RSSetScissorRects(&[target.rect]);
+                };
+
+                tonemap_pipeline.populate_command_list(
+                    gpu,
+                    &mut drawer.command_list,
+                    &gpu.descriptor_ring.stage(tonemap_srv),
+                    auto_exposure_pipeline.luminance_address(),
+                    target.output_format,
+                    samples,
+                    target.render_scale,
+                );
+            })
+            .reads(tonemap_source, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE)
+            .reads(luminance, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE)
+            .writes(color, D3D12_RESOURCE_STATE_RENDER_TARGET),
+        ),
+    }
+
+    if let Some(msaa) = &target.msaa {
+        graph.add_node(
+            RenderNode::new("resolve", |drawer| unsafe {
+                drawer.command_list.ResolveSubresource(
+                    target.output,
+                    0,
+                    msaa.texture,
+                    0,
+                    target.output_format.dxgi_format(),
+                );
+            })
+            .reads(color, D3D12_RESOURCE_STATE_RESOLVE_SOURCE)
+            .writes(output, D3D12_RESOURCE_STATE_RESOLVE_DEST),
+        );
     }
+
+    if let Some(gizmo_pipeline) = gizmo_pipeline.filter(|pipeline| !pipeline.is_empty()) {
+        graph.add_node(
+            RenderNode::new("gizmos", |drawer| {
+                unsafe {
+                    drawer.command_list.OMSetRenderTargets(
+                        1,
+                        Some(&target.output_handle),
+                        false,
+                        None,
+                    );
+                    drawer.command_list.RSSetViewports(&[target.viewport]);
+                    drawer.command_list.RSSetScissorRects(&[target.rect]);
+                }
+                let frame_slot = drawer.frame_slot();
+                gizmo_pipeline.populate_command_list(
+                    gpu,
+                    &mut drawer.command_list,
+                    camera.transform,
+                    camera.camera,
+                    camera.view_rect,
+                    target.output_format,
+                    frame_slot,
+                );
+            })
+            .writes(output, D3D12_RESOURCE_STATE_RENDER_TARGET),
+        );
+    }
+
+    graph.execute(drawer);
+
+    drawer.timestamps.end_view(&drawer.command_list);
+    drawer.end_event();
 }
 
-fn transition_barrier(
-    resource: &ID3D12Resource,
-    state_before: D3D12_RESOURCE_STATES,
-    state_after: D3D12_RESOURCE_STATES,
-) -> D3D12_RESOURCE_BARRIER {
-    D3D12_RESOURCE_BARRIER {
-        Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-        Flags: D3D12_RESOURCE_BARRIER_FLAG_NONE,
-        Anonymous: D3D12_RESOURCE_BARRIER_0 {
-            Transition: std::mem::ManuallyDrop::new(D3D12_RESOURCE_TRANSITION_BARRIER {
-                pResource: unsafe { std::mem::transmute_copy(resource) },
-                StateBefore: state_before,
-                StateAfter: state_after,
-                Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-            }),
-        },
+fn submit(gpu: &Gpu, drawer: &mut Drawer) {
+    unsafe {
+        drawer
+            .command_list
+            .Close()
+            .expect("Failed to close command list");
+    }
+
+    let command_list = drawer.command_list.cast().ok();
+    unsafe { gpu.queue.ExecuteCommandLists(&[command_list]) };
+    let slot = drawer.frame_slot();
+    drawer.frames[slot].fence_value = drawer.frame_fence.signal(&gpu.queue);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::{leak_report::wait_for_idle, test_utils::warp_gpu};
+
+    #[test]
+    fn frames_are_recorded_back_to_back() {
+        let gpu = warp_gpu();
+        let mut drawer = Drawer::new(&gpu);
+        // nothing waits for the first frame before the second one is recorded in the other slot
+        for frame in 0..FRAME_COUNT {
+            assert_eq!(
+                drawer.begin_frame(&gpu, FenceTimeout::default()),
+                frame as u64
+            );
+            assert_eq!(drawer.frame_slot(), frame);
+            drawer.timestamps.begin_view(&drawer.command_list);
+            drawer.timestamps.end_view(&drawer.command_list);
+            submit(&gpu, &mut drawer);
+        }
+        assert!(drawer.frames[0].fence_value < drawer.frames[1].fence_value);
+
+        let previous_slot = drawer.finish_previous_frame(&gpu, FenceTimeout::default());
+        assert_eq!(previous_slot, FRAME_COUNT - 2);
+        let timings = drawer.timestamps.read(previous_slot).unwrap();
+        assert_eq!(timings.views.len(), 1);
+
+        wait_for_idle(&gpu);
+        assert!(drawer.frames_finished());
+        let timings = drawer.timestamps.read(drawer.frame_slot()).unwrap();
+        assert_eq!(timings.views.len(), 1);
     }
 }
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+use bevy::prelude::*;
+use windows::Win32::Graphics::Direct3D12::ID3D12Fence;
+
+use super::{device_removed::log_removal_data, Gpu};
+
+/// How long the CPU waits for the GPU to finish a frame. A wait running out means the GPU hung:
+/// what is known about its state is logged and the app aborts, instead of freezing silently.
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Resource, Default)]
+pub struct FenceTimeout {
+    pub timeout: Duration,
+}
+
+impl Default for FenceTimeout {
+    fn default() -> Self {
+        Self {
+            timeout: Duration::from_secs(10),
+        }
+    }
+}
+
+pub(super) fn report_hang(
+    gpu: &Gpu,
+    fence: &ID3D12Fence,
+    value: u64,
+    timeout: FenceTimeout,
+    what: &str,
+) {
+    let completed = unsafe { fence.GetCompletedValue() };
+    let queue = unsafe { gpu.queue.GetDesc() };
+    let device_state = match unsafe { gpu.device.GetDeviceRemovedReason() } {
+        Ok(()) => "the device wasn't removed".to_string(),
+        Err(reason) => format!("the device was removed: {reason}"),
+    };
+    error!(
+        "GPU hang: waited {:?} for {what}.\n\
+         fence: last signaled {value}, last completed {completed}\n\
+         queue: {:?}, priority {}, {device_state}",
+        timeout.timeout, queue.Type, queue.Priority,
+    );
+    log_removal_data(gpu);
+}
@@ -0,0 +1,50 @@
+//! Events delimiting every frame the renderer draws.
+
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+
+use super::Gpu;
+
+/// CPU and GPU clocks read at the same moment.
+#[derive(Debug, Clone, Copy)]
+pub struct FrameTimestamp {
+    pub cpu: Instant,
+    /// GPU timestamp counter of the queue converted to time, comparable with timestamp queries
+    /// recorded on it.
+    pub gpu: Duration,
+}
+
+impl FrameTimestamp {
+    pub(crate) fn now(gpu: &Gpu) -> Self {
+        let mut gpu_ticks = 0;
+        let mut cpu_ticks = 0;
+        unsafe {
+            gpu.queue
+                .GetClockCalibration(&mut gpu_ticks, &mut cpu_ticks)
+        }
+        .expect("GetClockCalibration failed");
+        let frequency = unsafe { gpu.queue.GetTimestampFrequency() }
+            .expect("Failed to get timestamp frequency");
+        Self {
+            cpu: Instant::now(),
+            gpu: Duration::from_secs_f64(gpu_ticks as f64 / frequency as f64),
+        }
+    }
+}
+
+/// Sent when the drawer starts recording a frame, before scene data is uploaded.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct FrameRenderStarted {
+    /// Counts every frame drawn since startup, offline render frames included.
+    pub frame: u64,
+    pub timestamp: FrameTimestamp,
+}
+
+/// Sent once a frame is submitted and its windows presented, in [`super::RenderSet::Present`].
+/// The GPU may still be working on it.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct FrameRendered {
+    pub frame: u64,
+    pub timestamp: FrameTimestamp,
+}
@@ -0,0 +1,175 @@
+//! Record of the passes and barriers of the last drawn frame, dumped as Graphviz DOT to see
+//! what the renderer executes and where new passes fit.
+
+use std::fmt::Write;
+
+use windows::Win32::Graphics::Direct3D12::*;
+
+/// Passes, the resources they read and write and the barriers between them, in the order they
+/// were recorded. Rebuilt every frame, get the last one from [`super::Drawer::frame_graph`].
+#[derive(Default, Debug, Clone)]
+pub struct FrameGraph {
+    // steps before the first view, like uploads
+    frame: Vec<FrameStep>,
+    views: Vec<Vec<FrameStep>>,
+}
+
+#[derive(Debug, Clone)]
+enum FrameStep {
+    Pass {
+        name: &'static str,
+        reads: Vec<&'static str>,
+        writes: Vec<&'static str>,
+    },
+    Barrier {
+        resource: &'static str,
+        before: D3D12_RESOURCE_STATES,
+        after: D3D12_RESOURCE_STATES,
+    },
+}
+
+impl FrameGraph {
+    pub(crate) fn clear(&mut self) {
+        self.frame.clear();
+        self.views.clear();
+    }
+
+    /// Starts a view, steps recorded after this belong to it.
+    pub(crate) fn begin_view(&mut self) {
+        self.views.push(Vec::new());
+    }
+
+    pub(crate) fn pass(
+        &mut self,
+        name: &'static str,
+        reads: &[&'static str],
+        writes: &[&'static str],
+    ) {
+        self.steps().push(FrameStep::Pass {
+            name,
+            reads: reads.to_vec(),
+            writes: writes.to_vec(),
+        });
+    }
+
+    pub(crate) fn barrier(
+        &mut self,
+        resource: &'static str,
+        before: D3D12_RESOURCE_STATES,
+        after: D3D12_RESOURCE_STATES,
+    ) {
+        self.steps().push(FrameStep::Barrier {
+            resource,
+            before,
+            after,
+        });
+    }
+
+    fn steps(&mut self) -> &mut Vec<FrameStep> {
+        self.views.last_mut().unwrap_or(&mut self.frame)
+    }
+
+    /// The frame as a Graphviz digraph. Passes and barriers are chained in execution order by
+    /// bold edges, resources are linked to the passes that read and write them. Each view is a
+    /// cluster with its own resources.
+    pub fn dump_graphviz(&self) -> String {
+        let mut dot =
+            String::from("digraph frame {\n    rankdir=LR;\n    node [fontname=\"sans\"];\n");
+        let mut previous = None;
+        write_steps(&mut dot, "frame", &self.frame, &mut previous, "    ");
+        for (index, steps) in self.views.iter().enumerate() {
+            let prefix = format!("view{index}");
+            writeln!(dot, "    subgraph cluster_{prefix} {{").unwrap();
+            writeln!(dot, "        label=\"view {index}\";").unwrap();
+            write_steps(&mut dot, &prefix, steps, &mut previous, "        ");
+            dot.push_str("    }\n");
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+fn write_steps(
+    dot: &mut String,
+    prefix: &str,
+    steps: &[FrameStep],
+    previous: &mut Option<String>,
+    indent: &str,
+) {
+    let resource_id = |name: &str| format!("\"{prefix}/{name}\"");
+    let mut resources: Vec<&str> = Vec::new();
+    for (index, step) in steps.iter().enumerate() {
+        let id = format!("\"{prefix}/{index}\"");
+        match step {
+            FrameStep::Pass {
+                name,
+                reads,
+                writes,
+            } => {
+                writeln!(
+                    dot,
+                    "{indent}{id} [shape=box, style=filled, label=\"{name}\"];"
+                )
+                .unwrap();
+                for read in reads {
+                    writeln!(dot, "{indent}{} -> {id};", resource_id(read)).unwrap();
+                }
+                for write in writes {
+                    writeln!(dot, "{indent}{id} -> {};", resource_id(write)).unwrap();
+                }
+                resources.extend(reads.iter().chain(writes));
+            }
+            FrameStep::Barrier {
+                resource,
+                before,
+                after,
+            } => {
+                writeln!(
+                    dot,
+                    "{indent}{id} [shape=diamond, label=\"{resource}\\n{} -> {}\"];",
+                    state_name(*before),
+                    state_name(*after)
+                )
+                .unwrap();
+                writeln!(
+                    dot,
+                    "{indent}{} -> {id} [style=dashed];",
+                    resource_id(resource)
+                )
+                .unwrap();
+                resources.push(resource);
+            }
+        }
+        if let Some(previous) = previous.replace(id.clone()) {
+            writeln!(dot, "{indent}{previous} -> {id} [style=bold];").unwrap();
+        }
+    }
+    resources.sort_unstable();
+    resources.dedup();
+    for resource in resources {
+        writeln!(
+            dot,
+            "{indent}{} [shape=ellipse, label=\"{resource}\"];",
+            resource_id(resource)
+        )
+        .unwrap();
+    }
+}
+
+fn state_name(state: D3D12_RESOURCE_STATES) -> String {
+    const NAMES: [(D3D12_RESOURCE_STATES, &str); 8] = [
+        (D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE, "SHADER_RESOURCE"),
+        (D3D12_RESOURCE_STATE_RENDER_TARGET, "RENDER_TARGET"),
+        (D3D12_RESOURCE_STATE_UNORDERED_ACCESS, "UNORDERED_ACCESS"),
+        (D3D12_RESOURCE_STATE_RESOLVE_SOURCE, "RESOLVE_SOURCE"),
+        (D3D12_RESOURCE_STATE_RESOLVE_DEST, "RESOLVE_DEST"),
+        (D3D12_RESOURCE_STATE_COPY_SOURCE, "COPY_SOURCE"),
+        (D3D12_RESOURCE_STATE_COPY_DEST, "COPY_DEST"),
+        (D3D12_RESOURCE_STATE_PRESENT, "PRESENT"),
+    ];
+    NAMES
+        .iter()
+        .find(|(named, _)| *named == state)
+        .map(|(_, name)| name.to_string())
+        .unwrap_or_else(|| format!("{:#x}", state.0))
+}
@@ -0,0 +1,68 @@
+use std::time::{Duration, Instant};
+
+use bevy::prelude::*;
+
+/// How [`FrameLimiter`] waits for the next frame.
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum FrameWait {
+    /// Sleeps the whole wait, saves the most power but may overshoot by the timer resolution.
+    Sleep,
+    /// Busy waits, exact but keeps a CPU core busy.
+    Spin,
+    /// Sleeps until shortly before the next frame and spins the rest.
+    #[default]
+    SleepThenSpin,
+}
+
+/// Caps the frame rate, so the path tracer doesn't keep the GPU and CPU busy on battery
+/// powered devices when vsync alone isn't enough.
+///
+/// The wait runs once the swapchains have room for the next frame, and the GPU is at most the
+/// frames in flight behind, so it is paced together with the CPU.
+#[derive(Resource, Reflect, Debug, Clone, Copy, Default)]
+#[reflect(Resource, Default)]
+pub struct FrameLimiter {
+    /// Frames per second the app is capped to, `None` doesn't limit.
+    pub max_fps: Option<f32>,
+    pub wait: FrameWait,
+    #[reflect(ignore)]
+    next_frame: Option<Instant>,
+}
+
+// Windows sleeps are only accurate to about a millisecond
+const SPIN_MARGIN: Duration = Duration::from_millis(2);
+
+// runs right after switch_frame waited for the frame latency waitable
+pub(super) fn limit_frame_rate(mut limiter: ResMut<FrameLimiter>) {
+    let Some(max_fps) = limiter.max_fps.filter(|max_fps| *max_fps > 0.0) else {
+        limiter.next_frame = None;
+        return;
+    };
+    let interval = Duration::from_secs_f32(1.0 / max_fps);
+
+    let now = Instant::now();
+    let due = match limiter.next_frame {
+        Some(due) if due > now => {
+            wait_until(due, limiter.wait);
+            due
+        }
+        // a frame that took too long doesn't make the next ones shorter
+        _ => now,
+    };
+    limiter.next_frame = Some(due + interval);
+}
+
+fn wait_until(due: Instant, wait: FrameWait) {
+    let sleep_until = match wait {
+        FrameWait::Sleep => due,
+        FrameWait::Spin => Instant::now(),
+        FrameWait::SleepThenSpin => due - SPIN_MARGIN,
+    };
+    let now = Instant::now();
+    if sleep_until > now {
+        std::thread::sleep(sleep_until - now);
+    }
+    while Instant::now() < due {
+        std::hint::spin_loop();
+    }
+}
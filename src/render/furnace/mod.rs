@@ -0,0 +1,179 @@
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+
+use super::{
+    pipelines::PipelineStorage, RenderFinished, RenderRequest, RenderSchedule, RenderSet,
+    ResetAccumulation, View,
+};
+use crate::core::{Camera, Material};
+
+/// Side of the square furnace render, in pixels.
+const RESOLUTION: u32 = 64;
+/// Distance of the sphere from the camera, in sphere radii.
+const SPHERE_DISTANCE: f32 = 3.0;
+/// Part of the sphere radius measured, pixels at the silhouette also see the environment.
+const MEASURED_RADIUS: f32 = 0.9;
+/// Largest error of a passing test.
+const TOLERANCE: f32 = 0.01;
+
+/// Renders a white furnace: a sphere lit only by a uniform white environment, in front of the
+/// camera and in place of the scene. An energy conserving BRDF without absorption reflects all
+/// light, so the sphere measures exactly as bright as the environment. Darker means the
+/// sampling or BRDF code loses energy, brighter that it gains some.
+///
+/// The sphere is shaded with `material` made white and non-emissive. The render goes through
+/// a [`RenderRequest`] of `samples` samples per pixel, written to `output` as `.exr`, and the
+/// result is sent as [`FurnaceTestFinished`].
+#[derive(Event, Debug, Clone)]
+pub struct FurnaceTest {
+    pub material: Material,
+    pub samples: u32,
+    pub output: PathBuf,
+}
+
+/// Sent when a [`FurnaceTest`] is measured.
+#[derive(Event, Debug, Clone, Copy)]
+pub struct FurnaceTestFinished {
+    /// Average reflectance of the sphere per channel, 1 for an energy conserving material.
+    pub albedo: Vec3,
+    /// Largest deviation of a channel of `albedo` from 1.
+    pub error: f32,
+}
+
+/// What the path tracer renders instead of the scene while a furnace test runs.
+pub(crate) struct FurnaceScene {
+    pub material: Material,
+    pub center: Vec3,
+    pub radius: f32,
+}
+
+pub struct FurnaceTestPlugin;
+
+impl Plugin for FurnaceTestPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<FurnaceTest>()
+            .add_event::<FurnaceTestFinished>()
+            .add_systems(Update, (start_furnace_test, measure_furnace_test))
+            .add_systems(
+                RenderSchedule,
+                prepare_furnace_test.in_set(RenderSet::Prepare),
+            );
+    }
+}
+
+#[derive(Resource)]
+struct ActiveFurnaceTest {
+    scene: FurnaceScene,
+    view: View,
+    output: PathBuf,
+}
+
+fn start_furnace_test(
+    mut commands: Commands,
+    mut requests: EventReader<FurnaceTest>,
+    active: Option<Res<ActiveFurnaceTest>>,
+    cameras: Query<(&Camera, &GlobalTransform)>,
+    mut render_requests: EventWriter<RenderRequest>,
+) {
+    let Some(request) = requests.read().last() else {
+        return;
+    };
+    if active.is_some() {
+        warn!("A furnace test is already running, ignoring the new one");
+        return;
+    }
+    let Ok((camera, transform)) = cameras.get_single() else {
+        warn!("Furnace tests need exactly one camera");
+        return;
+    };
+
+    let camera = Camera {
+        aspect_ratio: 1.0,
+        ..camera.clone()
+    };
+    let material = Material {
+        base_color: Color::WHITE,
+        base_color_texture: None,
+        emissive: LinearRgba::BLACK,
+        ..request.material.clone()
+    };
+    let output = request.output.with_extension("exr");
+    commands.insert_resource(ActiveFurnaceTest {
+        scene: FurnaceScene {
+            material,
+            center: transform.translation() + *transform.forward() * SPHERE_DISTANCE,
+            radius: 1.0,
+        },
+        view: View::new(transform, &camera),
+        output: output.clone(),
+    });
+    render_requests.send(RenderRequest {
+        width: RESOLUTION,
+        height: RESOLUTION,
+        samples: request.samples,
+        output,
+        tile_size: None,
+    });
+}
+
+fn prepare_furnace_test(
+    active: Option<Res<ActiveFurnaceTest>>,
+    mut pipelines: ResMut<PipelineStorage>,
+) {
+    let scene = active.as_ref().map(|active| &active.scene);
+    for pipeline in pipelines.values_mut() {
+        pipeline.set_furnace_scene(scene);
+    }
+}
+
+fn measure_furnace_test(
+    mut commands: Commands,
+    active: Option<Res<ActiveFurnaceTest>>,
+    mut render_finished: EventReader<RenderFinished>,
+    mut finished_events: EventWriter<FurnaceTestFinished>,
+    mut reset_accumulation: EventWriter<ResetAccumulation>,
+) {
+    let Some(active) = active else {
+        return;
+    };
+    if !render_finished
+        .read()
+        .any(|finished| finished.output == active.output)
+    {
+        return;
+    }
+    commands.remove_resource::<ActiveFurnaceTest>();
+    // windows may have drawn a frame of the furnace in the meantime
+    reset_accumulation.send(ResetAccumulation);
+
+    let image = image::open(&active.output)
+        .expect("failed to read furnace render")
+        .into_rgba32f();
+    let measured_radius = active.scene.radius * MEASURED_RADIUS;
+    let mut sum = Vec3::ZERO;
+    let mut count = 0;
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let position = (UVec2::new(x, y).as_vec2() + 0.5) / RESOLUTION as f32;
+        let ray = active.view.ray(position);
+        let to_center = active.scene.center - ray.origin;
+        let closest = ray.get_point(to_center.dot(*ray.direction).max(0.0));
+        if closest.distance(active.scene.center) < measured_radius {
+            sum += Vec3::new(pixel[0], pixel[1], pixel[2]);
+            count += 1;
+        }
+    }
+    if count == 0 {
+        error!("Furnace test failed, the sphere didn't cover any pixel");
+        return;
+    }
+
+    let albedo = sum / count as f32;
+    let error = (albedo - Vec3::ONE).abs().max_element();
+    if error <= TOLERANCE {
+        info!("Furnace test passed, albedo {albedo}");
+    } else {
+        warn!("Furnace test failed, albedo {albedo} is off by {error}");
+    }
+    finished_events.send(FurnaceTestFinished { albedo, error });
+}
@@ -0,0 +1,120 @@
+mod scene;
+
+use bevy::{prelude::*, transform::TransformSystem};
+
+pub use scene::{GizmoVisibility, SceneGizmos};
+
+/// Lines drawn over the tone mapped image of every window and of [`super::Headless`], for
+/// debugging. Lines added during a frame are drawn at its end and cleared, so systems add
+/// them again every frame they should be seen.
+///
+/// Gizmos aren't depth tested, they show through the scene. Offline renders don't draw them.
+#[derive(Resource, Default)]
+pub struct Gizmos {
+    vertices: Vec<GizmoVertex>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct GizmoVertex {
+    position: [f32; 3],
+    // linear
+    color: [f32; 3],
+}
+
+// segments of circles, enough to look round at the sizes lights are usually placed at
+const CIRCLE_SEGMENTS: usize = 32;
+
+impl Gizmos {
+    pub fn line(&mut self, start: Vec3, end: Vec3, color: Color) {
+        let color = color.to_linear().to_f32_array_no_alpha();
+        self.vertices.extend([
+            GizmoVertex {
+                position: start.to_array(),
+                color,
+            },
+            GizmoVertex {
+                position: end.to_array(),
+                color,
+            },
+        ]);
+    }
+
+    /// Lines connecting consecutive `points`.
+    pub fn linestrip(&mut self, points: impl IntoIterator<Item = Vec3>, color: Color) {
+        let mut points = points.into_iter();
+        let Some(mut previous) = points.next() else {
+            return;
+        };
+        for point in points {
+            self.line(previous, point, color);
+            previous = point;
+        }
+    }
+
+    /// Circle around `center` in the plane orthogonal to `normal`.
+    pub fn circle(&mut self, center: Vec3, normal: Dir3, radius: f32, color: Color) {
+        let (x, y) = normal.any_orthonormal_pair();
+        self.linestrip(
+            (0..=CIRCLE_SEGMENTS).map(|segment| {
+                let angle = segment as f32 / CIRCLE_SEGMENTS as f32 * std::f32::consts::TAU;
+                center + radius * (angle.cos() * x + angle.sin() * y)
+            }),
+            color,
+        );
+    }
+
+    /// Three circles around the axes.
+    pub fn sphere(&mut self, center: Vec3, radius: f32, color: Color) {
+        for normal in [Dir3::X, Dir3::Y, Dir3::Z] {
+            self.circle(center, normal, radius, color);
+        }
+    }
+
+    /// Line with a head at `end`.
+    pub fn arrow(&mut self, start: Vec3, end: Vec3, color: Color) {
+        self.line(start, end, color);
+        let Ok(direction) = Dir3::new(end - start) else {
+            return;
+        };
+        let head_length = 0.2 * start.distance(end);
+        let (x, y) = direction.any_orthonormal_pair();
+        for side in [x, -x, y, -y] {
+            let back = end - head_length * (*direction - 0.5 * side);
+            self.line(end, back, color);
+        }
+    }
+
+    /// Number of lines added this frame.
+    pub fn len(&self) -> usize {
+        self.vertices.len() / 2
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    pub fn clear(&mut self) {
+        self.vertices.clear();
+    }
+
+    pub(crate) fn vertices(&self) -> &[GizmoVertex] {
+        &self.vertices
+    }
+}
+
+pub struct GizmoPlugin;
+
+impl Plugin for GizmoPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<Gizmos>()
+            .init_resource::<SceneGizmos>()
+            .register_type::<SceneGizmos>()
+            .register_type::<GizmoVisibility>()
+            .add_systems(
+                PostUpdate,
+                (scene::camera_gizmos, scene::light_gizmos)
+                    .after(TransformSystem::TransformPropagate),
+            );
+    }
+}
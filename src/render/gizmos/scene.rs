@@ -0,0 +1,189 @@
+use bevy::prelude::*;
+
+use super::Gizmos;
+use crate::{
+    core::{Camera, DirectionalLight, DiskLight, PointLight, RectLight, SpotLight},
+    render::View,
+};
+
+/// Built-in [`Gizmos`] of cameras and lights, to check where they are placed and what they
+/// cover. Entities can override these with [`GizmoVisibility`].
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq)]
+#[reflect(Resource, Default)]
+pub struct SceneGizmos {
+    /// Frustum wireframes of cameras. The frustum of the camera drawing it lies on the edges
+    /// of its own image.
+    pub cameras: bool,
+    /// Range spheres of point lights, cones of spot lights, outlines of area lights and arrows
+    /// along the direction lights emit in.
+    pub lights: bool,
+    /// Distance frustums are drawn to, cameras have no far plane.
+    pub frustum_length: f32,
+    /// Length of the arrows of directional and area lights.
+    pub arrow_length: f32,
+}
+
+impl Default for SceneGizmos {
+    fn default() -> Self {
+        Self {
+            cameras: false,
+            lights: false,
+            frustum_length: 1.0,
+            arrow_length: 1.0,
+        }
+    }
+}
+
+/// Whether the built-in gizmos of an entity are drawn, overriding [`SceneGizmos`].
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Component, Default)]
+pub enum GizmoVisibility {
+    /// Follows [`SceneGizmos`].
+    #[default]
+    Inherited,
+    Visible,
+    Hidden,
+}
+
+impl GizmoVisibility {
+    fn shows(visibility: Option<&GizmoVisibility>, global: bool) -> bool {
+        match visibility.copied().unwrap_or_default() {
+            GizmoVisibility::Inherited => global,
+            GizmoVisibility::Visible => true,
+            GizmoVisibility::Hidden => false,
+        }
+    }
+}
+
+const CAMERA_COLOR: Color = Color::srgb(0.9, 0.9, 0.9);
+
+pub(super) fn camera_gizmos(
+    cameras: Query<(&Camera, &GlobalTransform, Option<&GizmoVisibility>)>,
+    settings: Res<SceneGizmos>,
+    mut gizmos: ResMut<Gizmos>,
+) {
+    for (camera, transform, visibility) in &cameras {
+        if !GizmoVisibility::shows(visibility, settings.cameras) {
+            continue;
+        }
+
+        let view = View::new(transform, camera);
+        let length = settings.frustum_length;
+        let half_height = (camera.fov * 0.5).tan() * length;
+        let half_width = half_height * camera.aspect_ratio;
+        let camera_to_world = view.inverse_view_matrix();
+        let corner = |x: f32, y: f32| {
+            camera_to_world.transform_point3(Vec3::new(x * half_width, y * half_height, -length))
+        };
+        // clockwise from the top left
+        let corners = [
+            corner(-1.0, 1.0),
+            corner(1.0, 1.0),
+            corner(1.0, -1.0),
+            corner(-1.0, -1.0),
+        ];
+
+        for corner in corners {
+            gizmos.line(view.origin(), corner, CAMERA_COLOR);
+        }
+        gizmos.linestrip(corners.into_iter().chain([corners[0]]), CAMERA_COLOR);
+        // a triangle over the top edge tells up from down
+        gizmos.linestrip([corners[0], corner(0.0, 1.5), corners[1]], CAMERA_COLOR);
+    }
+}
+
+type LightGizmoQuery<'w, 's, L> = Query<
+    'w,
+    's,
+    (
+        &'static L,
+        &'static GlobalTransform,
+        Option<&'static GizmoVisibility>,
+    ),
+>;
+
+pub(super) fn light_gizmos(
+    point_lights: LightGizmoQuery<PointLight>,
+    spot_lights: LightGizmoQuery<SpotLight>,
+    directional_lights: LightGizmoQuery<DirectionalLight>,
+    rect_lights: LightGizmoQuery<RectLight>,
+    disk_lights: LightGizmoQuery<DiskLight>,
+    settings: Res<SceneGizmos>,
+    mut gizmos: ResMut<Gizmos>,
+) {
+    let shows = |visibility| GizmoVisibility::shows(visibility, settings.lights);
+    let arrow_length = settings.arrow_length;
+
+    for (light, transform, visibility) in &point_lights {
+        if shows(visibility) {
+            gizmos.sphere(transform.translation(), light.range, light.color);
+        }
+    }
+
+    for (light, transform, visibility) in &spot_lights {
+        if !shows(visibility) {
+            continue;
+        }
+        // the cone is capped where the outer angle meets the range sphere
+        let apex = transform.translation();
+        let cap_center = apex + transform.forward() * light.range * light.outer_angle.cos();
+        let cap_radius = light.range * light.outer_angle.sin();
+        gizmos.circle(cap_center, transform.forward(), cap_radius, light.color);
+        for side in [
+            transform.right(),
+            transform.left(),
+            transform.up(),
+            transform.down(),
+        ] {
+            gizmos.line(apex, cap_center + side * cap_radius, light.color);
+        }
+    }
+
+    for (light, transform, visibility) in &directional_lights {
+        if shows(visibility) {
+            let start = transform.translation();
+            gizmos.arrow(
+                start,
+                start + transform.forward() * arrow_length,
+                light.color,
+            );
+        }
+    }
+
+    for (light, transform, visibility) in &rect_lights {
+        if !shows(visibility) {
+            continue;
+        }
+        let center = transform.translation();
+        let right = transform.right() * light.width * 0.5;
+        let up = transform.up() * light.height * 0.5;
+        gizmos.linestrip(
+            [
+                center - right + up,
+                center + right + up,
+                center + right - up,
+                center - right - up,
+                center - right + up,
+            ],
+            light.color,
+        );
+        gizmos.arrow(
+            center,
+            center + transform.forward() * arrow_length,
+            light.color,
+        );
+    }
+
+    for (light, transform, visibility) in &disk_lights {
+        if !shows(visibility) {
+            continue;
+        }
+        let center = transform.translation();
+        gizmos.circle(center, transform.forward(), light.radius, light.color);
+        gizmos.arrow(
+            center,
+            center + transform.forward() * arrow_length,
+            light.color,
+        );
+    }
+}
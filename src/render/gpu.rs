@@ -3,40 +3,113 @@ use core::ffi::c_void;
 use std::{backtrace::Backtrace, ptr};
 use windows::{
     core::{Error, Interface, PCSTR},
-    Win32::Graphics::{
-        Direct3D::D3D_FEATURE_LEVEL_12_2,
-        Direct3D12::*,
-        Dxgi::{
-            CreateDXGIFactory2, IDXGIAdapter4, IDXGIFactory7, DXGI_CREATE_FACTORY_DEBUG,
-            DXGI_CREATE_FACTORY_FLAGS, DXGI_GPU_PREFERENCE_HIGH_PERFORMANCE,
+    Win32::{
+        Foundation::BOOL,
+        Graphics::{
+            Direct3D::D3D_FEATURE_LEVEL_12_2,
+            Direct3D12::*,
+            Dxgi::Common::DXGI_FORMAT,
+            Dxgi::{
+                CreateDXGIFactory2, IDXGIAdapter4, IDXGIFactory7, DXGI_CREATE_FACTORY_DEBUG,
+                DXGI_CREATE_FACTORY_FLAGS, DXGI_ERROR_UNSUPPORTED,
+                DXGI_FEATURE_PRESENT_ALLOW_TEARING, DXGI_GPU_PREFERENCE_HIGH_PERFORMANCE,
+            },
         },
     },
 };
 
+use super::{
+    bindless::{BindlessHeap, MIN_RESOURCE_BINDING_TIER},
+    constant_buffer::ConstantRing,
+    descriptor_allocator::DescriptorAllocators,
+    descriptor_ring::DescriptorRing,
+    device_removed::enable_removal_data,
+    gpu_features::GpuFeatures,
+    quirks::{quirks_for_adapter, vendor_name, DriverQuirks},
+    retired_resources::RetiredResources,
+    set_debug_name,
+};
+
+/// Scheduling priority of [`Gpu::queue`], the queue the frames are rendered on.
+#[derive(Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueuePriority {
+    #[default]
+    Normal,
+    /// Preferred over normal priority queues of this and other processes.
+    High,
+    /// Preferred over everything else on the GPU. Needs the process to hold the increase base
+    /// priority privilege, without it [`QueuePriority::High`] is used.
+    GlobalRealtime,
+}
+
+impl QueuePriority {
+    fn d3d12(&self) -> D3D12_COMMAND_QUEUE_PRIORITY {
+        match self {
+            QueuePriority::Normal => D3D12_COMMAND_QUEUE_PRIORITY_NORMAL,
+            QueuePriority::High => D3D12_COMMAND_QUEUE_PRIORITY_HIGH,
+            QueuePriority::GlobalRealtime => D3D12_COMMAND_QUEUE_PRIORITY_GLOBAL_REALTIME,
+        }
+    }
+}
+
+/// How the [`Gpu`] is created. The device is created while [`super::RenderPlugin`] is built,
+/// insert this before adding it for anything but the defaults.
+#[derive(Resource, Reflect, Debug, Clone, Copy, Default)]
+#[reflect(Resource, Default)]
+pub struct GpuSettings {
+    pub queue_priority: QueuePriority,
+    /// Creates [`Gpu::copy_queue`] for uploads that may trail the frames. It always runs at
+    /// normal priority, behind a [`QueuePriority::High`] frame queue.
+    pub background_copy_queue: bool,
+    /// Creates the device on WARP, the software rasterizer, without trying the hardware
+    /// adapter first.
+    pub force_warp: bool,
+}
+
 #[derive(Resource)]
 pub struct Gpu {
     pub factory: IDXGIFactory7,
     pub device: ID3D12Device9,
     pub queue: ID3D12CommandQueue,
-    pub command_allocator: ID3D12CommandAllocator,
+    /// Set with [`GpuSettings::background_copy_queue`].
+    pub copy_queue: Option<ID3D12CommandQueue>,
+    pub quirks: DriverQuirks,
+    /// Whether the device runs on WARP, the software rasterizer.
+    pub warp: bool,
+    /// Shader invisible descriptors of render targets, depth targets and textures.
+    pub descriptors: DescriptorAllocators,
+    /// Shader visible descriptors passes stage for the frame being recorded.
+    pub descriptor_ring: DescriptorRing,
+    /// Views of the scene, read by shaders through their ID.
+    pub bindless: BindlessHeap,
+    /// Constants written for the frames in flight.
+    pub constant_ring: ConstantRing,
+    /// Resources replaced while recording, dropped once the frames in flight are finished.
+    pub retired: RetiredResources,
 }
 
 impl Gpu {
+    /// Creates the device on the hardware adapter, falling back to WARP when that fails, so
+    /// the renderer also runs in VMs and on CI machines without a GPU.
     #[allow(clippy::missing_safety_doc)]
-    pub unsafe fn new(use_warp: bool) -> Result<Self, Error> {
-        let mut factory_flags = DXGI_CREATE_FACTORY_FLAGS(0);
+    pub unsafe fn with_warp_fallback(settings: GpuSettings) -> Result<Self, Error> {
+        if settings.force_warp {
+            return Self::new(true, settings);
+        }
+        Self::new(false, settings).or_else(|error| {
+            warn!("Failed to create the hardware device, falling back to WARP: {error}");
+            Self::new(true, settings)
+        })
+    }
 
+    #[allow(clippy::missing_safety_doc)]
+    pub unsafe fn new(use_warp: bool, settings: GpuSettings) -> Result<Self, Error> {
         let enable_debug_layer = cfg!(debug_assertions);
-
-        if enable_debug_layer {
-            let mut debug_interface: Option<ID3D12Debug4> = None;
-            D3D12GetDebugInterface(&mut debug_interface)?;
-            let debug_interface = debug_interface.unwrap();
-
-            debug_interface.EnableDebugLayer();
-            debug_interface.SetEnableGPUBasedValidation(true);
-            factory_flags = DXGI_CREATE_FACTORY_DEBUG;
-        }
+        let factory_flags = if enable_debug_layer {
+            DXGI_CREATE_FACTORY_DEBUG
+        } else {
+            DXGI_CREATE_FACTORY_FLAGS(0)
+        };
 
         let factory: IDXGIFactory7 = CreateDXGIFactory2(factory_flags)?;
 
@@ -45,11 +118,41 @@ impl Gpu {
         } else {
             factory.EnumAdapterByGpuPreference(0, DXGI_GPU_PREFERENCE_HIGH_PERFORMANCE)?
         };
+        let adapter_desc = adapter.GetDesc3()?;
+        info!(
+            "Using {} adapter {:#06x}:{:#06x}",
+            vendor_name(adapter_desc.VendorId),
+            adapter_desc.VendorId,
+            adapter_desc.DeviceId
+        );
+        let quirks = quirks_for_adapter(adapter_desc.VendorId, adapter_desc.DeviceId);
+
+        // The debug layer has to be enabled before the device is created
+        if enable_debug_layer {
+            let mut debug_interface: Option<ID3D12Debug4> = None;
+            D3D12GetDebugInterface(&mut debug_interface)?;
+            let debug_interface = debug_interface.unwrap();
+
+            debug_interface.EnableDebugLayer();
+            debug_interface.SetEnableGPUBasedValidation(!quirks.disable_gpu_based_validation);
+            enable_removal_data()?;
+        }
 
         let mut device: Option<ID3D12Device9> = None;
         D3D12CreateDevice(&adapter, D3D_FEATURE_LEVEL_12_2, &mut device)?;
         let device = device.unwrap();
 
+        let resource_binding_tier = GpuFeatures::of_device(&device).resource_binding_tier;
+        if resource_binding_tier < MIN_RESOURCE_BINDING_TIER {
+            return Err(Error::new(
+                DXGI_ERROR_UNSUPPORTED,
+                format!(
+                    "The bindless heap needs resource binding tier {MIN_RESOURCE_BINDING_TIER}, \
+                    the device only supports tier {resource_binding_tier}"
+                ),
+            ));
+        }
+
         if enable_debug_layer {
             let info_queue = device.cast::<ID3D12InfoQueue1>()?;
             let mut cookie = 0;
@@ -64,20 +167,91 @@ impl Gpu {
             }
         }
 
-        let queue: ID3D12CommandQueue = device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
+        let queue_desc = |priority: QueuePriority| D3D12_COMMAND_QUEUE_DESC {
             Type: D3D12_COMMAND_LIST_TYPE_DIRECT,
+            Priority: priority.d3d12().0,
             ..Default::default()
-        })?;
+        };
+        let queue: ID3D12CommandQueue =
+            match device.CreateCommandQueue(&queue_desc(settings.queue_priority)) {
+                Err(error) if settings.queue_priority == QueuePriority::GlobalRealtime => {
+                    warn!("no global realtime queue priority ({error}), using high priority");
+                    device.CreateCommandQueue(&queue_desc(QueuePriority::High))?
+                }
+                result => result?,
+            };
+        let copy_queue = if settings.background_copy_queue {
+            let copy_queue: ID3D12CommandQueue =
+                device.CreateCommandQueue(&D3D12_COMMAND_QUEUE_DESC {
+                    Type: D3D12_COMMAND_LIST_TYPE_COPY,
+                    Priority: D3D12_COMMAND_QUEUE_PRIORITY_NORMAL.0,
+                    ..Default::default()
+                })?;
+            set_debug_name(&copy_queue, "background copy queue");
+            Some(copy_queue)
+        } else {
+            None
+        };
 
-        let command_allocator = device.CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT)?;
+        set_debug_name(&queue, "direct queue");
+        let descriptors = DescriptorAllocators::new(&device);
+        let descriptor_ring = DescriptorRing::new(&device);
+        let bindless = BindlessHeap::new(&device);
+        let constant_ring = ConstantRing::new(&device);
 
         Ok(Self {
             factory,
             device,
             queue,
-            command_allocator,
+            copy_queue,
+            quirks,
+            warp: use_warp,
+            descriptors,
+            descriptor_ring,
+            bindless,
+            constant_ring,
+            retired: RetiredResources::default(),
         })
     }
+
+    /// Highest sample count up to `sample_count` the device can render `format` with.
+    pub fn supported_sample_count(&self, format: DXGI_FORMAT, sample_count: u32) -> u32 {
+        let mut count = sample_count.max(1);
+        while count > 1 {
+            let mut levels = D3D12_FEATURE_DATA_MULTISAMPLE_QUALITY_LEVELS {
+                Format: format,
+                SampleCount: count,
+                Flags: D3D12_MULTISAMPLE_QUALITY_LEVELS_FLAG_NONE,
+                NumQualityLevels: 0,
+            };
+            let supported = unsafe {
+                self.device.CheckFeatureSupport(
+                    D3D12_FEATURE_MULTISAMPLE_QUALITY_LEVELS,
+                    &mut levels as *mut _ as *mut c_void,
+                    std::mem::size_of_val(&levels) as u32,
+                )
+            };
+            if supported.is_ok() && levels.NumQualityLevels > 0 {
+                return count;
+            }
+            count /= 2;
+        }
+        1
+    }
+
+    /// Whether swapchains can present without waiting for the vertical blank and tear, which
+    /// variable refresh rate displays need to follow the frame rate.
+    pub fn supports_tearing(&self) -> bool {
+        let mut allow_tearing = BOOL(0);
+        let supported = unsafe {
+            self.factory.CheckFeatureSupport(
+                DXGI_FEATURE_PRESENT_ALLOW_TEARING,
+                &mut allow_tearing as *mut _ as *mut c_void,
+                std::mem::size_of_val(&allow_tearing) as u32,
+            )
+        };
+        supported.is_ok() && allow_tearing.as_bool()
+    }
 }
 
 #[allow(clippy::missing_safety_doc)]
@@ -0,0 +1,90 @@
+use bevy::prelude::*;
+use core::ffi::c_void;
+use windows::Win32::Graphics::Direct3D12::*;
+
+use super::Gpu;
+
+/// Optional features of the device, queried when it is created and again for every new
+/// device. Pipelines check them to choose between code paths.
+#[derive(Resource, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Resource, Default)]
+pub struct GpuFeatures {
+    /// Highest shader model as `(major, minor)`, `(5, 1)` on devices without DXIL shaders.
+    pub shader_model: (u32, u32),
+    /// DXR tier as `(major, minor)`, none without hardware raytracing.
+    pub raytracing_tier: Option<(u32, u32)>,
+    /// 1 to 3, how many descriptors shaders can reach. Tier 3 allows fully bindless heaps.
+    pub resource_binding_tier: u32,
+    pub mesh_shaders: bool,
+    /// Whether `ID3D12GraphicsCommandList7::Barrier` can be used in place of resource barriers.
+    pub enhanced_barriers: bool,
+}
+
+impl GpuFeatures {
+    pub fn new(gpu: &Gpu) -> Self {
+        let features = Self::of_device(&gpu.device);
+        info!("{features:?}");
+        features
+    }
+
+    /// Same as [`GpuFeatures::new`] without logging, for checks before the [`Gpu`] exists.
+    pub(super) fn of_device(device: &ID3D12Device9) -> Self {
+        let options: D3D12_FEATURE_DATA_D3D12_OPTIONS =
+            check_feature_support(device, D3D12_FEATURE_D3D12_OPTIONS, Default::default())
+                .unwrap_or_default();
+        let options5: D3D12_FEATURE_DATA_D3D12_OPTIONS5 =
+            check_feature_support(device, D3D12_FEATURE_D3D12_OPTIONS5, Default::default())
+                .unwrap_or_default();
+        let options7: D3D12_FEATURE_DATA_D3D12_OPTIONS7 =
+            check_feature_support(device, D3D12_FEATURE_D3D12_OPTIONS7, Default::default())
+                .unwrap_or_default();
+        // older runtimes don't know the options of enhanced barriers
+        let options12: D3D12_FEATURE_DATA_D3D12_OPTIONS12 =
+            check_feature_support(device, D3D12_FEATURE_D3D12_OPTIONS12, Default::default())
+                .unwrap_or_default();
+
+        let raytracing_tier = match options5.RaytracingTier {
+            D3D12_RAYTRACING_TIER_NOT_SUPPORTED => None,
+            tier => Some((tier.0 as u32 / 10, tier.0 as u32 % 10)),
+        };
+        Self {
+            shader_model: highest_shader_model(device),
+            raytracing_tier,
+            resource_binding_tier: options.ResourceBindingTier.0 as u32,
+            mesh_shaders: options7.MeshShaderTier != D3D12_MESH_SHADER_TIER_NOT_SUPPORTED,
+            enhanced_barriers: options12.EnhancedBarriersSupported.as_bool(),
+        }
+    }
+}
+
+// the runtime rejects shader models newer than it knows, so they are asked for from the newest
+fn highest_shader_model(device: &ID3D12Device9) -> (u32, u32) {
+    (D3D_SHADER_MODEL_6_0.0..=D3D_SHADER_MODEL_6_9.0)
+        .rev()
+        .find_map(|model| {
+            let data = D3D12_FEATURE_DATA_SHADER_MODEL {
+                HighestShaderModel: D3D_SHADER_MODEL(model),
+            };
+            check_feature_support(device, D3D12_FEATURE_SHADER_MODEL, data).ok()
+        })
+        .map_or((5, 1), |data| {
+            let model = data.HighestShaderModel.0 as u32;
+            (model >> 4, model & 0xf)
+        })
+}
+
+/// Fills `data` with the support of `feature`, some features read what is asked for from it.
+fn check_feature_support<T>(
+    device: &ID3D12Device9,
+    feature: D3D12_FEATURE,
+    mut data: T,
+) -> windows::core::Result<T> {
+    unsafe {
+        device.CheckFeatureSupport(
+            feature,
+            &mut data as *mut _ as *mut c_void,
+            std::mem::size_of::<T>() as u32,
+        )
+    }?;
+    Ok(data)
+}
@@ -0,0 +1,96 @@
+//! Timelines of the work submitted to the GPU queues.
+
+use windows::Win32::{
+    Foundation::WAIT_OBJECT_0,
+    Graphics::Direct3D12::*,
+    System::Threading::{CreateEventA, WaitForSingleObject},
+};
+
+use super::{
+    device_removed::is_device_removed,
+    fence_timeout::{report_hang, FenceTimeout},
+    set_debug_name, Gpu,
+};
+use crate::win_types::WinHandle;
+
+/// Fence whose value grows by one with every signal, so each value marks the work submitted
+/// to a queue before it. The CPU polls or waits for a value, other queues wait for it on the
+/// GPU.
+pub struct GpuFence {
+    fence: ID3D12Fence,
+    event: WinHandle,
+    last_signaled: u64,
+}
+
+impl GpuFence {
+    #[track_caller]
+    pub fn new(gpu: &Gpu, name: &str) -> Self {
+        let fence: ID3D12Fence = unsafe { gpu.device.CreateFence(0, D3D12_FENCE_FLAG_NONE) }
+            .expect("failed to create fence");
+        set_debug_name(&fence, name);
+        let event =
+            unsafe { CreateEventA(None, false, false, None).expect("Failed to create event") };
+        Self {
+            fence,
+            event: WinHandle(event),
+            last_signaled: 0,
+        }
+    }
+
+    /// Signals the next value once the GPU finished the work submitted to `queue` so far, and
+    /// returns it. Signals on a removed device are dropped, its fences count as complete.
+    pub fn signal(&mut self, queue: &ID3D12CommandQueue) -> u64 {
+        self.last_signaled += 1;
+        let result = unsafe { queue.Signal(&self.fence, self.last_signaled) };
+        if let Err(error) = result {
+            if !is_device_removed(error.code()) {
+                panic!("Signal Fence failed: {error}");
+            }
+        }
+        self.last_signaled
+    }
+
+    /// Value of the last [`GpuFence::signal`], 0 before the first one.
+    pub fn last_signaled(&self) -> u64 {
+        self.last_signaled
+    }
+
+    pub fn completed(&self) -> u64 {
+        unsafe { self.fence.GetCompletedValue() }
+    }
+
+    pub fn is_complete(&self, value: u64) -> bool {
+        self.completed() >= value
+    }
+
+    /// Blocks until the fence reaches `value`. A wait running out of `timeout` reports the
+    /// hang and panics, `waiting_for` names the work in the report.
+    pub fn wait(&self, gpu: &Gpu, value: u64, timeout: FenceTimeout, waiting_for: &str) {
+        if self.is_complete(value) {
+            return;
+        }
+        unsafe { self.fence.SetEventOnCompletion(value, self.event.0) }
+            .expect("SetEventOnCompletion failed");
+        let millis = timeout.timeout.as_millis().min(u32::MAX as u128 - 1) as u32;
+        if unsafe { WaitForSingleObject(self.event.0, millis) } == WAIT_OBJECT_0 {
+            return;
+        }
+
+        report_hang(gpu, &self.fence, value, timeout, waiting_for);
+        panic!(
+            "GPU didn't finish {waiting_for} within {:?}",
+            timeout.timeout
+        );
+    }
+
+    /// Blocks until the work of the last signal is finished.
+    pub fn wait_for_last(&self, gpu: &Gpu, timeout: FenceTimeout, waiting_for: &str) {
+        self.wait(gpu, self.last_signaled, timeout, waiting_for);
+    }
+
+    /// Makes `queue` wait on the GPU until the fence reaches `value` before it starts the work
+    /// submitted after, without blocking the CPU.
+    pub fn queue_wait(&self, queue: &ID3D12CommandQueue, value: u64) {
+        unsafe { queue.Wait(&self.fence, value) }.expect("Wait on fence failed");
+    }
+}
@@ -0,0 +1,317 @@
+use bevy::prelude::*;
+use windows::Win32::Graphics::{
+    Direct3D12::*,
+    Dxgi::Common::{DXGI_FORMAT_UNKNOWN, DXGI_SAMPLE_DESC},
+};
+
+use super::{
+    d3d::read_buffer, fence_timeout::FenceTimeout, render_target::FRAME_COUNT, set_debug_name,
+    Drawer, Gpu,
+};
+
+// enough for every pass of a handful of views
+const MAX_TIMESTAMPS: u32 = 256;
+
+/// GPU time spent in the last finished frame, in milliseconds.
+#[derive(Resource, Reflect, Debug, Clone, Default)]
+#[reflect(Resource, Default)]
+pub struct GpuTimings {
+    /// The views drawn in the frame, in the order they were drawn.
+    pub views: Vec<ViewTimings>,
+}
+
+impl GpuTimings {
+    /// Time of all views.
+    pub fn total(&self) -> f64 {
+        self.views.iter().map(|view| view.total).sum()
+    }
+
+    /// Time of the passes named `name` in all views, zero when none ran.
+    pub fn pass(&self, name: &str) -> f64 {
+        self.views
+            .iter()
+            .flat_map(|view| &view.passes)
+            .filter(|pass| pass.name == name)
+            .map(|pass| pass.milliseconds)
+            .sum()
+    }
+}
+
+/// GPU time of one view, its render graph nodes are timed one by one.
+#[derive(Reflect, Debug, Clone, Default)]
+pub struct ViewTimings {
+    /// In the order the passes ran.
+    pub passes: Vec<PassTiming>,
+    /// From the first to the last pass, with the barriers between them.
+    pub total: f64,
+}
+
+#[derive(Reflect, Debug, Clone, Default)]
+pub struct PassTiming {
+    pub name: String,
+    pub milliseconds: f64,
+}
+
+/// Timestamps of a view in the frame being recorded, indices into the query heap. `None` for
+/// timestamps that didn't fit into the heap.
+struct TimedView {
+    first: u32,
+    begin: Option<u32>,
+    end: Option<u32>,
+    passes: Vec<TimedPass>,
+}
+
+struct TimedPass {
+    name: &'static str,
+    begin: Option<u32>,
+    end: Option<u32>,
+}
+
+/// Views timed in a frame slot.
+#[derive(Default)]
+struct TimedFrame {
+    // queries written in the frame
+    count: u32,
+    views: Vec<TimedView>,
+    resolved: bool,
+}
+
+/// Timestamp query heap written by the drawer, every view gets its own range of queries that is
+/// resolved when the view is done. Each frame slot resolves into its own part of the readback
+/// buffer, which is read while the next frame is on the GPU.
+pub(crate) struct TimestampQueries {
+    heap: ID3D12QueryHeap,
+    readback_buffer: ID3D12Resource,
+    frequency: u64,
+    frames: [TimedFrame; FRAME_COUNT],
+    frame_slot: usize,
+}
+
+impl TimestampQueries {
+    pub fn new(gpu: &Gpu) -> Self {
+        let mut heap: Option<ID3D12QueryHeap> = None;
+        unsafe {
+            gpu.device.CreateQueryHeap(
+                &D3D12_QUERY_HEAP_DESC {
+                    Type: D3D12_QUERY_HEAP_TYPE_TIMESTAMP,
+                    Count: MAX_TIMESTAMPS,
+                    NodeMask: 0,
+                },
+                &mut heap,
+            )
+        }
+        .expect("Failed to create timestamp query heap");
+
+        let desc = D3D12_RESOURCE_DESC {
+            Alignment: 0,
+            Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+            Width: (FRAME_COUNT * MAX_TIMESTAMPS as usize * std::mem::size_of::<u64>()) as u64,
+            Height: 1,
+            DepthOrArraySize: 1,
+            MipLevels: 1,
+            Format: DXGI_FORMAT_UNKNOWN,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                ..Default::default()
+            },
+            Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+            Flags: D3D12_RESOURCE_FLAG_NONE,
+        };
+        let mut readback_buffer: Option<ID3D12Resource> = None;
+        unsafe {
+            gpu.device.CreateCommittedResource(
+                &D3D12_HEAP_PROPERTIES {
+                    Type: D3D12_HEAP_TYPE_READBACK,
+                    ..Default::default()
+                },
+                D3D12_HEAP_FLAG_NONE,
+                &desc,
+                D3D12_RESOURCE_STATE_COPY_DEST,
+                None,
+                &mut readback_buffer,
+            )
+        }
+        .expect("Failed to create timestamp readback buffer");
+
+        let frequency = unsafe { gpu.queue.GetTimestampFrequency() }
+            .expect("Failed to get timestamp frequency");
+
+        let heap = heap.expect("CreateQueryHeap was successful but heap is None");
+        let readback_buffer =
+            readback_buffer.expect("CreateCommittedResource was successful but buffer is None");
+        set_debug_name(&heap, "timestamp query heap");
+        set_debug_name(&readback_buffer, "timestamp readback buffer");
+
+        Self {
+            heap,
+            readback_buffer,
+            frequency,
+            frames: Default::default(),
+            frame_slot: 0,
+        }
+    }
+
+    /// Forgets the views last timed in `frame_slot`, their timestamps must be read already.
+    pub fn begin_frame(&mut self, frame_slot: usize) {
+        self.frame_slot = frame_slot;
+        self.frames[frame_slot] = TimedFrame::default();
+    }
+
+    pub fn begin_view(&mut self, command_list: &ID3D12GraphicsCommandList) {
+        let first = self.frames[self.frame_slot].count;
+        let begin = self.write(command_list);
+        self.frames[self.frame_slot].views.push(TimedView {
+            first,
+            begin,
+            end: None,
+            passes: Vec::new(),
+        });
+    }
+
+    pub fn begin_pass(&mut self, command_list: &ID3D12GraphicsCommandList, name: &'static str) {
+        let begin = self.write(command_list);
+        let view = self.frames[self.frame_slot]
+            .views
+            .last_mut()
+            .expect("passes are timed in a view");
+        view.passes.push(TimedPass {
+            name,
+            begin,
+            end: None,
+        });
+    }
+
+    pub fn end_pass(&mut self, command_list: &ID3D12GraphicsCommandList) {
+        let end = self.write(command_list);
+        let view = self.frames[self.frame_slot]
+            .views
+            .last_mut()
+            .expect("passes are timed in a view");
+        view.passes.last_mut().expect("no pass was begun").end = end;
+    }
+
+    /// Writes the last timestamp of the view and resolves its range of queries.
+    pub fn end_view(&mut self, command_list: &ID3D12GraphicsCommandList) {
+        let end = self.write(command_list);
+        let first_in_buffer = self.frame_slot as u32 * MAX_TIMESTAMPS;
+        let frame = &mut self.frames[self.frame_slot];
+        let view = frame.views.last_mut().expect("no view was begun");
+        view.end = end;
+        if view.first == frame.count {
+            return;
+        }
+        unsafe {
+            command_list.ResolveQueryData(
+                &self.heap,
+                D3D12_QUERY_TYPE_TIMESTAMP,
+                view.first,
+                frame.count - view.first,
+                &self.readback_buffer,
+                (first_in_buffer + view.first) as u64 * std::mem::size_of::<u64>() as u64,
+            )
+        };
+        frame.resolved = true;
+    }
+
+    fn write(&mut self, command_list: &ID3D12GraphicsCommandList) -> Option<u32> {
+        let frame = &mut self.frames[self.frame_slot];
+        if frame.count == MAX_TIMESTAMPS {
+            warn_once!("Out of GPU timestamp queries, the remaining passes aren't timed");
+            return None;
+        }
+        let index = frame.count;
+        frame.count += 1;
+        unsafe { command_list.EndQuery(&self.heap, D3D12_QUERY_TYPE_TIMESTAMP, index) };
+        Some(index)
+    }
+
+    /// Returns the timings of the views last resolved in `frame_slot`, once. The frame they
+    /// were recorded in must be finished on the GPU.
+    pub(super) fn read(&mut self, frame_slot: usize) -> Option<GpuTimings> {
+        let frame = &mut self.frames[frame_slot];
+        if !std::mem::take(&mut frame.resolved) {
+            return None;
+        }
+
+        let mut timestamps = vec![0; frame.count as usize];
+        let start = frame_slot * MAX_TIMESTAMPS as usize * std::mem::size_of::<u64>();
+        let size = timestamps.len() * std::mem::size_of::<u64>();
+        read_buffer(&self.readback_buffer, start..start + size, |data| {
+            for (timestamp, bytes) in timestamps.iter_mut().zip(data.chunks_exact(8)) {
+                *timestamp = u64::from_le_bytes(bytes.try_into().unwrap());
+            }
+        });
+        let milliseconds = |begin: Option<u32>, end: Option<u32>| match (begin, end) {
+            (Some(begin), Some(end)) => {
+                Some(self.milliseconds(timestamps[begin as usize], timestamps[end as usize]))
+            }
+            _ => None,
+        };
+        let views = self.frames[frame_slot]
+            .views
+            .iter()
+            .map(|view| ViewTimings {
+                passes: view
+                    .passes
+                    .iter()
+                    .filter_map(|pass| {
+                        Some(PassTiming {
+                            name: pass.name.to_string(),
+                            milliseconds: milliseconds(pass.begin, pass.end)?,
+                        })
+                    })
+                    .collect(),
+                total: milliseconds(view.begin, view.end).unwrap_or_default(),
+            })
+            .collect();
+        Some(GpuTimings { views })
+    }
+    fn milliseconds(&self, start: u64, end: u64) -> f64 {
+        end.saturating_sub(start) as f64 * 1000.0 / self.frequency as f64
+    }
+}
+
+/// Runs at the end of the frame, reads the timings of the frame before it.
+pub fn read_gpu_timings(
+    gpu: Res<Gpu>,
+    fence_timeout: Res<FenceTimeout>,
+    mut drawer: ResMut<Drawer>,
+    mut timings: ResMut<GpuTimings>,
+) {
+    let frame_slot = drawer.finish_previous_frame(&gpu, *fence_timeout);
+    if let Some(new_timings) = drawer.timestamps_mut().read(frame_slot) {
+        *timings = new_timings;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn view(passes: &[(&str, f64)], total: f64) -> ViewTimings {
+        ViewTimings {
+            passes: passes
+                .iter()
+                .map(|&(name, milliseconds)| PassTiming {
+                    name: name.to_string(),
+                    milliseconds,
+                })
+                .collect(),
+            total,
+        }
+    }
+
+    #[test]
+    fn passes_of_every_view_are_counted() {
+        let timings = GpuTimings {
+            views: vec![
+                view(&[("depth prepass", 0.5), ("path trace", 4.0)], 5.0),
+                view(&[("path trace", 2.0), ("gizmos", 0.25)], 3.0),
+            ],
+        };
+        assert_eq!(timings.pass("path trace"), 6.0);
+        assert_eq!(timings.pass("depth prepass"), 0.5);
+        assert_eq!(timings.pass("auto exposure"), 0.0);
+        assert_eq!(timings.total(), 8.0);
+    }
+}
@@ -0,0 +1,100 @@
+use bevy::prelude::*;
+use windows::Win32::Graphics::Direct3D12::ID3D12Resource;
+
+use super::{
+    drawer::ViewTarget, fence_timeout::FenceTimeout, offline::OffscreenTarget,
+    render_target::AccumulationPrecision, Gpu,
+};
+use crate::core::Camera;
+
+/// Renders into an off-screen target of this size every frame, so the renderer runs without
+/// any window: on CI, on servers or for batch rendering. Read the frames with
+/// [`FrameCapture`](super::FrameCapture), [`Screenshot`](super::Screenshot) or
+/// [`FrameSequence`](super::FrameSequence).
+///
+/// No swapchain paces the frames, use a [`FrameLimiter`](super::FrameLimiter) to not render as
+/// fast as the GPU allows. Open windows are still drawn.
+#[derive(Resource, Reflect, Debug, Clone, Copy, PartialEq, Eq)]
+#[reflect(Resource, Default)]
+pub struct Headless {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Default for Headless {
+    fn default() -> Self {
+        Self {
+            width: 1280,
+            height: 720,
+        }
+    }
+}
+
+/// Target [`Headless`] renders into, recreated when its size or the [`AccumulationPrecision`]
+/// changes.
+#[derive(Resource)]
+pub(crate) struct HeadlessTarget {
+    target: OffscreenTarget,
+    accumulated_frames: u32,
+}
+
+impl HeadlessTarget {
+    /// `camera` with the aspect ratio of the target.
+    pub fn camera(&self, camera: &Camera) -> Camera {
+        let size = self.target.size();
+        Camera {
+            aspect_ratio: size.x as f32 / size.y as f32,
+            ..camera.clone()
+        }
+    }
+
+    pub fn view_target(&self) -> ViewTarget<'_> {
+        self.target.view_target(self.accumulated_frames)
+    }
+
+    /// Tone mapped output, in `COPY_SOURCE`.
+    pub fn output(&self) -> &ID3D12Resource {
+        &self.target.output
+    }
+
+    /// In `ALL_SHADER_RESOURCE`.
+    pub fn hdr_target(&self) -> &ID3D12Resource {
+        &self.target.hdr_target
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.accumulated_frames = 0;
+    }
+
+    pub fn finish_frame(&mut self, gpu: &Gpu, timeout: FenceTimeout) {
+        self.target.finish_frame(gpu, timeout, "a headless frame");
+        self.accumulated_frames += 1;
+    }
+}
+
+pub fn prepare_headless_target(
+    mut commands: Commands,
+    headless: Option<Res<Headless>>,
+    target: Option<Res<HeadlessTarget>>,
+    precision: Res<AccumulationPrecision>,
+    gpu: Res<Gpu>,
+) {
+    let Some(headless) = headless else {
+        if target.is_some() {
+            commands.remove_resource::<HeadlessTarget>();
+        }
+        return;
+    };
+    let size = UVec2::new(headless.width.max(1), headless.height.max(1));
+    let format = precision.dxgi_format();
+    if target
+        .is_some_and(|target| target.target.size() == size && target.target.hdr_format() == format)
+    {
+        return;
+    }
+
+    commands.insert_resource(HeadlessTarget {
+        target: OffscreenTarget::new(&gpu, size, format),
+        accumulated_frames: 0,
+    });
+}
@@ -0,0 +1,29 @@
+//! Camera transform read from input right before a frame is recorded.
+
+use std::sync::Arc;
+
+use bevy::prelude::*;
+
+type LatchFn = dyn Fn(&GlobalTransform) -> Option<GlobalTransform> + Send + Sync;
+
+/// Replaces the camera transform of window frames right before they are recorded, skipping the
+/// latency between the systems moving the camera and the draw.
+///
+/// The function gets the transform from the ECS and returns the one to render with, or `None`
+/// to keep it. It runs on the render thread every frame, so it should only read input that is
+/// already at hand, for example a pose another thread keeps updated. Accumulation restarts
+/// whenever the latched transform changes. Offline renders ignore it.
+#[derive(Resource, Clone)]
+pub struct CameraLateLatch(Arc<LatchFn>);
+
+impl CameraLateLatch {
+    pub fn new(
+        latch: impl Fn(&GlobalTransform) -> Option<GlobalTransform> + Send + Sync + 'static,
+    ) -> Self {
+        Self(Arc::new(latch))
+    }
+
+    pub(crate) fn latch(&self, transform: &GlobalTransform) -> Option<GlobalTransform> {
+        (self.0)(transform)
+    }
+}
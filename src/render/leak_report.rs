@@ -0,0 +1,92 @@
+//! Debug names of GPU objects and the report of objects still alive when the app exits.
+
+use std::panic::Location;
+
+use bevy::{app::AppExit, prelude::*};
+use windows::{
+    core::{Interface, HSTRING},
+    Win32::Graphics::Direct3D12::*,
+};
+
+use super::{
+    capture::FrameCapture,
+    command_queue::GpuCommands,
+    drawer::Drawer,
+    fence_timeout::FenceTimeout,
+    gpu_fence::GpuFence,
+    material_textures::MaterialTextures,
+    offline::OfflineRender,
+    pipelines::{AutoExposurePipeline, PipelineStorage, TonemapPipeline},
+    render_target::WindowRenderTarget,
+    Gpu, RenderSchedule, RenderSet, UploadQueue,
+};
+
+/// Names `object` in debug layer messages, graphics debuggers and the leak report. Debug builds
+/// append the call site, `#[track_caller]` functions pass on the call site of their caller.
+#[track_caller]
+pub fn set_debug_name(object: &impl Interface, name: &str) {
+    let Ok(object) = object.cast::<ID3D12Object>() else {
+        return;
+    };
+    let name = if cfg!(debug_assertions) {
+        let location = Location::caller();
+        format!("{name} ({}:{})", location.file(), location.line())
+    } else {
+        name.to_owned()
+    };
+    unsafe { object.SetName(&HSTRING::from(name)) }.expect("failed to name GPU object");
+}
+
+/// In debug builds, releases every GPU object of the renderer once [`AppExit`] is sent and
+/// reports the ones still alive through the debug layer. Anything listed besides the device
+/// itself was kept by someone else, named objects show where they were created.
+pub struct LeakReportPlugin;
+
+impl Plugin for LeakReportPlugin {
+    fn build(&self, app: &mut App) {
+        if cfg!(debug_assertions) {
+            app.add_systems(
+                RenderSchedule,
+                report_live_objects
+                    .run_if(on_event::<AppExit>())
+                    .after(RenderSet::Present),
+            );
+        }
+    }
+}
+
+fn report_live_objects(world: &mut World) {
+    wait_for_idle(world.resource::<Gpu>());
+
+    world.remove_resource::<Drawer>();
+    world.remove_resource::<PipelineStorage>();
+    world.remove_resource::<TonemapPipeline>();
+    world.remove_resource::<AutoExposurePipeline>();
+    world.remove_resource::<MaterialTextures>();
+    world.remove_resource::<FrameCapture>();
+    world.remove_resource::<OfflineRender>();
+    world.remove_resource::<UploadQueue>();
+    world.remove_resource::<GpuCommands>();
+    let render_targets: Vec<Entity> = world
+        .query_filtered::<Entity, With<WindowRenderTarget>>()
+        .iter(world)
+        .collect();
+    for entity in render_targets {
+        world.entity_mut(entity).remove::<WindowRenderTarget>();
+    }
+
+    let gpu = world.resource::<Gpu>();
+    let Ok(debug_device) = gpu.device.cast::<ID3D12DebugDevice>() else {
+        warn!("Debug layer isn't available, can't report live GPU objects");
+        return;
+    };
+    info!("Renderer released its GPU objects, reporting the ones still alive");
+    unsafe { debug_device.ReportLiveDeviceObjects(D3D12_RLDO_DETAIL | D3D12_RLDO_IGNORE_INTERNAL) }
+        .expect("ReportLiveDeviceObjects failed");
+}
+
+pub(super) fn wait_for_idle(gpu: &Gpu) {
+    let mut fence = GpuFence::new(gpu, "idle fence");
+    fence.signal(&gpu.queue);
+    fence.wait_for_last(gpu, FenceTimeout::default(), "the GPU to become idle");
+}
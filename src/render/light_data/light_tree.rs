@@ -0,0 +1,218 @@
+use std::f32::consts::PI;
+
+use bevy::prelude::*;
+
+use super::{GpuLight, LIGHT_KIND_DIRECTIONAL, LIGHT_KIND_DISK, LIGHT_KIND_RECT, LIGHT_KIND_SPOT};
+
+/// Fewer local lights than this are all sampled at every shading point, the tree is only built
+/// once sampling a single light is cheaper than the extra noise.
+const MIN_TREE_LIGHTS: usize = 16;
+
+/// Node of a [`LightTree`]. Children of inner nodes are the next node and `right_child`, leaves
+/// have `right_child` 0 and point to a light.
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GpuLightNode {
+    bounds_min: [f32; 3],
+    right_child: u32,
+    bounds_max: [f32; 3],
+    light: u32,
+    // every light of the node emits within this cone, cos -1 is every direction
+    axis: [f32; 3],
+    cos_cone: f32,
+    // luminance of the emitted light, summed over the node
+    power: f32,
+    // 0 for the root, lets the path tracer compute the probability of picking a leaf
+    parent: u32,
+    __padding: [u32; 2],
+}
+
+#[derive(Clone, Copy)]
+struct Cone {
+    axis: Vec3,
+    angle: f32,
+}
+
+impl Cone {
+    const EVERYWHERE: Cone = Cone {
+        axis: Vec3::Z,
+        angle: PI,
+    };
+
+    fn union(self, other: Cone) -> Cone {
+        let (wide, narrow) = if self.angle >= other.angle {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        let between = wide.axis.angle_between(narrow.axis);
+        if (between + narrow.angle).min(PI) <= wide.angle {
+            return wide;
+        }
+        let angle = (wide.angle + between + narrow.angle) * 0.5;
+        let rotation_axis = wide.axis.cross(narrow.axis);
+        if angle >= PI || rotation_axis.length_squared() < 1e-12 {
+            return Cone::EVERYWHERE;
+        }
+        let rotation = Quat::from_axis_angle(rotation_axis.normalize(), angle - wide.angle);
+        Cone {
+            axis: rotation * wide.axis,
+            angle,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct LightBounds {
+    light: u32,
+    bounds: (Vec3, Vec3),
+    cone: Cone,
+    power: f32,
+}
+
+impl LightBounds {
+    fn new(index: usize, light: &GpuLight) -> Self {
+        let position = Vec3::from_array(light.position);
+        let direction = Vec3::from_array(light.direction);
+        let luminance = Vec3::from_array(light.color).dot(Vec3::new(0.2126, 0.7152, 0.0722));
+        let (extent, cone, power) = match light.kind {
+            LIGHT_KIND_SPOT => (
+                Vec3::splat(light.radius),
+                Cone {
+                    axis: direction,
+                    angle: light.spot_cos_outer.clamp(-1.0, 1.0).acos(),
+                },
+                luminance,
+            ),
+            LIGHT_KIND_RECT => {
+                let right = Vec3::from_array(light.right);
+                let up = Vec3::from_array(light.up);
+                let area = 4.0 * right.length() * up.length();
+                (
+                    right.abs() + up.abs(),
+                    Cone {
+                        axis: direction,
+                        angle: PI * 0.5,
+                    },
+                    luminance * area,
+                )
+            }
+            LIGHT_KIND_DISK => (
+                Vec3::splat(light.radius),
+                Cone {
+                    axis: direction,
+                    angle: PI * 0.5,
+                },
+                luminance * PI * light.radius * light.radius,
+            ),
+            _ => (Vec3::splat(light.radius), Cone::EVERYWHERE, luminance),
+        };
+        Self {
+            light: index as u32,
+            bounds: (position - extent, position + extent),
+            cone,
+            power,
+        }
+    }
+
+    fn centroid(&self) -> Vec3 {
+        (self.bounds.0 + self.bounds.1) * 0.5
+    }
+}
+
+/// Bounding volume hierarchy over the lights of [`super::LightData`], each node knowing the power
+/// and emission directions of its lights. The path tracer walks it to pick one light per shading
+/// point with probability proportional to its estimated contribution.
+///
+/// Directional lights have no position, they follow the tree in the node list and are always
+/// sampled.
+#[derive(Default)]
+pub struct LightTree {
+    nodes: Vec<GpuLightNode>,
+    tree_size: usize,
+}
+
+impl LightTree {
+    pub(super) fn build(lights: &[GpuLight]) -> Self {
+        let (infinite, local): (Vec<_>, Vec<_>) = lights
+            .iter()
+            .enumerate()
+            .map(|(index, light)| (light.kind, LightBounds::new(index, light)))
+            .filter(|(_, bounds)| bounds.power > 0.0)
+            .partition(|(kind, _)| *kind == LIGHT_KIND_DIRECTIONAL);
+
+        let mut tree = LightTree::default();
+        if local.len() >= MIN_TREE_LIGHTS {
+            let mut local: Vec<_> = local.into_iter().map(|(_, bounds)| bounds).collect();
+            tree.build_node(&mut local);
+            tree.tree_size = tree.nodes.len();
+        }
+        tree.nodes
+            .extend(infinite.iter().map(|(_, bounds)| GpuLightNode {
+                light: bounds.light,
+                ..default()
+            }));
+        tree
+    }
+
+    /// Tree nodes followed by the directional lights.
+    pub fn nodes(&self) -> &[GpuLightNode] {
+        &self.nodes
+    }
+
+    /// Nodes in the tree, 0 when lights are few enough to all be sampled.
+    pub fn tree_size(&self) -> usize {
+        self.tree_size
+    }
+
+    pub fn infinite_light_count(&self) -> usize {
+        self.nodes.len() - self.tree_size
+    }
+
+    fn build_node(&mut self, lights: &mut [LightBounds]) -> u32 {
+        let index = self.nodes.len();
+        let (bounds_min, bounds_max) = lights.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), light| (min.min(light.bounds.0), max.max(light.bounds.1)),
+        );
+        let cone = lights
+            .iter()
+            .skip(1)
+            .fold(lights[0].cone, |cone, light| cone.union(light.cone));
+        self.nodes.push(GpuLightNode {
+            bounds_min: bounds_min.to_array(),
+            bounds_max: bounds_max.to_array(),
+            axis: cone.axis.to_array(),
+            cos_cone: cone.angle.cos(),
+            power: lights.iter().map(|light| light.power).sum(),
+            ..default()
+        });
+
+        if let [light] = lights {
+            self.nodes[index].light = light.light;
+            return index as u32;
+        }
+
+        // median split along the widest axis of the light centers
+        let (centroid_min, centroid_max) = lights.iter().fold(
+            (Vec3::splat(f32::MAX), Vec3::splat(f32::MIN)),
+            |(min, max), light| (min.min(light.centroid()), max.max(light.centroid())),
+        );
+        let extent = centroid_max - centroid_min;
+        let axis = if extent.x >= extent.y && extent.x >= extent.z {
+            0
+        } else if extent.y >= extent.z {
+            1
+        } else {
+            2
+        };
+        lights.sort_unstable_by(|a, b| a.centroid()[axis].total_cmp(&b.centroid()[axis]));
+        let (left, right) = lights.split_at_mut(lights.len() / 2);
+        let left = self.build_node(left);
+        let right = self.build_node(right);
+        self.nodes[left as usize].parent = index as u32;
+        self.nodes[right as usize].parent = index as u32;
+        self.nodes[index].right_child = right;
+        index as u32
+    }
+}
@@ -0,0 +1,297 @@
+mod light_tree;
+
+use std::ops::Range;
+
+use bevy::{ecs::system::SystemParam, prelude::*, utils::HashMap};
+
+use crate::core::{DirectionalLight, DiskLight, PointLight, RectLight, SpotLight};
+
+use super::{RenderSchedule, RenderSet};
+
+pub use light_tree::{GpuLightNode, LightTree};
+
+pub const MAX_LIGHTS: usize = 256;
+
+const LIGHT_KIND_POINT: u32 = 0;
+const LIGHT_KIND_DIRECTIONAL: u32 = 1;
+const LIGHT_KIND_SPOT: u32 = 2;
+const LIGHT_KIND_RECT: u32 = 3;
+const LIGHT_KIND_DISK: u32 = 4;
+
+pub struct LightDataPlugin;
+
+impl Plugin for LightDataPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(LightData::new())
+            .add_systems(RenderSchedule, build_light_data.in_set(RenderSet::Extract));
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct GpuLight {
+    position: [f32; 3],
+    kind: u32,
+    direction: [f32; 3],
+    range: f32,
+    // color premultiplied by intensity
+    color: [f32; 3],
+    radius: f32,
+    // half extents for rect lights, unit axes for disk lights
+    right: [f32; 3],
+    spot_cos_inner: f32,
+    up: [f32; 3],
+    spot_cos_outer: f32,
+}
+
+impl GpuLight {
+    fn point(light: &PointLight, transform: &GlobalTransform) -> Self {
+        Self {
+            position: transform.translation().to_array(),
+            kind: LIGHT_KIND_POINT,
+            range: light.range,
+            color: (light.color.to_linear() * light.intensity).to_f32_array_no_alpha(),
+            radius: light.radius,
+            ..default()
+        }
+    }
+
+    fn directional(light: &DirectionalLight, transform: &GlobalTransform) -> Self {
+        Self {
+            kind: LIGHT_KIND_DIRECTIONAL,
+            direction: transform.forward().to_array(),
+            color: (light.color.to_linear() * light.illuminance).to_f32_array_no_alpha(),
+            ..default()
+        }
+    }
+
+    fn spot(light: &SpotLight, transform: &GlobalTransform) -> Self {
+        Self {
+            position: transform.translation().to_array(),
+            kind: LIGHT_KIND_SPOT,
+            direction: transform.forward().to_array(),
+            range: light.range,
+            color: (light.color.to_linear() * light.intensity).to_f32_array_no_alpha(),
+            radius: light.radius,
+            spot_cos_inner: light.inner_angle.cos(),
+            spot_cos_outer: light.outer_angle.cos(),
+            ..default()
+        }
+    }
+
+    fn rect(light: &RectLight, transform: &GlobalTransform) -> Self {
+        Self {
+            position: transform.translation().to_array(),
+            kind: LIGHT_KIND_RECT,
+            direction: transform.forward().to_array(),
+            color: (light.color.to_linear() * light.intensity).to_f32_array_no_alpha(),
+            right: (transform.right() * light.width * 0.5).to_array(),
+            up: (transform.up() * light.height * 0.5).to_array(),
+            ..default()
+        }
+    }
+
+    fn disk(light: &DiskLight, transform: &GlobalTransform) -> Self {
+        Self {
+            position: transform.translation().to_array(),
+            kind: LIGHT_KIND_DISK,
+            direction: transform.forward().to_array(),
+            color: (light.color.to_linear() * light.intensity).to_f32_array_no_alpha(),
+            radius: light.radius,
+            right: transform.right().to_array(),
+            up: transform.up().to_array(),
+            ..default()
+        }
+    }
+}
+
+/// Slot owner in [`LightData`], an entity can hold several kinds of lights.
+type LightKey = (Entity, u32);
+
+/// CPU mirror of the GPU light buffer.
+///
+/// Every light component keeps its slot while it lives, so only changed entries have to be
+/// uploaded. Removed lights are compacted by moving the last light into the freed slot.
+#[derive(Resource, Default)]
+pub struct LightData {
+    lights: Vec<GpuLight>,
+    owners: Vec<LightKey>,
+    slots: HashMap<LightKey, usize>,
+    dirty: Option<Range<usize>>,
+    updated: bool,
+    tree: LightTree,
+}
+
+impl LightData {
+    pub fn new() -> LightData {
+        LightData::default()
+    }
+
+    pub fn lights(&self) -> &[GpuLight] {
+        &self.lights
+    }
+
+    pub fn light_count(&self) -> usize {
+        self.lights.len()
+    }
+
+    /// Hierarchy used to pick lights, rebuilt whenever a light changes.
+    pub fn light_tree(&self) -> &LightTree {
+        &self.tree
+    }
+
+    /// Slots changed since the last [`Self::set_used`]. Can be empty when lights were only
+    /// removed from the end.
+    pub fn dirty_range(&self) -> Range<usize> {
+        let len = self.lights.len();
+        self.dirty
+            .clone()
+            .map_or(0..0, |range| range.start.min(len)..range.end.min(len))
+    }
+
+    pub fn set_used(&mut self) {
+        self.dirty = None;
+        self.updated = false;
+    }
+
+    pub fn updated(&self) -> bool {
+        self.updated
+    }
+
+    /// Marks every light as changed, so they are uploaded again.
+    pub(crate) fn reupload(&mut self) {
+        self.dirty = Some(0..self.lights.len());
+        self.updated = true;
+    }
+
+    fn mark_dirty(&mut self, index: usize) {
+        self.dirty = Some(match self.dirty.take() {
+            Some(range) => range.start.min(index)..range.end.max(index + 1),
+            None => index..index + 1,
+        });
+        self.updated = true;
+    }
+
+    fn set(&mut self, key: LightKey, light: GpuLight) {
+        if let Some(&index) = self.slots.get(&key) {
+            self.lights[index] = light;
+            self.mark_dirty(index);
+            return;
+        }
+
+        if self.lights.len() >= MAX_LIGHTS {
+            warn!("more than {MAX_LIGHTS} lights in the scene, {key:?} is ignored");
+            return;
+        }
+        let index = self.lights.len();
+        self.lights.push(light);
+        self.owners.push(key);
+        self.slots.insert(key, index);
+        self.mark_dirty(index);
+    }
+
+    fn remove(&mut self, key: LightKey) {
+        let Some(index) = self.slots.remove(&key) else {
+            return;
+        };
+
+        self.lights.swap_remove(index);
+        self.owners.swap_remove(index);
+        if let Some(&moved) = self.owners.get(index) {
+            self.slots.insert(moved, index);
+            self.mark_dirty(index);
+        }
+        self.updated = true;
+    }
+}
+
+type AnyLight = Or<(
+    With<PointLight>,
+    With<DirectionalLight>,
+    With<SpotLight>,
+    With<RectLight>,
+    With<DiskLight>,
+)>;
+
+type LightComponents<'a> = AnyOf<(
+    &'a PointLight,
+    &'a DirectionalLight,
+    &'a SpotLight,
+    &'a RectLight,
+    &'a DiskLight,
+)>;
+
+type ChangedLight = Or<(
+    Changed<PointLight>,
+    Changed<DirectionalLight>,
+    Changed<SpotLight>,
+    Changed<RectLight>,
+    Changed<DiskLight>,
+    Changed<GlobalTransform>,
+)>;
+
+#[derive(SystemParam)]
+pub struct RemovedLights<'w, 's> {
+    point: RemovedComponents<'w, 's, PointLight>,
+    directional: RemovedComponents<'w, 's, DirectionalLight>,
+    spot: RemovedComponents<'w, 's, SpotLight>,
+    rect: RemovedComponents<'w, 's, RectLight>,
+    disk: RemovedComponents<'w, 's, DiskLight>,
+}
+
+impl RemovedLights<'_, '_> {
+    fn read(&mut self) -> Vec<LightKey> {
+        let point = self.point.read().map(|entity| (entity, LIGHT_KIND_POINT));
+        let directional = self
+            .directional
+            .read()
+            .map(|entity| (entity, LIGHT_KIND_DIRECTIONAL));
+        let spot = self.spot.read().map(|entity| (entity, LIGHT_KIND_SPOT));
+        let rect = self.rect.read().map(|entity| (entity, LIGHT_KIND_RECT));
+        let disk = self.disk.read().map(|entity| (entity, LIGHT_KIND_DISK));
+        point
+            .chain(directional)
+            .chain(spot)
+            .chain(rect)
+            .chain(disk)
+            .collect()
+    }
+}
+
+pub fn build_light_data(
+    changed_lights: Query<(Entity, LightComponents, &GlobalTransform), (AnyLight, ChangedLight)>,
+    mut removed_lights: RemovedLights,
+    mut light_data: ResMut<LightData>,
+) {
+    for key in removed_lights.read() {
+        light_data.remove(key);
+    }
+
+    for (entity, (point, directional, spot, rect, disk), transform) in &changed_lights {
+        if let Some(light) = point {
+            light_data.set(
+                (entity, LIGHT_KIND_POINT),
+                GpuLight::point(light, transform),
+            );
+        }
+        if let Some(light) = directional {
+            light_data.set(
+                (entity, LIGHT_KIND_DIRECTIONAL),
+                GpuLight::directional(light, transform),
+            );
+        }
+        if let Some(light) = spot {
+            light_data.set((entity, LIGHT_KIND_SPOT), GpuLight::spot(light, transform));
+        }
+        if let Some(light) = rect {
+            light_data.set((entity, LIGHT_KIND_RECT), GpuLight::rect(light, transform));
+        }
+        if let Some(light) = disk {
+            light_data.set((entity, LIGHT_KIND_DISK), GpuLight::disk(light, transform));
+        }
+    }
+
+    if light_data.updated() {
+        light_data.tree = LightTree::build(&light_data.lights);
+    }
+}
@@ -0,0 +1,275 @@
+use bevy::{prelude::*, utils::HashMap};
+use windows::Win32::Graphics::{
+    Direct3D12::*,
+    Dxgi::Common::{DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_UNKNOWN, DXGI_SAMPLE_DESC},
+};
+
+use super::{
+    d3d::write_mapped,
+    set_debug_name,
+    upload::{UploadPriority, UploadQueue},
+    BindlessDescriptors, Drawer, Gpu, MeshData,
+};
+use crate::core::{Image, PlaceholderAssets};
+
+/// Texture slots of the scene, all of them have an ID in the bindless heap.
+pub const MAX_TEXTURES: usize = 1024;
+
+/// GPU copies of the textures in [`MeshData::textures`], one SRV per texture slot.
+///
+/// Slots whose texture is still loading or uploading show the placeholder texture, or the
+/// default white one when placeholders are disabled. The SRVs are in the
+/// [`super::BindlessHeap`], shaders read the texture of slot `n` at [`Self::first_id`] + `n`.
+/// They are rewritten in place, so only once the frames in flight are finished.
+#[derive(Resource)]
+pub struct MaterialTextures {
+    resident: HashMap<AssetId<Image>, ID3D12Resource>,
+    // MAX_TEXTURES SRVs
+    descriptors: BindlessDescriptors,
+    // texture of every slot, its SRV is written by the next bind
+    wanted: Vec<Option<ID3D12Resource>>,
+    bound: Vec<Option<ID3D12Resource>>,
+    updated: bool,
+}
+
+impl MaterialTextures {
+    pub fn new(gpu: &Gpu) -> Self {
+        let descriptors = gpu.bindless.allocate(MAX_TEXTURES);
+        for slot in 0..MAX_TEXTURES {
+            write_srv(gpu, None, descriptors.cpu_handle(slot));
+        }
+        Self {
+            resident: HashMap::new(),
+            descriptors,
+            wanted: vec![None; MAX_TEXTURES],
+            bound: vec![None; MAX_TEXTURES],
+            updated: false,
+        }
+    }
+
+    /// Bindless ID of the first texture slot.
+    pub fn first_id(&self) -> u32 {
+        self.descriptors.first_id()
+    }
+
+    /// Marks the current slots as drawn.
+    pub fn set_used(&mut self) {
+        self.updated = false;
+    }
+
+    /// Whether a slot shows a different texture since the last [`MaterialTextures::set_used`].
+    pub fn updated(&self) -> bool {
+        self.updated
+    }
+
+    /// Writes the SRVs of the slots whose texture changed. The frames in flight may read the
+    /// old SRVs, they must be finished.
+    pub(super) fn bind(&mut self, gpu: &Gpu, drawer: &Drawer) {
+        debug_assert!(
+            drawer.frames_finished(),
+            "texture SRVs rewritten while a frame in flight may read them"
+        );
+        for slot in 0..MAX_TEXTURES {
+            if self.bound[slot] == self.wanted[slot] {
+                continue;
+            }
+            write_srv(
+                gpu,
+                self.wanted[slot].as_ref(),
+                self.descriptors.cpu_handle(slot),
+            );
+            if let Some(old) = std::mem::replace(&mut self.bound[slot], self.wanted[slot].clone()) {
+                gpu.retired.retire(old);
+            }
+        }
+    }
+
+    /// Uploads `image` unless it's already on the GPU, returns the texture once its upload is
+    /// recorded.
+    fn texture(
+        &mut self,
+        gpu: &Gpu,
+        uploads: &mut UploadQueue,
+        id: AssetId<Image>,
+        image: Option<&Image>,
+    ) -> Option<ID3D12Resource> {
+        let texture = match self.resident.get(&id) {
+            Some(texture) => texture.clone(),
+            None => {
+                let texture = create_texture(gpu, uploads, image?);
+                self.resident.insert(id, texture.clone());
+                texture
+            }
+        };
+        (!uploads.is_queued(&texture)).then_some(texture)
+    }
+}
+
+/// Uploads textures of the current mesh data and picks the texture of every slot, bound once
+/// the frame is drawn.
+pub fn prepare_material_textures(
+    gpu: Res<Gpu>,
+    mesh_data: Res<MeshData>,
+    images: Res<Assets<Image>>,
+    placeholders: Res<PlaceholderAssets>,
+    mut image_events: EventReader<AssetEvent<Image>>,
+    mut uploads: ResMut<UploadQueue>,
+    mut textures: ResMut<MaterialTextures>,
+) {
+    for event in image_events.read() {
+        if let AssetEvent::Modified { id } | AssetEvent::Removed { id } = event {
+            // its upload or the frames in flight may still read it
+            if let Some(texture) = textures.resident.remove(id) {
+                gpu.retired.retire(texture);
+            }
+        }
+    }
+
+    let fallback_id = if placeholders.enabled {
+        placeholders.texture.id()
+    } else {
+        Handle::<Image>::default().id()
+    };
+    let fallback = textures.texture(&gpu, &mut uploads, fallback_id, images.get(fallback_id));
+
+    for slot in 0..MAX_TEXTURES {
+        let texture = match mesh_data.textures().get(slot) {
+            Some(&id) => textures
+                .texture(&gpu, &mut uploads, id, images.get(id))
+                .or_else(|| fallback.clone()),
+            None => None,
+        };
+        if textures.wanted[slot] != texture {
+            textures.wanted[slot] = texture;
+            textures.updated = true;
+        }
+    }
+}
+
+/// Textures are viewed in the format of their image, so sRGB ones are decoded when sampled. A
+/// `None` texture gives a null descriptor.
+fn write_srv(gpu: &Gpu, texture: Option<&ID3D12Resource>, descriptor: D3D12_CPU_DESCRIPTOR_HANDLE) {
+    let format = texture.map_or(DXGI_FORMAT_R8G8B8A8_UNORM, |texture| unsafe {
+        texture.GetDesc().Format
+    });
+    let srv_desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
+        Format: format,
+        ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
+        Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+        Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+            Texture2D: D3D12_TEX2D_SRV {
+                MipLevels: 1,
+                ..Default::default()
+            },
+        },
+    };
+    unsafe {
+        gpu.device
+            .CreateShaderResourceView(texture, Some(&srv_desc), descriptor)
+    };
+}
+
+/// Creates the texture and queues its upload.
+fn create_texture(gpu: &Gpu, uploads: &mut UploadQueue, image: &Image) -> ID3D12Resource {
+    let desc = D3D12_RESOURCE_DESC {
+        Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+        Alignment: 0,
+        Width: image.width() as u64,
+        Height: image.height(),
+        DepthOrArraySize: 1,
+        MipLevels: 1,
+        Format: image.texture_descriptor.Format,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            Quality: 0,
+        },
+        Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+        Flags: D3D12_RESOURCE_FLAG_NONE,
+    };
+    let mut texture: Option<ID3D12Resource> = None;
+    unsafe {
+        gpu.device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_DEFAULT,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &desc,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+            None,
+            &mut texture,
+        )
+    }
+    .expect("Failed to create texture");
+    let texture = texture.expect("CreateCommittedResource was successful but texture is None");
+    set_debug_name(&texture, "material texture");
+
+    let mut footprint = D3D12_PLACED_SUBRESOURCE_FOOTPRINT::default();
+    let mut size = 0;
+    unsafe {
+        gpu.device.GetCopyableFootprints(
+            &desc,
+            0,
+            1,
+            0,
+            Some(&mut footprint),
+            None,
+            None,
+            Some(&mut size),
+        )
+    };
+
+    let upload_buffer = create_upload_buffer(gpu, size);
+    let row_pitch = footprint.Footprint.RowPitch as usize;
+    let row_size = image.width() as usize * 4;
+    write_mapped(&upload_buffer, |data| {
+        for (row, pixels) in image.data.chunks(row_size).enumerate() {
+            data[row * row_pitch..][..row_size].copy_from_slice(pixels);
+        }
+    });
+
+    uploads.push_texture(
+        UploadPriority::Low,
+        &texture,
+        &upload_buffer,
+        footprint,
+        D3D12_RESOURCE_STATE_PIXEL_SHADER_RESOURCE,
+    );
+    texture
+}
+
+fn create_upload_buffer(gpu: &Gpu, size: u64) -> ID3D12Resource {
+    let desc = D3D12_RESOURCE_DESC {
+        Alignment: 0,
+        Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+        Width: size,
+        Height: 1,
+        DepthOrArraySize: 1,
+        MipLevels: 1,
+        Format: DXGI_FORMAT_UNKNOWN,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+        Flags: D3D12_RESOURCE_FLAG_NONE,
+    };
+    let mut buffer: Option<ID3D12Resource> = None;
+    unsafe {
+        gpu.device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_UPLOAD,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &desc,
+            D3D12_RESOURCE_STATE_GENERIC_READ,
+            None,
+            &mut buffer,
+        )
+    }
+    .expect("Failed to create texture upload buffer");
+    let buffer = buffer.expect("CreateCommittedResource was successful but buffer is None");
+    set_debug_name(&buffer, "material texture upload buffer");
+    buffer
+}
@@ -0,0 +1,134 @@
+use bevy::{prelude::*, utils::HashMap};
+
+/// Splits the mesh entities into a static and a dynamic group, so a few moving entities don't
+/// flatten and upload the whole scene again every frame.
+///
+/// Entities start out static. One that moves joins the dynamic group, which is flattened after
+/// the static one and is all that is rebuilt and uploaded while only dynamic entities change.
+/// Entities that stay still for [`InstanceSchedule::settle_frames`] frames go back to the static
+/// group. Every change of group rebuilds the static group once.
+#[derive(Resource, Reflect, Debug, Clone)]
+#[reflect(Resource, Default)]
+pub struct InstanceSchedule {
+    /// Frames without a transform change after which a dynamic entity becomes static again.
+    /// Keep it above the pauses of animations, or their entities keep switching groups.
+    pub settle_frames: u32,
+    #[reflect(ignore)]
+    frame: u32,
+    // dynamic entities and the frame they last moved in
+    #[reflect(ignore)]
+    dynamic: HashMap<Entity, u32>,
+}
+
+impl Default for InstanceSchedule {
+    fn default() -> Self {
+        Self {
+            settle_frames: 120,
+            frame: 0,
+            dynamic: HashMap::default(),
+        }
+    }
+}
+
+impl InstanceSchedule {
+    pub fn is_dynamic(&self, entity: Entity) -> bool {
+        self.dynamic.contains_key(&entity)
+    }
+
+    /// Entities in the dynamic group.
+    pub fn dynamic_count(&self) -> usize {
+        self.dynamic.len()
+    }
+
+    /// Starts a new frame in which the `moved` entities changed their transform. Returns
+    /// whether an entity changed groups.
+    pub(super) fn update(&mut self, moved: impl IntoIterator<Item = Entity>) -> bool {
+        self.frame = self.frame.wrapping_add(1);
+        let mut regrouped = false;
+        for entity in moved {
+            regrouped |= self.dynamic.insert(entity, self.frame).is_none();
+        }
+
+        let (frame, settle_frames) = (self.frame, self.settle_frames);
+        let dynamic_count = self.dynamic.len();
+        self.dynamic
+            .retain(|_, last_moved| frame.wrapping_sub(*last_moved) < settle_frames);
+        regrouped || self.dynamic.len() != dynamic_count
+    }
+
+    /// Drops a despawned entity, so it doesn't change groups later.
+    pub(super) fn forget(&mut self, entity: Entity) {
+        self.dynamic.remove(&entity);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn schedule() -> InstanceSchedule {
+        InstanceSchedule {
+            settle_frames: 3,
+            ..default()
+        }
+    }
+
+    #[test]
+    fn moving_entities_become_dynamic() {
+        let mut schedule = schedule();
+        let (moving, still) = (Entity::from_raw(0), Entity::from_raw(1));
+
+        assert!(!schedule.update([]));
+        assert!(schedule.update([moving]));
+        assert!(schedule.is_dynamic(moving));
+        assert!(!schedule.is_dynamic(still));
+
+        // moving on doesn't change its group
+        assert!(!schedule.update([moving]));
+        assert_eq!(schedule.dynamic_count(), 1);
+    }
+
+    #[test]
+    fn still_entities_return_to_static_after_settle_frames() {
+        let mut schedule = schedule();
+        let entity = Entity::from_raw(0);
+        schedule.update([entity]);
+
+        assert!(!schedule.update([]));
+        assert!(!schedule.update([]));
+        assert!(schedule.is_dynamic(entity));
+        assert!(schedule.update([]));
+        assert!(!schedule.is_dynamic(entity));
+        assert!(!schedule.update([]));
+    }
+
+    #[test]
+    fn moving_again_restarts_settling() {
+        let mut schedule = schedule();
+        let entity = Entity::from_raw(0);
+        schedule.update([entity]);
+        schedule.update([]);
+        schedule.update([]);
+
+        assert!(!schedule.update([entity]));
+        assert!(!schedule.update([]));
+        assert!(!schedule.update([]));
+        assert!(schedule.is_dynamic(entity));
+        assert!(schedule.update([]));
+        assert!(!schedule.is_dynamic(entity));
+    }
+
+    #[test]
+    fn forgotten_entities_dont_change_groups() {
+        let mut schedule = schedule();
+        let entity = Entity::from_raw(0);
+        schedule.update([entity]);
+
+        schedule.forget(entity);
+        assert!(!schedule.is_dynamic(entity));
+        assert_eq!(schedule.dynamic_count(), 0);
+        for _ in 0..4 {
+            assert!(!schedule.update([]));
+        }
+    }
+}
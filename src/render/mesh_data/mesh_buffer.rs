@@ -1,268 +1,281 @@
-use windows::Win32::Graphics::{
-    Direct3D12::*,
-    Dxgi::Common::{DXGI_FORMAT_UNKNOWN, DXGI_SAMPLE_DESC},
+use windows::Win32::Graphics::Direct3D12::{D3D12_INDEX_BUFFER_VIEW, D3D12_VERTEX_BUFFER_VIEW};
+
+use crate::render::{
+    structured_buffer::StructuredBuffer,
+    upload::{UploadPriority, UploadQueue},
+    BindlessDescriptors, Gpu,
 };
 
-use crate::render::{DescriptorHeap, Gpu};
+use super::{MaterialData, MeshData, MAX_CUSTOM_ATTRIBUTES, MAX_INDICES, MAX_VERTICES};
 
-use super::MeshData;
+const MAX_TRIANGLES: usize = MAX_INDICES / 3;
 
+/// GPU copy of [`MeshData`], for pipelines that read the scene geometry.
 pub struct MeshBuffer {
-    gpu_vertex_buffer: ID3D12Resource,
-    upload_vertex_buffer: ID3D12Resource,
-    gpu_index_buffer: ID3D12Resource,
-    upload_index_buffer: ID3D12Resource,
+    vertex_buffer: StructuredBuffer<[f32; 3]>,
+    uv_buffer: StructuredBuffer<[f32; 2]>,
+    index_buffer: StructuredBuffer<u32>,
+    material_buffer: StructuredBuffer<MaterialData>,
+    custom_attribute_buffers: Vec<StructuredBuffer<[f32; 4]>>,
 }
 
 impl MeshBuffer {
     pub fn new(gpu: &Gpu) -> Self {
-        let mut gpu_vertex_buffer: Option<ID3D12Resource> = None;
-        let mut upload_vertex_buffer: Option<ID3D12Resource> = None;
-        let mut gpu_index_buffer: Option<ID3D12Resource> = None;
-        let mut upload_index_buffer: Option<ID3D12Resource> = None;
+        Self::with_custom_attributes(gpu, 0)
+    }
 
-        unsafe {
-            let default_heap_properties = D3D12_HEAP_PROPERTIES {
-                Type: D3D12_HEAP_TYPE_DEFAULT,
-                CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
-                MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
-                CreationNodeMask: 0,
-                VisibleNodeMask: 0,
-            };
+    /// Also uploads the first `channel_count` channels of [`super::CustomVertexAttributes`].
+    pub fn with_custom_attributes(gpu: &Gpu, channel_count: usize) -> Self {
+        assert!(channel_count <= MAX_CUSTOM_ATTRIBUTES);
+        Self {
+            vertex_buffer: StructuredBuffer::new(gpu, MAX_VERTICES),
+            uv_buffer: StructuredBuffer::new(gpu, MAX_VERTICES),
+            index_buffer: StructuredBuffer::new(gpu, MAX_INDICES),
+            material_buffer: StructuredBuffer::new(gpu, MAX_TRIANGLES),
+            custom_attribute_buffers: (0..channel_count)
+                .map(|_| StructuredBuffer::new(gpu, MAX_VERTICES))
+                .collect(),
+        }
+    }
 
-            let upload_heap_properties = D3D12_HEAP_PROPERTIES {
-                Type: D3D12_HEAP_TYPE_UPLOAD,
-                CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
-                MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
-                CreationNodeMask: 0,
-                VisibleNodeMask: 0,
-            };
+    /// Writes `data` to the upload buffers and queues it for upload. Indices go last, so
+    /// triangles don't reference vertices and materials that aren't uploaded yet. While the
+    /// static group of `data` is unchanged only the dynamic group after it is uploaded.
+    pub fn set_new_data(&self, data: &MeshData, uploads: &mut UploadQueue) {
+        let Some(from) = data.unchanged_static_group() else {
+            self.upload_all(data, uploads);
+            return;
+        };
 
-            let vertex_buffer_size = 1024 * 1024;
-            let index_buffer_size = 1024 * 1024;
+        self.vertex_buffer
+            .write_range(from.positions, &data.positions[from.positions..]);
+        self.uv_buffer
+            .write_range(from.positions, &data.uvs[from.positions..]);
+        self.index_buffer
+            .write_range(from.indices, &data.indices[from.indices..]);
+        self.material_buffer
+            .write_range(from.materials, &data.materials[from.materials..]);
+        for (channel, buffer) in self.custom_attribute_buffers.iter().enumerate() {
+            let values = data.custom_attribute(channel);
+            if values.len() > from.positions {
+                buffer.write_range(from.positions, &values[from.positions..]);
+                buffer.upload_range(
+                    uploads,
+                    UploadPriority::Normal,
+                    from.positions..values.len(),
+                );
+            }
+        }
 
-            let index_buffer_desc = D3D12_RESOURCE_DESC {
-                Alignment: 0,
-                Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
-                Width: index_buffer_size,
-                Height: 1,
-                DepthOrArraySize: 1,
-                MipLevels: 1,
-                Format: DXGI_FORMAT_UNKNOWN,
-                SampleDesc: DXGI_SAMPLE_DESC {
-                    Count: 1,
-                    ..Default::default()
-                },
-                Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
-                Flags: D3D12_RESOURCE_FLAG_NONE,
-            };
-            let vertex_buffer_desc = D3D12_RESOURCE_DESC {
-                Alignment: 0,
-                Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
-                Width: vertex_buffer_size,
-                Height: 1,
-                DepthOrArraySize: 1,
-                MipLevels: 1,
-                Format: DXGI_FORMAT_UNKNOWN,
-                SampleDesc: DXGI_SAMPLE_DESC {
-                    Count: 1,
-                    ..Default::default()
-                },
-                Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
-                Flags: D3D12_RESOURCE_FLAG_NONE,
-            };
+        self.vertex_buffer.upload_range(
+            uploads,
+            UploadPriority::Normal,
+            from.positions..data.positions.len(),
+        );
+        self.uv_buffer.upload_range(
+            uploads,
+            UploadPriority::Normal,
+            from.positions..data.uvs.len(),
+        );
+        self.material_buffer.upload_range(
+            uploads,
+            UploadPriority::Normal,
+            from.materials..data.materials.len(),
+        );
+        self.index_buffer.upload_range(
+            uploads,
+            UploadPriority::Normal,
+            from.indices..data.indices.len(),
+        );
+    }
 
-            gpu.device
-                .CreateCommittedResource(
-                    &default_heap_properties,
-                    D3D12_HEAP_FLAG_NONE,
-                    &vertex_buffer_desc,
-                    D3D12_RESOURCE_STATE_COMMON,
-                    None,
-                    &mut gpu_vertex_buffer,
-                )
-                .expect("Could not create GPU vertex buffer");
+    fn upload_all(&self, data: &MeshData, uploads: &mut UploadQueue) {
+        self.vertex_buffer.write(&data.positions);
+        self.uv_buffer.write(&data.uvs);
+        self.index_buffer.write(&data.indices);
+        self.material_buffer.write(&data.materials);
+        for (channel, buffer) in self.custom_attribute_buffers.iter().enumerate() {
+            let values = data.custom_attribute(channel);
+            buffer.write(values);
+            buffer.upload(uploads, UploadPriority::Normal, values.len());
+        }
 
-            gpu.device
-                .CreateCommittedResource(
-                    &upload_heap_properties,
-                    D3D12_HEAP_FLAG_NONE,
-                    &vertex_buffer_desc,
-                    D3D12_RESOURCE_STATE_GENERIC_READ,
-                    None,
-                    &mut upload_vertex_buffer,
-                )
-                .expect("Could not create upload vertex buffer");
+        self.vertex_buffer
+            .upload(uploads, UploadPriority::Normal, data.positions.len());
+        self.uv_buffer
+            .upload(uploads, UploadPriority::Normal, data.uvs.len());
+        self.material_buffer
+            .upload(uploads, UploadPriority::Normal, data.materials.len());
+        self.index_buffer
+            .upload(uploads, UploadPriority::Normal, data.indices.len());
+    }
 
-            gpu.device
-                .CreateCommittedResource(
-                    &default_heap_properties,
-                    D3D12_HEAP_FLAG_NONE,
-                    &index_buffer_desc,
-                    D3D12_RESOURCE_STATE_COMMON,
-                    None,
-                    &mut gpu_index_buffer,
-                )
-                .expect("Could not create GPU index buffer");
+    /// Positions in slot 0 and uvs in slot 1, for drawing the triangles through the input
+    /// assembler.
+    pub fn vertex_buffer_views(&self) -> [D3D12_VERTEX_BUFFER_VIEW; 2] {
+        [
+            self.vertex_buffer.vertex_buffer_view(),
+            self.uv_buffer.vertex_buffer_view(),
+        ]
+    }
 
-            gpu.device
-                .CreateCommittedResource(
-                    &upload_heap_properties,
-                    D3D12_HEAP_FLAG_NONE,
-                    &index_buffer_desc,
-                    D3D12_RESOURCE_STATE_GENERIC_READ,
-                    None,
-                    &mut upload_index_buffer,
-                )
-                .expect("Could not create upload index buffer");
-        }
-        Self {
-            gpu_vertex_buffer: gpu_vertex_buffer.unwrap(),
-            upload_vertex_buffer: upload_vertex_buffer.unwrap(),
-            gpu_index_buffer: gpu_index_buffer.unwrap(),
-            upload_index_buffer: upload_index_buffer.unwrap(),
-        }
+    pub fn index_buffer_view(&self) -> D3D12_INDEX_BUFFER_VIEW {
+        self.index_buffer.index_buffer_view()
     }
 
-    pub fn set_new_data(&self, data: &MeshData) {
-        unsafe {
-            let mut dst_data_vertex = std::ptr::null_mut();
-            self.upload_vertex_buffer
-                .Map(0, None, Some(&mut dst_data_vertex))
-                .expect("failed to map vertex buffer");
-            std::ptr::copy_nonoverlapping(
-                data.positions.as_ptr(),
-                dst_data_vertex as *mut [f32; 3],
-                data.positions.len(),
-            );
-            self.upload_vertex_buffer.Unmap(0, None);
+    /// Writes the SRVs of the vertices, indices, materials and uvs to `srvs` from `first` on.
+    pub fn write_srvs(&self, gpu: &Gpu, srvs: &BindlessDescriptors, first: usize) {
+        self.vertex_buffer.write_srv(gpu, srvs.cpu_handle(first));
+        self.index_buffer.write_srv(gpu, srvs.cpu_handle(first + 1));
+        self.material_buffer
+            .write_srv(gpu, srvs.cpu_handle(first + 2));
+        self.uv_buffer.write_srv(gpu, srvs.cpu_handle(first + 3));
+    }
 
-            let mut dst_data_index = std::ptr::null_mut();
-            self.upload_index_buffer
-                .Map(0, None, Some(&mut dst_data_index))
-                .expect("failed to map index buffer");
-            std::ptr::copy_nonoverlapping(
-                data.indices.as_ptr(),
-                dst_data_index as *mut u32,
-                data.indices.len(),
-            );
-            self.upload_index_buffer.Unmap(0, None);
+    /// Writes one SRV per custom attribute channel to `srvs` from `first` on, in channel order.
+    pub fn write_custom_attribute_srvs(&self, gpu: &Gpu, srvs: &BindlessDescriptors, first: usize) {
+        for (channel, buffer) in self.custom_attribute_buffers.iter().enumerate() {
+            buffer.write_srv(gpu, srvs.cpu_handle(first + channel));
         }
     }
+}
 
-    pub fn upload(&self, command_list: &mut ID3D12GraphicsCommandList) {
-        unsafe {
-            let barriers_before = [
-                D3D12_RESOURCE_BARRIER {
-                    Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-                    Flags: D3D12_RESOURCE_BARRIER_FLAG_NONE,
-                    Anonymous: D3D12_RESOURCE_BARRIER_0 {
-                        Transition: std::mem::ManuallyDrop::new(
-                            D3D12_RESOURCE_TRANSITION_BARRIER {
-                                pResource: std::mem::transmute_copy(&self.gpu_vertex_buffer),
-                                Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-                                StateBefore: D3D12_RESOURCE_STATE_GENERIC_READ,
-                                StateAfter: D3D12_RESOURCE_STATE_COPY_DEST,
-                            },
-                        ),
-                    },
-                },
-                D3D12_RESOURCE_BARRIER {
-                    Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-                    Flags: D3D12_RESOURCE_BARRIER_FLAG_NONE,
-                    Anonymous: D3D12_RESOURCE_BARRIER_0 {
-                        Transition: std::mem::ManuallyDrop::new(
-                            D3D12_RESOURCE_TRANSITION_BARRIER {
-                                pResource: std::mem::transmute_copy(&self.gpu_index_buffer),
-                                Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-                                StateBefore: D3D12_RESOURCE_STATE_GENERIC_READ,
-                                StateAfter: D3D12_RESOURCE_STATE_COPY_DEST,
-                            },
-                        ),
-                    },
-                },
-            ];
-            command_list.ResourceBarrier(&barriers_before);
-            command_list.CopyResource(&self.gpu_vertex_buffer, &self.upload_vertex_buffer);
-            command_list.CopyResource(&self.gpu_index_buffer, &self.upload_index_buffer);
+#[cfg(test)]
+mod tests {
+    use bevy::prelude::*;
 
-            let barriers_after = [
-                D3D12_RESOURCE_BARRIER {
-                    Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-                    Flags: D3D12_RESOURCE_BARRIER_FLAG_NONE,
-                    Anonymous: D3D12_RESOURCE_BARRIER_0 {
-                        Transition: std::mem::ManuallyDrop::new(
-                            D3D12_RESOURCE_TRANSITION_BARRIER {
-                                pResource: std::mem::transmute_copy(&self.gpu_vertex_buffer),
-                                Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-                                StateBefore: D3D12_RESOURCE_STATE_COPY_DEST,
-                                StateAfter: D3D12_RESOURCE_STATE_GENERIC_READ,
-                            },
-                        ),
-                    },
-                },
-                D3D12_RESOURCE_BARRIER {
-                    Type: D3D12_RESOURCE_BARRIER_TYPE_TRANSITION,
-                    Flags: D3D12_RESOURCE_BARRIER_FLAG_NONE,
-                    Anonymous: D3D12_RESOURCE_BARRIER_0 {
-                        Transition: std::mem::ManuallyDrop::new(
-                            D3D12_RESOURCE_TRANSITION_BARRIER {
-                                pResource: std::mem::transmute_copy(&self.gpu_index_buffer),
-                                Subresource: D3D12_RESOURCE_BARRIER_ALL_SUBRESOURCES,
-                                StateBefore: D3D12_RESOURCE_STATE_COPY_DEST,
-                                StateAfter: D3D12_RESOURCE_STATE_GENERIC_READ,
-                            },
-                        ),
-                    },
-                },
-            ];
-            command_list.ResourceBarrier(&barriers_after);
-        }
+    use super::*;
+    use crate::{
+        core::{InstanceOpacity, Material, Mesh, PrimitiveTopology},
+        render::{
+            mesh_data::CustomVertexAttributes,
+            test_utils::{as_bytes, execute, read_back, warp_gpu},
+        },
+    };
+
+    fn quad() -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.positions = vec![
+            [0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0],
+            [1.0, 1.0, 0.0],
+        ];
+        mesh.uvs = Some(vec![[0.0, 1.0], [1.0, 1.0], [0.0, 0.0], [1.0, 0.0]]);
+        mesh.indices = Some(vec![0, 1, 2, 2, 1, 3]);
+        mesh.insert_custom_attribute("_WEIGHT", vec![0.25, 0.5, 0.75, 1.0]);
+        mesh
     }
 
-    pub fn write_to_descriptor_heap(&self, gpu: &Gpu, descriptor_heap: &mut DescriptorHeap) {
-        let vertex_srv_desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
-            Format: DXGI_FORMAT_UNKNOWN,
-            ViewDimension: D3D12_SRV_DIMENSION_BUFFER,
-            Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
-            Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
-                Buffer: D3D12_BUFFER_SRV {
-                    FirstElement: 0,
-                    NumElements: 1024,
-                    StructureByteStride: std::mem::size_of::<[f32; 3]>() as u32,
-                    Flags: D3D12_BUFFER_SRV_FLAG_NONE,
-                },
-            },
+    fn mesh_data(custom_attributes: &CustomVertexAttributes) -> MeshData {
+        let mut data = MeshData::new();
+        let material = Material {
+            metallic: 1.0,
+            ..default()
         };
-        unsafe {
-            let handle = descriptor_heap.cpu_handle();
-            gpu.device.CreateShaderResourceView(
-                &self.gpu_vertex_buffer,
-                Some(&vertex_srv_desc),
-                handle,
-            );
+        let transform = GlobalTransform::from_translation(Vec3::new(1.0, 2.0, 3.0));
+        let opacity = InstanceOpacity(0.5);
+        data.add_mesh(
+            &quad(),
+            &material,
+            Some(&opacity),
+            &transform,
+            custom_attributes,
+        );
+        data.add_mesh(
+            &quad(),
+            &Material::default(),
+            None,
+            &GlobalTransform::IDENTITY,
+            custom_attributes,
+        );
+        data
+    }
+
+    fn assert_uploaded<T: Copy>(gpu: &Gpu, buffer: &StructuredBuffer<T>, expected: &[T]) {
+        let expected = unsafe { as_bytes(expected) };
+        assert_eq!(
+            read_back(gpu, buffer.gpu_buffer(), expected.len()),
+            expected
+        );
+    }
+
+    fn assert_mesh_uploaded(gpu: &Gpu, buffer: &MeshBuffer, data: &MeshData) {
+        assert_uploaded(gpu, &buffer.vertex_buffer, &data.positions);
+        assert_uploaded(gpu, &buffer.uv_buffer, &data.uvs);
+        assert_uploaded(gpu, &buffer.index_buffer, &data.indices);
+        assert_uploaded(gpu, &buffer.material_buffer, &data.materials);
+        for (channel, buffer) in buffer.custom_attribute_buffers.iter().enumerate() {
+            assert_uploaded(gpu, buffer, data.custom_attribute(channel));
         }
+    }
 
-        let index_srv_desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
-            Format: DXGI_FORMAT_UNKNOWN,
-            ViewDimension: D3D12_SRV_DIMENSION_BUFFER,
-            Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
-            Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
-                Buffer: D3D12_BUFFER_SRV {
-                    FirstElement: 0,
-                    NumElements: 1024,
-                    StructureByteStride: std::mem::size_of::<u32>() as u32,
-                    Flags: D3D12_BUFFER_SRV_FLAG_NONE,
-                },
-            },
-        };
-        unsafe {
-            let handle = descriptor_heap.cpu_handle();
-            gpu.device.CreateShaderResourceView(
-                &self.gpu_index_buffer,
-                Some(&index_srv_desc),
-                handle,
-            );
+    #[test]
+    fn uploads_mesh_data() {
+        let gpu = warp_gpu();
+        let mut custom_attributes = CustomVertexAttributes::default();
+        custom_attributes.register("_WEIGHT");
+        let data = mesh_data(&custom_attributes);
+        let buffer = MeshBuffer::with_custom_attributes(&gpu, 1);
+
+        let mut uploads = UploadQueue::default();
+        buffer.set_new_data(&data, &mut uploads);
+        execute(&gpu, |command_list| {
+            uploads.record(command_list, u64::MAX, 0)
+        });
+
+        assert!(uploads.is_empty());
+        assert_mesh_uploaded(&gpu, &buffer, &data);
+    }
+
+    #[test]
+    fn uploads_split_over_frames_arrive_whole() {
+        let gpu = warp_gpu();
+        let data = mesh_data(&CustomVertexAttributes::default());
+        let buffer = MeshBuffer::new(&gpu);
+
+        let mut uploads = UploadQueue::default();
+        buffer.set_new_data(&data, &mut uploads);
+        // not a multiple of any element size, so copies end in the middle of elements
+        while !uploads.is_empty() {
+            execute(&gpu, |command_list| uploads.record(command_list, 10, 0));
         }
+
+        assert_mesh_uploaded(&gpu, &buffer, &data);
+    }
+
+    #[test]
+    fn uploads_dynamic_group_after_static_one() {
+        let gpu = warp_gpu();
+        let custom_attributes = CustomVertexAttributes::default();
+        let mut data = mesh_data(&custom_attributes);
+        data.end_static_group();
+        data.add_mesh(
+            &quad(),
+            &Material::default(),
+            None,
+            &GlobalTransform::IDENTITY,
+            &custom_attributes,
+        );
+        let buffer = MeshBuffer::new(&gpu);
+        let mut uploads = UploadQueue::default();
+        buffer.set_new_data(&data, &mut uploads);
+        data.set_used();
+
+        data.clear_dynamic_group();
+        let moved = GlobalTransform::from_translation(Vec3::X);
+        data.add_mesh(
+            &quad(),
+            &Material::default(),
+            None,
+            &moved,
+            &custom_attributes,
+        );
+        buffer.set_new_data(&data, &mut uploads);
+        execute(&gpu, |command_list| {
+            uploads.record(command_list, u64::MAX, 0)
+        });
+
+        assert_mesh_uploaded(&gpu, &buffer, &data);
     }
 }
@@ -1,87 +1,599 @@
+mod instance_schedule;
 mod mesh_buffer;
+mod streaming;
 
 use bevy::prelude::*;
 
-use crate::core::Mesh;
+use crate::core::{Camera, Image, InstanceOpacity, Material, Mesh, PlaceholderAssets, Visibility};
 
-use super::RenderSchedule;
+use super::{material_textures::MAX_TEXTURES, RenderSchedule, RenderSet, View};
 
+pub use instance_schedule::InstanceSchedule;
 pub use mesh_buffer::MeshBuffer;
+pub use streaming::GeometryStreaming;
+
+/// Most custom vertex attributes that can be registered.
+pub const MAX_CUSTOM_ATTRIBUTES: usize = 8;
+
+/// Most vertices the [`MeshBuffer`] holds, meshes past it are left out of [`MeshData`].
+pub const MAX_VERTICES: usize = 1024 * 1024 / std::mem::size_of::<[f32; 3]>();
+
+/// Most indices the [`MeshBuffer`] holds, meshes past it are left out of [`MeshData`].
+pub const MAX_INDICES: usize = 1024 * 1024 / std::mem::size_of::<u32>();
 
 pub struct MeshPlugin;
 
 impl Plugin for MeshPlugin {
     fn build(&self, app: &mut App) {
         app.insert_resource(MeshData::new())
-            .add_systems(RenderSchedule, build_mesh_data);
+            .init_resource::<CustomVertexAttributes>()
+            .init_resource::<InstanceSchedule>()
+            .register_type::<InstanceSchedule>()
+            .init_resource::<GeometryStreaming>()
+            .register_type::<GeometryStreaming>()
+            .add_systems(RenderSchedule, build_mesh_data.in_set(RenderSet::Extract));
     }
 }
 
+/// GPU layout of a [`Material`], stored once per triangle.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct MaterialData {
+    base_color: [f32; 4],
+    emissive: [f32; 4],
+    transmission: f32,
+    ior: f32,
+    // index into MeshData::textures, NO_TEXTURE without one
+    base_color_texture: u32,
+    metallic: f32,
+    perceptual_roughness: f32,
+    clearcoat: f32,
+    clearcoat_perceptual_roughness: f32,
+    // of the instance, see InstanceOpacity
+    opacity: f32,
+}
+
+pub const NO_TEXTURE: u32 = u32::MAX;
+
+impl MaterialData {
+    pub(crate) fn new(material: &Material, base_color_texture: u32) -> Self {
+        Self {
+            base_color: material.base_color.to_linear().to_f32_array(),
+            emissive: material.emissive.to_f32_array(),
+            transmission: material.transmission.clamp(0.0, 1.0),
+            ior: material.ior,
+            base_color_texture,
+            metallic: material.metallic.clamp(0.0, 1.0),
+            perceptual_roughness: material.perceptual_roughness.clamp(0.0, 1.0),
+            clearcoat: material.clearcoat.clamp(0.0, 1.0),
+            clearcoat_perceptual_roughness: material.clearcoat_perceptual_roughness.clamp(0.0, 1.0),
+            opacity: 1.0,
+        }
+    }
+
+    pub(crate) fn with_opacity(self, opacity: Option<&InstanceOpacity>) -> Self {
+        Self {
+            opacity: opacity.map_or(1.0, |opacity| opacity.0.clamp(0.0, 1.0)),
+            ..self
+        }
+    }
+}
+
+/// Custom mesh attributes uploaded for custom pipelines, see [`Mesh::custom_attributes`].
+///
+/// Every registered attribute gets a channel, its index in registration order. A
+/// [`MeshBuffer`] created with custom attributes writes one SRV per channel, so in a shader
+/// channel `i` is the `i`th register after the first one of the table. Values are padded to
+/// `float4`, vertices of meshes without the attribute read zero.
+#[derive(Resource, Default, Debug)]
+pub struct CustomVertexAttributes {
+    names: Vec<String>,
+}
+
+impl CustomVertexAttributes {
+    /// Registers the attribute `name` and returns its channel, registering it again returns the
+    /// same channel.
+    pub fn register(&mut self, name: impl Into<String>) -> usize {
+        let name = name.into();
+        if let Some(channel) = self.channel(&name) {
+            return channel;
+        }
+        assert!(
+            self.names.len() < MAX_CUSTOM_ATTRIBUTES,
+            "can't register {name}, there are already {MAX_CUSTOM_ATTRIBUTES} custom attributes"
+        );
+        self.names.push(name);
+        self.names.len() - 1
+    }
+
+    pub fn channel(&self, name: &str) -> Option<usize> {
+        self.names.iter().position(|registered| registered == name)
+    }
+
+    pub fn names(&self) -> &[String] {
+        &self.names
+    }
+}
+
+/// Flattened, world-space geometry of every rendered mesh entity.
+///
+/// This is the only path from [`Mesh`] assets to the GPU: [`build_mesh_data`] rebuilds it
+/// from `Handle<Mesh>` + `GlobalTransform` entities whenever they change, with the default
+/// material for those without a `Handle<Material>`, and pipelines upload it through
+/// [`MeshBuffer`] while [`MeshData::updated`] is set.
+///
+/// Entities in cells that the [`GeometryStreaming`] didn't stream in are left out, and so are
+/// entities that don't fit in [`MAX_VERTICES`] or [`MAX_INDICES`] anymore. Entities of
+/// the static group of the [`InstanceSchedule`] come first. While only dynamic
+/// entities change, the static group is kept and only the part after it is rebuilt.
+///
+/// Within a group, entities in view of a camera come first, closest first, then the rest by
+/// distance. Uploads are spread over frames from the start of the buffers, so after a scene
+/// load what the cameras see arrives first, and so do its textures, which are ordered by
+/// their first use.
 #[derive(Resource, Default)]
 pub struct MeshData {
     positions: Vec<[f32; 3]>,
+    // one entry per position, with the material's uv transform applied
+    uvs: Vec<[f32; 2]>,
+    // one list per custom attribute channel, one entry per position
+    custom_attributes: Vec<Vec<[f32; 4]>>,
     indices: Vec<u32>,
+    // one entry per triangle
+    materials: Vec<MaterialData>,
+    textures: Vec<AssetId<Image>>,
+    // entity of every mesh and its first triangle, sorted by triangle
+    instances: Vec<(Entity, usize)>,
+    static_group: GroupEnd,
+    // the static group is unchanged since the last `set_used`
+    static_group_used: bool,
     updated: bool,
 }
 
+/// Lengths of the vectors of [`MeshData`] at the end of a group, custom attributes and uvs
+/// have one entry per position.
+#[derive(Clone, Copy, Debug, Default)]
+struct GroupEnd {
+    positions: usize,
+    indices: usize,
+    materials: usize,
+    textures: usize,
+    instances: usize,
+}
+
 impl MeshData {
     pub fn new() -> MeshData {
         MeshData::default()
     }
 
+    /// Number of indices, i.e. three per triangle.
     pub fn vertex_count(&self) -> usize {
         self.indices.len()
     }
 
+    /// Textures referenced by the materials, a material's texture index points into this.
+    pub fn textures(&self) -> &[AssetId<Image>] {
+        &self.textures
+    }
+
+    /// Values of custom attribute `channel` for every vertex, empty for unregistered channels.
+    pub fn custom_attribute(&self, channel: usize) -> &[[f32; 4]] {
+        self.custom_attributes
+            .get(channel)
+            .map_or(&[], |values| values.as_slice())
+    }
+
+    /// Closest triangle `ray` hits, with the entity it belongs to, its index in the entity's
+    /// mesh and the distance along the ray. Tests every triangle like the path tracer and counts
+    /// hits from both sides.
+    pub fn intersect(&self, ray: Ray3d) -> Option<(Entity, usize, f32)> {
+        let position = |index: u32| Vec3::from_array(self.positions[index as usize]);
+        let (triangle, distance) = self
+            .indices
+            .chunks_exact(3)
+            .enumerate()
+            .filter_map(|(triangle, indices)| {
+                let corners = [
+                    position(indices[0]),
+                    position(indices[1]),
+                    position(indices[2]),
+                ];
+                intersect_triangle(ray, corners).map(|distance| (triangle, distance))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))?;
+        let instance = self
+            .instances
+            .partition_point(|(_, first_triangle)| *first_triangle <= triangle)
+            .checked_sub(1)?;
+        let (entity, first_triangle) = self.instances[instance];
+        Some((entity, triangle - first_triangle, distance))
+    }
+
+    /// Marks the current data as uploaded.
     pub fn set_used(&mut self) {
         self.updated = false;
+        self.static_group_used = true;
+    }
+
+    /// Marks all of the data as changed, so it is uploaded again in full.
+    pub(crate) fn reupload(&mut self) {
+        self.updated = true;
+        self.static_group_used = false;
     }
 
+    /// Whether the data changed since the last [`MeshData::set_used`].
     pub fn updated(&self) -> bool {
         self.updated
     }
 
-    fn add_mesh(&mut self, mesh: &Mesh, transform: &GlobalTransform) {
+    /// Returns `false` and leaves the mesh out if it doesn't fit in the [`MeshBuffer`].
+    fn add_mesh(
+        &mut self,
+        mesh: &Mesh,
+        material: &Material,
+        opacity: Option<&InstanceOpacity>,
+        transform: &GlobalTransform,
+        custom_attributes: &CustomVertexAttributes,
+    ) -> bool {
+        let (vertex_count, index_count) = mesh_size(mesh);
+        if self.positions.len() + vertex_count > MAX_VERTICES
+            || self.indices.len() + index_count > MAX_INDICES
+        {
+            warn_once!(
+                "The scene has more than {MAX_VERTICES} vertices or {MAX_INDICES} indices, the \
+                farthest meshes are left out"
+            );
+            return false;
+        }
+
         // TODO: move matrix multiplication to GPU
         let matrix = transform.compute_matrix();
-        if mesh.indices.is_none() {
-            let start_index = self.positions.len() as u32;
-            let mut counter: u32 = 0;
-            mesh.positions.iter().for_each(|p| {
-                self.positions
-                    .push((matrix * Vec4::new(p[0], p[1], p[2], 1.0)).xyz().to_array());
-                self.indices.push(start_index + counter);
-                counter += 1;
-            });
-        } else {
-            self.positions.extend(
-                mesh.positions
-                    .iter()
-                    .map(|p| (matrix * Vec4::new(p[0], p[1], p[2], 1.0)).xyz().to_array()),
-            );
-            self.indices.extend(mesh.indices.as_ref().unwrap().iter());
+        let start_index = self.positions.len() as u32;
+        let index_count_before = self.indices.len();
+        self.positions.extend(
+            mesh.positions
+                .iter()
+                .map(|p| (matrix * Vec4::new(p[0], p[1], p[2], 1.0)).xyz().to_array()),
+        );
+        match &mesh.uvs {
+            Some(uvs) => self.uvs.extend(uvs.iter().map(|uv| {
+                material
+                    .uv_transform
+                    .transform_point2(Vec2::from_array(*uv))
+                    .to_array()
+            })),
+            None => self
+                .uvs
+                .extend(std::iter::repeat_n([0.0; 2], mesh.positions.len())),
         }
+        self.custom_attributes
+            .resize_with(custom_attributes.names.len(), Vec::new);
+        for (name, values) in custom_attributes
+            .names
+            .iter()
+            .zip(&mut self.custom_attributes)
+        {
+            match mesh.custom_attribute(name) {
+                Some(attribute) if attribute.len() == mesh.positions.len() => {
+                    values.extend((0..attribute.len()).map(|i| attribute.get_padded(i)))
+                }
+                attribute => {
+                    if attribute.is_some() {
+                        warn_once!("Custom attribute {name} doesn't have one value per position");
+                    }
+                    values.extend(std::iter::repeat_n([0.0; 4], mesh.positions.len()))
+                }
+            }
+        }
+        match &mesh.indices {
+            Some(indices) => self.indices.extend(indices.iter().map(|i| start_index + i)),
+            None => self
+                .indices
+                .extend((0..mesh.positions.len() as u32).map(|i| start_index + i)),
+        }
+
+        let triangle_count = (self.indices.len() - index_count_before) / 3;
+        let base_color_texture = material
+            .base_color_texture
+            .as_ref()
+            .map_or(NO_TEXTURE, |texture| self.texture_index(texture.id()));
+        let material = MaterialData::new(material, base_color_texture).with_opacity(opacity);
+        self.materials
+            .extend(std::iter::repeat_n(material, triangle_count));
+        true
+    }
+
+    fn texture_index(&mut self, texture: AssetId<Image>) -> u32 {
+        if let Some(index) = self.textures.iter().position(|id| *id == texture) {
+            return index as u32;
+        }
+        if self.textures.len() == MAX_TEXTURES {
+            warn_once!("More than {MAX_TEXTURES} textures in the scene, the rest are ignored");
+            return NO_TEXTURE;
+        }
+        self.textures.push(texture);
+        (self.textures.len() - 1) as u32
+    }
+
+    /// End of the static group while it stayed the same since the last
+    /// [`MeshData::set_used`], only the data after it changed then.
+    fn unchanged_static_group(&self) -> Option<GroupEnd> {
+        self.static_group_used.then_some(self.static_group)
     }
+
     fn clear(&mut self) {
         self.indices.clear();
         self.positions.clear();
+        self.uvs.clear();
+        self.custom_attributes.clear();
+        self.materials.clear();
+        self.textures.clear();
+        self.instances.clear();
+        self.static_group = GroupEnd::default();
+        self.static_group_used = false;
+    }
+
+    /// Entities added from now on belong to the dynamic group.
+    fn end_static_group(&mut self) {
+        self.static_group = GroupEnd {
+            positions: self.positions.len(),
+            indices: self.indices.len(),
+            materials: self.materials.len(),
+            textures: self.textures.len(),
+            instances: self.instances.len(),
+        };
+    }
+
+    /// Drops the dynamic group, keeping the static one.
+    fn clear_dynamic_group(&mut self) {
+        let end = self.static_group;
+        self.positions.truncate(end.positions);
+        self.uvs.truncate(end.positions);
+        for values in &mut self.custom_attributes {
+            values.truncate(end.positions);
+        }
+        self.indices.truncate(end.indices);
+        self.materials.truncate(end.materials);
+        self.textures.truncate(end.textures);
+        self.instances.truncate(end.instances);
+    }
+}
+
+/// Vertex and index count `mesh` takes up in [`MeshData`].
+fn mesh_size(mesh: &Mesh) -> (usize, usize) {
+    let index_count = mesh.indices.as_ref().map_or(mesh.positions.len(), Vec::len);
+    (mesh.positions.len(), index_count)
+}
+
+/// Distance along `ray` to the triangle with `corners`, same as `IntersectTriangle` in
+/// `demo.hlsl`.
+fn intersect_triangle(ray: Ray3d, [a, b, c]: [Vec3; 3]) -> Option<f32> {
+    let edge_ab = b - a;
+    let edge_ac = c - a;
+    let normal = edge_ab.cross(edge_ac);
+    let ao = ray.origin - a;
+    let dao = ao.cross(*ray.direction);
+
+    let determinant = -ray.direction.dot(normal);
+    if determinant.abs() < 1e-6 {
+        return None;
     }
+    let distance = ao.dot(normal) / determinant;
+    let u = edge_ac.dot(dao) / determinant;
+    let v = -edge_ab.dot(dao) / determinant;
+    (distance >= 0.0 && u >= 0.0 && v >= 0.0 && u + v <= 1.0).then_some(distance)
+}
+
+/// Whether an entity at `position` is outside of every view, and its distance to the closest
+/// one. Entities are sorted by this before they are added to [`MeshData`].
+fn upload_order(views: &[View], position: Vec3) -> (bool, f32) {
+    let in_view = views.iter().any(|view| {
+        view.project(position).is_some_and(|viewport_position| {
+            viewport_position.cmpge(Vec2::ZERO).all() && viewport_position.cmple(Vec2::ONE).all()
+        })
+    });
+    let distance = views
+        .iter()
+        .map(|view| view.origin().distance(position))
+        .fold(f32::INFINITY, f32::min);
+    (!in_view, distance)
 }
 
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 pub fn build_mesh_data(
-    changed_meshes: Query<Entity, (With<Handle<Mesh>>, Changed<GlobalTransform>)>,
-    all_mesh_handles: Query<(&Handle<Mesh>, &GlobalTransform)>,
+    changed_meshes: Query<
+        Entity,
+        (
+            With<Handle<Mesh>>,
+            Or<(
+                Changed<Handle<Mesh>>,
+                Changed<Handle<Material>>,
+                Changed<GlobalTransform>,
+                Changed<Visibility>,
+                Changed<InstanceOpacity>,
+            )>,
+        ),
+    >,
+    all_mesh_handles: Query<(
+        Entity,
+        &Handle<Mesh>,
+        Option<&Handle<Material>>,
+        &GlobalTransform,
+        Option<&Visibility>,
+        Option<&InstanceOpacity>,
+    )>,
     mesh_assets: Res<Assets<Mesh>>,
+    material_assets: Res<Assets<Material>>,
+    placeholders: Res<PlaceholderAssets>,
+    mut mesh_events: EventReader<AssetEvent<Mesh>>,
+    mut material_events: EventReader<AssetEvent<Material>>,
+    mut removed_meshes: RemovedComponents<Handle<Mesh>>,
+    mut removed_materials: RemovedComponents<Handle<Material>>,
+    mut removed_opacities: RemovedComponents<InstanceOpacity>,
+    // fading counts as moving, it changes the mesh data every frame as well
+    moved_meshes: Query<
+        (Entity, Ref<GlobalTransform>),
+        (
+            With<Handle<Mesh>>,
+            Or<(Changed<GlobalTransform>, Changed<InstanceOpacity>)>,
+        ),
+    >,
+    cameras: Query<(&GlobalTransform, &Camera)>,
+    custom_attributes: Res<CustomVertexAttributes>,
+    mut schedule: ResMut<InstanceSchedule>,
+    mut streaming: ResMut<GeometryStreaming>,
     mut mesh_data: ResMut<MeshData>,
 ) {
-    if changed_meshes.is_empty() {
+    let mut meshes_removed = false;
+    for entity in removed_meshes.read() {
+        schedule.forget(entity);
+        meshes_removed = true;
+    }
+    let opacities_removed = removed_opacities.read().count() > 0;
+    // the mesh falls back to the default material
+    let materials_removed = removed_materials.read().count() > 0;
+    // spawning isn't moving
+    let regrouped = schedule.update(
+        moved_meshes
+            .iter()
+            .filter(|(_, transform)| !transform.is_added())
+            .map(|(entity, _)| entity),
+    );
+    // loaded meshes replace their placeholders
+    let meshes_changed = mesh_events.read().any(|event| {
+        matches!(
+            event,
+            AssetEvent::Added { .. } | AssetEvent::Modified { .. }
+        )
+    });
+    // removed materials fall back to the default one
+    let materials_changed = material_events.read().any(|event| {
+        matches!(
+            event,
+            AssetEvent::Added { .. } | AssetEvent::Modified { .. } | AssetEvent::Removed { .. }
+        )
+    });
+    let streaming_changed = streaming.is_changed();
+    let views: Vec<View> = cameras
+        .iter()
+        .map(|(transform, camera)| View::new(transform, camera))
+        .collect();
+    let camera_positions: Vec<Vec3> = views.iter().map(View::origin).collect();
+    let placeholder_mesh = placeholders
+        .enabled
+        .then(|| mesh_assets.get(&placeholders.mesh))
+        .flatten();
+    let streamed = streaming.update(
+        &camera_positions,
+        all_mesh_handles
+            .iter()
+            .map(|(_, mesh_handle, _, transform, _, _)| {
+                let (vertex_count, index_count) = mesh_assets
+                    .get(mesh_handle)
+                    .or(placeholder_mesh)
+                    .map_or((0, 0), mesh_size);
+                (transform.translation(), vertex_count, index_count)
+            }),
+    );
+    let static_changed = streaming_changed
+        || streamed
+        || regrouped
+        || meshes_changed
+        || materials_changed
+        || meshes_removed
+        || opacities_removed
+        || materials_removed
+        || custom_attributes.is_changed()
+        || changed_meshes
+            .iter()
+            .any(|entity| !schedule.is_dynamic(entity));
+    let dynamic_changed = changed_meshes
+        .iter()
+        .any(|entity| schedule.is_dynamic(entity));
+    if !static_changed && !dynamic_changed {
         return;
     }
 
-    mesh_data.clear();
-    for (mesh_handle, mesh_global_transform) in all_mesh_handles.iter() {
-        let mesh = mesh_assets.get(mesh_handle).unwrap();
-        mesh_data.add_mesh(mesh, mesh_global_transform);
+    let default_material = Material::default();
+    let add_group = |mesh_data: &mut MeshData, dynamic: bool| {
+        let mut entities: Vec<_> = all_mesh_handles
+            .iter()
+            .filter(|(entity, _, _, transform, visibility, _)| {
+                *visibility != Some(&Visibility::Hidden)
+                    && schedule.is_dynamic(*entity) == dynamic
+                    && streaming.is_streamed_in(transform.translation())
+            })
+            .map(|entity| (upload_order(&views, entity.3.translation()), entity))
+            .collect();
+        entities.sort_by(|(a, _), (b, _)| a.0.cmp(&b.0).then(a.1.total_cmp(&b.1)));
+
+        for (_, (entity, mesh_handle, material_handle, transform, _, opacity)) in entities {
+            let mesh = match mesh_assets.get(mesh_handle) {
+                Some(mesh) => mesh,
+                None if placeholders.enabled => mesh_assets.get(&placeholders.mesh).unwrap(),
+                None => continue,
+            };
+            let fallback_material = if placeholders.enabled {
+                &placeholders.material
+            } else {
+                &Handle::default()
+            };
+            // entities without a material or with one that isn't loaded use the fallback, the
+            // default material when even that one was removed from the assets
+            let material = material_handle
+                .and_then(|handle| material_assets.get(handle))
+                .or_else(|| material_assets.get(fallback_material))
+                .unwrap_or(&default_material);
+            let first_triangle = mesh_data.materials.len();
+            if mesh_data.add_mesh(mesh, material, opacity, transform, &custom_attributes) {
+                mesh_data.instances.push((entity, first_triangle));
+            }
+        }
+    };
+
+    if static_changed {
+        mesh_data.clear();
+        add_group(&mut mesh_data, false);
+        mesh_data.end_static_group();
+    } else {
+        mesh_data.clear_dynamic_group();
     }
+    add_group(&mut mesh_data, true);
     mesh_data.updated = true;
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::core::PrimitiveTopology;
+
+    use super::*;
+
+    fn triangles(vertex_count: usize) -> Mesh {
+        let mut mesh = Mesh::new(PrimitiveTopology::TriangleList);
+        mesh.positions = vec![[0.0; 3]; vertex_count];
+        mesh
+    }
+
+    #[test]
+    fn meshes_past_the_buffer_capacity_are_left_out() {
+        let mut data = MeshData::new();
+        let add = |data: &mut MeshData, mesh: &Mesh| {
+            data.add_mesh(
+                mesh,
+                &Material::default(),
+                None,
+                &GlobalTransform::IDENTITY,
+                &CustomVertexAttributes::default(),
+            )
+        };
+
+        assert!(!add(&mut data, &triangles(MAX_VERTICES + 3)));
+        assert_eq!(data.vertex_count(), 0);
+
+        assert!(add(&mut data, &triangles(MAX_VERTICES - 3)));
+        assert!(!add(&mut data, &triangles(6)));
+        assert!(add(&mut data, &triangles(3)));
+        assert_eq!(data.positions.len(), MAX_VERTICES);
+        assert_eq!(data.materials.len(), MAX_VERTICES / 3);
+    }
+}
@@ -0,0 +1,200 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, HashSet},
+};
+
+use super::{MAX_INDICES, MAX_VERTICES};
+
+/// Streams the mesh entities into the GPU scene by spatial cells, so scenes larger than the
+/// mesh buffers can hold are rendered around the camera.
+///
+/// Entities are put into cubic cells of [`GeometryStreaming::cell_size`] by their origin. A
+/// cell is streamed in once its closest point is within [`GeometryStreaming::radius`] of a
+/// camera and streamed out again once it is further than `radius + hysteresis`, so moving
+/// along a cell border doesn't stream it in and out every frame. Cells are streamed in nearest
+/// first while their geometry fits in [`MAX_VERTICES`] and [`MAX_INDICES`], the ones past the
+/// first that doesn't fit stay out even within `radius`. Entities of cells that
+/// aren't streamed in are left out of [`super::MeshData`]. Streaming a cell in or out
+/// rebuilds the static group of the [`super::InstanceSchedule`], its upload is spread over
+/// frames by the [`crate::render::UploadBudget`] like any other.
+#[derive(Resource, Reflect, Debug, Clone)]
+#[reflect(Resource, Default)]
+pub struct GeometryStreaming {
+    /// Every entity is rendered while off.
+    pub enabled: bool,
+    /// Edge length of the cells, in world units.
+    pub cell_size: f32,
+    /// Distance to a camera within which cells are streamed in.
+    pub radius: f32,
+    /// How much further than `radius` a cell has to be to be streamed out again.
+    pub hysteresis: f32,
+    #[reflect(ignore)]
+    streamed_in: HashSet<IVec3>,
+}
+
+impl Default for GeometryStreaming {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            cell_size: 64.0,
+            radius: 256.0,
+            hysteresis: 32.0,
+            streamed_in: HashSet::default(),
+        }
+    }
+}
+
+impl GeometryStreaming {
+    /// Cell containing `position`.
+    pub fn cell(&self, position: Vec3) -> IVec3 {
+        (position / self.cell_size).floor().as_ivec3()
+    }
+
+    /// Whether an entity at `position` is rendered.
+    pub fn is_streamed_in(&self, position: Vec3) -> bool {
+        !self.enabled || self.streamed_in.contains(&self.cell(position))
+    }
+
+    /// Cells with entities that are streamed in.
+    pub fn streamed_in_count(&self) -> usize {
+        self.streamed_in.len()
+    }
+
+    /// Streams cells in and out for `cameras`. `entities` are the position, vertex count and
+    /// index count of every mesh entity. Returns whether a cell was streamed in or out.
+    pub(super) fn update(
+        &mut self,
+        cameras: &[Vec3],
+        entities: impl IntoIterator<Item = (Vec3, usize, usize)>,
+    ) -> bool {
+        if !self.enabled {
+            let changed = !self.streamed_in.is_empty();
+            self.streamed_in.clear();
+            return changed;
+        }
+        // without a camera there is nothing to stream for, keep what is there
+        if cameras.is_empty() {
+            return false;
+        }
+
+        // vertex and index count of every occupied cell
+        let mut occupied: HashMap<IVec3, (usize, usize)> = HashMap::default();
+        for (position, vertices, indices) in entities {
+            let size = occupied.entry(self.cell(position)).or_default();
+            size.0 += vertices;
+            size.1 += indices;
+        }
+        let mut in_range: Vec<_> = occupied
+            .iter()
+            .filter_map(|(&cell, &size)| {
+                let distance = cameras
+                    .iter()
+                    .map(|camera| self.distance_to_cell(*camera, cell))
+                    .fold(f32::INFINITY, f32::min);
+                let range = if self.streamed_in.contains(&cell) {
+                    self.radius + self.hysteresis
+                } else {
+                    self.radius
+                };
+                (distance <= range).then_some((distance, cell, size))
+            })
+            .collect();
+        in_range.sort_by(|(a, ..), (b, ..)| a.total_cmp(b));
+
+        let mut streamed_in = HashSet::with_capacity(in_range.len());
+        let (mut vertices, mut indices) = (0, 0);
+        for (_, cell, size) in in_range {
+            vertices += size.0;
+            indices += size.1;
+            if vertices > MAX_VERTICES || indices > MAX_INDICES {
+                warn_once!("Streamed in cells don't fit in the mesh buffers, reduce the radius");
+                break;
+            }
+            streamed_in.insert(cell);
+        }
+        // cells that lost all their entities don't change what is rendered
+        let changed = occupied
+            .keys()
+            .any(|cell| streamed_in.contains(cell) != self.streamed_in.contains(cell));
+        self.streamed_in = streamed_in;
+        changed
+    }
+
+    fn distance_to_cell(&self, position: Vec3, cell: IVec3) -> f32 {
+        let min = cell.as_vec3() * self.cell_size;
+        position.distance(position.clamp(min, min + self.cell_size))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn streaming() -> GeometryStreaming {
+        GeometryStreaming {
+            enabled: true,
+            cell_size: 10.0,
+            radius: 20.0,
+            hysteresis: 5.0,
+            ..default()
+        }
+    }
+
+    // one small mesh in the middle of the cell 3 cells away from the origin along x
+    const ENTITY: (Vec3, usize, usize) = (Vec3::new(35.0, 5.0, 5.0), 3, 3);
+
+    #[test]
+    fn cells_stream_in_within_radius_and_out_past_hysteresis() {
+        let mut streaming = streaming();
+
+        // the cell starts at x = 30
+        assert!(!streaming.update(&[Vec3::new(9.0, 5.0, 5.0)], [ENTITY]));
+        assert!(!streaming.is_streamed_in(ENTITY.0));
+
+        assert!(streaming.update(&[Vec3::new(10.0, 5.0, 5.0)], [ENTITY]));
+        assert!(streaming.is_streamed_in(ENTITY.0));
+
+        // within the hysteresis it stays in
+        assert!(!streaming.update(&[Vec3::new(6.0, 5.0, 5.0)], [ENTITY]));
+        assert!(!streaming.update(&[Vec3::new(5.0, 5.0, 5.0)], [ENTITY]));
+        assert!(streaming.is_streamed_in(ENTITY.0));
+
+        assert!(streaming.update(&[Vec3::new(4.0, 5.0, 5.0)], [ENTITY]));
+        assert!(!streaming.is_streamed_in(ENTITY.0));
+
+        // and has to come within the radius again
+        assert!(!streaming.update(&[Vec3::new(6.0, 5.0, 5.0)], [ENTITY]));
+        assert!(!streaming.is_streamed_in(ENTITY.0));
+    }
+
+    #[test]
+    fn nearest_cells_stream_in_while_they_fit_in_the_mesh_buffers() {
+        let mut streaming = streaming();
+        let half = MAX_VERTICES / 2;
+        let near = (Vec3::new(5.0, 5.0, 5.0), half, half);
+        let middle = (Vec3::new(15.0, 5.0, 5.0), half, half);
+        let far = (Vec3::new(25.0, 5.0, 5.0), 3, 3);
+
+        assert!(streaming.update(&[Vec3::ZERO], [far, middle, near]));
+        assert!(streaming.is_streamed_in(near.0));
+        assert!(streaming.is_streamed_in(middle.0));
+        assert!(!streaming.is_streamed_in(far.0));
+
+        // from the other side the far cell is the nearest
+        assert!(streaming.update(&[Vec3::new(30.0, 5.0, 5.0)], [far, middle, near]));
+        assert!(streaming.is_streamed_in(far.0));
+        assert!(streaming.is_streamed_in(middle.0));
+        assert!(!streaming.is_streamed_in(near.0));
+    }
+
+    #[test]
+    fn everything_is_streamed_in_while_disabled() {
+        let mut streaming = streaming();
+        streaming.update(&[Vec3::new(10.0, 5.0, 5.0)], [ENTITY]);
+
+        streaming.enabled = false;
+        assert!(streaming.update(&[], [ENTITY]));
+        assert!(streaming.is_streamed_in(Vec3::splat(1000.0)));
+        assert_eq!(streaming.streamed_in_count(), 0);
+    }
+}
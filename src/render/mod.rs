@@ -1,73 +1,326 @@
+mod accumulation;
+mod adapter_switch;
+mod aov;
+mod bindless;
+mod capture;
+mod command_queue;
+mod comparison;
 mod constant_buffer;
+mod d3d;
+mod descriptor_allocator;
 mod descriptor_heap;
+mod descriptor_ring;
+mod device_removed;
 mod drawer;
+mod fence_timeout;
+mod frame_events;
+mod frame_graph;
+mod frame_limiter;
+mod furnace;
+mod gizmos;
 mod gpu;
+mod gpu_features;
+mod gpu_fence;
+mod gpu_timings;
+mod headless;
+mod late_latch;
+mod leak_report;
+mod light_data;
+mod material_textures;
 mod mesh_data;
+mod offline;
 mod pipelines;
+mod pix;
+mod primitive_data;
+mod quirks;
+mod raycast;
+mod render_graph;
 mod render_target;
+mod retired_resources;
+mod scene_prep;
+mod settings;
+mod structured_buffer;
+#[cfg(test)]
+mod test_utils;
+mod upload;
+mod vertex_buffer;
+mod view;
 
 use bevy::{app::MainScheduleOrder, ecs::schedule::ScheduleLabel, prelude::*};
 
-use drawer::draw;
-use mesh_data::MeshPlugin;
+use accumulation::AccumulationPlugin;
+use adapter_switch::AdapterSwitchPlugin;
+use capture::{read_frame_capture, FrameSequencePlugin, ScreenshotPlugin};
+use command_queue::gpu_command_queue;
+use comparison::ComparisonPlugin;
+use device_removed::DeviceRecoveryPlugin;
+use drawer::{draw, present};
+use frame_limiter::limit_frame_rate;
+use furnace::FurnaceTestPlugin;
+use gizmos::GizmoPlugin;
+use gpu_timings::read_gpu_timings;
+use headless::prepare_headless_target;
+use leak_report::LeakReportPlugin;
+use light_data::LightDataPlugin;
+use material_textures::{prepare_material_textures, MaterialTextures};
+use mesh_data::{build_mesh_data, MeshPlugin};
+use offline::OfflineRenderPlugin;
 use pipelines::{
-    create_pathtracer_pipeline, PathTracerShaderHandle, PipelineStorage, PATH_TRACER_PIPELINE_ID,
+    create_auto_exposure_pipeline, create_deferred_pipeline, create_gizmo_pipeline,
+    create_pathtracer_pipeline, create_raster_forward_pipeline, create_tonemap_pipeline,
+    prepare_debug_view, prepare_gizmos, prepare_tonemap, read_path_statistics,
+    retry_failed_pipeline_states, scene_pipeline_is, AutoExposureShaderHandle,
+    DeferredGBufferShaderHandle, DeferredLightingShaderHandle, DepthPrepassShaderHandle,
+    GizmoShaderHandle, HybridGBufferShaderHandle, PathTracerShaderHandle, PipelineStorage,
+    RadianceCacheShaderHandle, RasterForwardShaderHandle, TonemapShaderHandle,
+    DEFERRED_PIPELINE_ID, PATH_TRACER_PIPELINE_ID, RASTER_FORWARD_PIPELINE_ID,
 };
-use render_target::{create_render_targets, switch_frame, RtvHeap, FRAME_COUNT};
+use primitive_data::PrimitiveDataPlugin;
+use render_target::{create_render_targets, switch_frame};
+use scene_prep::ScenePrepPlugin;
+use settings::RenderSettingsPlugin;
 
+pub use accumulation::ResetAccumulation;
+pub use adapter_switch::SwitchAdapter;
+pub use aov::{AovClear, AovDesc, AovRegistry, AovTarget, MAX_AOVS};
+pub use bindless::{BindlessDescriptors, BindlessHeap, BINDLESS_DESCRIPTORS, BINDLESS_SPACES};
+pub use capture::{
+    CapturedAov, CapturedFrame, CapturedHdrFrame, FrameCapture, FrameSequence,
+    FrameSequenceCallback, FrameSequenceSettings, Screenshot, ScreenshotImage, ScreenshotTaken,
+};
+pub use command_queue::{GpuCommandContext, GpuCommandQueue};
+pub use comparison::{CompareRenders, ComparisonFinished, ComparisonMode};
+pub use constant_buffer::{ConstantRing, CONSTANT_RING_SIZE};
+pub use descriptor_allocator::{
+    DescriptorAllocator, DescriptorAllocators, Descriptors, DESCRIPTORS_PER_HEAP,
+};
 pub use descriptor_heap::DescriptorHeap;
+pub use descriptor_ring::{DescriptorRing, TransientDescriptors, RING_DESCRIPTORS};
+pub use device_removed::DeviceRecovered;
 pub use drawer::Drawer;
-pub use gpu::Gpu;
-pub use mesh_data::MeshData;
-use windows::Win32::Graphics::Direct3D12::{
-    D3D12_DESCRIPTOR_HEAP_FLAG_NONE, D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
+pub use fence_timeout::FenceTimeout;
+pub use frame_events::{FrameRenderStarted, FrameRendered, FrameTimestamp};
+pub use frame_graph::FrameGraph;
+pub use frame_limiter::{FrameLimiter, FrameWait};
+pub use furnace::{FurnaceTest, FurnaceTestFinished};
+pub use gizmos::{GizmoVisibility, Gizmos, SceneGizmos};
+pub use gpu::{Gpu, GpuSettings, QueuePriority};
+pub use gpu_features::GpuFeatures;
+pub use gpu_fence::GpuFence;
+pub use gpu_timings::{GpuTimings, PassTiming, ViewTimings};
+pub use headless::Headless;
+pub use late_latch::CameraLateLatch;
+pub use leak_report::set_debug_name;
+pub use light_data::LightData;
+pub use mesh_data::{
+    CustomVertexAttributes, GeometryStreaming, InstanceSchedule, MeshBuffer, MeshData,
+    MAX_CUSTOM_ATTRIBUTES,
+};
+pub use offline::{RenderFinished, RenderRequest};
+pub use pipelines::{
+    DebugView, Dithering, FinalBlit, Integrator, PathStatistics, PathTracerSettings,
+    PostProcessOverride, ScenePipeline, SceneTarget, Tonemapping,
+};
+pub use primitive_data::PrimitiveData;
+pub use quirks::{DriverQuirks, QuirkEntry, QUIRKS};
+pub use raycast::{Raycast, RaycastHit};
+pub use render_target::{
+    AccumulationPrecision, BackBufferFormat, ConfigureSwapchain, ExternalWindow, Msaa, PresentMode,
+    WindowPresentation, WindowRenderTarget,
 };
+pub use retired_resources::RetiredResources;
+pub use scene_prep::{ScenePrepState, SceneRenderReady};
+pub use settings::{RenderSettings, RenderSettingsError, RenderSettingsHandle};
+pub use upload::{UploadBudget, UploadPriority, UploadQueue};
+pub use view::View;
 
 pub struct RenderPlugin;
 
 impl Plugin for RenderPlugin {
     fn build(&self, app: &mut App) {
         app.init_schedule(RenderSchedule);
+        app.configure_sets(
+            RenderSchedule,
+            (
+                RenderSet::Extract,
+                RenderSet::Prepare,
+                RenderSet::Draw,
+                RenderSet::Present,
+            )
+                .chain(),
+        );
         app.world_mut()
             .resource_mut::<MainScheduleOrder>()
             .insert_after(Last, RenderSchedule);
 
-        let gpu = unsafe { Gpu::new(false) }.expect("Failed to initialize renderer");
+        let gpu_settings = app
+            .world()
+            .get_resource::<GpuSettings>()
+            .copied()
+            .unwrap_or_default();
+        let gpu = unsafe { Gpu::with_warp_fallback(gpu_settings) }
+            .expect("Failed to initialize renderer");
+        let gpu_features = GpuFeatures::new(&gpu);
         let drawer = Drawer::new(&gpu);
+        let material_textures = MaterialTextures::new(&gpu);
+        let (gpu_command_queue, gpu_commands) = gpu_command_queue();
 
         let asset_server = app.world_mut().resource_mut::<AssetServer>();
         let shader_handle = asset_server.load("demo.hlsl");
-        let rtv_heap = DescriptorHeap::new(
-            &gpu,
-            D3D12_DESCRIPTOR_HEAP_TYPE_RTV,
-            FRAME_COUNT,
-            D3D12_DESCRIPTOR_HEAP_FLAG_NONE,
-        );
+        let tonemap_shader_handle = asset_server.load("tonemap.hlsl");
+        let auto_exposure_shader_handle = asset_server.load("auto_exposure.hlsl");
+        let radiance_cache_shader_handle = asset_server.load("radiance_cache.hlsl");
+        let depth_prepass_shader_handle = asset_server.load("depth_prepass.hlsl");
+        let hybrid_g_buffer_shader_handle = asset_server.load("hybrid_gbuffer.hlsl");
+        let raster_forward_shader_handle = asset_server.load("raster_forward.hlsl");
+        let deferred_g_buffer_shader_handle = asset_server.load("deferred_gbuffer.hlsl");
+        let deferred_lighting_shader_handle = asset_server.load("deferred_lighting.hlsl");
+        let gizmo_shader_handle = asset_server.load("gizmos.hlsl");
 
         app.insert_resource(gpu)
+            .insert_resource(gpu_settings)
+            .register_type::<GpuSettings>()
+            .insert_resource(gpu_features)
+            .register_type::<GpuFeatures>()
             .insert_resource(PathTracerShaderHandle(shader_handle))
+            .insert_resource(TonemapShaderHandle(tonemap_shader_handle))
+            .insert_resource(AutoExposureShaderHandle(auto_exposure_shader_handle))
+            .insert_resource(RadianceCacheShaderHandle(radiance_cache_shader_handle))
+            .insert_resource(DepthPrepassShaderHandle(depth_prepass_shader_handle))
+            .insert_resource(HybridGBufferShaderHandle(hybrid_g_buffer_shader_handle))
+            .insert_resource(RasterForwardShaderHandle(raster_forward_shader_handle))
+            .insert_resource(DeferredGBufferShaderHandle(deferred_g_buffer_shader_handle))
+            .insert_resource(DeferredLightingShaderHandle(
+                deferred_lighting_shader_handle,
+            ))
+            .insert_resource(GizmoShaderHandle(gizmo_shader_handle))
+            .init_resource::<Tonemapping>()
+            .register_type::<Tonemapping>()
+            .init_resource::<GpuTimings>()
+            .register_type::<GpuTimings>()
+            .init_resource::<PathTracerSettings>()
+            .register_type::<PathTracerSettings>()
+            .init_resource::<PathStatistics>()
+            .register_type::<PathStatistics>()
+            .init_resource::<DebugView>()
+            .register_type::<DebugView>()
+            .init_resource::<AovRegistry>()
+            .init_resource::<ScenePipeline>()
+            .register_type::<ScenePipeline>()
+            .init_resource::<FenceTimeout>()
+            .register_type::<FenceTimeout>()
+            .init_resource::<FrameLimiter>()
+            .register_type::<FrameLimiter>()
+            .register_type::<Headless>()
+            .init_resource::<UploadBudget>()
+            .register_type::<UploadBudget>()
+            .init_resource::<UploadQueue>()
+            .register_type::<WindowPresentation>()
+            .register_type::<PresentMode>()
+            .register_type::<BackBufferFormat>()
+            .init_resource::<Msaa>()
+            .register_type::<Msaa>()
+            .init_resource::<Dithering>()
+            .register_type::<Dithering>()
+            .init_resource::<FinalBlit>()
+            .register_type::<FinalBlit>()
+            .register_type::<PostProcessOverride>()
+            .init_resource::<AccumulationPrecision>()
+            .register_type::<AccumulationPrecision>()
+            .init_resource::<FrameCapture>()
             .insert_resource(drawer)
+            .insert_resource(material_textures)
+            .insert_resource(gpu_command_queue)
+            .insert_resource(gpu_commands)
             .insert_resource(PipelineStorage::new())
-            .insert_resource(RtvHeap(rtv_heap))
             .add_event::<ResizeEvent>()
+            .add_event::<FrameRenderStarted>()
+            .add_event::<FrameRendered>()
+            .add_systems(
+                RenderSchedule,
+                prepare_material_textures
+                    .after(build_mesh_data)
+                    .in_set(RenderSet::Extract),
+            )
+            .add_systems(
+                RenderSchedule,
+                retry_failed_pipeline_states.in_set(RenderSet::Prepare),
+            )
             .add_systems(
                 RenderSchedule,
                 (
                     create_render_targets,
+                    prepare_headless_target,
                     create_pathtracer_pipeline,
-                    draw::<PATH_TRACER_PIPELINE_ID>,
+                    create_raster_forward_pipeline,
+                    create_deferred_pipeline,
+                    create_tonemap_pipeline,
+                    create_auto_exposure_pipeline,
+                    create_gizmo_pipeline,
+                    prepare_tonemap,
+                    prepare_gizmos,
+                    prepare_debug_view,
+                    draw::<PATH_TRACER_PIPELINE_ID>
+                        .run_if(scene_pipeline_is::<PATH_TRACER_PIPELINE_ID>),
+                    draw::<RASTER_FORWARD_PIPELINE_ID>
+                        .run_if(scene_pipeline_is::<RASTER_FORWARD_PIPELINE_ID>),
+                    draw::<DEFERRED_PIPELINE_ID>.run_if(scene_pipeline_is::<DEFERRED_PIPELINE_ID>),
+                )
+                    .chain()
+                    .in_set(RenderSet::Draw),
+            )
+            .add_systems(
+                RenderSchedule,
+                (
+                    present,
                     switch_frame,
+                    limit_frame_rate,
+                    read_gpu_timings,
+                    read_path_statistics,
+                    read_frame_capture,
                 )
-                    .chain(),
+                    .chain()
+                    .in_set(RenderSet::Present),
             );
 
-        app.add_plugins(MeshPlugin);
+        app.add_plugins((
+            MeshPlugin,
+            LightDataPlugin,
+            PrimitiveDataPlugin,
+            AccumulationPlugin,
+            RenderSettingsPlugin,
+            ScenePrepPlugin,
+            ComparisonPlugin,
+            OfflineRenderPlugin,
+            ScreenshotPlugin,
+            FrameSequencePlugin,
+            FurnaceTestPlugin,
+            LeakReportPlugin,
+            AdapterSwitchPlugin,
+            GizmoPlugin,
+            DeviceRecoveryPlugin,
+        ));
     }
 }
 
 #[derive(ScheduleLabel, Debug, Clone, PartialEq, Eq, Hash)]
 pub struct RenderSchedule;
 
+/// Stages of [`RenderSchedule`], run in order.
+#[derive(SystemSet, Debug, Clone, PartialEq, Eq, Hash)]
+pub enum RenderSet {
+    /// Gathers scene data (meshes, lights) from the world.
+    Extract,
+    /// Reacts to the extracted data before anything is recorded.
+    Prepare,
+    /// Records and submits GPU work.
+    Draw,
+    /// Presents the drawn windows and reads back the previous frame. Systems ordered after
+    /// [`RenderSet::Draw`] and before this set run right before present, with the frame
+    /// already submitted.
+    Present,
+}
+
 #[derive(Event)]
 pub struct ResizeEvent {
     pub entity: Entity,
@@ -0,0 +1,334 @@
+mod target;
+
+use std::path::PathBuf;
+
+use bevy::prelude::*;
+use image::{imageops, Rgba32FImage, RgbaImage};
+use windows::Win32::Graphics::Direct3D12::*;
+
+use super::{
+    capture::{unpremultiply, TextureReadback},
+    drawer::ViewTarget,
+    fence_timeout::FenceTimeout,
+    render_target::{AccumulationPrecision, FRAME_COUNT},
+    Drawer, Gpu, PathTracerSettings, RenderSchedule, RenderSet,
+};
+use crate::core::Camera;
+
+pub(crate) use target::OffscreenTarget;
+
+/// Renders the scene off-screen at any resolution and writes the result to `output`.
+///
+/// `samples` samples per pixel are accumulated over as many frames as
+/// [`PathTracerSettings::samples_per_frame`] needs. An `.exr` output gets the linear scene
+/// radiance before exposure and tone mapping, any other extension the tone mapped image.
+/// Windows aren't redrawn while the render runs, [`RenderFinished`] is sent once the file is
+/// written. Keep the camera still, a moving camera restarts the render.
+#[derive(Event, Debug, Clone)]
+pub struct RenderRequest {
+    pub width: u32,
+    pub height: u32,
+    pub samples: u32,
+    pub output: PathBuf,
+    /// Renders the image in square tiles of this size one after another and stitches them
+    /// together, only one tile has to fit in GPU memory. Sizes above the largest texture
+    /// D3D12 supports are always tiled. Auto exposure is measured per tile, use a fixed
+    /// exposure for tiled renders.
+    pub tile_size: Option<u32>,
+}
+
+/// Sent when the image of a [`RenderRequest`] is written.
+#[derive(Event, Debug, Clone)]
+pub struct RenderFinished {
+    pub output: PathBuf,
+}
+
+pub struct OfflineRenderPlugin;
+
+impl Plugin for OfflineRenderPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_event::<RenderRequest>()
+            .add_event::<RenderFinished>()
+            .add_systems(Update, start_offline_render)
+            .add_systems(
+                RenderSchedule,
+                finish_offline_render.after(RenderSet::Present),
+            );
+    }
+}
+
+/// Image the tiles of a render are copied into.
+enum StitchedImage {
+    Float(Rgba32FImage),
+    Unorm(RgbaImage),
+}
+
+/// Off-screen target of the running [`RenderRequest`].
+#[derive(Resource)]
+pub struct OfflineRender {
+    request: RenderRequest,
+    frames: u32,
+    accumulated_frames: u32,
+    tile_size: u32,
+    tiles: UVec2,
+    tile: u32,
+    image: StitchedImage,
+    target: OffscreenTarget,
+    // one per frame slot, read at the end of the frame after the one the copy is recorded in
+    readbacks: [Option<TextureReadback>; FRAME_COUNT],
+    // tile copied into the readback of a slot and not stitched yet
+    copied_tiles: [Option<u32>; FRAME_COUNT],
+    stitched_tiles: u32,
+}
+
+impl OfflineRender {
+    fn new(
+        gpu: &Gpu,
+        request: RenderRequest,
+        samples_per_frame: u32,
+        precision: AccumulationPrecision,
+    ) -> Self {
+        let width = request.width.max(1);
+        let height = request.height.max(1);
+        let frames = request.samples.div_ceil(samples_per_frame.max(1)).max(1);
+        let tile_size = request
+            .tile_size
+            .unwrap_or(u32::MAX)
+            .clamp(1, D3D12_REQ_TEXTURE2D_U_OR_V_DIMENSION);
+        let tiles = UVec2::new(width.div_ceil(tile_size), height.div_ceil(tile_size));
+        // a single tile is only as large as the image
+        let target_size = if tiles == UVec2::ONE {
+            UVec2::new(width, height)
+        } else {
+            UVec2::splat(tile_size)
+        };
+        let image = if writes_exr(&request) {
+            StitchedImage::Float(Rgba32FImage::new(width, height))
+        } else {
+            StitchedImage::Unorm(RgbaImage::new(width, height))
+        };
+
+        let target = OffscreenTarget::new(gpu, target_size, precision.dxgi_format());
+
+        Self {
+            request,
+            frames,
+            accumulated_frames: 0,
+            tile_size,
+            tiles,
+            tile: 0,
+            image,
+            target,
+            readbacks: Default::default(),
+            copied_tiles: [None; FRAME_COUNT],
+            stitched_tiles: 0,
+        }
+    }
+
+    /// `camera` with the aspect ratio of the render.
+    pub(crate) fn camera(&self, camera: &Camera) -> Camera {
+        Camera {
+            aspect_ratio: self.width() as f32 / self.height() as f32,
+            ..camera.clone()
+        }
+    }
+
+    fn width(&self) -> u32 {
+        self.request.width.max(1)
+    }
+
+    fn height(&self) -> u32 {
+        self.request.height.max(1)
+    }
+
+    fn tile_count(&self) -> u32 {
+        self.tiles.x * self.tiles.y
+    }
+
+    /// Pixel position of `tile` in the image.
+    fn tile_position(&self, tile: u32) -> UVec2 {
+        UVec2::new(tile % self.tiles.x, tile / self.tiles.x) * self.tile_size
+    }
+
+    /// Whether every tile is rendered, the render finishes once the copy of the last one is
+    /// read back.
+    pub(crate) fn is_rendered(&self) -> bool {
+        self.tile == self.tile_count()
+    }
+
+    /// Part of the image the current tile covers, tiles at the right and bottom edges reach
+    /// past it.
+    pub(crate) fn view_rect(&self) -> Rect {
+        let size = self.target.size().as_vec2();
+        let image_size = Vec2::new(self.width() as f32, self.height() as f32);
+        let min = self.tile_position(self.tile).as_vec2() / image_size;
+        Rect::from_corners(min, min + size / image_size)
+    }
+
+    pub(crate) fn view_target(&self) -> ViewTarget<'_> {
+        self.target.view_target(self.accumulated_frames)
+    }
+
+    /// Restarts the render from the first tile, copies in flight are dropped.
+    pub(crate) fn reset_accumulation(&mut self) {
+        self.accumulated_frames = 0;
+        self.tile = 0;
+        self.copied_tiles = [None; FRAME_COUNT];
+        self.stitched_tiles = 0;
+    }
+
+    /// Copies the result into the readback of `frame_slot` once the frame being recorded is the
+    /// last one of the tile.
+    pub(crate) fn record_readback(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &ID3D12GraphicsCommandList,
+        frame_slot: usize,
+    ) {
+        if self.accumulated_frames + 1 < self.frames {
+            return;
+        }
+        let (texture, state) = if writes_exr(&self.request) {
+            (
+                &self.target.hdr_target,
+                D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+            )
+        } else {
+            (&self.target.output, D3D12_RESOURCE_STATE_COPY_SOURCE)
+        };
+        // the GPU finished the last copy of the slot before the frame began
+        let readback = match self.readbacks[frame_slot].take() {
+            Some(readback) if readback.fits(texture) => readback,
+            _ => TextureReadback::new(gpu, texture),
+        };
+        readback.record(command_list, texture, state);
+        self.readbacks[frame_slot] = Some(readback);
+        self.copied_tiles[frame_slot] = Some(self.tile);
+    }
+
+    /// Moves on to the next tile after its last frame. The frames aren't waited for, the
+    /// frame slots pace the render.
+    pub(crate) fn finish_frame(&mut self) {
+        self.accumulated_frames += 1;
+        if self.accumulated_frames == self.frames {
+            self.tile += 1;
+            self.accumulated_frames = 0;
+        }
+    }
+
+    /// Copies the tile read back in `frame_slot` into the image, returns whether it was the
+    /// last one.
+    fn stitch_tile(&mut self, tile: u32, frame_slot: usize) -> bool {
+        let UVec2 {
+            x: width,
+            y: height,
+        } = self.target.size();
+        let position = self.tile_position(tile);
+        let readback = self.readbacks[frame_slot]
+            .as_ref()
+            .expect("tile copied without a readback buffer");
+        match &mut self.image {
+            StitchedImage::Float(image) => {
+                let tile = Rgba32FImage::from_raw(width, height, readback.read_float())
+                    .expect("readback size doesn't match the render");
+                imageops::replace(image, &tile, position.x as i64, position.y as i64);
+            }
+            StitchedImage::Unorm(image) => {
+                let frame = readback.read();
+                let tile = RgbaImage::from_raw(frame.width, frame.height, frame.pixels)
+                    .expect("readback size doesn't match the render");
+                imageops::replace(image, &tile, position.x as i64, position.y as i64);
+            }
+        }
+
+        self.stitched_tiles += 1;
+        self.stitched_tiles == self.tile_count()
+    }
+
+    fn save(&mut self) -> image::ImageResult<()> {
+        match &mut self.image {
+            StitchedImage::Float(image) => image.save(&self.request.output),
+            StitchedImage::Unorm(image) => {
+                // the output is premultiplied, PNG isn't
+                unpremultiply(image);
+                image.save(&self.request.output)
+            }
+        }
+    }
+
+    /// Request of the render, to start it again from scratch.
+    pub(crate) fn into_request(self) -> RenderRequest {
+        self.request
+    }
+}
+
+fn start_offline_render(
+    mut commands: Commands,
+    mut requests: EventReader<RenderRequest>,
+    active: Option<Res<OfflineRender>>,
+    settings: Res<PathTracerSettings>,
+    precision: Res<AccumulationPrecision>,
+    gpu: Res<Gpu>,
+) {
+    let Some(request) = requests.read().last() else {
+        return;
+    };
+    if active.is_some() {
+        warn!("An offline render is already running, ignoring the new one");
+        return;
+    }
+
+    let render = OfflineRender::new(
+        &gpu,
+        request.clone(),
+        settings.samples_per_frame,
+        *precision,
+    );
+    info!(
+        "Rendering {}x{} in {} tiles with {} frames each to {}",
+        request.width,
+        request.height,
+        render.tile_count(),
+        render.frames,
+        request.output.display()
+    );
+    commands.insert_resource(render);
+}
+
+/// Stitches the tile copied in the frame before, and writes the image once it was the last.
+fn finish_offline_render(
+    mut commands: Commands,
+    gpu: Res<Gpu>,
+    fence_timeout: Res<FenceTimeout>,
+    drawer: Res<Drawer>,
+    render: Option<ResMut<OfflineRender>>,
+    mut finished_events: EventWriter<RenderFinished>,
+) {
+    let Some(mut render) = render else {
+        return;
+    };
+    let frame_slot = drawer.finish_previous_frame(&gpu, *fence_timeout);
+    let Some(tile) = render.copied_tiles[frame_slot].take() else {
+        return;
+    };
+    if !render.stitch_tile(tile, frame_slot) {
+        return;
+    }
+    commands.remove_resource::<OfflineRender>();
+
+    render.save().expect("failed to write offline render");
+    info!(
+        "Offline render written to {}",
+        render.request.output.display()
+    );
+    finished_events.send(RenderFinished {
+        output: render.request.output.clone(),
+    });
+}
+
+fn writes_exr(request: &RenderRequest) -> bool {
+    request
+        .output
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("exr"))
+}
@@ -0,0 +1,150 @@
+use bevy::prelude::*;
+use windows::Win32::{
+    Foundation::RECT,
+    Graphics::{
+        Direct3D12::*,
+        Dxgi::Common::{DXGI_FORMAT, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC},
+    },
+};
+
+use crate::render::{
+    drawer::ViewTarget,
+    fence_timeout::FenceTimeout,
+    gpu_fence::GpuFence,
+    render_target::{
+        create_depth_target, create_hdr_target, create_rect, create_viewport, BackBufferFormat,
+    },
+    set_debug_name, Descriptors, Gpu,
+};
+
+/// HDR, depth and tone mapped output targets drawn without a swapchain. The output is
+/// `R8G8B8A8_UNORM` and rests in `COPY_SOURCE`, the HDR target in `ALL_SHADER_RESOURCE`.
+pub(crate) struct OffscreenTarget {
+    pub hdr_target: ID3D12Resource,
+    hdr_format: DXGI_FORMAT,
+    hdr_srv: Descriptors,
+    pub output: ID3D12Resource,
+    // holds the views of both targets
+    _rtvs: Descriptors,
+    hdr_rtv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    output_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    _depth_target: ID3D12Resource,
+    _dsv: Descriptors,
+    dsv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    viewport: D3D12_VIEWPORT,
+    rect: RECT,
+    fence: GpuFence,
+}
+
+impl OffscreenTarget {
+    pub fn new(gpu: &Gpu, size: UVec2, hdr_format: DXGI_FORMAT) -> Self {
+        let hdr_target = create_hdr_target(&gpu.device, size, hdr_format);
+        let output = create_output_texture(gpu, size.x, size.y);
+        let rtvs = gpu.descriptors.rtv.allocate(2);
+        let hdr_rtv_handle = rtvs.cpu_handle(0);
+        let output_handle = rtvs.cpu_handle(1);
+        let depth_target = create_depth_target(&gpu.device, size);
+        let dsv = gpu.descriptors.dsv.allocate(1);
+        let dsv_handle = dsv.cpu_handle(0);
+        let hdr_srv = gpu.descriptors.cbv_srv_uav.allocate(1);
+        unsafe {
+            gpu.device
+                .CreateRenderTargetView(&hdr_target, None, hdr_rtv_handle);
+            gpu.device
+                .CreateRenderTargetView(&output, None, output_handle);
+            gpu.device
+                .CreateShaderResourceView(&hdr_target, None, hdr_srv.cpu_handle(0));
+            gpu.device
+                .CreateDepthStencilView(&depth_target, None, dsv_handle);
+        }
+
+        Self {
+            hdr_target,
+            hdr_format,
+            hdr_srv,
+            output,
+            _rtvs: rtvs,
+            hdr_rtv_handle,
+            output_handle,
+            _depth_target: depth_target,
+            _dsv: dsv,
+            dsv_handle,
+            viewport: create_viewport(size.x as f32, size.y as f32),
+            rect: create_rect(size.x as i32, size.y as i32),
+            fence: GpuFence::new(gpu, "off-screen target fence"),
+        }
+    }
+
+    pub fn size(&self) -> UVec2 {
+        UVec2::new(self.viewport.Width as u32, self.viewport.Height as u32)
+    }
+
+    pub fn hdr_format(&self) -> DXGI_FORMAT {
+        self.hdr_format
+    }
+
+    pub fn view_target(&self, accumulated_frames: u32) -> ViewTarget<'_> {
+        ViewTarget {
+            viewport: self.viewport,
+            rect: self.rect,
+            hdr_viewport: self.viewport,
+            hdr_rect: self.rect,
+            render_scale: 1.0,
+            hdr_format: self.hdr_format,
+            hdr_target: &self.hdr_target,
+            hdr_rtv_handle: self.hdr_rtv_handle,
+            hdr_srv: &self.hdr_srv,
+            blit_descriptors: None,
+            dsv_handle: self.dsv_handle,
+            aovs: &[],
+            accumulated_frames,
+            output: &self.output,
+            output_handle: self.output_handle,
+            output_state: D3D12_RESOURCE_STATE_COPY_SOURCE,
+            output_format: BackBufferFormat::Rgba8Unorm,
+            msaa: None,
+        }
+    }
+
+    /// Waits for the submitted frame, there is no swapchain pacing the frames. `what` names the
+    /// frame in the hang report.
+    pub fn finish_frame(&mut self, gpu: &Gpu, timeout: FenceTimeout, what: &str) {
+        self.fence.signal(&gpu.queue);
+        self.fence.wait_for_last(gpu, timeout, what);
+    }
+}
+
+fn create_output_texture(gpu: &Gpu, width: u32, height: u32) -> ID3D12Resource {
+    let mut texture: Option<ID3D12Resource> = None;
+    unsafe {
+        gpu.device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_DEFAULT,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                Width: width as u64,
+                Height: height,
+                DepthOrArraySize: 1,
+                MipLevels: 1,
+                Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+                Flags: D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+                ..Default::default()
+            },
+            D3D12_RESOURCE_STATE_COPY_SOURCE,
+            None,
+            &mut texture,
+        )
+    }
+    .expect("failed to create off-screen target");
+    let texture = texture.unwrap();
+    set_debug_name(&texture, "off-screen output");
+    texture
+}
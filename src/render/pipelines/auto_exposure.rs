@@ -0,0 +1,264 @@
+use bevy::prelude::*;
+use windows::{
+    core::s,
+    Win32::Graphics::{
+        Direct3D12::*,
+        Dxgi::Common::{DXGI_FORMAT_UNKNOWN, DXGI_SAMPLE_DESC},
+    },
+};
+
+use crate::{
+    core::{AutoExposure, Shader},
+    render::{
+        constant_buffer::ConstantBuffer,
+        d3d::{global_uav_barrier, transition_barrier, uav_barrier},
+        set_debug_name, Gpu, TransientDescriptors,
+    },
+};
+
+use super::{
+    pipeline_state::{
+        compile_compute_shader, create_compute_pipeline_state, create_root_signature_from_desc,
+    },
+    root_bindings::{RootBindings, RootSignature},
+};
+
+const HISTOGRAM_BINS: usize = 256;
+const GROUP_SIZE: u32 = 16;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct AutoExposureSettings {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    adaptation: f32,
+    __padding: u32,
+}
+
+/// Compute passes measuring the average luminance of a window's HDR target.
+///
+/// The adapted luminance stays on the GPU and is read by the tone mapping pass.
+#[derive(Resource)]
+pub struct AutoExposurePipeline {
+    root_signature: RootSignature,
+    histogram_state: ID3D12PipelineState,
+    average_state: ID3D12PipelineState,
+    settings_constant_buffer: ConstantBuffer<AutoExposureSettings>,
+    histogram_buffer: ID3D12Resource,
+    luminance_buffer: ID3D12Resource,
+    enabled: bool,
+    global_uav_barriers: bool,
+}
+
+impl AutoExposurePipeline {
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    /// GPU address of the adapted average luminance, a single `float`.
+    pub fn luminance_address(&self) -> u64 {
+        unsafe { self.luminance_buffer.GetGPUVirtualAddress() }
+    }
+
+    pub fn write_settings(&mut self, auto_exposure: Option<&AutoExposure>, delta_seconds: f32) {
+        self.enabled = auto_exposure.is_some();
+        let Some(auto_exposure) = auto_exposure else {
+            return;
+        };
+
+        self.settings_constant_buffer.write(&AutoExposureSettings {
+            min_log_luminance: auto_exposure.min_log_luminance,
+            log_luminance_range: auto_exposure.max_log_luminance - auto_exposure.min_log_luminance,
+            adaptation: 1.0 - (-auto_exposure.speed * delta_seconds).exp(),
+            __padding: 0,
+        });
+    }
+
+    /// Records the histogram and averaging dispatches over the HDR target of the staged
+    /// `hdr_srv`, which must be readable from compute shaders.
+    pub fn populate_command_list(
+        &self,
+        command_list: &mut ID3D12GraphicsCommandList,
+        hdr_srv: &TransientDescriptors,
+        width: u32,
+        height: u32,
+    ) {
+        unsafe {
+            // Buffers decay to COMMON after every ExecuteCommandLists
+            command_list.ResourceBarrier(&[transition_barrier(
+                &self.luminance_buffer,
+                D3D12_RESOURCE_STATE_COMMON,
+                D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+            )]);
+
+            command_list.SetDescriptorHeaps(&[Some(hdr_srv.heap())]);
+            let mut bindings = RootBindings::compute(command_list, &self.root_signature);
+            bindings.cbv(0, self.settings_constant_buffer.gpu_adress());
+            bindings.transient_table(1, hdr_srv);
+            bindings.uav(2, self.histogram_buffer.GetGPUVirtualAddress());
+            bindings.uav(3, self.luminance_buffer.GetGPUVirtualAddress());
+            bindings.check_complete();
+
+            command_list.SetPipelineState(&self.histogram_state);
+            command_list.Dispatch(width.div_ceil(GROUP_SIZE), height.div_ceil(GROUP_SIZE), 1);
+
+            if self.global_uav_barriers {
+                command_list.ResourceBarrier(&[global_uav_barrier()]);
+            } else {
+                command_list.ResourceBarrier(&[uav_barrier(&self.histogram_buffer)]);
+            }
+
+            command_list.SetPipelineState(&self.average_state);
+            command_list.Dispatch(1, 1, 1);
+
+            command_list.ResourceBarrier(&[transition_barrier(
+                &self.luminance_buffer,
+                D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+            )]);
+        }
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct AutoExposureShaderHandle(pub Handle<Shader>);
+
+/// Default heap buffer in `COMMON` that allows unordered access.
+pub(super) fn create_uav_buffer(gpu: &Gpu, size: u64, name: &str) -> ID3D12Resource {
+    let desc = D3D12_RESOURCE_DESC {
+        Alignment: 0,
+        Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+        Width: size,
+        Height: 1,
+        DepthOrArraySize: 1,
+        MipLevels: 1,
+        Format: DXGI_FORMAT_UNKNOWN,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+        Flags: D3D12_RESOURCE_FLAG_ALLOW_UNORDERED_ACCESS,
+    };
+
+    let mut buffer: Option<ID3D12Resource> = None;
+    unsafe {
+        gpu.device
+            .CreateCommittedResource(
+                &D3D12_HEAP_PROPERTIES {
+                    Type: D3D12_HEAP_TYPE_DEFAULT,
+                    ..Default::default()
+                },
+                D3D12_HEAP_FLAG_NONE,
+                &desc,
+                D3D12_RESOURCE_STATE_COMMON,
+                None,
+                &mut buffer,
+            )
+            .expect("Could not create UAV buffer");
+    }
+    let buffer = buffer.expect("CreateCommittedResource was successful but buffer is None");
+    set_debug_name(&buffer, name);
+    buffer
+}
+
+fn create_root_signature(gpu: &Gpu) -> RootSignature {
+    let ranges = [D3D12_DESCRIPTOR_RANGE {
+        RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+        NumDescriptors: 1,
+        BaseShaderRegister: 0,
+        RegisterSpace: 0,
+        OffsetInDescriptorsFromTableStart: D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND,
+    }];
+
+    let root_parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: ranges.len() as u32,
+                    pDescriptorRanges: ranges.as_ptr(),
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_UAV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_UAV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+    ];
+    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_NONE,
+        NumParameters: root_parameters.len() as u32,
+        pParameters: root_parameters.as_ptr(),
+        NumStaticSamplers: 0,
+        pStaticSamplers: std::ptr::null(),
+    };
+
+    create_root_signature_from_desc(gpu, "auto exposure", &root_signature_desc)
+}
+
+pub fn create_auto_exposure_pipeline(
+    mut commands: Commands,
+    gpu: Res<Gpu>,
+    shader_handle: Res<AutoExposureShaderHandle>,
+    shaders: Res<Assets<Shader>>,
+    pipeline: Option<Res<AutoExposurePipeline>>,
+) {
+    if pipeline.is_some() {
+        return;
+    }
+
+    let Some(shader_source) = shaders.get(&shader_handle.0) else {
+        return;
+    };
+
+    let root_signature = create_root_signature(&gpu);
+    let histogram_shader = compile_compute_shader(shader_source, s!("CSBuildHistogram"));
+    let average_shader = compile_compute_shader(shader_source, s!("CSAverage"));
+
+    commands.insert_resource(AutoExposurePipeline {
+        histogram_state: create_compute_pipeline_state(&gpu, &histogram_shader, &root_signature),
+        average_state: create_compute_pipeline_state(&gpu, &average_shader, &root_signature),
+        root_signature,
+        settings_constant_buffer: ConstantBuffer::create(&gpu),
+        histogram_buffer: create_uav_buffer(
+            &gpu,
+            (HISTOGRAM_BINS * std::mem::size_of::<u32>()) as u64,
+            "auto exposure histogram",
+        ),
+        luminance_buffer: create_uav_buffer(
+            &gpu,
+            std::mem::size_of::<f32>() as u64,
+            "auto exposure luminance",
+        ),
+        enabled: false,
+        global_uav_barriers: gpu.quirks.global_uav_barriers,
+    });
+}
@@ -0,0 +1,56 @@
+use bevy::prelude::*;
+
+use super::PipelineStorage;
+
+/// Replaces the shaded image with a visualization of the primary hits, for diagnosing geometry,
+/// intersection and sampling problems. Exposure and tone mapping are skipped while it is on.
+#[derive(Resource, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Resource, Default)]
+pub enum DebugView {
+    #[default]
+    None,
+    /// World space normals mapped from `[-1, 1]` to `[0, 1]`.
+    Normals,
+    /// Distance to the camera, black up close and white far away or without a hit.
+    Depth,
+    /// Texture coordinates, wrapped to `[0, 1]`.
+    Uvs,
+    /// Triangles the primary ray crosses, from blue for one to red for 32 and more. There is no
+    /// acceleration structure yet, every ray still tests every triangle.
+    BvhHeatmap,
+    /// Bounces a path takes before it escapes or is terminated, from blue for none to red for
+    /// [`super::PathTracerSettings::max_bounces`].
+    BounceCount,
+    /// How the path of the pixel ended: blue when it escaped, yellow on an area light, green by
+    /// Russian roulette, red at [`super::PathTracerSettings::max_bounces`] and magenta in the
+    /// radiance cache. Counted over the whole image by [`super::PathStatistics`].
+    PathEnd,
+    /// The registered [`crate::render::AovRegistry`] AOV of this name as stored, for float and
+    /// unorm formats. Views without it show the shaded image.
+    Aov(&'static str),
+}
+
+impl DebugView {
+    pub(super) fn shader_index(&self) -> u32 {
+        match self {
+            DebugView::None | DebugView::Aov(_) => 0,
+            DebugView::Normals => 1,
+            DebugView::Depth => 2,
+            DebugView::Uvs => 3,
+            DebugView::BvhHeatmap => 4,
+            DebugView::BounceCount => 5,
+            DebugView::PathEnd => 6,
+        }
+    }
+}
+
+pub fn prepare_debug_view(debug_view: Res<DebugView>, mut pipelines: ResMut<PipelineStorage>) {
+    // the scene is drawn as usual next to the shown AOV
+    let debug_view = match *debug_view {
+        DebugView::Aov(_) => DebugView::None,
+        debug_view => debug_view,
+    };
+    for pipeline in pipelines.values_mut() {
+        pipeline.set_debug_view(debug_view);
+    }
+}
@@ -0,0 +1,476 @@
+use bevy::prelude::*;
+use windows::Win32::Graphics::{
+    Direct3D::D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+    Direct3D12::*,
+    Dxgi::Common::{
+        DXGI_FORMAT, DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_FORMAT_R32_FLOAT,
+        DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_FORMAT_R8G8_UNORM,
+    },
+};
+
+use crate::{
+    core::{Background, Camera, Shader},
+    render::{
+        bindless::bindless_ranges,
+        constant_buffer::ConstantBuffer,
+        d3d::transition_barrier,
+        furnace::FurnaceScene,
+        light_data::{GpuLight, MAX_LIGHTS},
+        material_textures::MaterialTextures,
+        mesh_data::MeshBuffer,
+        render_target::{
+            create_depth_target, create_hdr_target, AccumulationPrecision, DEPTH_FORMAT,
+        },
+        set_debug_name,
+        structured_buffer::StructuredBuffer,
+        upload::{UploadPriority, UploadQueue},
+        vertex_buffer::VertexBuffer,
+        BindlessDescriptors, Descriptors, Gpu, LightData, MeshData, PrimitiveData,
+    },
+};
+
+use super::{
+    grown_size,
+    pipeline_state::{
+        compile_shaders, create_root_signature_from_desc, BlendMode, SpecializedPipelineStates,
+        TargetDesc, VertexLayout,
+    },
+    root_bindings::{RootBindings, RootSignature},
+    view_projection, CameraData, DebugView, PathStatistics, PathTracerSettings, Pipeline,
+    PipelineStorage, SceneTarget, DEFERRED_PIPELINE_ID,
+};
+
+/// Targets of the G-buffer pass, in the order of its `SV_TARGET` outputs and of the G-buffer
+/// registers in `deferred_lighting.hlsl`.
+const G_BUFFER_TARGETS: [(DXGI_FORMAT, &str); 5] = [
+    // base color, textures applied
+    (DXGI_FORMAT_R8G8B8A8_UNORM, "G-buffer albedo"),
+    (DXGI_FORMAT_R16G16B16A16_FLOAT, "G-buffer normal"),
+    // distance to the camera, 0 where nothing was drawn
+    (DXGI_FORMAT_R32_FLOAT, "G-buffer depth"),
+    // metallic and perceptual roughness
+    (DXGI_FORMAT_R8G8_UNORM, "G-buffer material"),
+    (DXGI_FORMAT_R16G16B16A16_FLOAT, "G-buffer emission"),
+];
+const G_BUFFER_CLEAR_COLOR: [f32; 4] = [0.0, 0.0, 0.0, 1.0];
+
+// bindless IDs of the vertices, indices, materials, uvs and lights
+const SRV_COUNT: usize = 5;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GBufferFrameData {
+    view_projection: [[f32; 4]; 4],
+    camera_position: [f32; 3],
+    __padding: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct LightingData {
+    light_count: u32,
+    debug_view: u32,
+    __padding: [u32; 2],
+}
+
+/// Surfaces seen by the camera, written by the G-buffer pass and shaded by the lighting pass.
+/// Grows to the largest target drawn to, only the part covered by the viewport is used. Has a
+/// depth target of its own, as the one of the [`SceneTarget`] may be smaller.
+struct GBuffer {
+    targets: Vec<ID3D12Resource>,
+    rtvs: Descriptors,
+    // read by the lighting pass
+    srvs: BindlessDescriptors,
+    _depth_target: ID3D12Resource,
+    dsv: Descriptors,
+    size: UVec2,
+}
+
+impl GBuffer {
+    fn new(gpu: &Gpu, size: UVec2) -> Self {
+        // consecutive, the pass binds them as one range
+        let rtvs = gpu.descriptors.rtv.allocate(G_BUFFER_TARGETS.len());
+        let srvs = gpu.bindless.allocate(G_BUFFER_TARGETS.len());
+        let targets = G_BUFFER_TARGETS
+            .iter()
+            .enumerate()
+            .map(|(index, (format, name))| {
+                let target = create_hdr_target(&gpu.device, size, *format);
+                set_debug_name(&target, name);
+                unsafe {
+                    gpu.device
+                        .CreateRenderTargetView(&target, None, rtvs.cpu_handle(index));
+                    gpu.device
+                        .CreateShaderResourceView(&target, None, srvs.cpu_handle(index));
+                }
+                target
+            })
+            .collect();
+        let depth_target = create_depth_target(&gpu.device, size);
+        let dsv = gpu.descriptors.dsv.allocate(1);
+        unsafe {
+            gpu.device
+                .CreateDepthStencilView(&depth_target, None, dsv.cpu_handle(0))
+        };
+        Self {
+            targets,
+            rtvs,
+            srvs,
+            _depth_target: depth_target,
+            dsv,
+            size,
+        }
+    }
+
+    fn transition(
+        &self,
+        command_list: &ID3D12GraphicsCommandList,
+        before: D3D12_RESOURCE_STATES,
+        after: D3D12_RESOURCE_STATES,
+    ) {
+        let barriers: Vec<_> = self
+            .targets
+            .iter()
+            .map(|target| transition_barrier(target, before, after))
+            .collect();
+        unsafe { command_list.ResourceBarrier(&barriers) };
+    }
+}
+
+/// Rasterizes the meshes into a G-buffer and shades it in a fullscreen pass, see
+/// [`super::ScenePipeline::Deferred`].
+pub struct DeferredPipeline {
+    root_signature: RootSignature,
+    g_buffer_states: SpecializedPipelineStates,
+    lighting_states: SpecializedPipelineStates,
+    vertex_buffer: VertexBuffer,
+    g_buffer_constant_buffer: ConstantBuffer<GBufferFrameData>,
+    camera_constant_buffer: ConstantBuffer<CameraData>,
+    lighting_constant_buffer: ConstantBuffer<LightingData>,
+    mesh_buffer: MeshBuffer,
+    index_count: u32,
+    light_buffer: StructuredBuffer<GpuLight>,
+    light_count: u32,
+    srvs: BindlessDescriptors,
+    first_texture: u32,
+    g_buffer: Option<GBuffer>,
+    debug_view: DebugView,
+}
+
+impl Pipeline for DeferredPipeline {
+    fn populate_command_list(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &mut ID3D12GraphicsCommandList,
+        target: &SceneTarget,
+    ) {
+        let g_buffer = self
+            .g_buffer
+            .as_ref()
+            .expect("the G-buffer is drawn in the prepass");
+        let lighting_state = self.lighting_states.get(gpu, target.desc);
+        unsafe {
+            command_list.SetDescriptorHeaps(&[Some(gpu.bindless.heap().heap())]);
+            let mut bindings = RootBindings::graphics(command_list, &self.root_signature);
+            bindings.table(1, gpu.bindless.heap());
+            // only read by the lighting pass, bound for both so nothing is left unbound
+            bindings.cbv(2, self.lighting_constant_buffer.gpu_adress());
+            bindings.constants(
+                3,
+                &[
+                    self.srvs.first_id(),
+                    self.first_texture,
+                    g_buffer.srvs.first_id(),
+                ],
+                0,
+            );
+            // every pixel of the view is shaded, there is nothing to clear or accumulate
+            command_list.OMSetRenderTargets(1, Some(&target.rtv_handle), false, None);
+            command_list.SetPipelineState(lighting_state);
+            bindings.cbv(0, self.camera_constant_buffer.gpu_adress());
+            bindings.check_complete();
+            command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            command_list.IASetVertexBuffers(0, Some(&[*self.vertex_buffer.view()]));
+            command_list.DrawInstanced(6, 1, 0, 0);
+        }
+    }
+
+    fn prepass(&self) -> Option<&'static str> {
+        Some("G-buffer")
+    }
+
+    fn populate_prepass(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &mut ID3D12GraphicsCommandList,
+        target: &SceneTarget,
+    ) {
+        let current_size = self.g_buffer.as_ref().map(|g_buffer| g_buffer.size);
+        if let Some(size) = grown_size(current_size, target.size) {
+            // the frames in flight may still read the old targets through their own SRVs
+            if let Some(old_g_buffer) = self.g_buffer.replace(GBuffer::new(gpu, size)) {
+                gpu.retired.retire(old_g_buffer);
+            }
+        }
+        let g_buffer = self.g_buffer.as_ref().unwrap();
+        let dsv_handle = g_buffer.dsv.cpu_handle(0);
+
+        let g_buffer_state = self
+            .g_buffer_states
+            .get(gpu, TargetDesc::new(G_BUFFER_TARGETS[0].0, 1));
+        unsafe {
+            command_list.SetDescriptorHeaps(&[Some(gpu.bindless.heap().heap())]);
+            let mut bindings = RootBindings::graphics(command_list, &self.root_signature);
+            bindings.table(1, gpu.bindless.heap());
+            // only read by the lighting pass, bound for both so nothing is left unbound
+            bindings.cbv(2, self.lighting_constant_buffer.gpu_adress());
+            bindings.constants(
+                3,
+                &[
+                    self.srvs.first_id(),
+                    self.first_texture,
+                    g_buffer.srvs.first_id(),
+                ],
+                0,
+            );
+
+            g_buffer.transition(
+                command_list,
+                D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+                D3D12_RESOURCE_STATE_RENDER_TARGET,
+            );
+            let first_rtv = g_buffer.rtvs.cpu_handle(0);
+            command_list.OMSetRenderTargets(
+                G_BUFFER_TARGETS.len() as u32,
+                Some(&first_rtv),
+                true,
+                Some(&dsv_handle),
+            );
+            for index in 0..G_BUFFER_TARGETS.len() {
+                command_list.ClearRenderTargetView(
+                    g_buffer.rtvs.cpu_handle(index),
+                    &G_BUFFER_CLEAR_COLOR,
+                    None,
+                );
+            }
+            command_list.ClearDepthStencilView(dsv_handle, D3D12_CLEAR_FLAG_DEPTH, 0.0, 0, &[]);
+            if self.index_count > 0 {
+                command_list.SetPipelineState(g_buffer_state);
+                bindings.cbv(0, self.g_buffer_constant_buffer.gpu_adress());
+                bindings.check_complete();
+                command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+                command_list.IASetVertexBuffers(0, Some(&self.mesh_buffer.vertex_buffer_views()));
+                command_list.IASetIndexBuffer(Some(&self.mesh_buffer.index_buffer_view()));
+                command_list.DrawIndexedInstanced(self.index_count, 1, 0, 0, 0);
+            }
+            g_buffer.transition(
+                command_list,
+                D3D12_RESOURCE_STATE_RENDER_TARGET,
+                D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+            );
+        }
+    }
+
+    fn write_frame_data(
+        &mut self,
+        transform: &GlobalTransform,
+        camera: &Camera,
+        background: &Background,
+        _settings: &PathTracerSettings,
+        _frame_index: u32,
+        view_rect: Rect,
+    ) {
+        self.g_buffer_constant_buffer.write(&GBufferFrameData {
+            view_projection: view_projection(transform, camera, view_rect).to_cols_array_2d(),
+            camera_position: transform.translation().to_array(),
+            __padding: 0,
+        });
+        self.camera_constant_buffer
+            .write(&CameraData::new(transform, camera, background, view_rect));
+        self.lighting_constant_buffer.write(&LightingData {
+            light_count: self.light_count,
+            debug_view: self.debug_view.shader_index(),
+            __padding: [0; 2],
+        });
+    }
+
+    fn set_mesh_data(&mut self, data: &MeshData, uploads: &mut UploadQueue) {
+        self.mesh_buffer.set_new_data(data, uploads);
+        self.index_count = data.vertex_count() as u32;
+    }
+
+    fn set_light_data(&mut self, data: &LightData, uploads: &mut UploadQueue) {
+        let range = data.dirty_range();
+        if !range.is_empty() {
+            self.light_buffer
+                .write_range(range.start, &data.lights()[range.clone()]);
+            self.light_buffer
+                .upload_range(uploads, UploadPriority::High, range);
+        }
+        self.light_count = data.light_count() as u32;
+    }
+
+    fn set_primitive_data(&mut self, _data: &PrimitiveData, _uploads: &mut UploadQueue) {
+        // analytic primitives aren't rasterized
+    }
+
+    fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.debug_view = debug_view;
+    }
+
+    fn set_furnace_scene(&mut self, _scene: Option<&FurnaceScene>) {
+        // the furnace test measures the path tracer only
+    }
+
+    fn read_path_statistics(&mut self, _frame_slot: usize) -> Option<PathStatistics> {
+        None
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct DeferredGBufferShaderHandle(pub Handle<Shader>);
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct DeferredLightingShaderHandle(pub Handle<Shader>);
+
+/// Shared by both passes: b0 is the frame data of the pass, the table the bindless heap, b1 the
+/// lighting settings and b2 the IDs of the scene buffers, the first texture slot and the
+/// G-buffer.
+fn create_root_signature(gpu: &Gpu) -> RootSignature {
+    let ranges = bindless_ranges();
+
+    let root_parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: ranges.len() as u32,
+                    pDescriptorRanges: ranges.as_ptr(),
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 2,
+                    RegisterSpace: 0,
+                    Num32BitValues: 3,
+                },
+            },
+        },
+    ];
+    let texture_sampler = D3D12_STATIC_SAMPLER_DESC {
+        Filter: D3D12_FILTER_MIN_MAG_MIP_LINEAR,
+        AddressU: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        AddressV: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        AddressW: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        MipLODBias: 0.0,
+        MaxAnisotropy: 1,
+        ComparisonFunc: D3D12_COMPARISON_FUNC_NEVER,
+        BorderColor: D3D12_STATIC_BORDER_COLOR_OPAQUE_BLACK,
+        MinLOD: 0.0,
+        MaxLOD: D3D12_FLOAT32_MAX,
+        ShaderRegister: 0,
+        RegisterSpace: 0,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+    };
+    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
+        NumParameters: root_parameters.len() as u32,
+        pParameters: root_parameters.as_ptr(),
+        NumStaticSamplers: 1,
+        pStaticSamplers: &texture_sampler,
+    };
+
+    create_root_signature_from_desc(gpu, "deferred", &root_signature_desc)
+}
+
+pub fn create_deferred_pipeline(
+    gpu: Res<Gpu>,
+    g_buffer_shader_handle: Res<DeferredGBufferShaderHandle>,
+    lighting_shader_handle: Res<DeferredLightingShaderHandle>,
+    shaders: Res<Assets<Shader>>,
+    textures: Res<MaterialTextures>,
+    precision: Res<AccumulationPrecision>,
+    mut pipelines: ResMut<PipelineStorage>,
+) {
+    if pipelines.contains_key(&DEFERRED_PIPELINE_ID) {
+        return;
+    }
+    let (Some(g_buffer_source), Some(lighting_source)) = (
+        shaders.get(&g_buffer_shader_handle.0),
+        shaders.get(&lighting_shader_handle.0),
+    ) else {
+        return;
+    };
+
+    let root_signature = create_root_signature(&gpu);
+    let additional_formats: Vec<_> = G_BUFFER_TARGETS[1..]
+        .iter()
+        .map(|(format, _)| *format)
+        .collect();
+    let mut g_buffer_states = SpecializedPipelineStates::new(
+        compile_shaders(g_buffer_source),
+        &root_signature,
+        BlendMode::Opaque,
+    )
+    .with_vertex_layout(VertexLayout::Mesh)
+    .with_depth(DEPTH_FORMAT)
+    .with_additional_targets(&additional_formats);
+    g_buffer_states.get(&gpu, TargetDesc::new(G_BUFFER_TARGETS[0].0, 1));
+    let mut lighting_states = SpecializedPipelineStates::new(
+        compile_shaders(lighting_source),
+        &root_signature,
+        BlendMode::Opaque,
+    );
+    lighting_states.get(&gpu, TargetDesc::new(precision.dxgi_format(), 1));
+
+    let mesh_buffer = MeshBuffer::new(&gpu);
+    let light_buffer = StructuredBuffer::<GpuLight>::new(&gpu, MAX_LIGHTS);
+    let srvs = gpu.bindless.allocate(SRV_COUNT);
+    mesh_buffer.write_srvs(&gpu, &srvs, 0);
+    light_buffer.write_srv(&gpu, srvs.cpu_handle(4));
+
+    let pipeline = DeferredPipeline {
+        root_signature,
+        g_buffer_states,
+        lighting_states,
+        vertex_buffer: VertexBuffer::fullscreen_quad(&gpu),
+        g_buffer_constant_buffer: ConstantBuffer::create(&gpu),
+        camera_constant_buffer: ConstantBuffer::create(&gpu),
+        lighting_constant_buffer: ConstantBuffer::create(&gpu),
+        mesh_buffer,
+        index_count: 0,
+        light_buffer,
+        light_count: 0,
+        srvs,
+        first_texture: textures.first_id(),
+        g_buffer: None,
+        debug_view: DebugView::None,
+    };
+
+    pipelines.insert(DEFERRED_PIPELINE_ID, Box::new(pipeline));
+}
@@ -0,0 +1,218 @@
+use bevy::prelude::*;
+use windows::Win32::Graphics::{
+    Direct3D::D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+    Direct3D12::*,
+    Dxgi::Common::{DXGI_FORMAT, DXGI_FORMAT_R32_UINT},
+};
+
+use crate::{
+    core::Shader,
+    render::{
+        constant_buffer::ConstantBuffer,
+        d3d::transition_barrier,
+        mesh_data::MeshBuffer,
+        render_target::{create_depth_target, create_hdr_target, DEPTH_FORMAT},
+        set_debug_name, BindlessDescriptors, Descriptors, Gpu,
+    },
+};
+
+use super::{
+    grown_size,
+    pipeline_state::{
+        compile_shaders, create_root_signature_from_desc, BlendMode, SpecializedPipelineStates,
+        TargetDesc, VertexLayout,
+    },
+    root_bindings::{RootBindings, RootSignature},
+};
+
+/// Triangle index plus one, must match `primary_triangles` in `demo.hlsl`.
+const TRIANGLE_FORMAT: DXGI_FORMAT = DXGI_FORMAT_R32_UINT;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PrepassFrameData {
+    view_projection: [[f32; 4]; 4],
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct DepthPrepassShaderHandle(pub Handle<Shader>);
+
+/// The triangle target, its bindless SRV and its depth target, grown to the largest target
+/// drawn to.
+struct PrepassTargets {
+    triangles: ID3D12Resource,
+    rtv: Descriptors,
+    srv: BindlessDescriptors,
+    _depth_target: ID3D12Resource,
+    dsv: Descriptors,
+    size: UVec2,
+}
+
+impl PrepassTargets {
+    fn new(gpu: &Gpu, size: UVec2) -> Self {
+        let triangles = create_hdr_target(&gpu.device, size, TRIANGLE_FORMAT);
+        set_debug_name(&triangles, "depth prepass triangles");
+        let rtv = gpu.descriptors.rtv.allocate(1);
+        let srv = gpu.bindless.allocate(1);
+        write_srv(gpu, Some(&triangles), &srv);
+        let depth_target = create_depth_target(&gpu.device, size);
+        let dsv = gpu.descriptors.dsv.allocate(1);
+        unsafe {
+            gpu.device
+                .CreateRenderTargetView(&triangles, None, rtv.cpu_handle(0));
+            gpu.device
+                .CreateDepthStencilView(&depth_target, None, dsv.cpu_handle(0));
+        }
+        Self {
+            triangles,
+            rtv,
+            srv,
+            _depth_target: depth_target,
+            dsv,
+            size,
+        }
+    }
+}
+
+/// Rasterizes the meshes before the path tracing pass, see
+/// [`super::PathTracerSettings::depth_prepass`].
+///
+/// Stores the triangle seen through every pixel center, which the primary rays test first.
+/// When they hit it the other triangles can't be closer and are skipped, leaving only the
+/// analytic primitives to test.
+pub(super) struct DepthPrepass {
+    root_signature: RootSignature,
+    states: SpecializedPipelineStates,
+    constant_buffer: ConstantBuffer<PrepassFrameData>,
+    targets: Option<PrepassTargets>,
+    // read until the first pass
+    null_srv: BindlessDescriptors,
+}
+
+impl DepthPrepass {
+    pub(super) fn new(gpu: &Gpu, shader_source: &Shader) -> Self {
+        let root_signature = create_root_signature(gpu);
+        let mut states = SpecializedPipelineStates::new(
+            compile_shaders(shader_source),
+            &root_signature,
+            BlendMode::Opaque,
+        )
+        .with_vertex_layout(VertexLayout::Mesh)
+        .with_depth(DEPTH_FORMAT);
+        states.get(gpu, TargetDesc::new(TRIANGLE_FORMAT, 1));
+        let null_srv = gpu.bindless.allocate(1);
+        write_srv(gpu, None, &null_srv);
+        Self {
+            root_signature,
+            states,
+            constant_buffer: ConstantBuffer::create(gpu),
+            targets: None,
+            null_srv,
+        }
+    }
+
+    /// Bindless ID of the SRV of the triangles, read by the path tracer. Changes when the
+    /// targets grow, a null view before the first pass.
+    pub(super) fn srv_id(&self) -> u32 {
+        self.targets
+            .as_ref()
+            .map_or(&self.null_srv, |targets| &targets.srv)
+            .first_id()
+    }
+
+    pub(super) fn write_frame_data(&mut self, view_projection: Mat4) {
+        self.constant_buffer.write(&PrepassFrameData {
+            view_projection: view_projection.to_cols_array_2d(),
+        });
+    }
+
+    /// Records the pass over the first `index_count` indices of `mesh_buffer`, with the viewport
+    /// of the scene pass. Leaves the triangle target bound, the caller binds its target again.
+    pub(super) fn record(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &ID3D12GraphicsCommandList,
+        mesh_buffer: &MeshBuffer,
+        index_count: u32,
+        size: UVec2,
+    ) {
+        let current_size = self.targets.as_ref().map(|targets| targets.size);
+        if let Some(size) = grown_size(current_size, size) {
+            // the frames in flight may still read the old targets through their own SRV
+            if let Some(old_targets) = self.targets.replace(PrepassTargets::new(gpu, size)) {
+                gpu.retired.retire(old_targets);
+            }
+        }
+        let targets = self.targets.as_ref().unwrap();
+        let rtv_handle = targets.rtv.cpu_handle(0);
+        let dsv_handle = targets.dsv.cpu_handle(0);
+
+        let state = self.states.get(gpu, TargetDesc::new(TRIANGLE_FORMAT, 1));
+        unsafe {
+            command_list.ResourceBarrier(&[transition_barrier(
+                &targets.triangles,
+                D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+                D3D12_RESOURCE_STATE_RENDER_TARGET,
+            )]);
+            command_list.OMSetRenderTargets(1, Some(&rtv_handle), false, Some(&dsv_handle));
+            command_list.ClearRenderTargetView(rtv_handle, &[0.0; 4], None);
+            command_list.ClearDepthStencilView(dsv_handle, D3D12_CLEAR_FLAG_DEPTH, 0.0, 0, &[]);
+            if index_count > 0 {
+                command_list.SetPipelineState(state);
+                let mut bindings = RootBindings::graphics(command_list, &self.root_signature);
+                bindings.cbv(0, self.constant_buffer.gpu_adress());
+                bindings.check_complete();
+                command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+                command_list.IASetVertexBuffers(0, Some(&mesh_buffer.vertex_buffer_views()));
+                command_list.IASetIndexBuffer(Some(&mesh_buffer.index_buffer_view()));
+                command_list.DrawIndexedInstanced(index_count, 1, 0, 0, 0);
+            }
+            command_list.ResourceBarrier(&[transition_barrier(
+                &targets.triangles,
+                D3D12_RESOURCE_STATE_RENDER_TARGET,
+                D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+            )]);
+        }
+    }
+}
+
+/// A `None` target gives a null view, reading 0 like a pass that drew nothing.
+fn write_srv(gpu: &Gpu, triangles: Option<&ID3D12Resource>, srv: &BindlessDescriptors) {
+    let srv_desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
+        Format: TRIANGLE_FORMAT,
+        ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
+        Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+        Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+            Texture2D: D3D12_TEX2D_SRV {
+                MipLevels: 1,
+                ..Default::default()
+            },
+        },
+    };
+    unsafe {
+        gpu.device
+            .CreateShaderResourceView(triangles, Some(&srv_desc), srv.cpu_handle(0))
+    };
+}
+
+fn create_root_signature(gpu: &Gpu) -> RootSignature {
+    let root_parameters = [D3D12_ROOT_PARAMETER {
+        ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_VERTEX,
+        Anonymous: D3D12_ROOT_PARAMETER_0 {
+            Descriptor: D3D12_ROOT_DESCRIPTOR {
+                ShaderRegister: 0,
+                RegisterSpace: 0,
+            },
+        },
+    }];
+    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
+        NumParameters: root_parameters.len() as u32,
+        pParameters: root_parameters.as_ptr(),
+        NumStaticSamplers: 0,
+        pStaticSamplers: std::ptr::null(),
+    };
+
+    create_root_signature_from_desc(gpu, "depth prepass", &root_signature_desc)
+}
@@ -0,0 +1,169 @@
+use bevy::prelude::*;
+use windows::Win32::Graphics::{Direct3D::D3D_PRIMITIVE_TOPOLOGY_LINELIST, Direct3D12::*};
+
+use crate::{
+    core::{Camera, Shader},
+    render::{
+        gizmos::{GizmoVertex, Gizmos},
+        render_target::FRAME_COUNT,
+        vertex_buffer::VertexBuffer,
+        BackBufferFormat, Gpu,
+    },
+};
+
+use super::{
+    pipeline_state::{
+        compile_shaders, create_root_signature_from_desc, BlendMode, SpecializedPipelineStates,
+        TargetDesc, VertexLayout,
+    },
+    root_bindings::{RootBindings, RootSignature},
+    view_projection,
+};
+
+/// Lines drawn in one frame, the rest are dropped.
+const MAX_GIZMO_LINES: usize = 16384;
+
+/// Draws the lines of [`Gizmos`] over the tone mapped output of a view.
+#[derive(Resource)]
+pub struct GizmoPipeline {
+    root_signature: RootSignature,
+    states: SpecializedPipelineStates,
+    // one per frame slot, the frames in flight may still read the others
+    vertex_buffers: [VertexBuffer; FRAME_COUNT],
+    vertices: Vec<GizmoVertex>,
+    // slot whose vertex buffer holds `vertices`, None until the first view of the frame
+    written_slot: Option<usize>,
+}
+
+impl GizmoPipeline {
+    pub fn is_empty(&self) -> bool {
+        self.vertices.is_empty()
+    }
+
+    /// Draws into the bound render target, of `format` and with a single sample.
+    #[allow(clippy::too_many_arguments)]
+    pub fn populate_command_list(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &mut ID3D12GraphicsCommandList,
+        transform: &GlobalTransform,
+        camera: &Camera,
+        view_rect: Rect,
+        format: BackBufferFormat,
+        frame_slot: usize,
+    ) {
+        // the GPU finished the last frame of the slot before this one began
+        if self.written_slot != Some(frame_slot) {
+            self.vertex_buffers[frame_slot].write(&self.vertices);
+            self.written_slot = Some(frame_slot);
+        }
+        let vertex_buffer = &self.vertex_buffers[frame_slot];
+        let state = self
+            .states
+            .get(gpu, TargetDesc::new(format.dxgi_format(), 1));
+        let view_projection = view_projection(transform, camera, view_rect);
+        let mut constants = view_projection.to_cols_array().map(f32::to_bits).to_vec();
+        constants.push(format.is_linear() as u32);
+        unsafe {
+            command_list.SetPipelineState(state);
+            let mut bindings = RootBindings::graphics(command_list, &self.root_signature);
+            bindings.constants(0, &constants, 0);
+            bindings.check_complete();
+
+            command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_LINELIST);
+            command_list.IASetVertexBuffers(0, Some(&[*vertex_buffer.view()]));
+            command_list.DrawInstanced(self.vertices.len() as u32, 1, 0, 0);
+        }
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct GizmoShaderHandle(pub Handle<Shader>);
+
+fn create_root_signature(gpu: &Gpu) -> RootSignature {
+    let root_parameters = [
+        // view projection, followed by whether the target takes linear values
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                    Num32BitValues: 17,
+                },
+            },
+        },
+    ];
+    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
+        NumParameters: root_parameters.len() as u32,
+        pParameters: root_parameters.as_ptr(),
+        NumStaticSamplers: 0,
+        pStaticSamplers: std::ptr::null(),
+    };
+
+    create_root_signature_from_desc(gpu, "gizmos", &root_signature_desc)
+}
+
+pub fn create_gizmo_pipeline(
+    mut commands: Commands,
+    gpu: Res<Gpu>,
+    shader_handle: Res<GizmoShaderHandle>,
+    shaders: Res<Assets<Shader>>,
+    pipeline: Option<Res<GizmoPipeline>>,
+) {
+    if pipeline.is_some() {
+        return;
+    }
+
+    let Some(shader_source) = shaders.get(&shader_handle.0) else {
+        return;
+    };
+
+    let root_signature = create_root_signature(&gpu);
+    let states = SpecializedPipelineStates::new(
+        compile_shaders(shader_source),
+        &root_signature,
+        BlendMode::Opaque,
+    )
+    .with_vertex_layout(VertexLayout::Lines);
+
+    commands.insert_resource(GizmoPipeline {
+        root_signature,
+        states,
+        vertex_buffers: std::array::from_fn(|slot| {
+            VertexBuffer::with_capacity::<GizmoVertex>(
+                &gpu,
+                MAX_GIZMO_LINES * 2,
+                &format!("gizmo lines {slot}"),
+            )
+        }),
+        vertices: Vec::new(),
+        written_slot: None,
+    });
+}
+
+/// Hands the lines of this frame to the pipeline and clears [`Gizmos`]. They are written to the
+/// vertex buffer of the frame slot when the first view draws them.
+pub fn prepare_gizmos(
+    mut gizmos: ResMut<Gizmos>,
+    pipeline: Option<ResMut<GizmoPipeline>>,
+    mut warned: Local<bool>,
+) {
+    if let Some(mut pipeline) = pipeline {
+        let vertices = gizmos.vertices();
+        let count = vertices.len().min(MAX_GIZMO_LINES * 2);
+        if count < vertices.len() && !*warned {
+            warn!(
+                "{} gizmo lines were added in one frame, only {MAX_GIZMO_LINES} are drawn",
+                gizmos.len()
+            );
+            *warned = true;
+        }
+        pipeline.vertices.clear();
+        pipeline.vertices.extend_from_slice(&vertices[..count]);
+        pipeline.written_slot = None;
+    }
+    gizmos.clear();
+}
@@ -0,0 +1,336 @@
+use bevy::prelude::*;
+use windows::Win32::Graphics::{
+    Direct3D::D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
+    Direct3D12::*,
+    Dxgi::Common::{
+        DXGI_FORMAT, DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_FORMAT_R32_FLOAT, DXGI_FORMAT_R32_UINT,
+        DXGI_FORMAT_R8G8B8A8_UNORM,
+    },
+};
+
+use crate::{
+    core::Shader,
+    render::{
+        bindless::bindless_ranges,
+        constant_buffer::ConstantBuffer,
+        d3d::transition_barrier,
+        mesh_data::MeshBuffer,
+        render_target::{create_depth_target, create_hdr_target, DEPTH_FORMAT},
+        set_debug_name, BindlessDescriptors, Descriptors, Gpu,
+    },
+};
+
+use super::{
+    grown_size,
+    pipeline_state::{
+        compile_shaders, create_root_signature_from_desc, BlendMode, SpecializedPipelineStates,
+        TargetDesc, VertexLayout,
+    },
+    root_bindings::{RootBindings, RootSignature},
+};
+
+/// Targets of the G-buffer pass, in the order of its `SV_TARGET` outputs and of the G-buffer
+/// SRVs in `demo.hlsl`.
+pub(super) const G_BUFFER_TARGETS: [(DXGI_FORMAT, &str); 4] = [
+    // base color, textures applied
+    (DXGI_FORMAT_R8G8B8A8_UNORM, "hybrid G-buffer albedo"),
+    // of the triangle, not turned to the camera
+    (DXGI_FORMAT_R16G16B16A16_FLOAT, "hybrid G-buffer normal"),
+    // distance to the camera
+    (DXGI_FORMAT_R32_FLOAT, "hybrid G-buffer depth"),
+    // triangle index plus one, materials are per triangle
+    (DXGI_FORMAT_R32_UINT, "hybrid G-buffer material ID"),
+];
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct GBufferFrameData {
+    view_projection: [[f32; 4]; 4],
+    camera_position: [f32; 3],
+    frame_index: u32,
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct HybridGBufferShaderHandle(pub Handle<Shader>);
+
+/// The G-buffer targets, their bindless SRVs and their depth target, grown to the largest
+/// target drawn to.
+struct GBufferTargets {
+    targets: Vec<ID3D12Resource>,
+    rtvs: Descriptors,
+    srvs: BindlessDescriptors,
+    _depth_target: ID3D12Resource,
+    dsv: Descriptors,
+    size: UVec2,
+}
+
+impl GBufferTargets {
+    fn new(gpu: &Gpu, size: UVec2) -> Self {
+        // consecutive, the pass binds them as one range
+        let rtvs = gpu.descriptors.rtv.allocate(G_BUFFER_TARGETS.len());
+        let targets: Vec<_> = G_BUFFER_TARGETS
+            .iter()
+            .enumerate()
+            .map(|(index, (format, name))| {
+                let target = create_hdr_target(&gpu.device, size, *format);
+                set_debug_name(&target, name);
+                unsafe {
+                    gpu.device
+                        .CreateRenderTargetView(&target, None, rtvs.cpu_handle(index))
+                };
+                target
+            })
+            .collect();
+        let srvs = gpu.bindless.allocate(G_BUFFER_TARGETS.len());
+        write_srvs(gpu, Some(&targets), &srvs);
+        let depth_target = create_depth_target(&gpu.device, size);
+        let dsv = gpu.descriptors.dsv.allocate(1);
+        unsafe {
+            gpu.device
+                .CreateDepthStencilView(&depth_target, None, dsv.cpu_handle(0))
+        };
+        Self {
+            targets,
+            rtvs,
+            srvs,
+            _depth_target: depth_target,
+            dsv,
+            size,
+        }
+    }
+
+    fn transition(
+        &self,
+        command_list: &ID3D12GraphicsCommandList,
+        before: D3D12_RESOURCE_STATES,
+        after: D3D12_RESOURCE_STATES,
+    ) {
+        let barriers: Vec<_> = self
+            .targets
+            .iter()
+            .map(|target| transition_barrier(target, before, after))
+            .collect();
+        unsafe { command_list.ResourceBarrier(&barriers) };
+    }
+}
+
+/// Rasterizes the primary hits of the path tracer into a G-buffer, see
+/// [`super::Integrator::Hybrid`].
+///
+/// Stores the albedo, normal, distance and material of the surface seen through every pixel
+/// center. The path tracer shades them as the first vertex of its paths, tracing only the
+/// shadow rays and bounces from there.
+pub(super) struct HybridGBuffer {
+    root_signature: RootSignature,
+    states: SpecializedPipelineStates,
+    constant_buffer: ConstantBuffer<GBufferFrameData>,
+    targets: Option<GBufferTargets>,
+    // the scene buffers and the first texture slot of the path tracer
+    bindless_ids: [u32; 2],
+    // read until the first pass
+    null_srvs: BindlessDescriptors,
+}
+
+impl HybridGBuffer {
+    /// Reads the meshes through the bindless `scene_srvs` of the path tracer.
+    pub(super) fn new(
+        gpu: &Gpu,
+        shader_source: &Shader,
+        scene_srvs: &BindlessDescriptors,
+        first_texture: u32,
+    ) -> Self {
+        let root_signature = create_root_signature(gpu);
+        let additional_formats: Vec<_> = G_BUFFER_TARGETS[1..]
+            .iter()
+            .map(|(format, _)| *format)
+            .collect();
+        let mut states = SpecializedPipelineStates::new(
+            compile_shaders(shader_source),
+            &root_signature,
+            BlendMode::Opaque,
+        )
+        .with_vertex_layout(VertexLayout::Mesh)
+        .with_depth(DEPTH_FORMAT)
+        .with_additional_targets(&additional_formats);
+        states.get(gpu, TargetDesc::new(G_BUFFER_TARGETS[0].0, 1));
+        let null_srvs = gpu.bindless.allocate(G_BUFFER_TARGETS.len());
+        write_srvs(gpu, None, &null_srvs);
+        Self {
+            root_signature,
+            states,
+            constant_buffer: ConstantBuffer::create(gpu),
+            targets: None,
+            bindless_ids: [scene_srvs.first_id(), first_texture],
+            null_srvs,
+        }
+    }
+
+    /// Bindless ID of the first G-buffer SRV, the others follow in the order of
+    /// [`G_BUFFER_TARGETS`]. Changes when the G-buffer grows, null views before the first pass.
+    pub(super) fn first_srv_id(&self) -> u32 {
+        self.targets
+            .as_ref()
+            .map_or(&self.null_srvs, |targets| &targets.srvs)
+            .first_id()
+    }
+
+    pub(super) fn write_frame_data(
+        &mut self,
+        view_projection: Mat4,
+        camera_position: Vec3,
+        frame_index: u32,
+    ) {
+        self.constant_buffer.write(&GBufferFrameData {
+            view_projection: view_projection.to_cols_array_2d(),
+            camera_position: camera_position.to_array(),
+            frame_index,
+        });
+    }
+
+    /// Records the pass over the first `index_count` indices of `mesh_buffer`, with the viewport
+    /// of the scene pass. Leaves the G-buffer bound, the caller binds its target again.
+    pub(super) fn record(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &ID3D12GraphicsCommandList,
+        mesh_buffer: &MeshBuffer,
+        index_count: u32,
+        size: UVec2,
+    ) {
+        let current_size = self.targets.as_ref().map(|targets| targets.size);
+        if let Some(size) = grown_size(current_size, size) {
+            // the frames in flight may still read the old targets through their own SRVs
+            if let Some(old_targets) = self.targets.replace(GBufferTargets::new(gpu, size)) {
+                gpu.retired.retire(old_targets);
+            }
+        }
+        let targets = self.targets.as_ref().unwrap();
+        let dsv_handle = targets.dsv.cpu_handle(0);
+
+        let state = self
+            .states
+            .get(gpu, TargetDesc::new(G_BUFFER_TARGETS[0].0, 1));
+        unsafe {
+            targets.transition(
+                command_list,
+                D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+                D3D12_RESOURCE_STATE_RENDER_TARGET,
+            );
+            let first_rtv = targets.rtvs.cpu_handle(0);
+            command_list.OMSetRenderTargets(
+                G_BUFFER_TARGETS.len() as u32,
+                Some(&first_rtv),
+                true,
+                Some(&dsv_handle),
+            );
+            for index in 0..G_BUFFER_TARGETS.len() {
+                command_list.ClearRenderTargetView(targets.rtvs.cpu_handle(index), &[0.0; 4], None);
+            }
+            command_list.ClearDepthStencilView(dsv_handle, D3D12_CLEAR_FLAG_DEPTH, 0.0, 0, &[]);
+            if index_count > 0 {
+                command_list.SetPipelineState(state);
+                command_list.SetDescriptorHeaps(&[Some(gpu.bindless.heap().heap())]);
+                let mut bindings = RootBindings::graphics(command_list, &self.root_signature);
+                bindings.cbv(0, self.constant_buffer.gpu_adress());
+                bindings.table(1, gpu.bindless.heap());
+                bindings.constants(2, &self.bindless_ids, 0);
+                bindings.check_complete();
+                command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+                command_list.IASetVertexBuffers(0, Some(&mesh_buffer.vertex_buffer_views()));
+                command_list.IASetIndexBuffer(Some(&mesh_buffer.index_buffer_view()));
+                command_list.DrawIndexedInstanced(index_count, 1, 0, 0, 0);
+            }
+            targets.transition(
+                command_list,
+                D3D12_RESOURCE_STATE_RENDER_TARGET,
+                D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+            );
+        }
+    }
+}
+
+/// `None` targets give null views, reading 0 like a pass that drew nothing.
+fn write_srvs(gpu: &Gpu, targets: Option<&[ID3D12Resource]>, srvs: &BindlessDescriptors) {
+    for (index, (format, _)) in G_BUFFER_TARGETS.iter().enumerate() {
+        let srv_desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
+            Format: *format,
+            ViewDimension: D3D12_SRV_DIMENSION_TEXTURE2D,
+            Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+            Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                Texture2D: D3D12_TEX2D_SRV {
+                    MipLevels: 1,
+                    ..Default::default()
+                },
+            },
+        };
+        let target = targets.map(|targets| &targets[index]);
+        unsafe {
+            gpu.device
+                .CreateShaderResourceView(target, Some(&srv_desc), srvs.cpu_handle(index))
+        };
+    }
+}
+
+/// b0 is the frame data, the table the bindless heap and b1 the IDs of the scene buffers and the
+/// first texture slot.
+fn create_root_signature(gpu: &Gpu) -> RootSignature {
+    let ranges = bindless_ranges();
+
+    let root_parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: ranges.len() as u32,
+                    pDescriptorRanges: ranges.as_ptr(),
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                    Num32BitValues: 2,
+                },
+            },
+        },
+    ];
+    let texture_sampler = D3D12_STATIC_SAMPLER_DESC {
+        Filter: D3D12_FILTER_MIN_MAG_MIP_LINEAR,
+        AddressU: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        AddressV: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        AddressW: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        MipLODBias: 0.0,
+        MaxAnisotropy: 1,
+        ComparisonFunc: D3D12_COMPARISON_FUNC_NEVER,
+        BorderColor: D3D12_STATIC_BORDER_COLOR_OPAQUE_BLACK,
+        MinLOD: 0.0,
+        MaxLOD: D3D12_FLOAT32_MAX,
+        ShaderRegister: 0,
+        RegisterSpace: 0,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+    };
+    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
+        NumParameters: root_parameters.len() as u32,
+        pParameters: root_parameters.as_ptr(),
+        NumStaticSamplers: 1,
+        pStaticSamplers: &texture_sampler,
+    };
+
+    create_root_signature_from_desc(gpu, "hybrid G-buffer", &root_signature_desc)
+}
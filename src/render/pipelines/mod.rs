@@ -1,22 +1,163 @@
+mod auto_exposure;
+mod debug_view;
+mod deferred;
+mod depth_prepass;
+mod gizmos;
+mod hybrid_g_buffer;
 mod naive_pathtracer;
+mod path_statistics;
+mod pipeline_state;
+mod radiance_cache;
+mod raster_forward;
+mod root_bindings;
+mod tonemapping;
 
 use bevy::{prelude::*, utils::HashMap};
-use windows::Win32::Graphics::Direct3D12::{ID3D12GraphicsCommandList, ID3D12PipelineState};
+use windows::Win32::Graphics::Direct3D12::{
+    ID3D12GraphicsCommandList, D3D12_CPU_DESCRIPTOR_HANDLE,
+};
 
-use super::MeshData;
-use crate::core::Camera;
+use super::{
+    furnace::FurnaceScene, upload::UploadQueue, AovTarget, Gpu, LightData, MeshData, PrimitiveData,
+    View,
+};
+use crate::core::{Background, Camera};
 
-pub use naive_pathtracer::{create_pathtracer_pipeline, PathTracerShaderHandle};
+pub use auto_exposure::{
+    create_auto_exposure_pipeline, AutoExposurePipeline, AutoExposureShaderHandle,
+};
+pub use debug_view::{prepare_debug_view, DebugView};
+pub use deferred::{
+    create_deferred_pipeline, DeferredGBufferShaderHandle, DeferredLightingShaderHandle,
+};
+pub use depth_prepass::DepthPrepassShaderHandle;
+pub use gizmos::{create_gizmo_pipeline, prepare_gizmos, GizmoPipeline, GizmoShaderHandle};
+pub use hybrid_g_buffer::HybridGBufferShaderHandle;
+pub use naive_pathtracer::{
+    create_pathtracer_pipeline, Integrator, PathTracerSettings, PathTracerShaderHandle,
+};
+pub use path_statistics::{read_path_statistics, PathStatistics};
+pub use pipeline_state::{retry_failed_pipeline_states, TargetDesc};
+pub use radiance_cache::RadianceCacheShaderHandle;
+pub use raster_forward::{create_raster_forward_pipeline, RasterForwardShaderHandle};
+pub use tonemapping::{
+    create_tonemap_pipeline, prepare_tonemap, Dithering, FinalBlit, PostProcessOverride,
+    TonemapPipeline, TonemapShaderHandle, Tonemapping,
+};
 
 type PipelineId = usize;
 
 pub const PATH_TRACER_PIPELINE_ID: PipelineId = 0;
+pub const RASTER_FORWARD_PIPELINE_ID: PipelineId = 1;
+pub const DEFERRED_PIPELINE_ID: PipelineId = 2;
+
+/// Pipeline the scene is drawn with. All of them receive the scene data all the time, switching
+/// doesn't upload anything again.
+#[derive(Resource, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Resource, Default)]
+pub enum ScenePipeline {
+    /// Progressive path tracing, see [`PathTracerSettings`].
+    #[default]
+    PathTracer,
+    /// Rasterized meshes lit by the punctual lights without shadows, for fast previews.
+    /// Analytic primitives and area lights aren't drawn.
+    RasterForward,
+    /// Rasterized meshes stored in a G-buffer of albedo, normal, depth, material and emission,
+    /// then lit like [`ScenePipeline::RasterForward`] in a fullscreen pass.
+    Deferred,
+}
+
+impl ScenePipeline {
+    pub const ALL: [ScenePipeline; 3] = [
+        ScenePipeline::PathTracer,
+        ScenePipeline::RasterForward,
+        ScenePipeline::Deferred,
+    ];
+
+    pub fn id(&self) -> PipelineId {
+        match self {
+            ScenePipeline::PathTracer => PATH_TRACER_PIPELINE_ID,
+            ScenePipeline::RasterForward => RASTER_FORWARD_PIPELINE_ID,
+            ScenePipeline::Deferred => DEFERRED_PIPELINE_ID,
+        }
+    }
+}
+
+/// Run condition of the systems drawing with pipeline `PIPELINE_ID`.
+pub fn scene_pipeline_is<const PIPELINE_ID: usize>(scene_pipeline: Res<ScenePipeline>) -> bool {
+    scene_pipeline.id() == PIPELINE_ID
+}
+
+/// Render target of the scene pass, bound and with its viewport set when
+/// [`Pipeline::populate_command_list`] is called.
+#[derive(Debug, Clone, Copy)]
+pub struct SceneTarget<'a> {
+    pub desc: TargetDesc,
+    pub rtv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    /// Depth target of the same size in `render_target::DEPTH_FORMAT`, not bound. Its content
+    /// is undefined until the pipeline clears it.
+    pub dsv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    /// Pixels of the target the viewport can cover, from the top left corner.
+    pub size: UVec2,
+    /// The AOVs of the [`super::AovRegistry`] in the size of the target, not bound.
+    pub aovs: &'a [AovTarget],
+    /// Which of the frames in flight is recorded, for readbacks that need a copy per frame.
+    pub frame_slot: usize,
+}
+
+impl SceneTarget<'_> {
+    /// AOV `name` in `RENDER_TARGET`, already cleared as its [`super::AovClear`] says. None for
+    /// offline renders and headless targets.
+    pub fn aov(&self, name: &str) -> Option<&AovTarget> {
+        self.aovs.iter().find(|aov| aov.name() == name)
+    }
+}
 
 pub trait Pipeline: Send + Sync {
-    fn populate_command_list(&self, command_list: &mut ID3D12GraphicsCommandList);
-    fn state(&self) -> &ID3D12PipelineState;
-    fn write_camera_data(&mut self, transform: &GlobalTransform, camera: &Camera);
-    fn set_mesh_data(&mut self, data: &MeshData, command_list: &mut ID3D12GraphicsCommandList);
+    /// Records the pass drawing into `target`.
+    fn populate_command_list(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &mut ID3D12GraphicsCommandList,
+        target: &SceneTarget,
+    );
+    /// Name of the pass [`Pipeline::populate_prepass`] records before the main pass of this
+    /// frame, `None` when there is none. Known once the frame data is written.
+    fn prepass(&self) -> Option<&'static str> {
+        None
+    }
+    /// Records the pass drawing what the main pass reads, like a G-buffer or depth, in its own
+    /// render graph node so it is timed on its own.
+    fn populate_prepass(
+        &mut self,
+        _gpu: &Gpu,
+        _command_list: &mut ID3D12GraphicsCommandList,
+        _target: &SceneTarget,
+    ) {
+    }
+    /// Writes constants that change every frame, after mesh and light data of the frame are set.
+    /// `frame_index` is the index of the frame in the current accumulation. `view_rect` is the
+    /// part of the camera image the target covers, in normalized viewport positions.
+    fn write_frame_data(
+        &mut self,
+        transform: &GlobalTransform,
+        camera: &Camera,
+        background: &Background,
+        settings: &PathTracerSettings,
+        frame_index: u32,
+        view_rect: Rect,
+    );
+    /// Queues new mesh data for upload, it reaches the GPU as the [`super::UploadBudget`] allows.
+    fn set_mesh_data(&mut self, data: &MeshData, uploads: &mut UploadQueue);
+    fn set_light_data(&mut self, data: &LightData, uploads: &mut UploadQueue);
+    fn set_primitive_data(&mut self, data: &PrimitiveData, uploads: &mut UploadQueue);
+    fn set_debug_view(&mut self, debug_view: DebugView);
+    /// Renders `scene` in place of the scene data, `None` goes back to the scene.
+    fn set_furnace_scene(&mut self, scene: Option<&FurnaceScene>);
+    /// Statistics of the paths traced since the last call, up to the frame last recorded in
+    /// `frame_slot`, which must be finished on the GPU. Always `None` for pipelines that don't
+    /// trace paths.
+    fn read_path_statistics(&mut self, frame_slot: usize) -> Option<PathStatistics>;
 }
 
 #[derive(Resource, Deref, DerefMut)]
@@ -34,40 +175,93 @@ struct CameraData {
     inverse_view_matrix: [[f32; 4]; 4],
     aspect_ratio: f32,
     fov: f32,
-    __padding: [u32; 2],
+    background_mode: u32,
+    __padding: u32,
+    // linear, alpha unused
+    background_color: [f32; 4],
+    // offset and size of the rendered part of the image, in viewport positions
+    view_rect: [f32; 4],
 }
 
+const BACKGROUND_ENVIRONMENT: u32 = 0;
+const BACKGROUND_COLOR: u32 = 1;
+const BACKGROUND_TRANSPARENT: u32 = 2;
+
 #[repr(C)]
-#[derive(Copy, Clone)]
-struct MeshInfo {
+#[derive(Copy, Clone, Default)]
+struct SceneInfo {
     vertex_count: u32,
-    __padding: [u32; 3],
-}
-
-impl MeshInfo {
-    fn new(vertex_count: u32) -> Self {
-        Self {
-            vertex_count,
-            __padding: [0; 3],
-        }
-    }
+    light_count: u32,
+    frame_index: u32,
+    light_tree_size: u32,
+    infinite_light_count: u32,
+    primitive_count: u32,
+    __padding: [u32; 2],
 }
 
 impl CameraData {
-    fn new(transform: &GlobalTransform, camera: &Camera) -> Self {
-        let forward = transform.forward() * 1.0;
-        let up = transform.up() * 1.0;
-        let eye_position = -transform.translation();
-        let target_position = eye_position + forward;
-
-        let view_matrix = Mat4::look_at_lh(eye_position, target_position, up);
-        let inverse_view_matrix = view_matrix.inverse();
+    fn new(
+        transform: &GlobalTransform,
+        camera: &Camera,
+        background: &Background,
+        view_rect: Rect,
+    ) -> Self {
+        let inverse_view_matrix = View::new(transform, camera).inverse_view_matrix();
+        let (background_mode, background_color) = match background {
+            Background::Environment => (BACKGROUND_ENVIRONMENT, LinearRgba::NONE),
+            Background::Color(color) => (BACKGROUND_COLOR, color.to_linear()),
+            Background::Transparent => (BACKGROUND_TRANSPARENT, LinearRgba::NONE),
+        };
 
         Self {
             inverse_view_matrix: inverse_view_matrix.to_cols_array_2d(),
             aspect_ratio: camera.aspect_ratio,
             fov: camera.fov,
-            __padding: [0; 2],
+            background_mode,
+            __padding: 0,
+            background_color: background_color.to_f32_array(),
+            view_rect: [
+                view_rect.min.x,
+                view_rect.min.y,
+                view_rect.width(),
+                view_rect.height(),
+            ],
         }
     }
 }
+
+/// Distance of the near plane of rasterizing pipelines, the far plane is at infinity.
+const NEAR: f32 = 0.01;
+
+/// World to clip space of rasterizing pipelines, with reversed depth. Matches the primary rays
+/// of the path tracer, for `view_rect` see [`Pipeline::write_frame_data`].
+fn view_projection(transform: &GlobalTransform, camera: &Camera, view_rect: Rect) -> Mat4 {
+    let projection = Mat4::perspective_infinite_reverse_rh(camera.fov, camera.aspect_ratio, NEAR);
+    crop_matrix(view_rect) * projection * View::new(transform, camera).view_matrix()
+}
+
+/// Maps the part `view_rect` of the clip space of the whole camera image to the whole clip
+/// space, for tiles of offline renders.
+fn crop_matrix(view_rect: Rect) -> Mat4 {
+    let size = view_rect.size();
+    Mat4::from_cols(
+        Vec4::new(1.0 / size.x, 0.0, 0.0, 0.0),
+        Vec4::new(0.0, 1.0 / size.y, 0.0, 0.0),
+        Vec4::Z,
+        Vec4::new(
+            (1.0 - 2.0 * view_rect.min.x) / size.x - 1.0,
+            1.0 - (1.0 - 2.0 * view_rect.min.y) / size.y,
+            0.0,
+            1.0,
+        ),
+    )
+}
+
+/// Size to recreate a texture of `current` size with to fit `needed`, `None` while it fits.
+fn grown_size(current: Option<UVec2>, needed: UVec2) -> Option<UVec2> {
+    match current {
+        Some(current) if current.cmpge(needed).all() => None,
+        Some(current) => Some(current.max(needed)),
+        None => Some(needed),
+    }
+}
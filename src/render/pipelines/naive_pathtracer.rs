@@ -1,92 +1,397 @@
-use std::ffi::c_void;
-
 use bevy::prelude::*;
-use windows::{
-    core::*,
-    Win32::Graphics::{
-        Direct3D::{
-            Fxc::{D3DCompile, D3DCOMPILE_DEBUG, D3DCOMPILE_SKIP_OPTIMIZATION},
-            ID3DBlob, D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST,
-        },
-        Direct3D12::*,
-        Dxgi::Common::{
-            DXGI_FORMAT_R32G32B32_FLOAT, DXGI_FORMAT_R32G32_FLOAT, DXGI_FORMAT_R8G8B8A8_UNORM,
-            DXGI_SAMPLE_DESC,
-        },
-    },
-};
+use serde::Deserialize;
+use windows::Win32::Graphics::{Direct3D::D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST, Direct3D12::*};
 
 use crate::{
-    core::{Camera, Shader, VertexBuffer},
+    core::{Background, Camera, Shader},
     render::{
-        constant_buffer::ConstantBuffer, mesh_data::MeshBuffer, DescriptorHeap, Gpu, MeshData,
+        bindless::bindless_ranges,
+        constant_buffer::ConstantBuffer,
+        furnace::FurnaceScene,
+        light_data::{GpuLight, GpuLightNode, MAX_LIGHTS},
+        material_textures::MaterialTextures,
+        mesh_data::{MaterialData, MeshBuffer, NO_TEXTURE},
+        primitive_data::{GpuPrimitive, MAX_PRIMITIVES},
+        render_target::AccumulationPrecision,
+        structured_buffer::StructuredBuffer,
+        upload::{UploadPriority, UploadQueue},
+        vertex_buffer::VertexBuffer,
+        BindlessDescriptors, Gpu, LightData, MeshData, PrimitiveData,
     },
 };
 
-use super::{CameraData, MeshInfo, Pipeline, PipelineStorage, PATH_TRACER_PIPELINE_ID};
+// bindless IDs of the vertices, indices, materials, uvs, lights, the light tree and primitives.
+// The depth prepass and the hybrid G-buffer have IDs of their own.
+const SRV_COUNT: usize = 7;
+
+use super::{
+    depth_prepass::{DepthPrepass, DepthPrepassShaderHandle},
+    hybrid_g_buffer::{HybridGBuffer, HybridGBufferShaderHandle},
+    path_statistics::PathStatisticsBuffer,
+    pipeline_state::{
+        compile_shaders, create_root_signature_from_desc, BlendMode, SpecializedPipelineStates,
+        TargetDesc,
+    },
+    radiance_cache::{RadianceCache, RadianceCacheShaderHandle},
+    root_bindings::{RootBindings, RootSignature},
+    view_projection, CameraData, DebugView, PathStatistics, Pipeline, PipelineStorage, SceneInfo,
+    SceneTarget, PATH_TRACER_PIPELINE_ID,
+};
+
+/// How the path tracer finds the surfaces seen by the camera.
+#[derive(Reflect, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Integrator {
+    /// Traces the primary rays like the rest of the path.
+    #[default]
+    PathTracing,
+    /// Rasterizes the meshes into a G-buffer of albedo, normal, depth and material ID, and
+    /// starts the paths from the surfaces stored there. Only the shadow rays and the bounces
+    /// are traced, primary visibility costs a raster pass. Primary hits are taken at the pixel
+    /// centers, so [`PathTracerSettings::jitter`] doesn't antialias edges, and
+    /// [`PathTracerSettings::depth_prepass`] is skipped.
+    Hybrid,
+}
+
+/// Quality settings of the path tracer, changing them restarts accumulation.
+#[derive(Resource, Reflect, Deserialize, Debug, Clone, Copy)]
+#[reflect(Resource, Default)]
+#[serde(default)]
+pub struct PathTracerSettings {
+    /// Bounces after the primary hit, 0 gives direct lighting only.
+    pub max_bounces: u32,
+    /// Paths traced per pixel every frame.
+    pub samples_per_frame: u32,
+    /// Mixed into the per pixel random state, different seeds give different noise patterns.
+    pub seed: u32,
+    /// Luminance limit of light arriving through indirect bounces, suppresses fireflies at the
+    /// cost of some energy. `0.0` disables clamping.
+    pub max_indirect_radiance: f32,
+    /// Lowest perceptual roughness of surfaces hit after a diffuse bounce, in `[0, 1]`. Blurs
+    /// noisy caustic paths, `0.0` disables regularization.
+    pub regularization: f32,
+    /// Renders ambient occlusion instead of the shaded image, every sample traces one short
+    /// occlusion ray from the primary hit. Useful to check geometry before shading works.
+    pub ambient_occlusion: bool,
+    /// Length of the occlusion rays in world units.
+    pub ambient_occlusion_radius: f32,
+    /// Offsets primary rays inside their pixel along a scrambled Sobol sequence, so
+    /// accumulation antialiases edges. The sequence continues over the frames of an
+    /// accumulation, every power of two of samples covers the pixel evenly.
+    pub jitter: bool,
+    /// Caches the radiance leaving surfaces in a world space grid and ends paths at their
+    /// first diffuse bounce once the cell they reach holds enough samples. Greatly reduces
+    /// noise in diffuse interiors at the cost of some bias, glossy reflections of the cached
+    /// surfaces come out blurred.
+    pub radiance_cache: bool,
+    /// Size of the radiance cache cells in world units. Smaller cells keep more detail but
+    /// take longer to fill.
+    pub radiance_cache_cell_size: f32,
+    /// Counts how the paths end and how long they get into [`PathStatistics`], at the cost of
+    /// a few atomic adds per pixel. Not collected by the debug views and ambient occlusion.
+    pub path_statistics: bool,
+    /// Rasterizes the meshes before tracing and starts the primary rays from the triangle
+    /// seen through their pixel, skipping the other triangles when they hit it. Primary
+    /// visibility gets much cheaper, at the cost of sometimes missing geometry thinner than a
+    /// pixel in front of that triangle when [`PathTracerSettings::jitter`] moves the rays off
+    /// the pixel center.
+    pub depth_prepass: bool,
+    /// How the surfaces seen by the camera are found, see [`Integrator`].
+    pub integrator: Integrator,
+}
+
+impl Default for PathTracerSettings {
+    fn default() -> Self {
+        Self {
+            max_bounces: 10,
+            samples_per_frame: 2,
+            seed: 0,
+            max_indirect_radiance: 10.0,
+            regularization: 0.5,
+            ambient_occlusion: false,
+            ambient_occlusion_radius: 1.0,
+            jitter: true,
+            radiance_cache: false,
+            radiance_cache_cell_size: 0.25,
+            path_statistics: false,
+            depth_prepass: false,
+            integrator: Integrator::PathTracing,
+        }
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PathTracerSettingsData {
+    max_bounces: u32,
+    samples_per_frame: u32,
+    seed: u32,
+    max_indirect_radiance: f32,
+    regularization: f32,
+    ambient_occlusion: u32,
+    ambient_occlusion_radius: f32,
+    debug_view: u32,
+    radiance_cache: u32,
+    radiance_cache_cell_size: f32,
+    radiance_cache_frame: u32,
+    furnace_test: u32,
+    path_statistics: u32,
+    jitter: u32,
+    depth_prepass: u32,
+    hybrid: u32,
+    // center and radius
+    furnace_sphere: [f32; 4],
+    furnace_material: MaterialData,
+}
 
 pub struct PathTracerPipeline {
-    root_signature: ID3D12RootSignature,
+    root_signature: RootSignature,
     vertex_buffer: VertexBuffer,
-    state: ID3D12PipelineState,
+    states: SpecializedPipelineStates,
     camera_constant_buffer: ConstantBuffer<CameraData>,
-    mesh_info_constant_buffer: ConstantBuffer<MeshInfo>,
+    scene_info: SceneInfo,
+    scene_info_constant_buffer: ConstantBuffer<SceneInfo>,
+    settings_constant_buffer: ConstantBuffer<PathTracerSettingsData>,
     mesh_buffer: MeshBuffer,
-    srv_heap: DescriptorHeap,
+    light_buffer: StructuredBuffer<GpuLight>,
+    light_tree_buffer: StructuredBuffer<GpuLightNode>,
+    primitive_buffer: StructuredBuffer<GpuPrimitive>,
+    srvs: BindlessDescriptors,
+    first_texture: u32,
+    debug_view: DebugView,
+    radiance_cache: RadianceCache,
+    // cache settings of the last frame, the cache is reset when they change
+    radiance_cache_settings: Option<f32>,
+    furnace_scene: Option<([f32; 4], MaterialData)>,
+    path_statistics: PathStatisticsBuffer,
+    // collected in the pass of this frame
+    collect_path_statistics: bool,
+    depth_prepass: DepthPrepass,
+    // drawn before the pass of this frame
+    use_depth_prepass: bool,
+    hybrid_g_buffer: HybridGBuffer,
+    // drawn before the pass of this frame, in place of the depth prepass
+    use_hybrid: bool,
 }
 
 impl Pipeline for PathTracerPipeline {
-    fn populate_command_list(&self, command_list: &mut ID3D12GraphicsCommandList) {
+    fn populate_command_list(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &mut ID3D12GraphicsCommandList,
+        target: &SceneTarget,
+    ) {
+        if self.radiance_cache_settings.is_some() {
+            self.radiance_cache.begin(command_list);
+        }
+        if self.collect_path_statistics {
+            self.path_statistics.begin(command_list);
+        }
+        let state = self.states.get(gpu, target.desc);
         unsafe {
-            command_list.SetPipelineState(&self.state);
-            command_list.SetDescriptorHeaps(&[Some(self.srv_heap.heap())]);
-            command_list.SetGraphicsRootSignature(&self.root_signature);
-
-            command_list
-                .SetGraphicsRootConstantBufferView(0, self.camera_constant_buffer.gpu_adress());
-            command_list
-                .SetGraphicsRootConstantBufferView(1, self.mesh_info_constant_buffer.gpu_adress());
-            command_list.SetGraphicsRootDescriptorTable(2, self.srv_heap.gpu_handle());
+            command_list.SetPipelineState(state);
+            command_list.SetDescriptorHeaps(&[Some(gpu.bindless.heap().heap())]);
+
+            let mut bindings = RootBindings::graphics(command_list, &self.root_signature);
+            bindings.cbv(0, self.camera_constant_buffer.gpu_adress());
+            bindings.cbv(1, self.scene_info_constant_buffer.gpu_adress());
+            bindings.cbv(2, self.settings_constant_buffer.gpu_adress());
+            bindings.table(3, gpu.bindless.heap());
+            bindings.uav(4, self.radiance_cache.gpu_address());
+            bindings.uav(5, self.path_statistics.gpu_address());
+            let bindless_ids = [
+                self.srvs.first_id(),
+                self.first_texture,
+                self.hybrid_g_buffer.first_srv_id(),
+                self.depth_prepass.srv_id(),
+            ];
+            bindings.constants(6, &bindless_ids, 0);
+            bindings.check_complete();
 
             command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
             command_list.IASetVertexBuffers(0, Some(&[*self.vertex_buffer.view()]));
             command_list.DrawInstanced(6, 1, 0, 0);
         }
+        if self.radiance_cache_settings.is_some() {
+            self.radiance_cache.end(command_list);
+        }
+        if self.collect_path_statistics {
+            self.path_statistics.end(command_list, target.frame_slot);
+        }
+    }
+
+    fn prepass(&self) -> Option<&'static str> {
+        if self.use_hybrid {
+            Some("hybrid G-buffer")
+        } else if self.use_depth_prepass {
+            Some("depth prepass")
+        } else {
+            None
+        }
+    }
+
+    fn populate_prepass(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &mut ID3D12GraphicsCommandList,
+        target: &SceneTarget,
+    ) {
+        if self.use_hybrid {
+            self.hybrid_g_buffer.record(
+                gpu,
+                command_list,
+                &self.mesh_buffer,
+                self.scene_info.vertex_count,
+                target.size,
+            );
+        } else if self.use_depth_prepass {
+            self.depth_prepass.record(
+                gpu,
+                command_list,
+                &self.mesh_buffer,
+                self.scene_info.vertex_count,
+                target.size,
+            );
+        }
     }
 
-    fn write_camera_data(&mut self, transform: &GlobalTransform, camera: &Camera) {
-        let data = CameraData::new(transform, camera);
+    fn write_frame_data(
+        &mut self,
+        transform: &GlobalTransform,
+        camera: &Camera,
+        background: &Background,
+        settings: &PathTracerSettings,
+        frame_index: u32,
+        view_rect: Rect,
+    ) {
+        let radiance_cache_settings = (settings.radiance_cache
+            && self.debug_view == DebugView::None
+            && self.furnace_scene.is_none())
+        .then_some(settings.radiance_cache_cell_size);
+        self.collect_path_statistics = settings.path_statistics
+            && !settings.ambient_occlusion
+            && self.debug_view == DebugView::None;
+        // the furnace replaces the meshes the prepasses would draw
+        self.use_hybrid = settings.integrator == Integrator::Hybrid && self.furnace_scene.is_none();
+        self.use_depth_prepass =
+            settings.depth_prepass && !self.use_hybrid && self.furnace_scene.is_none();
+        if self.use_hybrid {
+            self.hybrid_g_buffer.write_frame_data(
+                view_projection(transform, camera, view_rect),
+                transform.translation(),
+                frame_index,
+            );
+        }
+        if self.use_depth_prepass {
+            self.depth_prepass
+                .write_frame_data(view_projection(transform, camera, view_rect));
+        }
+        if radiance_cache_settings != self.radiance_cache_settings {
+            self.radiance_cache.reset();
+            self.radiance_cache_settings = radiance_cache_settings;
+        }
+        // the furnace is seen in front of the environment, without any scene data
+        let (background, scene_info) = match self.furnace_scene {
+            Some(_) => (
+                &Background::Environment,
+                SceneInfo {
+                    frame_index,
+                    ..default()
+                },
+            ),
+            None => (
+                background,
+                SceneInfo {
+                    frame_index,
+                    ..self.scene_info
+                },
+            ),
+        };
+        let data = CameraData::new(transform, camera, background, view_rect);
         self.camera_constant_buffer.write(&data);
+        self.scene_info_constant_buffer.write(&scene_info);
+        let (furnace_sphere, furnace_material) = self
+            .furnace_scene
+            .unwrap_or(([0.0; 4], MaterialData::new(&default(), NO_TEXTURE)));
+        self.settings_constant_buffer
+            .write(&PathTracerSettingsData {
+                max_bounces: settings.max_bounces,
+                samples_per_frame: settings.samples_per_frame,
+                seed: settings.seed,
+                max_indirect_radiance: settings.max_indirect_radiance,
+                regularization: settings.regularization.clamp(0.0, 1.0),
+                ambient_occlusion: settings.ambient_occlusion as u32,
+                ambient_occlusion_radius: settings.ambient_occlusion_radius,
+                debug_view: self.debug_view.shader_index(),
+                radiance_cache: radiance_cache_settings.is_some() as u32,
+                radiance_cache_cell_size: settings.radiance_cache_cell_size,
+                radiance_cache_frame: self.radiance_cache.next_frame(),
+                furnace_test: self.furnace_scene.is_some() as u32,
+                path_statistics: self.collect_path_statistics as u32,
+                jitter: settings.jitter as u32,
+                depth_prepass: self.use_depth_prepass as u32,
+                hybrid: self.use_hybrid as u32,
+                furnace_sphere,
+                furnace_material,
+            });
+    }
+
+    fn set_mesh_data(&mut self, data: &MeshData, uploads: &mut UploadQueue) {
+        self.mesh_buffer.set_new_data(data, uploads);
+        self.radiance_cache.reset();
+        self.scene_info.vertex_count = data.vertex_count() as u32;
+    }
+
+    fn set_light_data(&mut self, data: &LightData, uploads: &mut UploadQueue) {
+        let range = data.dirty_range();
+        if !range.is_empty() {
+            self.light_buffer
+                .write_range(range.start, &data.lights()[range.clone()]);
+            self.light_buffer
+                .upload_range(uploads, UploadPriority::High, range);
+        }
+        self.scene_info.light_count = data.light_count() as u32;
+        self.radiance_cache.reset();
+
+        let tree = data.light_tree();
+        self.light_tree_buffer.write(tree.nodes());
+        self.light_tree_buffer
+            .upload(uploads, UploadPriority::High, tree.nodes().len());
+        self.scene_info.light_tree_size = tree.tree_size() as u32;
+        self.scene_info.infinite_light_count = tree.infinite_light_count() as u32;
+    }
+
+    fn set_primitive_data(&mut self, data: &PrimitiveData, uploads: &mut UploadQueue) {
+        let primitives = data.primitives();
+        self.primitive_buffer.write(primitives);
+        self.primitive_buffer
+            .upload(uploads, UploadPriority::Normal, primitives.len());
+        self.scene_info.primitive_count = primitives.len() as u32;
+        self.radiance_cache.reset();
+    }
+
+    fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.debug_view = debug_view;
     }
 
-    fn state(&self) -> &ID3D12PipelineState {
-        &self.state
+    fn set_furnace_scene(&mut self, scene: Option<&FurnaceScene>) {
+        self.furnace_scene = scene.map(|scene| {
+            (
+                scene.center.extend(scene.radius).to_array(),
+                MaterialData::new(&scene.material, NO_TEXTURE),
+            )
+        });
     }
 
-    fn set_mesh_data(&mut self, data: &MeshData, command_list: &mut ID3D12GraphicsCommandList) {
-        self.mesh_buffer.set_new_data(data);
-        self.mesh_buffer.upload(command_list);
-        self.mesh_info_constant_buffer
-            .write(&MeshInfo::new(data.vertex_count() as u32))
+    fn read_path_statistics(&mut self, frame_slot: usize) -> Option<PathStatistics> {
+        self.path_statistics.read(frame_slot)
     }
 }
 
 #[derive(Resource, Deref, DerefMut)]
 pub struct PathTracerShaderHandle(pub Handle<Shader>);
 
-struct PathTracerShaders {
-    vertex_shader: ID3DBlob,
-    pixel_shader: ID3DBlob,
-}
-
-pub fn create_root_signature(gpu: &Gpu) -> ID3D12RootSignature {
-    let ranges = [D3D12_DESCRIPTOR_RANGE {
-        RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
-        NumDescriptors: 2,
-        BaseShaderRegister: 0,
-        RegisterSpace: 0,
-        OffsetInDescriptorsFromTableStart: D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND,
-    }];
+fn create_root_signature(gpu: &Gpu) -> RootSignature {
+    let ranges = bindless_ranges();
 
     let descriptor_table_srv = D3D12_ROOT_DESCRIPTOR_TABLE {
         NumDescriptorRanges: ranges.len() as u32,
@@ -114,236 +419,113 @@ pub fn create_root_signature(gpu: &Gpu) -> ID3D12RootSignature {
         },
     };
 
-    let root_descriptor_mesh_info_cbv = D3D12_ROOT_DESCRIPTOR {
+    let root_descriptor_scene_info_cbv = D3D12_ROOT_DESCRIPTOR {
         ShaderRegister: 1,
         RegisterSpace: 0,
     };
 
-    let root_parameter_mesh_info_cbv = D3D12_ROOT_PARAMETER {
+    let root_parameter_scene_info_cbv = D3D12_ROOT_PARAMETER {
         ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
         ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
         Anonymous: D3D12_ROOT_PARAMETER_0 {
-            Descriptor: root_descriptor_mesh_info_cbv,
+            Descriptor: root_descriptor_scene_info_cbv,
         },
     };
 
-    let root_parameters = [
-        root_parameter_camera_cbv,
-        root_parameter_mesh_info_cbv,
-        root_parameter_srv,
-    ];
-    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
-        Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
-        NumParameters: root_parameters.len() as u32,
-        pParameters: root_parameters.as_ptr(),
-        NumStaticSamplers: 0,
-        pStaticSamplers: std::ptr::null(),
+    let root_descriptor_settings_cbv = D3D12_ROOT_DESCRIPTOR {
+        ShaderRegister: 2,
+        RegisterSpace: 0,
     };
 
-    let mut signature: Option<ID3DBlob> = None;
-    let mut error: Option<ID3DBlob> = None;
-
-    unsafe {
-        let result = D3D12SerializeRootSignature(
-            &root_signature_desc,
-            D3D_ROOT_SIGNATURE_VERSION_1,
-            &mut signature,
-            Some(&mut error),
-        );
-        match result {
-            Ok(_) => {}
-            Err(e) => {
-                panic!(
-                    "Failed to serialize root signature: error: {:?}, more error {:?}",
-                    error, e
-                );
-            }
-        }
+    let root_parameter_settings_cbv = D3D12_ROOT_PARAMETER {
+        ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        Anonymous: D3D12_ROOT_PARAMETER_0 {
+            Descriptor: root_descriptor_settings_cbv,
+        },
     };
-    let signature =
-        signature.expect("D3D12SerializeRootSignature was successful but signature is None");
-    unsafe {
-        gpu.device
-            .CreateRootSignature(
-                0,
-                std::slice::from_raw_parts(
-                    signature.GetBufferPointer() as *const u8,
-                    signature.GetBufferSize(),
-                ),
-            )
-            .expect("Failed to create root signature")
-    }
-}
-
-fn compile_shaders(shader_source: &Shader) -> PathTracerShaders {
-    let mut vertex_shader: Option<ID3DBlob> = None;
-    let mut pixel_shader: Option<ID3DBlob> = None;
-    let mut vertex_error_msg: Option<ID3DBlob> = None;
-    let mut pixel_error_msg: Option<ID3DBlob> = None;
 
-    let compile_flags = if cfg!(debug_assertions) {
-        D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION
-    } else {
-        0
+    let root_parameter_radiance_cache_uav = D3D12_ROOT_PARAMETER {
+        ParameterType: D3D12_ROOT_PARAMETER_TYPE_UAV,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        Anonymous: D3D12_ROOT_PARAMETER_0 {
+            Descriptor: D3D12_ROOT_DESCRIPTOR {
+                ShaderRegister: 0,
+                RegisterSpace: 0,
+            },
+        },
     };
-    let shader_code = shader_source.pcstr();
-    unsafe {
-        let result_vs = D3DCompile(
-            shader_code.as_ptr() as *const c_void,
-            shader_code.as_bytes().len(),
-            None,
-            None,
-            None,
-            s!("VSMain"),
-            s!("vs_5_0"),
-            compile_flags,
-            0,
-            &mut vertex_shader,
-            Some(&mut vertex_error_msg),
-        );
-
-        let result_ps = D3DCompile(
-            shader_code.as_ptr() as *const c_void,
-            shader_code.as_bytes().len(),
-            None,
-            None,
-            None,
-            s!("PSMain"),
-            s!("ps_5_0"),
-            compile_flags,
-            0,
-            &mut pixel_shader,
-            Some(&mut pixel_error_msg),
-        );
-
-        if let Some(blob) = vertex_error_msg {
-            let message = std::str::from_utf8(std::slice::from_raw_parts(
-                blob.GetBufferPointer() as *const u8,
-                blob.GetBufferSize(),
-            ))
-            .unwrap_or("Failed to read error message");
-            warn!("Vertex shader compilation message: {}", message);
-        }
-        if let Err(e) = result_vs {
-            panic!("Vertex shader compilation failed: {}", e);
-        }
 
-        if let Some(blob) = pixel_error_msg {
-            let message = std::str::from_utf8(std::slice::from_raw_parts(
-                blob.GetBufferPointer() as *const u8,
-                blob.GetBufferSize(),
-            ))
-            .unwrap_or("Failed to read error message");
-            warn!("Pixel shader compilation message: {}", message);
-        }
-        if let Err(e) = result_ps {
-            panic!("Pixel shader compilation failed: {}", e);
-        }
-    }
-
-    let vertex_shader = vertex_shader.expect("Compile was successful but vertex shader is None");
-    let pixel_shader = pixel_shader.expect("Compile was successful but pixel shader is None");
-    PathTracerShaders {
-        vertex_shader,
-        pixel_shader,
-    }
-}
-
-fn create_pipeline_state(
-    gpu: &Gpu,
-    shaders: &PathTracerShaders,
-    root_signature: &ID3D12RootSignature,
-) -> ID3D12PipelineState {
-    let position_element_desc = D3D12_INPUT_ELEMENT_DESC {
-        SemanticName: s!("POSITION"),
-        SemanticIndex: 0,
-        Format: DXGI_FORMAT_R32G32B32_FLOAT,
-        InputSlot: 0,
-        AlignedByteOffset: 0,
-        InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
-        InstanceDataStepRate: 0,
+    let root_parameter_path_statistics_uav = D3D12_ROOT_PARAMETER {
+        ParameterType: D3D12_ROOT_PARAMETER_TYPE_UAV,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        Anonymous: D3D12_ROOT_PARAMETER_0 {
+            Descriptor: D3D12_ROOT_DESCRIPTOR {
+                ShaderRegister: 1,
+                RegisterSpace: 0,
+            },
+        },
     };
 
-    let uv_element_desc = D3D12_INPUT_ELEMENT_DESC {
-        SemanticName: s!("TEXCOORD"),
-        SemanticIndex: 0,
-        Format: DXGI_FORMAT_R32G32_FLOAT,
-        InputSlot: 0,
-        AlignedByteOffset: D3D12_APPEND_ALIGNED_ELEMENT,
-        InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
-        InstanceDataStepRate: 0,
+    // IDs of the scene buffers, the first texture slot, the G-buffer and the depth prepass in
+    // the bindless heap
+    let root_parameter_bindless_ids = D3D12_ROOT_PARAMETER {
+        ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+        Anonymous: D3D12_ROOT_PARAMETER_0 {
+            Constants: D3D12_ROOT_CONSTANTS {
+                ShaderRegister: 3,
+                RegisterSpace: 0,
+                Num32BitValues: 4,
+            },
+        },
     };
 
-    let input_element_descs = [position_element_desc, uv_element_desc];
-    let input_layout_desc = D3D12_INPUT_LAYOUT_DESC {
-        pInputElementDescs: input_element_descs.as_ptr(),
-        NumElements: input_element_descs.len() as u32,
+    let root_parameters = [
+        root_parameter_camera_cbv,
+        root_parameter_scene_info_cbv,
+        root_parameter_settings_cbv,
+        root_parameter_srv,
+        root_parameter_radiance_cache_uav,
+        root_parameter_path_statistics_uav,
+        root_parameter_bindless_ids,
+    ];
+    let texture_sampler = D3D12_STATIC_SAMPLER_DESC {
+        Filter: D3D12_FILTER_MIN_MAG_MIP_LINEAR,
+        AddressU: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        AddressV: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        AddressW: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        MipLODBias: 0.0,
+        MaxAnisotropy: 1,
+        ComparisonFunc: D3D12_COMPARISON_FUNC_NEVER,
+        BorderColor: D3D12_STATIC_BORDER_COLOR_OPAQUE_BLACK,
+        MinLOD: 0.0,
+        MaxLOD: D3D12_FLOAT32_MAX,
+        ShaderRegister: 0,
+        RegisterSpace: 0,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
     };
-
-    let mut pipeline_state_desc = D3D12_GRAPHICS_PIPELINE_STATE_DESC {
-        InputLayout: input_layout_desc,
-        pRootSignature: unsafe { std::mem::transmute_copy(root_signature) },
-        VS: D3D12_SHADER_BYTECODE {
-            pShaderBytecode: unsafe { shaders.vertex_shader.GetBufferPointer() },
-            BytecodeLength: unsafe { shaders.vertex_shader.GetBufferSize() },
-        },
-        PS: D3D12_SHADER_BYTECODE {
-            pShaderBytecode: unsafe { shaders.pixel_shader.GetBufferPointer() },
-            BytecodeLength: unsafe { shaders.pixel_shader.GetBufferSize() },
-        },
-        RasterizerState: D3D12_RASTERIZER_DESC {
-            FillMode: D3D12_FILL_MODE_SOLID,
-            CullMode: D3D12_CULL_MODE_NONE,
-            ..Default::default()
-        },
-        BlendState: D3D12_BLEND_DESC {
-            AlphaToCoverageEnable: false.into(),
-            IndependentBlendEnable: false.into(),
-            RenderTarget: [
-                D3D12_RENDER_TARGET_BLEND_DESC {
-                    BlendEnable: false.into(),
-                    LogicOpEnable: false.into(),
-                    SrcBlend: D3D12_BLEND_ONE,
-                    DestBlend: D3D12_BLEND_ZERO,
-                    BlendOp: D3D12_BLEND_OP_ADD,
-                    SrcBlendAlpha: D3D12_BLEND_ONE,
-                    DestBlendAlpha: D3D12_BLEND_ZERO,
-                    BlendOpAlpha: D3D12_BLEND_OP_ADD,
-                    LogicOp: D3D12_LOGIC_OP_NOOP,
-                    RenderTargetWriteMask: D3D12_COLOR_WRITE_ENABLE_ALL.0 as u8,
-                },
-                D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                D3D12_RENDER_TARGET_BLEND_DESC::default(),
-                D3D12_RENDER_TARGET_BLEND_DESC::default(),
-            ],
-        },
-        DepthStencilState: D3D12_DEPTH_STENCIL_DESC::default(),
-        SampleMask: u32::MAX,
-        PrimitiveTopologyType: D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
-        NumRenderTargets: 1,
-        SampleDesc: DXGI_SAMPLE_DESC {
-            Count: 1,
-            ..Default::default()
-        },
-        ..Default::default()
+    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
+        NumParameters: root_parameters.len() as u32,
+        pParameters: root_parameters.as_ptr(),
+        NumStaticSamplers: 1,
+        pStaticSamplers: &texture_sampler,
     };
-    pipeline_state_desc.RTVFormats[0] = DXGI_FORMAT_R8G8B8A8_UNORM;
 
-    unsafe {
-        gpu.device
-            .CreateGraphicsPipelineState(&pipeline_state_desc)
-            .expect("Failed to create pipeline state")
-    }
+    create_root_signature_from_desc(gpu, "path tracer", &root_signature_desc)
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn create_pathtracer_pipeline(
     gpu: Res<Gpu>,
     shader_handle: Res<PathTracerShaderHandle>,
+    radiance_cache_shader_handle: Res<RadianceCacheShaderHandle>,
+    depth_prepass_shader_handle: Res<DepthPrepassShaderHandle>,
+    hybrid_g_buffer_shader_handle: Res<HybridGBufferShaderHandle>,
     shaders: Res<Assets<Shader>>,
+    textures: Res<MaterialTextures>,
+    precision: Res<AccumulationPrecision>,
     mut pipelines: ResMut<PipelineStorage>,
 ) {
     if pipelines.contains_key(&PATH_TRACER_PIPELINE_ID) {
@@ -351,34 +533,77 @@ pub fn create_pathtracer_pipeline(
     }
 
     let shader_source = shaders.get(&shader_handle.0);
-    if shader_source.is_none() {
+    let radiance_cache_shader_source = shaders.get(&radiance_cache_shader_handle.0);
+    let depth_prepass_shader_source = shaders.get(&depth_prepass_shader_handle.0);
+    let hybrid_g_buffer_shader_source = shaders.get(&hybrid_g_buffer_shader_handle.0);
+    let (
+        Some(shader_source),
+        Some(radiance_cache_shader_source),
+        Some(depth_prepass_shader_source),
+        Some(hybrid_g_buffer_shader_source),
+    ) = (
+        shader_source,
+        radiance_cache_shader_source,
+        depth_prepass_shader_source,
+        hybrid_g_buffer_shader_source,
+    )
+    else {
         return;
-    }
+    };
 
-    let compiled_shaders = compile_shaders(shader_source.unwrap());
+    let compiled_shaders = compile_shaders(shader_source);
     let root_signature = create_root_signature(&gpu);
-    let state = create_pipeline_state(&gpu, &compiled_shaders, &root_signature);
+    let mut states =
+        SpecializedPipelineStates::new(compiled_shaders, &root_signature, BlendMode::Accumulate);
+    // the path tracer draws into HDR targets only, create that state up front
+    states.get(&gpu, TargetDesc::new(precision.dxgi_format(), 1));
     let vertex_buffer = VertexBuffer::fullscreen_quad(&gpu);
     let camera_constant_buffer = ConstantBuffer::<CameraData>::create(&gpu);
-    let mesh_info_constant_buffer = ConstantBuffer::<MeshInfo>::create(&gpu);
+    let scene_info_constant_buffer = ConstantBuffer::<SceneInfo>::create(&gpu);
+    let scene_info = SceneInfo::default();
+    let settings_constant_buffer = ConstantBuffer::<PathTracerSettingsData>::create(&gpu);
     let mesh_buffer = MeshBuffer::new(&gpu);
-    let mut srv_heap = DescriptorHeap::new(
+    let light_buffer = StructuredBuffer::<GpuLight>::new(&gpu, MAX_LIGHTS);
+    // local lights take at most 2n - 1 tree nodes, directional lights one node each
+    let light_tree_buffer = StructuredBuffer::<GpuLightNode>::new(&gpu, 2 * MAX_LIGHTS);
+    let primitive_buffer = StructuredBuffer::<GpuPrimitive>::new(&gpu, MAX_PRIMITIVES);
+    let srvs = gpu.bindless.allocate(SRV_COUNT);
+    mesh_buffer.write_srvs(&gpu, &srvs, 0);
+    light_buffer.write_srv(&gpu, srvs.cpu_handle(4));
+    light_tree_buffer.write_srv(&gpu, srvs.cpu_handle(5));
+    primitive_buffer.write_srv(&gpu, srvs.cpu_handle(6));
+    let depth_prepass = DepthPrepass::new(&gpu, depth_prepass_shader_source);
+    let hybrid_g_buffer = HybridGBuffer::new(
         &gpu,
-        D3D12_DESCRIPTOR_HEAP_TYPE_CBV_SRV_UAV,
-        2,
-        D3D12_DESCRIPTOR_HEAP_FLAG_SHADER_VISIBLE,
+        hybrid_g_buffer_shader_source,
+        &srvs,
+        textures.first_id(),
     );
 
-    mesh_buffer.write_to_descriptor_heap(&gpu, &mut srv_heap);
-
     let pipeline = PathTracerPipeline {
-        state,
+        states,
         root_signature,
         vertex_buffer,
         camera_constant_buffer,
-        mesh_info_constant_buffer,
+        scene_info,
+        scene_info_constant_buffer,
+        settings_constant_buffer,
         mesh_buffer,
-        srv_heap,
+        light_buffer,
+        light_tree_buffer,
+        primitive_buffer,
+        srvs,
+        first_texture: textures.first_id(),
+        debug_view: DebugView::None,
+        radiance_cache: RadianceCache::new(&gpu, radiance_cache_shader_source),
+        radiance_cache_settings: None,
+        furnace_scene: None,
+        path_statistics: PathStatisticsBuffer::new(&gpu),
+        collect_path_statistics: false,
+        depth_prepass,
+        use_depth_prepass: false,
+        hybrid_g_buffer,
+        use_hybrid: false,
     };
 
     pipelines.insert(PATH_TRACER_PIPELINE_ID, Box::new(pipeline));
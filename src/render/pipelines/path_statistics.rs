@@ -0,0 +1,202 @@
+use bevy::prelude::*;
+use windows::Win32::Graphics::{
+    Direct3D12::*,
+    Dxgi::Common::{DXGI_FORMAT_UNKNOWN, DXGI_SAMPLE_DESC},
+};
+
+use crate::render::{
+    d3d::{read_buffer, transition_barrier},
+    fence_timeout::FenceTimeout,
+    render_target::FRAME_COUNT,
+    set_debug_name, Drawer, Gpu,
+};
+
+use super::{auto_exposure::create_uav_buffer, PipelineStorage};
+
+/// Sum of the path lengths followed by one counter per termination cause, must match
+/// `PATH_STATISTICS_*` and `PATH_END_*` in `demo.hlsl`.
+const COUNTER_COUNT: usize = 6;
+const BUFFER_SIZE: u64 = (COUNTER_COUNT * std::mem::size_of::<u32>()) as u64;
+
+/// How the paths of the last frame went, collected while
+/// [`super::PathTracerSettings::path_statistics`] is on. Helps tuning
+/// [`super::PathTracerSettings::max_bounces`] for a scene, paths cut off by the limit are
+/// missing light while paths ended by Russian roulette or escaping are complete. Stays at the
+/// last collected values while collection is off.
+#[derive(Resource, Reflect, Debug, Clone, Copy, Default, PartialEq)]
+#[reflect(Resource, Default)]
+pub struct PathStatistics {
+    /// Paths traced, one per sample.
+    pub paths: u32,
+    /// Bounces after the primary hit, averaged over all paths.
+    pub average_path_length: f32,
+    /// Paths that left the scene and picked up the environment or the background.
+    pub escaped: u32,
+    /// Paths ended by hitting an area light.
+    pub emitter: u32,
+    /// Paths ended by Russian roulette once little of their throughput was left.
+    pub russian_roulette: u32,
+    /// Paths still going at [`super::PathTracerSettings::max_bounces`].
+    pub max_bounces: u32,
+    /// Paths ended by a lookup into the radiance cache.
+    pub radiance_cache: u32,
+}
+
+impl PathStatistics {
+    fn from_counters(counters: [u32; COUNTER_COUNT]) -> Self {
+        let [length_sum, escaped, emitter, russian_roulette, max_bounces, radiance_cache] =
+            counters;
+        let paths = escaped + emitter + russian_roulette + max_bounces + radiance_cache;
+        Self {
+            paths,
+            average_path_length: length_sum as f32 / paths.max(1) as f32,
+            escaped,
+            emitter,
+            russian_roulette,
+            max_bounces,
+            radiance_cache,
+        }
+    }
+}
+
+/// Counters the path tracer adds its paths to, copied to the readback buffer of the frame slot
+/// after every pass.
+///
+/// The counters are never cleared, they wrap around and the statistics of a frame are the
+/// difference to the counters read after the previous one.
+pub(super) struct PathStatisticsBuffer {
+    counters: ID3D12Resource,
+    readback_buffers: [ID3D12Resource; FRAME_COUNT],
+    previous: [u32; COUNTER_COUNT],
+    copied: [bool; FRAME_COUNT],
+}
+
+impl PathStatisticsBuffer {
+    pub(super) fn new(gpu: &Gpu) -> Self {
+        let desc = D3D12_RESOURCE_DESC {
+            Alignment: 0,
+            Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+            Width: BUFFER_SIZE,
+            Height: 1,
+            DepthOrArraySize: 1,
+            MipLevels: 1,
+            Format: DXGI_FORMAT_UNKNOWN,
+            SampleDesc: DXGI_SAMPLE_DESC {
+                Count: 1,
+                ..Default::default()
+            },
+            Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+            Flags: D3D12_RESOURCE_FLAG_NONE,
+        };
+        let readback_buffers = std::array::from_fn(|slot| {
+            let mut readback_buffer: Option<ID3D12Resource> = None;
+            unsafe {
+                gpu.device.CreateCommittedResource(
+                    &D3D12_HEAP_PROPERTIES {
+                        Type: D3D12_HEAP_TYPE_READBACK,
+                        ..Default::default()
+                    },
+                    D3D12_HEAP_FLAG_NONE,
+                    &desc,
+                    D3D12_RESOURCE_STATE_COPY_DEST,
+                    None,
+                    &mut readback_buffer,
+                )
+            }
+            .expect("Failed to create path statistics readback buffer");
+            let readback_buffer =
+                readback_buffer.expect("CreateCommittedResource was successful but buffer is None");
+            set_debug_name(
+                &readback_buffer,
+                &format!("path statistics readback buffer {slot}"),
+            );
+            readback_buffer
+        });
+
+        Self {
+            // committed resources start out zeroed, like `previous`
+            counters: create_uav_buffer(gpu, BUFFER_SIZE, "path statistics"),
+            readback_buffers,
+            previous: [0; COUNTER_COUNT],
+            copied: [false; FRAME_COUNT],
+        }
+    }
+
+    pub(super) fn gpu_address(&self) -> u64 {
+        unsafe { self.counters.GetGPUVirtualAddress() }
+    }
+
+    /// Leaves the counters ready for unordered access by the path tracer.
+    pub(super) fn begin(&self, command_list: &ID3D12GraphicsCommandList) {
+        unsafe {
+            // Buffers decay to COMMON after every ExecuteCommandLists
+            command_list.ResourceBarrier(&[transition_barrier(
+                &self.counters,
+                D3D12_RESOURCE_STATE_COMMON,
+                D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+            )]);
+        }
+    }
+
+    /// Copies the counters to the readback buffer of `frame_slot` after the path tracing pass.
+    pub(super) fn end(&mut self, command_list: &ID3D12GraphicsCommandList, frame_slot: usize) {
+        unsafe {
+            command_list.ResourceBarrier(&[transition_barrier(
+                &self.counters,
+                D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+            )]);
+            command_list.CopyBufferRegion(
+                &self.readback_buffers[frame_slot],
+                0,
+                &self.counters,
+                0,
+                BUFFER_SIZE,
+            );
+            command_list.ResourceBarrier(&[transition_barrier(
+                &self.counters,
+                D3D12_RESOURCE_STATE_COPY_SOURCE,
+                D3D12_RESOURCE_STATE_COMMON,
+            )]);
+        }
+        self.copied[frame_slot] = true;
+    }
+
+    /// Statistics of the paths traced since the last call, up to the last copy in
+    /// `frame_slot`. The frame it was recorded in must be finished on the GPU.
+    pub(super) fn read(&mut self, frame_slot: usize) -> Option<PathStatistics> {
+        if !std::mem::take(&mut self.copied[frame_slot]) {
+            return None;
+        }
+
+        let mut counters = [0; COUNTER_COUNT];
+        let readback_buffer = &self.readback_buffers[frame_slot];
+        read_buffer(readback_buffer, 0..BUFFER_SIZE as usize, |data| {
+            for (counter, bytes) in counters.iter_mut().zip(data.chunks_exact(4)) {
+                *counter = u32::from_le_bytes(bytes.try_into().unwrap());
+            }
+        });
+        let mut frame = [0; COUNTER_COUNT];
+        for ((frame, counter), previous) in frame.iter_mut().zip(counters).zip(self.previous) {
+            *frame = counter.wrapping_sub(previous);
+        }
+        self.previous = counters;
+        Some(PathStatistics::from_counters(frame))
+    }
+}
+
+/// Runs at the end of the frame, reads the statistics of the frame before it.
+pub fn read_path_statistics(
+    gpu: Res<Gpu>,
+    fence_timeout: Res<FenceTimeout>,
+    drawer: Res<Drawer>,
+    mut pipelines: ResMut<PipelineStorage>,
+    mut statistics: ResMut<PathStatistics>,
+) {
+    let frame_slot = drawer.finish_previous_frame(&gpu, *fence_timeout);
+    for pipeline in pipelines.values_mut() {
+        if let Some(read) = pipeline.read_path_statistics(frame_slot) {
+            *statistics = read;
+        }
+    }
+}
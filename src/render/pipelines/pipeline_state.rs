@@ -0,0 +1,504 @@
+use std::{
+    ffi::c_void,
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+use bevy::{prelude::*, utils::HashMap};
+use windows::{
+    core::*,
+    Win32::Graphics::{
+        Direct3D::{
+            Fxc::{D3DCompile, D3DCOMPILE_DEBUG, D3DCOMPILE_SKIP_OPTIMIZATION},
+            ID3DBlob,
+        },
+        Direct3D12::*,
+        Dxgi::Common::{
+            DXGI_FORMAT, DXGI_FORMAT_R32G32B32_FLOAT, DXGI_FORMAT_R32G32_FLOAT, DXGI_SAMPLE_DESC,
+        },
+    },
+};
+
+use super::root_bindings::RootSignature;
+use crate::{
+    core::Shader,
+    render::{
+        d3d::{blob_bytes, borrow_interface, shader_bytecode},
+        set_debug_name, Gpu, RenderSettings,
+    },
+};
+
+/// Drawn in place of a pipeline state that failed to be created: one triangle covering the
+/// target for every three vertices, in pink.
+const ERROR_SHADER: &str = r#"
+float4 VSMain(uint vertex_id : SV_VertexID) : SV_POSITION
+{
+    float2 uv = float2(((vertex_id % 3) << 1) & 2, (vertex_id % 3) & 2);
+    // nearest depth, so it passes the reverse Z test
+    return float4(uv * float2(2.0f, -2.0f) + float2(-1.0f, 1.0f), 1.0f, 1.0f);
+}
+
+float4 PSMain() : SV_TARGET
+{
+    return float4(1.0f, 0.0f, 1.0f, 1.0f);
+}
+"#;
+
+/// Bumped by [`retry_failed_pipeline_states`], pipeline states that failed in an earlier
+/// generation are created again.
+static RETRY_GENERATION: AtomicU32 = AtomicU32::new(0);
+
+/// How a graphics pipeline writes to its render target.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum BlendMode {
+    /// Overwrites the target.
+    Opaque,
+    /// Lerps from the target to the output by the blend factor set on the command list.
+    Accumulate,
+}
+
+/// Vertex inputs of a graphics pipeline.
+#[derive(Debug, Clone, Copy)]
+pub(super) enum VertexLayout {
+    /// Interleaved positions and uvs of the fullscreen quad.
+    FullscreenQuad,
+    /// Positions in slot 0 and uvs in slot 1, the vertex buffers of
+    /// [`crate::render::MeshBuffer`].
+    Mesh,
+    /// Interleaved positions and colors of line list vertices.
+    Lines,
+}
+
+pub(super) struct CompiledShaders {
+    vertex_shader: ID3DBlob,
+    pixel_shader: ID3DBlob,
+}
+
+/// Render target a graphics pipeline state is created for.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TargetDesc {
+    pub format: DXGI_FORMAT,
+    pub sample_count: u32,
+}
+
+impl TargetDesc {
+    pub fn new(format: DXGI_FORMAT, sample_count: u32) -> Self {
+        Self {
+            format,
+            sample_count,
+        }
+    }
+}
+
+impl Hash for TargetDesc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.format.0.hash(state);
+        self.sample_count.hash(state);
+    }
+}
+
+/// Pipeline states of one set of shaders, created the first time a [`TargetDesc`] is drawn to.
+pub(super) struct SpecializedPipelineStates {
+    shaders: CompiledShaders,
+    root_signature: ID3D12RootSignature,
+    // of the root signature, names the pipeline states
+    name: &'static str,
+    blend_mode: BlendMode,
+    vertex_layout: VertexLayout,
+    // depth tested and written when set
+    depth_format: Option<DXGI_FORMAT>,
+    // render targets after the one of the TargetDesc
+    additional_formats: Vec<DXGI_FORMAT>,
+    states: HashMap<TargetDesc, ID3D12PipelineState>,
+    // targets whose state in `states` is the error pipeline, with the retry generation it
+    // failed in
+    failed: HashMap<TargetDesc, u32>,
+}
+
+impl SpecializedPipelineStates {
+    pub(super) fn new(
+        shaders: CompiledShaders,
+        root_signature: &RootSignature,
+        blend_mode: BlendMode,
+    ) -> Self {
+        Self {
+            shaders,
+            root_signature: root_signature.signature().clone(),
+            name: root_signature.name(),
+            blend_mode,
+            vertex_layout: VertexLayout::FullscreenQuad,
+            depth_format: None,
+            additional_formats: Vec::new(),
+            states: HashMap::new(),
+            failed: HashMap::new(),
+        }
+    }
+
+    pub(super) fn with_vertex_layout(mut self, vertex_layout: VertexLayout) -> Self {
+        self.vertex_layout = vertex_layout;
+        self
+    }
+
+    /// Tests and writes depth in a buffer of `format`, nearer fragments have greater depth.
+    pub(super) fn with_depth(mut self, format: DXGI_FORMAT) -> Self {
+        self.depth_format = Some(format);
+        self
+    }
+
+    /// Also draws into render targets of `formats`, bound after the target of the
+    /// [`TargetDesc`]. They share its sample count and blend mode.
+    pub(super) fn with_additional_targets(mut self, formats: &[DXGI_FORMAT]) -> Self {
+        self.additional_formats = formats.to_vec();
+        self
+    }
+
+    /// Falls back to a pipeline state drawing pink if the one of the shaders can't be created,
+    /// which is created again after [`retry_failed_pipeline_states`] runs.
+    pub(super) fn get(&mut self, gpu: &Gpu, target: TargetDesc) -> &ID3D12PipelineState {
+        let generation = RETRY_GENERATION.load(Ordering::Relaxed);
+        if self
+            .failed
+            .get(&target)
+            .is_some_and(|failed| *failed != generation)
+        {
+            self.failed.remove(&target);
+            // the frames in flight may still draw with the error pipeline
+            if let Some(state) = self.states.remove(&target) {
+                gpu.retired.retire(state);
+            }
+        }
+
+        if !self.states.contains_key(&target) {
+            let state = create_pipeline_state(
+                gpu,
+                &self.shaders,
+                &self.root_signature,
+                target,
+                self.blend_mode,
+                self.vertex_layout,
+                self.depth_format,
+                &self.additional_formats,
+            )
+            .unwrap_or_else(|_| {
+                self.failed.insert(target, generation);
+                create_pipeline_state(
+                    gpu,
+                    &compile_shaders(&Shader::from_source(ERROR_SHADER)),
+                    &self.root_signature,
+                    target,
+                    BlendMode::Opaque,
+                    self.vertex_layout,
+                    self.depth_format,
+                    &self.additional_formats,
+                )
+                .expect("Failed to create the error pipeline state")
+            });
+            set_debug_name(
+                &state,
+                &format!(
+                    "{} pipeline state for {:?} x{}",
+                    self.name, target.format, target.sample_count
+                ),
+            );
+            self.states.insert(target, state);
+        }
+        &self.states[&target]
+    }
+}
+
+/// Retries creating the pipeline states that fell back to the error pipeline once shaders or
+/// render settings change.
+pub fn retry_failed_pipeline_states(
+    mut shader_events: EventReader<AssetEvent<Shader>>,
+    mut settings_events: EventReader<AssetEvent<RenderSettings>>,
+) {
+    let shaders_changed = shader_events.read().count() > 0;
+    let settings_changed = settings_events.read().count() > 0;
+    if shaders_changed || settings_changed {
+        RETRY_GENERATION.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// `name` identifies the pipeline in the errors of [`super::root_bindings::RootBindings`].
+pub(super) fn create_root_signature_from_desc(
+    gpu: &Gpu,
+    name: &'static str,
+    root_signature_desc: &D3D12_ROOT_SIGNATURE_DESC,
+) -> RootSignature {
+    let mut signature: Option<ID3DBlob> = None;
+    let mut error: Option<ID3DBlob> = None;
+
+    unsafe {
+        let result = D3D12SerializeRootSignature(
+            root_signature_desc,
+            D3D_ROOT_SIGNATURE_VERSION_1,
+            &mut signature,
+            Some(&mut error),
+        );
+        match result {
+            Ok(_) => {}
+            Err(e) => {
+                panic!(
+                    "Failed to serialize root signature: error: {:?}, more error {:?}",
+                    error, e
+                );
+            }
+        }
+    };
+    let signature =
+        signature.expect("D3D12SerializeRootSignature was successful but signature is None");
+    let root_signature = unsafe {
+        gpu.device
+            .CreateRootSignature(0, blob_bytes(&signature))
+            .expect("Failed to create root signature")
+    };
+    set_debug_name(&root_signature, &format!("{name} root signature"));
+    RootSignature::new(root_signature, name, root_signature_desc)
+}
+
+pub(super) fn compile_shaders(shader_source: &Shader) -> CompiledShaders {
+    let mut vertex_shader: Option<ID3DBlob> = None;
+    let mut pixel_shader: Option<ID3DBlob> = None;
+    let mut vertex_error_msg: Option<ID3DBlob> = None;
+    let mut pixel_error_msg: Option<ID3DBlob> = None;
+
+    let compile_flags = if cfg!(debug_assertions) {
+        D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION
+    } else {
+        0
+    };
+    let shader_code = shader_source.pcstr();
+    unsafe {
+        let result_vs = D3DCompile(
+            shader_code.as_ptr() as *const c_void,
+            shader_code.as_bytes().len(),
+            None,
+            None,
+            None,
+            s!("VSMain"),
+            s!("vs_5_1"),
+            compile_flags,
+            0,
+            &mut vertex_shader,
+            Some(&mut vertex_error_msg),
+        );
+
+        let result_ps = D3DCompile(
+            shader_code.as_ptr() as *const c_void,
+            shader_code.as_bytes().len(),
+            None,
+            None,
+            None,
+            s!("PSMain"),
+            s!("ps_5_1"),
+            compile_flags,
+            0,
+            &mut pixel_shader,
+            Some(&mut pixel_error_msg),
+        );
+
+        if let Some(blob) = vertex_error_msg {
+            let message =
+                std::str::from_utf8(blob_bytes(&blob)).unwrap_or("Failed to read error message");
+            warn!("Vertex shader compilation message: {}", message);
+        }
+        if let Err(e) = result_vs {
+            panic!("Vertex shader compilation failed: {}", e);
+        }
+
+        if let Some(blob) = pixel_error_msg {
+            let message =
+                std::str::from_utf8(blob_bytes(&blob)).unwrap_or("Failed to read error message");
+            warn!("Pixel shader compilation message: {}", message);
+        }
+        if let Err(e) = result_ps {
+            panic!("Pixel shader compilation failed: {}", e);
+        }
+    }
+
+    let vertex_shader = vertex_shader.expect("Compile was successful but vertex shader is None");
+    let pixel_shader = pixel_shader.expect("Compile was successful but pixel shader is None");
+    CompiledShaders {
+        vertex_shader,
+        pixel_shader,
+    }
+}
+
+pub(super) fn compile_compute_shader(shader_source: &Shader, entry_point: PCSTR) -> ID3DBlob {
+    let mut compute_shader: Option<ID3DBlob> = None;
+    let mut error_msg: Option<ID3DBlob> = None;
+
+    let compile_flags = if cfg!(debug_assertions) {
+        D3DCOMPILE_DEBUG | D3DCOMPILE_SKIP_OPTIMIZATION
+    } else {
+        0
+    };
+    let shader_code = shader_source.pcstr();
+    unsafe {
+        let result = D3DCompile(
+            shader_code.as_ptr() as *const c_void,
+            shader_code.as_bytes().len(),
+            None,
+            None,
+            None,
+            entry_point,
+            s!("cs_5_0"),
+            compile_flags,
+            0,
+            &mut compute_shader,
+            Some(&mut error_msg),
+        );
+
+        if let Some(blob) = error_msg {
+            let message =
+                std::str::from_utf8(blob_bytes(&blob)).unwrap_or("Failed to read error message");
+            warn!("Compute shader compilation message: {}", message);
+        }
+        if let Err(e) = result {
+            panic!("Compute shader compilation failed: {}", e);
+        }
+    }
+
+    compute_shader.expect("Compile was successful but compute shader is None")
+}
+
+#[track_caller]
+pub(super) fn create_compute_pipeline_state(
+    gpu: &Gpu,
+    compute_shader: &ID3DBlob,
+    root_signature: &RootSignature,
+) -> ID3D12PipelineState {
+    let pipeline_state_desc = D3D12_COMPUTE_PIPELINE_STATE_DESC {
+        pRootSignature: borrow_interface(root_signature.signature()),
+        CS: shader_bytecode(compute_shader),
+        ..Default::default()
+    };
+
+    let state: ID3D12PipelineState = unsafe {
+        gpu.device
+            .CreateComputePipelineState(&pipeline_state_desc)
+            .expect("Failed to create compute pipeline state")
+    };
+    set_debug_name(
+        &state,
+        &format!("{} compute pipeline state", root_signature.name()),
+    );
+    state
+}
+
+#[allow(clippy::too_many_arguments)]
+fn create_pipeline_state(
+    gpu: &Gpu,
+    shaders: &CompiledShaders,
+    root_signature: &ID3D12RootSignature,
+    target: TargetDesc,
+    blend_mode: BlendMode,
+    vertex_layout: VertexLayout,
+    depth_format: Option<DXGI_FORMAT>,
+    additional_formats: &[DXGI_FORMAT],
+) -> windows::core::Result<ID3D12PipelineState> {
+    let (blend_enable, src_blend, dest_blend) = match blend_mode {
+        BlendMode::Opaque => (false, D3D12_BLEND_ONE, D3D12_BLEND_ZERO),
+        BlendMode::Accumulate => (true, D3D12_BLEND_BLEND_FACTOR, D3D12_BLEND_INV_BLEND_FACTOR),
+    };
+
+    let position_element_desc = D3D12_INPUT_ELEMENT_DESC {
+        SemanticName: s!("POSITION"),
+        SemanticIndex: 0,
+        Format: DXGI_FORMAT_R32G32B32_FLOAT,
+        InputSlot: 0,
+        AlignedByteOffset: 0,
+        InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+        InstanceDataStepRate: 0,
+    };
+
+    let (semantic_name, format, slot) = match vertex_layout {
+        VertexLayout::FullscreenQuad => (s!("TEXCOORD"), DXGI_FORMAT_R32G32_FLOAT, 0),
+        VertexLayout::Mesh => (s!("TEXCOORD"), DXGI_FORMAT_R32G32_FLOAT, 1),
+        VertexLayout::Lines => (s!("COLOR"), DXGI_FORMAT_R32G32B32_FLOAT, 0),
+    };
+    let attribute_element_desc = D3D12_INPUT_ELEMENT_DESC {
+        SemanticName: semantic_name,
+        SemanticIndex: 0,
+        Format: format,
+        InputSlot: slot,
+        AlignedByteOffset: D3D12_APPEND_ALIGNED_ELEMENT,
+        InputSlotClass: D3D12_INPUT_CLASSIFICATION_PER_VERTEX_DATA,
+        InstanceDataStepRate: 0,
+    };
+    let topology_type = match vertex_layout {
+        VertexLayout::FullscreenQuad | VertexLayout::Mesh => D3D12_PRIMITIVE_TOPOLOGY_TYPE_TRIANGLE,
+        VertexLayout::Lines => D3D12_PRIMITIVE_TOPOLOGY_TYPE_LINE,
+    };
+
+    let input_element_descs = [position_element_desc, attribute_element_desc];
+    let input_layout_desc = D3D12_INPUT_LAYOUT_DESC {
+        pInputElementDescs: input_element_descs.as_ptr(),
+        NumElements: input_element_descs.len() as u32,
+    };
+
+    let mut pipeline_state_desc = D3D12_GRAPHICS_PIPELINE_STATE_DESC {
+        InputLayout: input_layout_desc,
+        pRootSignature: borrow_interface(root_signature),
+        VS: shader_bytecode(&shaders.vertex_shader),
+        PS: shader_bytecode(&shaders.pixel_shader),
+        RasterizerState: D3D12_RASTERIZER_DESC {
+            FillMode: D3D12_FILL_MODE_SOLID,
+            CullMode: D3D12_CULL_MODE_NONE,
+            ..Default::default()
+        },
+        BlendState: D3D12_BLEND_DESC {
+            AlphaToCoverageEnable: false.into(),
+            IndependentBlendEnable: false.into(),
+            RenderTarget: [
+                D3D12_RENDER_TARGET_BLEND_DESC {
+                    BlendEnable: blend_enable.into(),
+                    LogicOpEnable: false.into(),
+                    SrcBlend: src_blend,
+                    DestBlend: dest_blend,
+                    BlendOp: D3D12_BLEND_OP_ADD,
+                    SrcBlendAlpha: src_blend,
+                    DestBlendAlpha: dest_blend,
+                    BlendOpAlpha: D3D12_BLEND_OP_ADD,
+                    LogicOp: D3D12_LOGIC_OP_NOOP,
+                    RenderTargetWriteMask: D3D12_COLOR_WRITE_ENABLE_ALL.0 as u8,
+                },
+                D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                D3D12_RENDER_TARGET_BLEND_DESC::default(),
+                D3D12_RENDER_TARGET_BLEND_DESC::default(),
+            ],
+        },
+        DepthStencilState: D3D12_DEPTH_STENCIL_DESC {
+            DepthEnable: depth_format.is_some().into(),
+            DepthWriteMask: D3D12_DEPTH_WRITE_MASK_ALL,
+            DepthFunc: D3D12_COMPARISON_FUNC_GREATER,
+            ..Default::default()
+        },
+        DSVFormat: depth_format.unwrap_or_default(),
+        SampleMask: u32::MAX,
+        PrimitiveTopologyType: topology_type,
+        NumRenderTargets: 1 + additional_formats.len() as u32,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: target.sample_count,
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    pipeline_state_desc.RTVFormats[0] = target.format;
+    pipeline_state_desc.RTVFormats[1..=additional_formats.len()]
+        .copy_from_slice(additional_formats);
+
+    let result = unsafe { gpu.device.CreateGraphicsPipelineState(&pipeline_state_desc) };
+    if let Err(error) = &result {
+        error!(
+            "Failed to create pipeline state, drawing pink until shaders or render settings \
+             change: {error}\n{target:?}, {blend_mode:?}, {vertex_layout:?}\n\
+             {pipeline_state_desc:#?}"
+        );
+    }
+    result
+}
@@ -0,0 +1,158 @@
+use bevy::prelude::*;
+use windows::{core::s, Win32::Graphics::Direct3D12::*};
+
+use crate::{
+    core::Shader,
+    render::{
+        constant_buffer::ConstantBuffer,
+        d3d::{transition_barrier, uav_barrier},
+        Gpu,
+    },
+};
+
+use super::{
+    auto_exposure::create_uav_buffer,
+    pipeline_state::{
+        compile_compute_shader, create_compute_pipeline_state, create_root_signature_from_desc,
+    },
+    root_bindings::{RootBindings, RootSignature},
+};
+
+/// Cells of the hash grid, a power of two. Must match `RADIANCE_CACHE_ENTRIES` in the shaders.
+const ENTRY_COUNT: u32 = 1 << 18;
+/// Bytes per cell, must match `RADIANCE_CACHE_STRIDE` in the shaders.
+const ENTRY_SIZE: u32 = 48;
+const GROUP_SIZE: u32 = 64;
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct ResolveData {
+    frame: u32,
+    reset: u32,
+    __padding: [u32; 2],
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct RadianceCacheShaderHandle(pub Handle<Shader>);
+
+/// World space hash grid of the radiance leaving surfaces, see
+/// [`super::PathTracerSettings::radiance_cache`].
+///
+/// The path tracer adds the radiance its paths measure to the cells they pass and reads cells
+/// back to end paths early. Before every path tracing pass a compute pass blends the samples
+/// of the previous pass into the cells and evicts cells nothing used for a while.
+pub(super) struct RadianceCache {
+    root_signature: RootSignature,
+    resolve_state: ID3D12PipelineState,
+    resolve_constant_buffer: ConstantBuffer<ResolveData>,
+    buffer: ID3D12Resource,
+    frame: u32,
+    needs_reset: bool,
+}
+
+impl RadianceCache {
+    pub(super) fn new(gpu: &Gpu, shader_source: &Shader) -> Self {
+        let root_signature = create_root_signature(gpu);
+        let resolve_shader = compile_compute_shader(shader_source, s!("CSResolve"));
+        Self {
+            resolve_state: create_compute_pipeline_state(gpu, &resolve_shader, &root_signature),
+            root_signature,
+            resolve_constant_buffer: ConstantBuffer::create(gpu),
+            buffer: create_uav_buffer(
+                gpu,
+                ENTRY_COUNT as u64 * ENTRY_SIZE as u64,
+                "radiance cache",
+            ),
+            frame: 0,
+            needs_reset: true,
+        }
+    }
+
+    /// Drops every cell before the next pass, once the scene changed.
+    pub(super) fn reset(&mut self) {
+        self.needs_reset = true;
+    }
+
+    pub(super) fn gpu_address(&self) -> u64 {
+        unsafe { self.buffer.GetGPUVirtualAddress() }
+    }
+
+    /// Advances the frame counter and returns it, cells remember the last frame that used
+    /// them.
+    pub(super) fn next_frame(&mut self) -> u32 {
+        self.frame = self.frame.wrapping_add(1);
+        self.frame
+    }
+
+    /// Records the resolve pass and leaves the cache ready for unordered access by the path
+    /// tracer. Compute state is changed, graphics state is left alone.
+    pub(super) fn begin(&mut self, command_list: &ID3D12GraphicsCommandList) {
+        self.resolve_constant_buffer.write(&ResolveData {
+            frame: self.frame,
+            reset: self.needs_reset as u32,
+            __padding: [0; 2],
+        });
+        self.needs_reset = false;
+
+        unsafe {
+            // Buffers decay to COMMON after every ExecuteCommandLists
+            command_list.ResourceBarrier(&[transition_barrier(
+                &self.buffer,
+                D3D12_RESOURCE_STATE_COMMON,
+                D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+            )]);
+            let mut bindings = RootBindings::compute(command_list, &self.root_signature);
+            bindings.cbv(0, self.resolve_constant_buffer.gpu_adress());
+            bindings.uav(1, self.gpu_address());
+            bindings.check_complete();
+            command_list.SetPipelineState(&self.resolve_state);
+            command_list.Dispatch(ENTRY_COUNT / GROUP_SIZE, 1, 1);
+            command_list.ResourceBarrier(&[uav_barrier(&self.buffer)]);
+        }
+    }
+
+    /// Returns the cache to the state it decays to, after the path tracing pass.
+    pub(super) fn end(&self, command_list: &ID3D12GraphicsCommandList) {
+        unsafe {
+            command_list.ResourceBarrier(&[transition_barrier(
+                &self.buffer,
+                D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+                D3D12_RESOURCE_STATE_COMMON,
+            )]);
+        }
+    }
+}
+
+fn create_root_signature(gpu: &Gpu) -> RootSignature {
+    let root_parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_UAV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+    ];
+    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_NONE,
+        NumParameters: root_parameters.len() as u32,
+        pParameters: root_parameters.as_ptr(),
+        NumStaticSamplers: 0,
+        pStaticSamplers: std::ptr::null(),
+    };
+
+    create_root_signature_from_desc(gpu, "radiance cache", &root_signature_desc)
+}
@@ -0,0 +1,276 @@
+use bevy::prelude::*;
+use windows::Win32::Graphics::{Direct3D::D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST, Direct3D12::*};
+
+use crate::{
+    core::{Background, Camera, Shader},
+    render::{
+        bindless::bindless_ranges,
+        constant_buffer::ConstantBuffer,
+        furnace::FurnaceScene,
+        light_data::{GpuLight, MAX_LIGHTS},
+        material_textures::MaterialTextures,
+        mesh_data::MeshBuffer,
+        render_target::{AccumulationPrecision, DEPTH_FORMAT},
+        structured_buffer::StructuredBuffer,
+        upload::{UploadPriority, UploadQueue},
+        BindlessDescriptors, Gpu, LightData, MeshData, PrimitiveData,
+    },
+};
+
+use super::{
+    pipeline_state::{
+        compile_shaders, create_root_signature_from_desc, BlendMode, SpecializedPipelineStates,
+        TargetDesc, VertexLayout,
+    },
+    root_bindings::{RootBindings, RootSignature},
+    view_projection, DebugView, PathStatistics, PathTracerSettings, Pipeline, PipelineStorage,
+    SceneTarget, RASTER_FORWARD_PIPELINE_ID,
+};
+
+// bindless IDs of the vertices, indices, materials, uvs and lights
+const SRV_COUNT: usize = 5;
+
+/// Radiance of the environment, must match `GetEnvironmentLight` in `demo.hlsl`.
+const ENVIRONMENT_COLOR: [f32; 4] = [0.2, 0.3, 0.3, 1.0];
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct FrameData {
+    view_projection: [[f32; 4]; 4],
+    camera_position: [f32; 3],
+    light_count: u32,
+    debug_view: u32,
+    __padding: [u32; 3],
+}
+
+/// Draws the meshes through the rasterizer, see [`super::ScenePipeline::RasterForward`].
+pub struct RasterForwardPipeline {
+    root_signature: RootSignature,
+    states: SpecializedPipelineStates,
+    frame_constant_buffer: ConstantBuffer<FrameData>,
+    mesh_buffer: MeshBuffer,
+    index_count: u32,
+    light_buffer: StructuredBuffer<GpuLight>,
+    light_count: u32,
+    srvs: BindlessDescriptors,
+    first_texture: u32,
+    clear_color: [f32; 4],
+    debug_view: DebugView,
+}
+
+impl Pipeline for RasterForwardPipeline {
+    fn populate_command_list(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &mut ID3D12GraphicsCommandList,
+        target: &SceneTarget,
+    ) {
+        let state = self.states.get(gpu, target.desc);
+        unsafe {
+            command_list.OMSetRenderTargets(
+                1,
+                Some(&target.rtv_handle),
+                false,
+                Some(&target.dsv_handle),
+            );
+            // every frame is complete on its own, there is nothing to accumulate
+            command_list.ClearRenderTargetView(target.rtv_handle, &self.clear_color, None);
+            command_list.ClearDepthStencilView(
+                target.dsv_handle,
+                D3D12_CLEAR_FLAG_DEPTH,
+                0.0,
+                0,
+                &[],
+            );
+            if self.index_count == 0 {
+                return;
+            }
+
+            command_list.SetPipelineState(state);
+            command_list.SetDescriptorHeaps(&[Some(gpu.bindless.heap().heap())]);
+            let mut bindings = RootBindings::graphics(command_list, &self.root_signature);
+            bindings.cbv(0, self.frame_constant_buffer.gpu_adress());
+            bindings.table(1, gpu.bindless.heap());
+            bindings.constants(2, &[self.srvs.first_id(), self.first_texture], 0);
+            bindings.check_complete();
+
+            command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            command_list.IASetVertexBuffers(0, Some(&self.mesh_buffer.vertex_buffer_views()));
+            command_list.IASetIndexBuffer(Some(&self.mesh_buffer.index_buffer_view()));
+            command_list.DrawIndexedInstanced(self.index_count, 1, 0, 0, 0);
+        }
+    }
+
+    fn write_frame_data(
+        &mut self,
+        transform: &GlobalTransform,
+        camera: &Camera,
+        background: &Background,
+        _settings: &PathTracerSettings,
+        _frame_index: u32,
+        view_rect: Rect,
+    ) {
+        self.frame_constant_buffer.write(&FrameData {
+            view_projection: view_projection(transform, camera, view_rect).to_cols_array_2d(),
+            camera_position: transform.translation().to_array(),
+            light_count: self.light_count,
+            debug_view: self.debug_view.shader_index(),
+            __padding: [0; 3],
+        });
+
+        self.clear_color = match (self.debug_view, background) {
+            // far away
+            (DebugView::Depth, _) => [1.0; 4],
+            (DebugView::Normals | DebugView::Uvs, _) => [0.0, 0.0, 0.0, 1.0],
+            (_, Background::Environment) => ENVIRONMENT_COLOR,
+            (_, Background::Color(color)) => color.to_linear().with_alpha(1.0).to_f32_array(),
+            (_, Background::Transparent) => [0.0; 4],
+        };
+    }
+
+    fn set_mesh_data(&mut self, data: &MeshData, uploads: &mut UploadQueue) {
+        self.mesh_buffer.set_new_data(data, uploads);
+        self.index_count = data.vertex_count() as u32;
+    }
+
+    fn set_light_data(&mut self, data: &LightData, uploads: &mut UploadQueue) {
+        let range = data.dirty_range();
+        if !range.is_empty() {
+            self.light_buffer
+                .write_range(range.start, &data.lights()[range.clone()]);
+            self.light_buffer
+                .upload_range(uploads, UploadPriority::High, range);
+        }
+        self.light_count = data.light_count() as u32;
+    }
+
+    fn set_primitive_data(&mut self, _data: &PrimitiveData, _uploads: &mut UploadQueue) {
+        // analytic primitives aren't rasterized
+    }
+
+    fn set_debug_view(&mut self, debug_view: DebugView) {
+        self.debug_view = debug_view;
+    }
+
+    fn set_furnace_scene(&mut self, _scene: Option<&FurnaceScene>) {
+        // the furnace test measures the path tracer only
+    }
+
+    fn read_path_statistics(&mut self, _frame_slot: usize) -> Option<PathStatistics> {
+        None
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct RasterForwardShaderHandle(pub Handle<Shader>);
+
+/// b0 is the frame data, the table the bindless heap and b1 the IDs of the scene buffers and
+/// the first texture slot in it.
+fn create_root_signature(gpu: &Gpu) -> RootSignature {
+    let ranges = bindless_ranges();
+
+    let root_parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: ranges.len() as u32,
+                    pDescriptorRanges: ranges.as_ptr(),
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                    Num32BitValues: 2,
+                },
+            },
+        },
+    ];
+    let texture_sampler = D3D12_STATIC_SAMPLER_DESC {
+        Filter: D3D12_FILTER_MIN_MAG_MIP_LINEAR,
+        AddressU: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        AddressV: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        AddressW: D3D12_TEXTURE_ADDRESS_MODE_WRAP,
+        MipLODBias: 0.0,
+        MaxAnisotropy: 1,
+        ComparisonFunc: D3D12_COMPARISON_FUNC_NEVER,
+        BorderColor: D3D12_STATIC_BORDER_COLOR_OPAQUE_BLACK,
+        MinLOD: 0.0,
+        MaxLOD: D3D12_FLOAT32_MAX,
+        ShaderRegister: 0,
+        RegisterSpace: 0,
+        ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+    };
+    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
+        NumParameters: root_parameters.len() as u32,
+        pParameters: root_parameters.as_ptr(),
+        NumStaticSamplers: 1,
+        pStaticSamplers: &texture_sampler,
+    };
+
+    create_root_signature_from_desc(gpu, "raster forward", &root_signature_desc)
+}
+
+pub fn create_raster_forward_pipeline(
+    gpu: Res<Gpu>,
+    shader_handle: Res<RasterForwardShaderHandle>,
+    shaders: Res<Assets<Shader>>,
+    textures: Res<MaterialTextures>,
+    precision: Res<AccumulationPrecision>,
+    mut pipelines: ResMut<PipelineStorage>,
+) {
+    if pipelines.contains_key(&RASTER_FORWARD_PIPELINE_ID) {
+        return;
+    }
+    let Some(shader_source) = shaders.get(&shader_handle.0) else {
+        return;
+    };
+
+    let root_signature = create_root_signature(&gpu);
+    let mut states = SpecializedPipelineStates::new(
+        compile_shaders(shader_source),
+        &root_signature,
+        BlendMode::Opaque,
+    )
+    .with_vertex_layout(VertexLayout::Mesh)
+    .with_depth(DEPTH_FORMAT);
+    states.get(&gpu, TargetDesc::new(precision.dxgi_format(), 1));
+    let mesh_buffer = MeshBuffer::new(&gpu);
+    let light_buffer = StructuredBuffer::<GpuLight>::new(&gpu, MAX_LIGHTS);
+    let srvs = gpu.bindless.allocate(SRV_COUNT);
+    mesh_buffer.write_srvs(&gpu, &srvs, 0);
+    light_buffer.write_srv(&gpu, srvs.cpu_handle(4));
+
+    let pipeline = RasterForwardPipeline {
+        root_signature,
+        states,
+        frame_constant_buffer: ConstantBuffer::create(&gpu),
+        mesh_buffer,
+        index_count: 0,
+        light_buffer,
+        light_count: 0,
+        srvs,
+        first_texture: textures.first_id(),
+        clear_color: ENVIRONMENT_COLOR,
+        debug_view: DebugView::None,
+    };
+
+    pipelines.insert(RASTER_FORWARD_PIPELINE_ID, Box::new(pipeline));
+}
@@ -0,0 +1,323 @@
+use windows::Win32::Graphics::Direct3D12::*;
+
+use crate::render::{DescriptorHeap, TransientDescriptors};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ParameterKind {
+    Cbv,
+    Srv,
+    Uav,
+    Constants { count: u32 },
+    Table { descriptors: u32 },
+}
+
+#[derive(Debug, Clone)]
+struct RootParameter {
+    kind: ParameterKind,
+    // registers the parameter covers, for error messages
+    description: String,
+}
+
+/// Root signature of a pipeline with the layout of its parameters, which [`RootBindings`] checks
+/// the bound arguments against.
+#[derive(Clone)]
+pub(super) struct RootSignature {
+    signature: ID3D12RootSignature,
+    name: &'static str,
+    parameters: Vec<RootParameter>,
+}
+
+impl RootSignature {
+    /// `desc` must be the description `signature` was created from.
+    pub(super) fn new(
+        signature: ID3D12RootSignature,
+        name: &'static str,
+        desc: &D3D12_ROOT_SIGNATURE_DESC,
+    ) -> Self {
+        let parameters = if desc.NumParameters == 0 {
+            &[]
+        } else {
+            unsafe { std::slice::from_raw_parts(desc.pParameters, desc.NumParameters as usize) }
+        };
+        Self {
+            signature,
+            name,
+            parameters: parameters.iter().map(root_parameter).collect(),
+        }
+    }
+
+    pub(super) fn signature(&self) -> &ID3D12RootSignature {
+        &self.signature
+    }
+
+    pub(super) fn name(&self) -> &'static str {
+        self.name
+    }
+}
+
+fn root_parameter(parameter: &D3D12_ROOT_PARAMETER) -> RootParameter {
+    let descriptor = |register: char| unsafe {
+        let descriptor = parameter.Anonymous.Descriptor;
+        format!(
+            "{register}{} space {}",
+            descriptor.ShaderRegister, descriptor.RegisterSpace
+        )
+    };
+    match parameter.ParameterType {
+        D3D12_ROOT_PARAMETER_TYPE_CBV => RootParameter {
+            kind: ParameterKind::Cbv,
+            description: format!("a CBV at {}", descriptor('b')),
+        },
+        D3D12_ROOT_PARAMETER_TYPE_SRV => RootParameter {
+            kind: ParameterKind::Srv,
+            description: format!("a SRV at {}", descriptor('t')),
+        },
+        D3D12_ROOT_PARAMETER_TYPE_UAV => RootParameter {
+            kind: ParameterKind::Uav,
+            description: format!("a UAV at {}", descriptor('u')),
+        },
+        D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS => {
+            let constants = unsafe { parameter.Anonymous.Constants };
+            RootParameter {
+                kind: ParameterKind::Constants {
+                    count: constants.Num32BitValues,
+                },
+                description: format!(
+                    "{} constants at b{} space {}",
+                    constants.Num32BitValues, constants.ShaderRegister, constants.RegisterSpace
+                ),
+            }
+        }
+        _ => {
+            let table = unsafe { parameter.Anonymous.DescriptorTable };
+            let ranges = if table.NumDescriptorRanges == 0 {
+                &[]
+            } else {
+                unsafe {
+                    std::slice::from_raw_parts(
+                        table.pDescriptorRanges,
+                        table.NumDescriptorRanges as usize,
+                    )
+                }
+            };
+            let mut descriptors = 0;
+            let mut next_offset = 0;
+            let mut registers = Vec::new();
+            for range in ranges {
+                let offset = if range.OffsetInDescriptorsFromTableStart
+                    == D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND
+                {
+                    next_offset
+                } else {
+                    range.OffsetInDescriptorsFromTableStart
+                };
+                // unbounded ranges take whatever the heap has
+                let count = if range.NumDescriptors == u32::MAX {
+                    0
+                } else {
+                    range.NumDescriptors
+                };
+                next_offset = offset + count;
+                descriptors = descriptors.max(next_offset);
+                let register = match range.RangeType {
+                    D3D12_DESCRIPTOR_RANGE_TYPE_CBV => 'b',
+                    D3D12_DESCRIPTOR_RANGE_TYPE_UAV => 'u',
+                    D3D12_DESCRIPTOR_RANGE_TYPE_SAMPLER => 's',
+                    _ => 't',
+                };
+                registers.push(format!(
+                    "{count} from {register}{} space {}",
+                    range.BaseShaderRegister, range.RegisterSpace
+                ));
+            }
+            RootParameter {
+                kind: ParameterKind::Table { descriptors },
+                description: format!("a table of {}", registers.join(", ")),
+            }
+        }
+    }
+}
+
+/// Sets the root signature and root arguments of a draw or dispatch.
+///
+/// In debug builds every argument is checked against the layout of the root signature, and
+/// [`RootBindings::check_complete`] checks that no parameter was left unbound. Mismatches panic
+/// with the parameter and what the root signature expects, instead of the GPU reading garbage.
+pub(super) struct RootBindings<'a> {
+    command_list: &'a ID3D12GraphicsCommandList,
+    root_signature: &'a RootSignature,
+    compute: bool,
+    bound: Vec<bool>,
+}
+
+impl<'a> RootBindings<'a> {
+    pub(super) fn graphics(
+        command_list: &'a ID3D12GraphicsCommandList,
+        root_signature: &'a RootSignature,
+    ) -> Self {
+        unsafe { command_list.SetGraphicsRootSignature(&root_signature.signature) };
+        Self::new(command_list, root_signature, false)
+    }
+
+    pub(super) fn compute(
+        command_list: &'a ID3D12GraphicsCommandList,
+        root_signature: &'a RootSignature,
+    ) -> Self {
+        unsafe { command_list.SetComputeRootSignature(&root_signature.signature) };
+        Self::new(command_list, root_signature, true)
+    }
+
+    fn new(
+        command_list: &'a ID3D12GraphicsCommandList,
+        root_signature: &'a RootSignature,
+        compute: bool,
+    ) -> Self {
+        Self {
+            command_list,
+            root_signature,
+            compute,
+            bound: vec![false; root_signature.parameters.len()],
+        }
+    }
+
+    pub(super) fn cbv(&mut self, index: u32, address: u64) {
+        self.check(index, "a CBV", |kind| kind == ParameterKind::Cbv);
+        unsafe {
+            if self.compute {
+                self.command_list
+                    .SetComputeRootConstantBufferView(index, address);
+            } else {
+                self.command_list
+                    .SetGraphicsRootConstantBufferView(index, address);
+            }
+        }
+    }
+
+    pub(super) fn srv(&mut self, index: u32, address: u64) {
+        self.check(index, "a SRV", |kind| kind == ParameterKind::Srv);
+        unsafe {
+            if self.compute {
+                self.command_list
+                    .SetComputeRootShaderResourceView(index, address);
+            } else {
+                self.command_list
+                    .SetGraphicsRootShaderResourceView(index, address);
+            }
+        }
+    }
+
+    pub(super) fn uav(&mut self, index: u32, address: u64) {
+        self.check(index, "a UAV", |kind| kind == ParameterKind::Uav);
+        unsafe {
+            if self.compute {
+                self.command_list
+                    .SetComputeRootUnorderedAccessView(index, address);
+            } else {
+                self.command_list
+                    .SetGraphicsRootUnorderedAccessView(index, address);
+            }
+        }
+    }
+
+    /// Binds the table to the start of `heap`, which has to hold all its descriptors.
+    pub(super) fn table(&mut self, index: u32, heap: &DescriptorHeap) {
+        let heap_size = heap.descriptor_count() as u32;
+        self.check(
+            index,
+            &format!("a table in a heap of {heap_size} descriptors"),
+            |kind| matches!(kind, ParameterKind::Table { descriptors } if descriptors <= heap_size),
+        );
+        unsafe {
+            if self.compute {
+                self.command_list
+                    .SetComputeRootDescriptorTable(index, heap.gpu_handle());
+            } else {
+                self.command_list
+                    .SetGraphicsRootDescriptorTable(index, heap.gpu_handle());
+            }
+        }
+    }
+
+    /// Binds the table to descriptors staged in the [`crate::render::DescriptorRing`], which
+    /// have to hold all its descriptors. The ring heap has to be set on the command list.
+    pub(super) fn transient_table(&mut self, index: u32, descriptors: &TransientDescriptors) {
+        let count = descriptors.count() as u32;
+        self.check(
+            index,
+            &format!("a table of {count} staged descriptors"),
+            |kind| matches!(kind, ParameterKind::Table { descriptors } if descriptors <= count),
+        );
+        unsafe {
+            if self.compute {
+                self.command_list
+                    .SetComputeRootDescriptorTable(index, descriptors.gpu_handle());
+            } else {
+                self.command_list
+                    .SetGraphicsRootDescriptorTable(index, descriptors.gpu_handle());
+            }
+        }
+    }
+
+    /// Sets the constants from `offset` on to `values`.
+    pub(super) fn constants(&mut self, index: u32, values: &[u32], offset: u32) {
+        let end = offset + values.len() as u32;
+        self.check(
+            index,
+            &format!("constants up to {end}"),
+            |kind| matches!(kind, ParameterKind::Constants { count } if end <= count),
+        );
+        unsafe {
+            if self.compute {
+                self.command_list.SetComputeRoot32BitConstants(
+                    index,
+                    values.len() as u32,
+                    values.as_ptr().cast(),
+                    offset,
+                );
+            } else {
+                self.command_list.SetGraphicsRoot32BitConstants(
+                    index,
+                    values.len() as u32,
+                    values.as_ptr().cast(),
+                    offset,
+                );
+            }
+        }
+    }
+
+    /// Checks that every root parameter is bound, call it right before drawing or
+    /// dispatching.
+    pub(super) fn check_complete(&self) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let name = self.root_signature.name;
+        for (index, bound) in self.bound.iter().enumerate() {
+            assert!(
+                *bound,
+                "{name} root parameter {index} ({}) isn't bound",
+                self.root_signature.parameters[index].description
+            );
+        }
+    }
+
+    fn check(&mut self, index: u32, bound_as: &str, matches: impl FnOnce(ParameterKind) -> bool) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let name = self.root_signature.name;
+        let parameters = &self.root_signature.parameters;
+        let Some(parameter) = parameters.get(index as usize) else {
+            panic!(
+                "{name} root signature has {} parameters, {bound_as} is bound to parameter {index}",
+                parameters.len()
+            );
+        };
+        assert!(
+            matches(parameter.kind),
+            "{name} root parameter {index} is {}, but {bound_as} is bound to it",
+            parameter.description
+        );
+        self.bound[index as usize] = true;
+    }
+}
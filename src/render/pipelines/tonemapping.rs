@@ -0,0 +1,458 @@
+use bevy::prelude::*;
+use serde::Deserialize;
+use windows::{
+    core::s,
+    Win32::{
+        Foundation::RECT,
+        Graphics::{Direct3D::D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST, Direct3D12::*},
+    },
+};
+
+use crate::{
+    core::{AutoExposure, Camera, Exposure, Shader},
+    render::{
+        constant_buffer::ConstantBuffer, vertex_buffer::VertexBuffer, BackBufferFormat, Gpu,
+        TransientDescriptors,
+    },
+};
+
+use super::{
+    auto_exposure::AutoExposurePipeline,
+    debug_view::DebugView,
+    pipeline_state::{
+        compile_compute_shader, compile_shaders, create_compute_pipeline_state,
+        create_root_signature_from_desc, BlendMode, SpecializedPipelineStates, TargetDesc,
+    },
+    root_bindings::{RootBindings, RootSignature},
+};
+
+/// Operator used to map the HDR scene radiance to the displayable range.
+#[derive(Resource, Reflect, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Resource, Default)]
+pub enum Tonemapping {
+    /// Only clamps to `[0, 1]`.
+    None,
+    Reinhard,
+    #[default]
+    AcesFitted,
+}
+
+impl Tonemapping {
+    fn shader_index(&self) -> u32 {
+        match self {
+            Tonemapping::None => 0,
+            Tonemapping::Reinhard => 1,
+            Tonemapping::AcesFitted => 2,
+        }
+    }
+}
+
+/// Noise added before the tone mapped image is quantized to the back buffer format, so smooth
+/// gradients don't show banding. Float back buffers aren't dithered.
+#[derive(Resource, Reflect, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Resource, Default)]
+pub enum Dithering {
+    None,
+    /// 8x8 Bayer matrix, a regular pattern.
+    Ordered,
+    /// Interleaved gradient noise, close to blue noise without a noise texture.
+    #[default]
+    Noise,
+}
+
+impl Dithering {
+    fn shader_index(&self) -> u32 {
+        match self {
+            Dithering::None => 0,
+            Dithering::Ordered => 1,
+            Dithering::Noise => 2,
+        }
+    }
+}
+
+/// How the tone mapping pass writes the back buffer of a window.
+#[derive(Resource, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Resource, Default)]
+pub enum FinalBlit {
+    /// Draws a fullscreen quad into the back buffer.
+    #[default]
+    Draw,
+    /// Writes the back buffer from a compute shader through a UAV, without the raster
+    /// pipeline. Read when the render target of a window is created, since its back buffers
+    /// have to allow unordered access. Windows whose swapchain doesn't support that, and views
+    /// drawn with MSAA, keep drawing.
+    Compute,
+}
+
+/// Post processing of a [`Camera`] that differs from the global resources, so an editor
+/// preview and the main camera can look different in the same app. `None` fields follow the
+/// global setting.
+///
+/// Exposure is set per camera already, with [`Exposure`] and [`AutoExposure`].
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Component, Default)]
+pub struct PostProcessOverride {
+    pub tonemapping: Option<Tonemapping>,
+    pub dithering: Option<Dithering>,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct TonemapSettings {
+    tonemapping: u32,
+    exposure: f32,
+    auto_exposure: u32,
+    dithering: u32,
+}
+
+const BLIT_GROUP_SIZE: u32 = 8;
+
+/// Fullscreen pass resolving the HDR target of a window into its back buffer, drawn or
+/// dispatched depending on [`FinalBlit`].
+#[derive(Resource)]
+pub struct TonemapPipeline {
+    root_signature: RootSignature,
+    blit_root_signature: RootSignature,
+    blit_state: ID3D12PipelineState,
+    vertex_buffer: VertexBuffer,
+    states: SpecializedPipelineStates,
+    settings_constant_buffer: ConstantBuffer<TonemapSettings>,
+}
+
+impl TonemapPipeline {
+    pub fn write_settings(
+        &mut self,
+        tonemapping: Tonemapping,
+        exposure: f32,
+        auto_exposure: bool,
+        dithering: Dithering,
+    ) {
+        self.settings_constant_buffer.write(&TonemapSettings {
+            tonemapping: tonemapping.shader_index(),
+            exposure,
+            auto_exposure: auto_exposure as u32,
+            dithering: dithering.shader_index(),
+        });
+    }
+
+    /// `hdr_srv` is the staged SRV of the HDR target. `average_luminance` is the GPU address of
+    /// the auto-exposure result, only read when auto-exposure is enabled. The HDR target is
+    /// read at output pixel positions scaled by `render_scale`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn populate_command_list(
+        &mut self,
+        gpu: &Gpu,
+        command_list: &mut ID3D12GraphicsCommandList,
+        hdr_srv: &TransientDescriptors,
+        average_luminance: u64,
+        format: BackBufferFormat,
+        sample_count: u32,
+        render_scale: f32,
+    ) {
+        let state = self
+            .states
+            .get(gpu, TargetDesc::new(format.dxgi_format(), sample_count));
+        unsafe {
+            command_list.SetPipelineState(state);
+            command_list.SetDescriptorHeaps(&[Some(hdr_srv.heap())]);
+
+            let mut bindings = RootBindings::graphics(command_list, &self.root_signature);
+            bindings.cbv(0, self.settings_constant_buffer.gpu_adress());
+            bindings.transient_table(1, hdr_srv);
+            bindings.srv(2, average_luminance);
+            let unorm_max = format.unorm_max().unwrap_or(0) as f32;
+            bindings.constants(
+                3,
+                &[
+                    format.is_linear() as u32,
+                    render_scale.to_bits(),
+                    unorm_max.to_bits(),
+                ],
+                0,
+            );
+            bindings.check_complete();
+
+            command_list.IASetPrimitiveTopology(D3D_PRIMITIVE_TOPOLOGY_TRIANGLELIST);
+            command_list.IASetVertexBuffers(0, Some(&[*self.vertex_buffer.view()]));
+            command_list.DrawInstanced(6, 1, 0, 0);
+        }
+    }
+
+    /// Compute version of [`Self::populate_command_list`]. `blit_descriptors` are the staged
+    /// SRV of the HDR target followed by a UAV of the `width` by `height` output, `rect` is the
+    /// part of the output the view covers and everything outside of it is cleared.
+    #[allow(clippy::too_many_arguments)]
+    pub fn populate_blit_command_list(
+        &self,
+        command_list: &mut ID3D12GraphicsCommandList,
+        blit_descriptors: &TransientDescriptors,
+        average_luminance: u64,
+        format: BackBufferFormat,
+        render_scale: f32,
+        rect: RECT,
+        width: u32,
+        height: u32,
+    ) {
+        unsafe {
+            command_list.SetPipelineState(&self.blit_state);
+            command_list.SetDescriptorHeaps(&[Some(blit_descriptors.heap())]);
+
+            let mut bindings = RootBindings::compute(command_list, &self.blit_root_signature);
+            bindings.cbv(0, self.settings_constant_buffer.gpu_adress());
+            bindings.transient_table(1, blit_descriptors);
+            bindings.srv(2, average_luminance);
+            let unorm_max = format.unorm_max().unwrap_or(0) as f32;
+            bindings.constants(
+                3,
+                &[
+                    format.is_linear() as u32,
+                    render_scale.to_bits(),
+                    unorm_max.to_bits(),
+                ],
+                0,
+            );
+            bindings.constants(
+                4,
+                &[
+                    rect.left as u32,
+                    rect.top as u32,
+                    rect.right as u32,
+                    rect.bottom as u32,
+                ],
+                0,
+            );
+            bindings.check_complete();
+
+            command_list.Dispatch(
+                width.div_ceil(BLIT_GROUP_SIZE),
+                height.div_ceil(BLIT_GROUP_SIZE),
+                1,
+            );
+        }
+    }
+}
+
+#[derive(Resource, Deref, DerefMut)]
+pub struct TonemapShaderHandle(pub Handle<Shader>);
+
+fn create_root_signature(gpu: &Gpu) -> RootSignature {
+    let ranges = [D3D12_DESCRIPTOR_RANGE {
+        RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+        NumDescriptors: 1,
+        BaseShaderRegister: 0,
+        RegisterSpace: 0,
+        OffsetInDescriptorsFromTableStart: D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND,
+    }];
+
+    let root_parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: ranges.len() as u32,
+                    pDescriptorRanges: ranges.as_ptr(),
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_SRV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        // whether the target takes linear values
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_PIXEL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                    Num32BitValues: 3,
+                },
+            },
+        },
+    ];
+    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_ALLOW_INPUT_ASSEMBLER_INPUT_LAYOUT,
+        NumParameters: root_parameters.len() as u32,
+        pParameters: root_parameters.as_ptr(),
+        NumStaticSamplers: 0,
+        pStaticSamplers: std::ptr::null(),
+    };
+
+    create_root_signature_from_desc(gpu, "tonemap", &root_signature_desc)
+}
+
+fn create_blit_root_signature(gpu: &Gpu) -> RootSignature {
+    let ranges = [
+        D3D12_DESCRIPTOR_RANGE {
+            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_SRV,
+            NumDescriptors: 1,
+            BaseShaderRegister: 0,
+            RegisterSpace: 0,
+            OffsetInDescriptorsFromTableStart: D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND,
+        },
+        D3D12_DESCRIPTOR_RANGE {
+            RangeType: D3D12_DESCRIPTOR_RANGE_TYPE_UAV,
+            NumDescriptors: 1,
+            BaseShaderRegister: 0,
+            RegisterSpace: 0,
+            OffsetInDescriptorsFromTableStart: D3D12_DESCRIPTOR_RANGE_OFFSET_APPEND,
+        },
+    ];
+
+    let root_parameters = [
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_CBV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 0,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_DESCRIPTOR_TABLE,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                DescriptorTable: D3D12_ROOT_DESCRIPTOR_TABLE {
+                    NumDescriptorRanges: ranges.len() as u32,
+                    pDescriptorRanges: ranges.as_ptr(),
+                },
+            },
+        },
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_SRV,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Descriptor: D3D12_ROOT_DESCRIPTOR {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                },
+            },
+        },
+        // same target constants as the fullscreen pass
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 1,
+                    RegisterSpace: 0,
+                    Num32BitValues: 3,
+                },
+            },
+        },
+        // part of the output covered by the view
+        D3D12_ROOT_PARAMETER {
+            ParameterType: D3D12_ROOT_PARAMETER_TYPE_32BIT_CONSTANTS,
+            ShaderVisibility: D3D12_SHADER_VISIBILITY_ALL,
+            Anonymous: D3D12_ROOT_PARAMETER_0 {
+                Constants: D3D12_ROOT_CONSTANTS {
+                    ShaderRegister: 2,
+                    RegisterSpace: 0,
+                    Num32BitValues: 4,
+                },
+            },
+        },
+    ];
+    let root_signature_desc = D3D12_ROOT_SIGNATURE_DESC {
+        Flags: D3D12_ROOT_SIGNATURE_FLAG_NONE,
+        NumParameters: root_parameters.len() as u32,
+        pParameters: root_parameters.as_ptr(),
+        NumStaticSamplers: 0,
+        pStaticSamplers: std::ptr::null(),
+    };
+
+    create_root_signature_from_desc(gpu, "tonemap blit", &root_signature_desc)
+}
+
+pub fn create_tonemap_pipeline(
+    mut commands: Commands,
+    gpu: Res<Gpu>,
+    shader_handle: Res<TonemapShaderHandle>,
+    shaders: Res<Assets<Shader>>,
+    pipeline: Option<Res<TonemapPipeline>>,
+) {
+    if pipeline.is_some() {
+        return;
+    }
+
+    let Some(shader_source) = shaders.get(&shader_handle.0) else {
+        return;
+    };
+
+    let compiled_shaders = compile_shaders(shader_source);
+    let root_signature = create_root_signature(&gpu);
+    let states =
+        SpecializedPipelineStates::new(compiled_shaders, &root_signature, BlendMode::Opaque);
+    let blit_root_signature = create_blit_root_signature(&gpu);
+    let blit_shader = compile_compute_shader(shader_source, s!("CSMain"));
+
+    commands.insert_resource(TonemapPipeline {
+        root_signature,
+        blit_state: create_compute_pipeline_state(&gpu, &blit_shader, &blit_root_signature),
+        blit_root_signature,
+        vertex_buffer: VertexBuffer::fullscreen_quad(&gpu),
+        states,
+        settings_constant_buffer: ConstantBuffer::create(&gpu),
+    });
+}
+
+#[allow(clippy::type_complexity)]
+pub fn prepare_tonemap(
+    cameras: Query<
+        (
+            Option<&Exposure>,
+            Option<&AutoExposure>,
+            Option<&PostProcessOverride>,
+        ),
+        With<Camera>,
+    >,
+    tonemapping: Res<Tonemapping>,
+    dithering: Res<Dithering>,
+    debug_view: Res<DebugView>,
+    time: Res<Time>,
+    tonemap_pipeline: Option<ResMut<TonemapPipeline>>,
+    auto_exposure_pipeline: Option<ResMut<AutoExposurePipeline>>,
+) {
+    let (Some(mut tonemap_pipeline), Some(mut auto_exposure_pipeline)) =
+        (tonemap_pipeline, auto_exposure_pipeline)
+    else {
+        return;
+    };
+    let Ok((exposure, auto_exposure, post_process)) = cameras.get_single() else {
+        return;
+    };
+
+    auto_exposure_pipeline.write_settings(auto_exposure, time.delta_seconds());
+    // debug views are shown as they are
+    if *debug_view != DebugView::None {
+        tonemap_pipeline.write_settings(Tonemapping::None, 1.0, false, Dithering::None);
+        return;
+    }
+    let post_process = post_process.copied().unwrap_or_default();
+    tonemap_pipeline.write_settings(
+        post_process.tonemapping.unwrap_or(*tonemapping),
+        exposure.copied().unwrap_or_default().multiplier(),
+        auto_exposure_pipeline.enabled(),
+        post_process.dithering.unwrap_or(*dithering),
+    );
+}
@@ -0,0 +1,51 @@
+//! Events and markers in command lists, which PIX and RenderDoc show the passes of a capture
+//! under. Only recorded with the `pix` feature, they cost a string conversion per pass.
+
+use std::ffi::c_void;
+
+use windows::Win32::Graphics::Direct3D12::ID3D12GraphicsCommandList;
+
+// metadata of events whose data is a null terminated UTF-16 string, understood by PIX and
+// RenderDoc without the PIX event runtime
+const WINPIX_EVENT_UNICODE_VERSION: u32 = 0;
+
+/// Opens an event named `name`, closed by [`end_event`]. Events nest.
+pub(crate) fn begin_event(command_list: &ID3D12GraphicsCommandList, name: &str) {
+    if !cfg!(feature = "pix") {
+        return;
+    }
+    let name = wide(name);
+    unsafe {
+        command_list.BeginEvent(
+            WINPIX_EVENT_UNICODE_VERSION,
+            Some(name.as_ptr() as *const c_void),
+            std::mem::size_of_val(name.as_slice()) as u32,
+        )
+    };
+}
+
+pub(crate) fn end_event(command_list: &ID3D12GraphicsCommandList) {
+    if !cfg!(feature = "pix") {
+        return;
+    }
+    unsafe { command_list.EndEvent() };
+}
+
+/// Marks a single point of the command list.
+pub(crate) fn set_marker(command_list: &ID3D12GraphicsCommandList, name: &str) {
+    if !cfg!(feature = "pix") {
+        return;
+    }
+    let name = wide(name);
+    unsafe {
+        command_list.SetMarker(
+            WINPIX_EVENT_UNICODE_VERSION,
+            Some(name.as_ptr() as *const c_void),
+            std::mem::size_of_val(name.as_slice()) as u32,
+        )
+    };
+}
+
+fn wide(name: &str) -> Vec<u16> {
+    name.encode_utf16().chain([0]).collect()
+}
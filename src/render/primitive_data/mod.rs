@@ -0,0 +1,216 @@
+use bevy::prelude::*;
+
+use crate::core::{
+    BoxPrimitive, InstanceOpacity, Material, PlanePrimitive, SpherePrimitive, Visibility,
+};
+
+use super::{
+    mesh_data::{MaterialData, NO_TEXTURE},
+    RenderSchedule, RenderSet,
+};
+
+pub const MAX_PRIMITIVES: usize = 1024;
+
+const PRIMITIVE_KIND_SPHERE: u32 = 0;
+const PRIMITIVE_KIND_PLANE: u32 = 1;
+const PRIMITIVE_KIND_BOX: u32 = 2;
+
+pub struct PrimitiveDataPlugin;
+
+impl Plugin for PrimitiveDataPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<PrimitiveData>().add_systems(
+            RenderSchedule,
+            build_primitive_data.in_set(RenderSet::Extract),
+        );
+    }
+}
+
+/// GPU layout of an analytic primitive, intersected in its local space.
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct GpuPrimitive {
+    world_to_local: [[f32; 4]; 4],
+    // radius in x for spheres, half sizes otherwise
+    size: [f32; 3],
+    kind: u32,
+    material: MaterialData,
+}
+
+impl GpuPrimitive {
+    fn new(
+        kind: u32,
+        size: Vec3,
+        material: &Material,
+        opacity: Option<&InstanceOpacity>,
+        transform: &GlobalTransform,
+    ) -> Self {
+        Self {
+            world_to_local: transform.compute_matrix().inverse().to_cols_array_2d(),
+            size: size.to_array(),
+            kind,
+            material: MaterialData::new(material, NO_TEXTURE).with_opacity(opacity),
+        }
+    }
+
+    /// Distance along `ray` to the primitive, same as `IntersectPrimitive` in `demo.hlsl`.
+    fn intersect(&self, ray: Ray3d) -> Option<f32> {
+        let world_to_local = Mat4::from_cols_array_2d(&self.world_to_local);
+        // not normalized, so distances stay in world units
+        let origin = world_to_local.transform_point3(ray.origin);
+        let direction = world_to_local.transform_vector3(*ray.direction);
+        let size = Vec3::from_array(self.size);
+
+        match self.kind {
+            PRIMITIVE_KIND_SPHERE => {
+                let a = direction.length_squared();
+                let b = origin.dot(direction);
+                let c = origin.length_squared() - size.x * size.x;
+                let discriminant = b * b - a * c;
+                if discriminant < 0.0 {
+                    return None;
+                }
+                let near = (-b - discriminant.sqrt()) / a;
+                let far = (-b + discriminant.sqrt()) / a;
+                let distance = if near >= 0.0 { near } else { far };
+                (distance >= 0.0).then_some(distance)
+            }
+            PRIMITIVE_KIND_PLANE => {
+                if direction.y.abs() <= 1e-8 {
+                    return None;
+                }
+                let distance = -origin.y / direction.y;
+                let position = origin + direction * distance;
+                (distance >= 0.0 && position.x.abs() <= size.x && position.z.abs() <= size.z)
+                    .then_some(distance)
+            }
+            _ => {
+                let t0 = (-size - origin) / direction;
+                let t1 = (size - origin) / direction;
+                let near = t0.min(t1).max_element();
+                let far = t0.max(t1).min_element();
+                let distance = if near >= 0.0 { near } else { far };
+                (near <= far && far >= 0.0).then_some(distance)
+            }
+        }
+    }
+}
+
+/// Analytic primitives of the scene, rebuilt whenever one of them or a material changes.
+#[derive(Resource, Default)]
+pub struct PrimitiveData {
+    primitives: Vec<GpuPrimitive>,
+    // one entry per primitive
+    entities: Vec<Entity>,
+    updated: bool,
+}
+
+impl PrimitiveData {
+    pub fn primitives(&self) -> &[GpuPrimitive] {
+        &self.primitives
+    }
+
+    pub fn set_used(&mut self) {
+        self.updated = false;
+    }
+
+    pub fn updated(&self) -> bool {
+        self.updated
+    }
+
+    pub(crate) fn reupload(&mut self) {
+        self.updated = true;
+    }
+
+    /// Closest primitive `ray` hits, with its entity and the distance along the ray.
+    pub fn intersect(&self, ray: Ray3d) -> Option<(Entity, f32)> {
+        self.primitives
+            .iter()
+            .zip(&self.entities)
+            .filter_map(|(primitive, entity)| {
+                primitive.intersect(ray).map(|distance| (*entity, distance))
+            })
+            .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    }
+}
+
+type AnyPrimitive = Or<(
+    With<SpherePrimitive>,
+    With<PlanePrimitive>,
+    With<BoxPrimitive>,
+)>;
+
+type ChangedPrimitive = Or<(
+    Changed<SpherePrimitive>,
+    Changed<PlanePrimitive>,
+    Changed<BoxPrimitive>,
+    Changed<Handle<Material>>,
+    Changed<GlobalTransform>,
+    Changed<Visibility>,
+    Changed<InstanceOpacity>,
+)>;
+
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
+fn build_primitive_data(
+    changed_primitives: Query<(), (AnyPrimitive, ChangedPrimitive)>,
+    primitives: Query<(
+        Entity,
+        AnyOf<(&SpherePrimitive, &PlanePrimitive, &BoxPrimitive)>,
+        &Handle<Material>,
+        &GlobalTransform,
+        Option<&Visibility>,
+        Option<&InstanceOpacity>,
+    )>,
+    material_assets: Res<Assets<Material>>,
+    mut material_events: EventReader<AssetEvent<Material>>,
+    mut removed_spheres: RemovedComponents<SpherePrimitive>,
+    mut removed_planes: RemovedComponents<PlanePrimitive>,
+    mut removed_boxes: RemovedComponents<BoxPrimitive>,
+    mut removed_opacities: RemovedComponents<InstanceOpacity>,
+    mut primitive_data: ResMut<PrimitiveData>,
+) {
+    let removed = removed_spheres.read().count()
+        + removed_planes.read().count()
+        + removed_boxes.read().count()
+        + removed_opacities.read().count()
+        > 0;
+    let materials_changed = material_events.read().any(|event| {
+        matches!(
+            event,
+            AssetEvent::Added { .. } | AssetEvent::Modified { .. } | AssetEvent::Removed { .. }
+        )
+    });
+    if changed_primitives.is_empty() && !removed && !materials_changed {
+        return;
+    }
+
+    primitive_data.primitives.clear();
+    primitive_data.entities.clear();
+    for (entity, (sphere, plane, cuboid), material, transform, visibility, opacity) in &primitives {
+        if visibility == Some(&Visibility::Hidden) {
+            continue;
+        }
+        let material = material_assets
+            .get(material)
+            .or_else(|| material_assets.get(&Handle::default()))
+            .unwrap();
+        let shapes = [
+            sphere.map(|sphere| (PRIMITIVE_KIND_SPHERE, Vec3::splat(sphere.radius))),
+            plane.map(|plane| (PRIMITIVE_KIND_PLANE, plane.half_size.extend(0.0).xzy())),
+            cuboid.map(|cuboid| (PRIMITIVE_KIND_BOX, cuboid.half_size)),
+        ];
+        for (kind, size) in shapes.into_iter().flatten() {
+            if primitive_data.primitives.len() == MAX_PRIMITIVES {
+                warn_once!(
+                    "More than {MAX_PRIMITIVES} primitives in the scene, the rest are ignored"
+                );
+                break;
+            }
+            primitive_data
+                .primitives
+                .push(GpuPrimitive::new(kind, size, material, opacity, transform));
+            primitive_data.entities.push(entity);
+        }
+    }
+    primitive_data.updated = true;
+}
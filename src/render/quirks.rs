@@ -0,0 +1,77 @@
+use std::ops::RangeInclusive;
+
+use bevy::prelude::*;
+
+pub const VENDOR_AMD: u32 = 0x1002;
+pub const VENDOR_NVIDIA: u32 = 0x10de;
+pub const VENDOR_INTEL: u32 = 0x8086;
+pub const VENDOR_MICROSOFT: u32 = 0x1414;
+
+pub(crate) fn vendor_name(vendor_id: u32) -> &'static str {
+    match vendor_id {
+        VENDOR_AMD => "AMD",
+        VENDOR_NVIDIA => "NVIDIA",
+        VENDOR_INTEL => "Intel",
+        VENDOR_MICROSOFT => "Microsoft",
+        _ => "Unknown",
+    }
+}
+
+/// Workarounds the renderer applies on specific adapters.
+///
+/// Looked up from [`QUIRKS`] when the device is created and available as [`super::Gpu::quirks`]
+/// so pipelines can pick an alternative code path.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DriverQuirks {
+    /// Don't enable GPU-based validation, even in debug builds.
+    pub disable_gpu_based_validation: bool,
+    /// Issue UAV barriers for all resources (null resource) instead of one barrier per resource.
+    pub global_uav_barriers: bool,
+}
+
+impl DriverQuirks {
+    fn merge(self, other: DriverQuirks) -> DriverQuirks {
+        DriverQuirks {
+            disable_gpu_based_validation: self.disable_gpu_based_validation
+                || other.disable_gpu_based_validation,
+            global_uav_barriers: self.global_uav_barriers || other.global_uav_barriers,
+        }
+    }
+}
+
+/// One row of the quirks table.
+pub struct QuirkEntry {
+    pub vendor_id: u32,
+    /// `None` matches every device of the vendor.
+    pub device_ids: Option<RangeInclusive<u32>>,
+    /// What goes wrong without the workaround and where it was reported.
+    pub reason: &'static str,
+    pub quirks: DriverQuirks,
+}
+
+impl QuirkEntry {
+    fn matches(&self, vendor_id: u32, device_id: u32) -> bool {
+        self.vendor_id == vendor_id
+            && self
+                .device_ids
+                .as_ref()
+                .is_none_or(|device_ids| device_ids.contains(&device_id))
+    }
+}
+
+/// Known driver issues. Add an entry per confirmed user report, with a link in `reason`.
+pub const QUIRKS: &[QuirkEntry] = &[];
+
+/// Quirks of the adapter with the given PCI ids, all matching entries are combined.
+pub fn quirks_for_adapter(vendor_id: u32, device_id: u32) -> DriverQuirks {
+    QUIRKS
+        .iter()
+        .filter(|entry| entry.matches(vendor_id, device_id))
+        .fold(DriverQuirks::default(), |quirks, entry| {
+            info!(
+                "Applying driver workaround for {vendor_id:#06x}:{device_id:#06x}: {}",
+                entry.reason
+            );
+            quirks.merge(entry.quirks)
+        })
+}
@@ -0,0 +1,62 @@
+use bevy::{ecs::system::SystemParam, prelude::*};
+
+use super::{MeshData, PrimitiveData, View};
+use crate::core::Camera;
+
+/// Closest surface hit by a [`Raycast`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RaycastHit {
+    pub entity: Entity,
+    /// Index of the hit triangle in the entity's mesh, `None` for analytic primitives.
+    pub triangle: Option<usize>,
+    pub position: Vec3,
+    /// Distance from the ray origin, in world units.
+    pub distance: f32,
+}
+
+/// Casts rays against the scene as the path tracer sees it, for picking and other editor style
+/// queries.
+///
+/// Rays test the world space geometry uploaded to the GPU: visible meshes, placeholders
+/// included, and analytic primitives. It is gathered in [`super::RenderSchedule`], so queries
+/// see the scene of the last drawn frame. There is no acceleration structure yet, every ray
+/// tests every triangle.
+#[derive(SystemParam)]
+pub struct Raycast<'w> {
+    mesh_data: Res<'w, MeshData>,
+    primitive_data: Res<'w, PrimitiveData>,
+}
+
+impl Raycast<'_> {
+    pub fn cast_ray(&self, ray: Ray3d) -> Option<RaycastHit> {
+        let mesh_hit = self
+            .mesh_data
+            .intersect(ray)
+            .map(|(entity, triangle, distance)| (entity, Some(triangle), distance));
+        let primitive_hit = self
+            .primitive_data
+            .intersect(ray)
+            .map(|(entity, distance)| (entity, None, distance));
+        let (entity, triangle, distance) = [mesh_hit, primitive_hit]
+            .into_iter()
+            .flatten()
+            .min_by(|(_, _, a), (_, _, b)| a.total_cmp(b))?;
+        Some(RaycastHit {
+            entity,
+            triangle,
+            position: ray.get_point(distance),
+            distance,
+        })
+    }
+
+    /// Casts the primary ray of `camera` through `viewport_position`, normalized like in
+    /// [`View::ray`]. A cursor position maps to it divided by the window size.
+    pub fn cast_from_viewport(
+        &self,
+        camera: &Camera,
+        transform: &GlobalTransform,
+        viewport_position: Vec2,
+    ) -> Option<RaycastHit> {
+        self.cast_ray(View::new(transform, camera).ray(viewport_position))
+    }
+}
@@ -0,0 +1,251 @@
+//! Passes of a view declared with the resources they access, run in an order that satisfies
+//! those accesses with the barriers between them inserted automatically.
+
+use windows::Win32::Graphics::Direct3D12::{ID3D12Resource, D3D12_RESOURCE_STATES};
+
+use super::drawer::Drawer;
+
+/// Resource of a [`RenderGraph`], returned when it is imported.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) struct GraphResource(usize);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Access {
+    Read,
+    Write,
+}
+
+struct ImportedResource<'a> {
+    name: &'static str,
+    // None for resources the passes transition themselves
+    resource: Option<&'a ID3D12Resource>,
+    state: D3D12_RESOURCE_STATES,
+}
+
+/// A pass and the resources it accesses, each at most once.
+pub(crate) struct RenderNode<'a> {
+    name: &'static str,
+    accesses: Vec<(GraphResource, Access, D3D12_RESOURCE_STATES)>,
+    record: Box<dyn FnOnce(&mut Drawer) + 'a>,
+}
+
+impl<'a> RenderNode<'a> {
+    /// `record` records the pass, with its resources already in the declared states.
+    pub(crate) fn new(name: &'static str, record: impl FnOnce(&mut Drawer) + 'a) -> Self {
+        Self {
+            name,
+            accesses: Vec::new(),
+            record: Box::new(record),
+        }
+    }
+
+    /// Runs after every pass that writes `resource`.
+    pub(crate) fn reads(self, resource: GraphResource, state: D3D12_RESOURCE_STATES) -> Self {
+        self.access(resource, Access::Read, state)
+    }
+
+    /// Writes `resource`, or changes it in place like a denoiser or bloom on the HDR target.
+    /// Runs after the passes added before it that write `resource` and before all that read it.
+    pub(crate) fn writes(self, resource: GraphResource, state: D3D12_RESOURCE_STATES) -> Self {
+        self.access(resource, Access::Write, state)
+    }
+
+    fn access(
+        mut self,
+        resource: GraphResource,
+        access: Access,
+        state: D3D12_RESOURCE_STATES,
+    ) -> Self {
+        self.accesses.push((resource, access, state));
+        self
+    }
+}
+
+/// Passes of one view, run by [`RenderGraph::execute`].
+///
+/// The order comes from the accesses: every resource is written before it is read, passes
+/// writing the same resource keep the order they were added in. Before
+/// every pass its resources are transitioned to the states it declared, afterwards they are
+/// returned to the states they were imported in. The passes and barriers are recorded in the
+/// [`super::FrameGraph`].
+#[derive(Default)]
+pub(crate) struct RenderGraph<'a> {
+    resources: Vec<ImportedResource<'a>>,
+    nodes: Vec<RenderNode<'a>>,
+}
+
+impl<'a> RenderGraph<'a> {
+    /// `resource` is in `state` when the graph starts and is returned to it at the end.
+    pub(crate) fn import(
+        &mut self,
+        name: &'static str,
+        resource: &'a ID3D12Resource,
+        state: D3D12_RESOURCE_STATES,
+    ) -> GraphResource {
+        self.push_resource(name, Some(resource), state)
+    }
+
+    /// A resource that only orders the passes, they transition it themselves and the states
+    /// they declare for it are ignored.
+    pub(crate) fn import_untracked(&mut self, name: &'static str) -> GraphResource {
+        self.push_resource(name, None, D3D12_RESOURCE_STATES::default())
+    }
+
+    fn push_resource(
+        &mut self,
+        name: &'static str,
+        resource: Option<&'a ID3D12Resource>,
+        state: D3D12_RESOURCE_STATES,
+    ) -> GraphResource {
+        self.resources.push(ImportedResource {
+            name,
+            resource,
+            state,
+        });
+        GraphResource(self.resources.len() - 1)
+    }
+
+    pub(crate) fn add_node(&mut self, node: RenderNode<'a>) {
+        self.nodes.push(node);
+    }
+
+    /// Records the passes in order into the command list of `drawer`.
+    pub(crate) fn execute(self, drawer: &mut Drawer) {
+        let order = self.execution_order();
+        let mut states: Vec<_> = self
+            .resources
+            .iter()
+            .map(|imported| imported.state)
+            .collect();
+        let mut nodes: Vec<_> = self.nodes.into_iter().map(Some).collect();
+        for index in order {
+            let node = nodes[index].take().unwrap();
+            let mut reads = Vec::new();
+            let mut writes = Vec::new();
+            for &(GraphResource(resource), access, state) in &node.accesses {
+                let imported = &self.resources[resource];
+                match access {
+                    Access::Read => reads.push(imported.name),
+                    Access::Write => writes.push(imported.name),
+                }
+                if let Some(d3d_resource) = imported.resource {
+                    if states[resource] != state {
+                        drawer.transition(imported.name, d3d_resource, states[resource], state);
+                        states[resource] = state;
+                    }
+                }
+            }
+            drawer.begin_event(node.name);
+            drawer.begin_timed_pass(node.name);
+            (node.record)(drawer);
+            drawer.end_timed_pass();
+            drawer.end_event();
+            drawer.record_pass(node.name, &reads, &writes);
+        }
+
+        for (imported, state) in self.resources.iter().zip(states) {
+            if let Some(d3d_resource) = imported.resource {
+                if state != imported.state {
+                    drawer.transition(imported.name, d3d_resource, state, imported.state);
+                }
+            }
+        }
+    }
+
+    /// Indices of the nodes in execution order, nodes without a dependency between them run in
+    /// the order they were added.
+    fn execution_order(&self) -> Vec<usize> {
+        let mut dependencies = vec![Vec::new(); self.nodes.len()];
+        for resource in 0..self.resources.len() {
+            let accessing = |wanted: Access| {
+                self.nodes
+                    .iter()
+                    .enumerate()
+                    .filter_map(move |(index, node)| {
+                        node.accesses
+                            .iter()
+                            .any(|&(GraphResource(accessed), access, _)| {
+                                accessed == resource && access == wanted
+                            })
+                            .then_some(index)
+                    })
+            };
+            let writers: Vec<usize> = accessing(Access::Write).collect();
+            for pair in writers.windows(2) {
+                dependencies[pair[1]].push(pair[0]);
+            }
+            if let Some(&last) = writers.last() {
+                for reader in accessing(Access::Read) {
+                    dependencies[reader].push(last);
+                }
+            }
+        }
+
+        let mut done = vec![false; self.nodes.len()];
+        let mut order = Vec::with_capacity(self.nodes.len());
+        while order.len() < self.nodes.len() {
+            let next = (0..self.nodes.len())
+                .find(|&index| !done[index] && dependencies[index].iter().all(|&dep| done[dep]))
+                .unwrap_or_else(|| {
+                    let waiting: Vec<_> = (0..self.nodes.len())
+                        .filter(|&index| !done[index])
+                        .map(|index| self.nodes[index].name)
+                        .collect();
+                    panic!("render graph passes {waiting:?} depend on each other")
+                });
+            done[next] = true;
+            order.push(next);
+        }
+        order
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use windows::Win32::Graphics::Direct3D12::{
+        D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE, D3D12_RESOURCE_STATE_RENDER_TARGET,
+        D3D12_RESOURCE_STATE_UNORDERED_ACCESS,
+    };
+
+    use super::*;
+
+    #[test]
+    fn orders_writes_before_reads() {
+        let mut graph = RenderGraph::default();
+        let hdr = graph.import_untracked("HDR target");
+        let output = graph.import_untracked("output");
+        graph.add_node(
+            RenderNode::new("tonemap", |_| {})
+                .reads(hdr, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE)
+                .writes(output, D3D12_RESOURCE_STATE_RENDER_TARGET),
+        );
+        graph.add_node(
+            RenderNode::new("path trace", |_| {}).writes(hdr, D3D12_RESOURCE_STATE_RENDER_TARGET),
+        );
+        graph.add_node(
+            RenderNode::new("denoise", |_| {}).writes(hdr, D3D12_RESOURCE_STATE_UNORDERED_ACCESS),
+        );
+
+        assert_eq!(graph.execution_order(), [1, 2, 0]);
+    }
+
+    #[test]
+    #[should_panic(expected = "depend on each other")]
+    fn panics_on_cycles() {
+        let mut graph = RenderGraph::default();
+        let a = graph.import_untracked("a");
+        let b = graph.import_untracked("b");
+        graph.add_node(
+            RenderNode::new("first", |_| {})
+                .reads(a, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE)
+                .writes(b, D3D12_RESOURCE_STATE_RENDER_TARGET),
+        );
+        graph.add_node(
+            RenderNode::new("second", |_| {})
+                .reads(b, D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE)
+                .writes(a, D3D12_RESOURCE_STATE_RENDER_TARGET),
+        );
+
+        graph.execution_order();
+    }
+}
@@ -1,136 +1,678 @@
-use bevy::{prelude::*, window::RawHandleWrapperHolder};
+use bevy::{
+    prelude::*,
+    window::{ClosingWindow, RawHandleWrapperHolder, WindowMode},
+};
 
 use raw_window_handle::RawWindowHandle;
+use serde::Deserialize;
 use smallvec::SmallVec;
 use windows::{
     core::Interface,
     Win32::{
-        Foundation::{HWND, RECT},
+        Foundation::{BOOL, DXGI_STATUS_OCCLUDED, HWND, RECT, WAIT_OBJECT_0},
         Graphics::{
             Direct3D12::*,
+            DirectComposition::{
+                DCompositionCreateDevice2, IDCompositionDesktopDevice, IDCompositionTarget,
+                IDCompositionVisual2,
+            },
             Dxgi::{
-                Common::{DXGI_ALPHA_MODE_IGNORE, DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC},
+                Common::{
+                    DXGI_ALPHA_MODE_IGNORE, DXGI_ALPHA_MODE_PREMULTIPLIED,
+                    DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709,
+                    DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709, DXGI_COLOR_SPACE_TYPE, DXGI_FORMAT,
+                    DXGI_FORMAT_D32_FLOAT, DXGI_FORMAT_R10G10B10A2_UNORM,
+                    DXGI_FORMAT_R16G16B16A16_FLOAT, DXGI_FORMAT_R32G32B32A32_FLOAT,
+                    DXGI_FORMAT_R8G8B8A8_UNORM, DXGI_SAMPLE_DESC,
+                },
                 *,
             },
         },
-        System::Threading::{CreateEventA, WaitForSingleObject, INFINITE},
+        System::Threading::WaitForSingleObjectEx,
     },
 };
 
-use super::{gpu::Gpu, DescriptorHeap, ResizeEvent};
-use crate::win_types::WinHandle;
+use super::{
+    aov::{AovRegistry, AovTarget, AovTargets},
+    device_removed::is_device_removed,
+    drawer::{MsaaTarget, ViewTarget},
+    fence_timeout::FenceTimeout,
+    gpu::Gpu,
+    gpu_fence::GpuFence,
+    set_debug_name, Descriptors, FinalBlit, ResizeEvent,
+};
+use crate::{
+    core::{Camera, CameraViewport},
+    win_types::WinHandle,
+};
 
 pub const FRAME_COUNT: usize = 2;
+/// Swapchain buffers plus the HDR and MSAA targets.
+pub const RTVS_PER_WINDOW: usize = FRAME_COUNT + 2;
+/// Depth target of the scene pass.
+pub const DSVS_PER_WINDOW: usize = 1;
+/// Format of the depth targets. Depth is reversed, nearer fragments have greater depth and
+/// targets are cleared to 0.
+pub const DEPTH_FORMAT: DXGI_FORMAT = DXGI_FORMAT_D32_FLOAT;
+
+/// How the swapchain of a window reaches the screen. Read once, when the render target of the
+/// window is created.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Component, Default)]
+pub enum WindowPresentation {
+    /// Swapchain presented straight to the window.
+    #[default]
+    Hwnd,
+    /// Composition swapchain shown through a DirectComposition visual on the window, the last
+    /// frame stays visible while the window is resized instead of black borders. Always used
+    /// for windows with `Window::transparent`.
+    Composition,
+    /// Composition swapchain that isn't shown anywhere, for handing
+    /// [`WindowRenderTarget::swapchain`] to a composition based UI framework.
+    External,
+}
+
+/// How presenting a window waits for the display, read every frame.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Component, Default)]
+pub enum PresentMode {
+    /// Presents on the vertical blank, the frame rate is limited to the refresh rate.
+    #[default]
+    Vsync,
+    /// Presents without waiting for the vertical blank. Tears where [`Gpu::supports_tearing`]
+    /// and the window uses [`WindowPresentation::Hwnd`], so variable refresh rate displays
+    /// follow the frame rate. Otherwise the compositor shows the newest frame without tearing.
+    Immediate,
+    /// Presents without waiting for the vertical blank and without tearing, the next frame only
+    /// starts once the swapchain has room for it, keeping at most one frame queued for the
+    /// display.
+    Mailbox,
+}
+
+impl PresentMode {
+    /// Sync interval passed to `IDXGISwapChain::Present`.
+    pub fn sync_interval(&self) -> u32 {
+        match self {
+            PresentMode::Vsync => 1,
+            PresentMode::Immediate | PresentMode::Mailbox => 0,
+        }
+    }
+
+    /// Flags passed to `IDXGISwapChain::Present` for a swapchain created with
+    /// `DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING` if `allows_tearing`.
+    pub fn present_flags(&self, allows_tearing: bool) -> DXGI_PRESENT {
+        if *self == PresentMode::Immediate && allows_tearing {
+            DXGI_PRESENT_ALLOW_TEARING
+        } else {
+            DXGI_PRESENT(0)
+        }
+    }
+}
+
+/// Format of the back buffers of a window. Changing it recreates the buffers on the next frame.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[reflect(Component, Default)]
+pub enum BackBufferFormat {
+    /// 8 bits per channel, sRGB encoded.
+    #[default]
+    Rgba8Unorm,
+    /// 10 bits per color channel and 2 bits of alpha, sRGB encoded. Less banding in gradients.
+    Rgb10A2Unorm,
+    /// Half floats in linear scRGB. The output is still tone mapped to `[0, 1]`.
+    Rgba16Float,
+}
+
+impl BackBufferFormat {
+    pub fn dxgi_format(&self) -> DXGI_FORMAT {
+        match self {
+            BackBufferFormat::Rgba8Unorm => DXGI_FORMAT_R8G8B8A8_UNORM,
+            BackBufferFormat::Rgb10A2Unorm => DXGI_FORMAT_R10G10B10A2_UNORM,
+            BackBufferFormat::Rgba16Float => DXGI_FORMAT_R16G16B16A16_FLOAT,
+        }
+    }
+
+    /// Whether the format stores linear values instead of sRGB encoded ones.
+    pub fn is_linear(&self) -> bool {
+        *self == BackBufferFormat::Rgba16Float
+    }
+
+    /// Largest value of a color channel for normalized integer formats.
+    pub fn unorm_max(&self) -> Option<u32> {
+        match self {
+            BackBufferFormat::Rgba8Unorm => Some(255),
+            BackBufferFormat::Rgb10A2Unorm => Some(1023),
+            BackBufferFormat::Rgba16Float => None,
+        }
+    }
+
+    fn color_space(&self) -> DXGI_COLOR_SPACE_TYPE {
+        if self.is_linear() {
+            DXGI_COLOR_SPACE_RGB_FULL_G10_NONE_P709
+        } else {
+            DXGI_COLOR_SPACE_RGB_FULL_G22_NONE_P709
+        }
+    }
+}
+
+/// Format of the HDR targets progressive accumulation is stored in, the scene is rendered in it
+/// before tone mapping. Changing it recreates the targets and restarts accumulation.
+#[derive(Resource, Reflect, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Resource, Default)]
+pub enum AccumulationPrecision {
+    /// Half floats. After about a thousand frames new ones are too small a part of the average
+    /// to change it.
+    #[default]
+    Half,
+    /// Single floats, for long accumulations. Twice the memory and bandwidth.
+    Full,
+}
+
+impl AccumulationPrecision {
+    pub fn dxgi_format(&self) -> DXGI_FORMAT {
+        match self {
+            AccumulationPrecision::Half => DXGI_FORMAT_R16G16B16A16_FLOAT,
+            AccumulationPrecision::Full => DXGI_FORMAT_R32G32B32A32_FLOAT,
+        }
+    }
+}
+
+/// Multisampling of the rasterized passes drawn into the back buffers of every window. They are
+/// drawn into a multisampled target that is resolved to the back buffer. The path traced image
+/// is antialiased by accumulation and never multisampled. Sample counts the device doesn't
+/// support for the back buffer format fall back to the highest supported one.
+#[derive(Resource, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Resource, Default)]
+pub enum Msaa {
+    #[default]
+    Off,
+    Sample2,
+    Sample4,
+    Sample8,
+}
+
+impl Msaa {
+    pub fn samples(&self) -> u32 {
+        match self {
+            Msaa::Off => 1,
+            Msaa::Sample2 => 2,
+            Msaa::Sample4 => 4,
+            Msaa::Sample8 => 8,
+        }
+    }
+}
 
-struct Fence {
-    fence: ID3D12Fence,
-    fence_value: u64,
-    fence_event: WinHandle,
+/// DirectComposition tree showing a composition swapchain on its window. Only kept so the
+/// objects live as long as the window.
+struct Composition {
+    _device: IDCompositionDesktopDevice,
+    _target: IDCompositionTarget,
+    _visual: IDCompositionVisual2,
 }
 
+// Composition objects are free threaded, they are also never touched after creation
+unsafe impl Send for Composition {}
+unsafe impl Sync for Composition {}
+
 #[derive(Component)]
 pub struct WindowRenderTarget {
     pub swapchain: IDXGISwapChain4,
+    // signaled when the swapchain can queue another frame, waited on for PresentMode::Mailbox
+    frame_latency_waitable: WinHandle,
+    allows_tearing: bool,
+    presentation: WindowPresentation,
+    // whether the window asked for exclusive fullscreen, the swapchain state is only changed
+    // when that does, so DXGI leaving fullscreen on its own isn't undone every frame
+    exclusive_requested: bool,
+    // DXGI fullscreen state of the swapchain
+    fullscreen: bool,
+    // the last present found the window hidden, it isn't drawn until a test present shows it
+    occluded: bool,
+    // set for WindowPresentation::Composition
+    _composition: Option<Composition>,
     rtvs: SmallVec<[ID3D12Resource; FRAME_COUNT]>,
     rtv_handles: SmallVec<[D3D12_CPU_DESCRIPTOR_HANDLE; FRAME_COUNT]>,
     swapchain_buffer_index: u32,
-    fence: Fence,
-    pub viewport: D3D12_VIEWPORT,
-    pub rect: RECT,
+    hdr_target: ID3D12Resource,
+    hdr_rtv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    hdr_srv: Descriptors,
+    // per back buffer the SRV of the HDR target and a UAV of the back buffer, empty unless the
+    // back buffers allow unordered access for FinalBlit::Compute
+    blit_descriptors: SmallVec<[Descriptors; FRAME_COUNT]>,
+    // size of the HDR target, for the rasterizing scene pipelines
+    depth_target: ID3D12Resource,
+    dsv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    // size of the HDR target as well
+    aovs: AovTargets,
+    // signaled after every present
+    fence: GpuFence,
+    accumulated_frames: u32,
+    format: BackBufferFormat,
+    // multisampled color target resolved into the back buffer, None without MSAA
+    msaa_target: Option<ID3D12Resource>,
+    msaa_rtv_handle: D3D12_CPU_DESCRIPTOR_HANDLE,
+    msaa_samples: u32,
+    layout: ViewportLayout,
+    // drawn this frame and waiting to be presented
+    drawn: bool,
+    // hold the views of the back buffers and the HDR, MSAA and depth targets
+    _rtvs: Descriptors,
+    _dsvs: Descriptors,
+}
+
+/// Where the camera is drawn in a window and what it is path traced into, derived from the back
+/// buffer size, the [`CameraViewport`] and the [`AccumulationPrecision`].
+#[derive(Clone, Copy, PartialEq)]
+struct ViewportLayout {
+    /// Part of the back buffer covered by the camera.
+    viewport: D3D12_VIEWPORT,
+    rect: RECT,
+    /// `viewport` scaled by the render scale, the part of the HDR target that is path traced.
+    hdr_viewport: D3D12_VIEWPORT,
+    hdr_rect: RECT,
+    hdr_size: UVec2,
+    hdr_format: DXGI_FORMAT,
+    render_scale: f32,
+}
+
+impl ViewportLayout {
+    fn new(
+        width: u32,
+        height: u32,
+        camera_viewport: &CameraViewport,
+        precision: AccumulationPrecision,
+    ) -> Self {
+        let size = UVec2::new(width, height).max(UVec2::ONE);
+        let render_scale = camera_viewport.render_scale.clamp(0.1, 1.0);
+        let to_pixels =
+            |position: Vec2| (position.clamp(Vec2::ZERO, Vec2::ONE) * size.as_vec2()).round();
+        // at least one pixel, inside of the back buffer
+        let min = to_pixels(camera_viewport.rect.min)
+            .as_uvec2()
+            .min(size - UVec2::ONE);
+        let max = to_pixels(camera_viewport.rect.max)
+            .as_uvec2()
+            .clamp(min + UVec2::ONE, size);
+
+        let hdr_size = (size.as_vec2() * render_scale).ceil().as_uvec2();
+        let hdr_min = min.as_vec2() * render_scale;
+        let hdr_max = max.as_vec2() * render_scale;
+        Self {
+            viewport: offset_viewport(min.as_vec2(), (max - min).as_vec2()),
+            rect: pixel_rect(min, max),
+            hdr_viewport: offset_viewport(hdr_min, hdr_max - hdr_min),
+            hdr_rect: pixel_rect(hdr_min.floor().as_uvec2(), hdr_max.ceil().as_uvec2()),
+            hdr_size,
+            hdr_format: precision.dxgi_format(),
+            render_scale,
+        }
+    }
+}
+
+/// Window owned by the host application instead of bevy, for embedding the renderer into
+/// existing Win32, WinForms or Qt applications. Spawn it on an entity of its own and keep
+/// `width` and `height` up to date when the host resizes the window, the render target follows
+/// on the next frame. Despawn the entity before the host destroys the window.
+///
+/// Only one render target is supported right now, so the app shouldn't open a bevy window.
+#[derive(Component, Debug, Clone, Copy)]
+pub struct ExternalWindow {
+    /// `HWND` of the window.
+    pub hwnd: isize,
+    /// Client area size in physical pixels.
+    pub width: u32,
+    pub height: u32,
+}
+
+impl ExternalWindow {
+    fn hwnd(&self) -> HWND {
+        HWND(self.hwnd as *mut core::ffi::c_void)
+    }
+}
+
+/// Called with the description of every swapchain before it is created or resized, to set
+/// parameters the renderer doesn't expose, like flags, scaling or stereo. Set through
+/// [`crate::ArcaPlugin::configure_swapchain`].
+///
+/// Keep `BufferCount` and the frame latency waitable flag, the renderer relies on both. The
+/// buffer usage is always the one the renderer picked.
+#[derive(Resource, Clone, Copy)]
+pub struct ConfigureSwapchain(pub fn(&mut DXGI_SWAP_CHAIN_DESC1));
+
+/// Size and format of whatever a render target presents to.
+struct Surface {
+    physical_width: u32,
+    physical_height: u32,
+    width: f32,
+    height: f32,
+    transparent: bool,
+    format: BackBufferFormat,
 }
 
-#[derive(Resource, Deref, DerefMut)]
-pub struct RtvHeap(pub DescriptorHeap);
+impl Surface {
+    fn from_window(window: &Window, format: Option<&BackBufferFormat>) -> Self {
+        Self {
+            physical_width: window.physical_width(),
+            physical_height: window.physical_height(),
+            width: window.width(),
+            height: window.height(),
+            transparent: window.transparent,
+            format: format.copied().unwrap_or_default(),
+        }
+    }
+
+    fn from_external(window: &ExternalWindow, format: Option<&BackBufferFormat>) -> Self {
+        Self {
+            physical_width: window.width.max(1),
+            physical_height: window.height.max(1),
+            width: window.width.max(1) as f32,
+            height: window.height.max(1) as f32,
+            transparent: false,
+            format: format.copied().unwrap_or_default(),
+        }
+    }
+}
 
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 pub fn create_render_targets(
-    mut windows: Query<(Entity, &Window, &RawHandleWrapperHolder), Without<WindowRenderTarget>>,
+    windows: Query<
+        (
+            Entity,
+            &Window,
+            &RawHandleWrapperHolder,
+            Option<&WindowPresentation>,
+            Option<&BackBufferFormat>,
+        ),
+        Without<WindowRenderTarget>,
+    >,
+    external_windows: Query<
+        (
+            Entity,
+            &ExternalWindow,
+            Option<&WindowPresentation>,
+            Option<&BackBufferFormat>,
+        ),
+        Without<WindowRenderTarget>,
+    >,
     mut commands: Commands,
-    mut rtv_heap: ResMut<RtvHeap>,
+    cameras: Query<Option<&CameraViewport>, With<Camera>>,
+    precision: Res<AccumulationPrecision>,
+    blit: Res<FinalBlit>,
     gpu: Res<Gpu>,
+    configure_swapchain: Option<Res<ConfigureSwapchain>>,
+    aov_registry: Res<AovRegistry>,
     mut resize_events: EventWriter<ResizeEvent>,
 ) {
-    for (entity, window, window_handle) in &mut windows {
+    let camera_viewport = camera_viewport(&cameras);
+    let windows = windows
+        .iter()
+        .map(|(entity, window, window_handle, presentation, format)| {
+            (
+                entity,
+                get_hwnd(window_handle),
+                Surface::from_window(window, format),
+                presentation,
+            )
+        });
+    let external_windows =
+        external_windows
+            .iter()
+            .map(|(entity, external, presentation, format)| {
+                (
+                    entity,
+                    external.hwnd(),
+                    Surface::from_external(external, format),
+                    presentation,
+                )
+            });
+    for (entity, hwnd, surface, presentation) in windows.chain(external_windows) {
         commands.entity(entity).insert(WindowRenderTarget::new(
-            window,
-            window_handle,
+            hwnd,
+            &surface,
+            presentation.copied().unwrap_or_default(),
+            *blit,
+            &camera_viewport,
+            *precision,
             &gpu,
-            &mut rtv_heap,
+            configure_swapchain.as_deref(),
+            &aov_registry,
         ));
         resize_events.send(ResizeEvent {
             entity,
-            width: window.width(),
-            height: window.height(),
+            width: surface.width,
+            height: surface.height,
         });
     }
 }
 
+#[allow(clippy::type_complexity, clippy::too_many_arguments)]
 pub fn switch_frame(
-    mut windows: Query<(&Window, &mut WindowRenderTarget, Entity)>,
+    mut windows: Query<(
+        AnyOf<(&Window, &ExternalWindow)>,
+        Option<&BackBufferFormat>,
+        Option<&PresentMode>,
+        &mut WindowRenderTarget,
+        Entity,
+        Has<ClosingWindow>,
+    )>,
+    cameras: Query<Option<&CameraViewport>, With<Camera>>,
+    precision: Res<AccumulationPrecision>,
     gpu: Res<Gpu>,
+    msaa: Res<Msaa>,
+    fence_timeout: Res<FenceTimeout>,
+    configure_swapchain: Option<Res<ConfigureSwapchain>>,
+    aov_registry: Res<AovRegistry>,
     mut resize_events: EventWriter<ResizeEvent>,
 ) {
-    for (window, mut render_target, entity) in &mut windows {
-        render_target.wait_frame_finished();
-        let new_swapchain_desc = create_swapchain_desc(window);
+    let camera_viewport = camera_viewport(&cameras);
+    for (window, format, present_mode, mut render_target, entity, closing) in &mut windows {
+        if closing {
+            // the window is despawned next frame, its swapchain must not be in use by then
+            render_target.wait_frames_finished(&gpu, *fence_timeout);
+        }
+        let surface = match window {
+            (Some(window), _) => Surface::from_window(window, format),
+            (None, Some(external)) => Surface::from_external(external, format),
+            (None, None) => unreachable!(),
+        };
+        // occluded windows don't present, nothing would signal the latency waitable
+        let mailbox = present_mode == Some(&PresentMode::Mailbox) && !render_target.is_occluded();
+        render_target.wait_frame_latency(mailbox, *fence_timeout);
         let old_swapchain_desc = unsafe { render_target.swapchain.GetDesc1() }.unwrap();
-        if new_swapchain_desc != old_swapchain_desc {
-            render_target.handle_resize(
-                &gpu.device,
-                new_swapchain_desc,
-                window.width(),
-                window.height(),
-            );
+        // ResizeBuffers keeps the usage and tearing support the swapchain was created with
+        let new_swapchain_desc = create_swapchain_desc(
+            &surface,
+            old_swapchain_desc.BufferUsage,
+            render_target.allows_tearing,
+            configure_swapchain.as_deref(),
+        );
+        let exclusive = window.0.is_some_and(|window| {
+            matches!(
+                window.mode,
+                WindowMode::SizedFullscreen | WindowMode::Fullscreen
+            )
+        });
+        let fullscreen_changed = render_target.update_fullscreen(exclusive);
+        if new_swapchain_desc != old_swapchain_desc || fullscreen_changed {
+            // the back buffers can only be resized once no frame in flight uses them
+            render_target.wait_frames_finished(&gpu, *fence_timeout);
+            render_target.handle_resize(&gpu.device, new_swapchain_desc, &surface);
             resize_events.send(ResizeEvent {
                 entity,
-                width: window.width(),
-                height: window.height(),
+                width: surface.width,
+                height: surface.height,
             });
         }
+        render_target.update_layout(&gpu, &surface, &camera_viewport, *precision);
+        render_target.update_aovs(&gpu, &aov_registry);
+        render_target.update_msaa_target(&gpu, msaa.samples());
         render_target.update_frame_index();
     }
 }
 
+impl Drop for WindowRenderTarget {
+    fn drop(&mut self) {
+        // swapchains must not be released in fullscreen
+        if self.fullscreen {
+            let _ = unsafe {
+                self.swapchain
+                    .SetFullscreenState(BOOL(0), None::<&IDXGIOutput>)
+            };
+        }
+    }
+}
+
 impl WindowRenderTarget {
+    #[allow(clippy::too_many_arguments)]
     fn new(
-        window: &Window,
-        window_handle: &RawHandleWrapperHolder,
+        hwnd: HWND,
+        surface: &Surface,
+        presentation: WindowPresentation,
+        blit: FinalBlit,
+        camera_viewport: &CameraViewport,
+        precision: AccumulationPrecision,
         gpu: &Gpu,
-        rtv_heap: &mut DescriptorHeap,
+        configure_swapchain: Option<&ConfigureSwapchain>,
+        aov_registry: &AovRegistry,
     ) -> Self {
-        let desc = create_swapchain_desc(window);
-        let swapchain = unsafe {
-            gpu.factory.CreateSwapChainForHwnd(
-                &gpu.queue,
-                get_hwnd(window_handle),
-                &desc,
-                None, // ??
-                None,
-            )
-        }
-        .expect("failed to create swapchain")
-        .cast::<IDXGISwapChain4>()
-        .expect("failed to cast swapchain to IDXGISwapChain4");
+        // Flip model swapchains of a window ignore alpha, transparent windows need a composition
+        // swapchain with premultiplied alpha that DirectComposition blends over the desktop
+        let presentation = match presentation {
+            WindowPresentation::Hwnd if surface.transparent => WindowPresentation::Composition,
+            presentation => presentation,
+        };
+        let allows_tearing = presentation == WindowPresentation::Hwnd && gpu.supports_tearing();
+        let usage = if blit == FinalBlit::Compute {
+            DXGI_USAGE(DXGI_USAGE_RENDER_TARGET_OUTPUT.0 | DXGI_USAGE_UNORDERED_ACCESS.0)
+        } else {
+            DXGI_USAGE_RENDER_TARGET_OUTPUT
+        };
+        let mut desc = create_swapchain_desc(surface, usage, allows_tearing, configure_swapchain);
+        let create_swapchain = |desc: &DXGI_SWAP_CHAIN_DESC1| unsafe {
+            match presentation {
+                WindowPresentation::Hwnd => gpu
+                    .factory
+                    .CreateSwapChainForHwnd(&gpu.queue, hwnd, desc, None, None),
+                WindowPresentation::Composition | WindowPresentation::External => gpu
+                    .factory
+                    .CreateSwapChainForComposition(&gpu.queue, desc, None),
+            }
+        };
+        let swapchain = match create_swapchain(&desc) {
+            Ok(swapchain) => swapchain,
+            Err(error) if desc.BufferUsage != DXGI_USAGE_RENDER_TARGET_OUTPUT => {
+                warn!(
+                    "back buffers don't allow unordered access ({error}), drawing the final blit"
+                );
+                desc.BufferUsage = DXGI_USAGE_RENDER_TARGET_OUTPUT;
+                create_swapchain(&desc).expect("failed to create swapchain")
+            }
+            Err(error) => panic!("failed to create swapchain: {error}"),
+        };
+        let composition = (presentation == WindowPresentation::Composition)
+            .then(|| create_composition(hwnd, &swapchain));
+        let swapchain = swapchain
+            .cast::<IDXGISwapChain4>()
+            .expect("failed to cast swapchain to IDXGISwapChain4");
+        set_color_space(&swapchain, surface.format);
+        unsafe { swapchain.SetMaximumFrameLatency(1) }
+            .expect("failed to set maximum frame latency");
+        let frame_latency_waitable =
+            WinHandle(unsafe { swapchain.GetFrameLatencyWaitableObject() });
 
         let frame_index = unsafe { swapchain.GetCurrentBackBufferIndex() };
-        let viewport = create_viewport(window.width(), window.height());
-        let rect = create_rect(window.width() as i32, window.height() as i32);
-        let fence = create_fence(gpu);
+        let layout = ViewportLayout::new(desc.Width, desc.Height, camera_viewport, precision);
+        let fence = GpuFence::new(gpu, "present fence");
+        let hdr_target = create_hdr_target(&gpu.device, layout.hdr_size, layout.hdr_format);
+        let hdr_srv = gpu.descriptors.cbv_srv_uav.allocate(1);
+        let depth_target = create_depth_target(&gpu.device, layout.hdr_size);
+        let rtvs = gpu.descriptors.rtv.allocate(RTVS_PER_WINDOW);
+        let dsvs = gpu.descriptors.dsv.allocate(DSVS_PER_WINDOW);
+        let mut aovs = AovTargets::new(gpu);
+        aovs.update(gpu, aov_registry, layout.hdr_size);
+        let blit_descriptors = if desc.BufferUsage == DXGI_USAGE_RENDER_TARGET_OUTPUT {
+            SmallVec::new()
+        } else {
+            (0..FRAME_COUNT)
+                .map(|_| gpu.descriptors.cbv_srv_uav.allocate(2))
+                .collect()
+        };
 
         let mut window_render_target = WindowRenderTarget {
             swapchain,
+            frame_latency_waitable,
+            allows_tearing,
+            presentation,
+            exclusive_requested: false,
+            fullscreen: false,
+            occluded: false,
+            _composition: composition,
             rtvs: SmallVec::new(),
-            rtv_handles: SmallVec::new(),
+            rtv_handles: (0..FRAME_COUNT).map(|i| rtvs.cpu_handle(i)).collect(),
             swapchain_buffer_index: frame_index,
+            hdr_target,
+            hdr_rtv_handle: rtvs.cpu_handle(FRAME_COUNT),
+            hdr_srv,
+            blit_descriptors,
+            depth_target,
+            dsv_handle: dsvs.cpu_handle(0),
+            aovs,
             fence,
-            viewport,
-            rect,
+            accumulated_frames: 0,
+            format: surface.format,
+            msaa_target: None,
+            msaa_rtv_handle: rtvs.cpu_handle(FRAME_COUNT + 1),
+            msaa_samples: 1,
+            layout,
+            drawn: false,
+            _rtvs: rtvs,
+            _dsvs: dsvs,
         };
 
-        window_render_target.create_descriptors(rtv_heap);
         window_render_target.create_rtvs(&gpu.device);
+        window_render_target.create_hdr_views(&gpu.device);
+        window_render_target.create_depth_view(&gpu.device);
         window_render_target
     }
 
+    /// Whether presents may pass `DXGI_PRESENT_ALLOW_TEARING`: the swapchain was created with
+    /// `DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING` and isn't in exclusive fullscreen, where that flag
+    /// is invalid.
+    pub fn allows_tearing(&self) -> bool {
+        self.allows_tearing && !self.fullscreen
+    }
+
+    /// Whether the swapchain is in exclusive fullscreen.
+    pub fn is_fullscreen(&self) -> bool {
+        self.fullscreen
+    }
+
+    /// Whether the window was hidden at the last present, occluded windows aren't drawn.
+    pub fn is_occluded(&self) -> bool {
+        self.occluded
+    }
+
+    /// Presents the drawn back buffer with `present_mode`.
+    pub(crate) fn present(&mut self, present_mode: PresentMode) {
+        let flags = present_mode.present_flags(self.allows_tearing());
+        let result = unsafe { self.swapchain.Present(present_mode.sync_interval(), flags) };
+        // the renderer is recreated by recover_removed_device after the presents
+        if is_device_removed(result) {
+            return;
+        }
+        result.ok().expect("Present failed");
+        self.occluded = result == DXGI_STATUS_OCCLUDED;
+    }
+
+    /// Checks whether an occluded window became visible again, without presenting.
+    pub(crate) fn test_occlusion(&mut self) {
+        let result = unsafe { self.swapchain.Present(0, DXGI_PRESENT_TEST) };
+        if is_device_removed(result) {
+            return;
+        }
+        result.ok().expect("test Present failed");
+        self.occluded = result == DXGI_STATUS_OCCLUDED;
+    }
+
     pub fn back_buffer(&self) -> &ID3D12Resource {
         &self.rtvs[self.swapchain_buffer_index as usize]
     }
@@ -139,38 +681,181 @@ impl WindowRenderTarget {
         self.rtv_handles[self.swapchain_buffer_index as usize]
     }
 
+    pub(crate) fn view_target(&self) -> ViewTarget<'_> {
+        ViewTarget {
+            viewport: self.layout.viewport,
+            rect: self.layout.rect,
+            hdr_viewport: self.layout.hdr_viewport,
+            hdr_rect: self.layout.hdr_rect,
+            hdr_format: self.layout.hdr_format,
+            render_scale: self.layout.render_scale,
+            hdr_target: &self.hdr_target,
+            hdr_rtv_handle: self.hdr_rtv_handle,
+            hdr_srv: &self.hdr_srv,
+            blit_descriptors: self
+                .blit_descriptors
+                .get(self.swapchain_buffer_index as usize),
+            dsv_handle: self.dsv_handle,
+            aovs: self.aovs.targets(),
+            accumulated_frames: self.accumulated_frames,
+            output: self.back_buffer(),
+            output_handle: self.back_buffer_handle(),
+            output_state: D3D12_RESOURCE_STATE_PRESENT,
+            output_format: self.format,
+            msaa: self.msaa_target.as_ref().map(|texture| MsaaTarget {
+                texture,
+                rtv_handle: self.msaa_rtv_handle,
+                samples: self.msaa_samples,
+            }),
+        }
+    }
+
+    /// Floating point target the scene is rendered to before tone mapping.
+    pub fn hdr_target(&self) -> &ID3D12Resource {
+        &self.hdr_target
+    }
+
+    pub fn hdr_target_handle(&self) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+        self.hdr_rtv_handle
+    }
+
+    /// Shader invisible SRV of [`Self::hdr_target`], staged in the
+    /// [`super::DescriptorRing`] to be bound.
+    pub fn hdr_srv(&self) -> &Descriptors {
+        &self.hdr_srv
+    }
+
+    /// Depth target in [`DEPTH_FORMAT`] of the size of [`Self::hdr_target`], always in
+    /// `DEPTH_WRITE`.
+    pub fn depth_target(&self) -> &ID3D12Resource {
+        &self.depth_target
+    }
+
+    pub fn depth_target_handle(&self) -> D3D12_CPU_DESCRIPTOR_HANDLE {
+        self.dsv_handle
+    }
+
+    /// The AOVs of the [`AovRegistry`], in the size of [`Self::hdr_target`].
+    pub fn aovs(&self) -> &[AovTarget] {
+        self.aovs.targets()
+    }
+
+    pub fn aov(&self, name: &str) -> Option<&AovTarget> {
+        self.aovs().iter().find(|aov| aov.name() == name)
+    }
+
+    pub fn format(&self) -> BackBufferFormat {
+        self.format
+    }
+
+    /// Number of frames averaged in [`Self::hdr_target`] so far.
+    pub fn accumulated_frames(&self) -> u32 {
+        self.accumulated_frames
+    }
+
+    pub fn reset_accumulation(&mut self) {
+        self.accumulated_frames = 0;
+    }
+
+    pub(crate) fn set_drawn(&mut self) {
+        self.drawn = true;
+    }
+
+    pub(crate) fn take_drawn(&mut self) -> bool {
+        std::mem::take(&mut self.drawn)
+    }
+
+    pub fn advance_accumulation(&mut self) {
+        self.accumulated_frames += 1;
+    }
+
     // TODO: can i not have queue here?
     pub fn signal_end_present(&mut self, queue: &ID3D12CommandQueue) {
-        unsafe {
-            queue
-                .Signal(&self.fence.fence, self.fence.fence_value)
-                .expect("Signal Fence failed");
-        }
-        self.fence.fence_value += 1;
+        self.fence.signal(queue);
     }
 
-    fn update_frame_index(&mut self) {
-        self.swapchain_buffer_index = unsafe { self.swapchain.GetCurrentBackBufferIndex() };
+    /// Sample count of the MSAA target, 1 when the back buffer is drawn to directly.
+    pub fn msaa_samples(&self) -> u32 {
+        self.msaa_samples
     }
 
-    fn wait_frame_finished(&mut self) {
-        let previous_fence_value = self.fence.fence_value - 1;
-        if unsafe { self.fence.fence.GetCompletedValue() } < previous_fence_value {
+    fn update_msaa_target(&mut self, gpu: &Gpu, requested_samples: u32) {
+        let format = self.format.dxgi_format();
+        let samples = gpu.supported_sample_count(format, requested_samples);
+        if samples < requested_samples {
+            warn_once!(
+                "{requested_samples}x MSAA isn't supported for {:?} back buffers, using {samples}x",
+                self.format
+            );
+        }
+        if samples == self.msaa_samples && (samples == 1 || self.msaa_target.is_some()) {
+            return;
+        }
+
+        self.msaa_samples = samples;
+        if let Some(old) = self.msaa_target.take() {
+            gpu.retired.retire(old);
+        }
+        self.msaa_target = (samples > 1).then(|| {
+            let desc = unsafe { self.back_buffer().GetDesc() };
+            let target = create_msaa_target(&gpu.device, &desc, samples);
             unsafe {
-                self.fence
-                    .fence
-                    .SetEventOnCompletion(previous_fence_value, self.fence.fence_event.0)
+                gpu.device
+                    .CreateRenderTargetView(&target, None, self.msaa_rtv_handle)
+            };
+            target
+        });
+    }
+
+    /// Enters or leaves exclusive fullscreen when `exclusive` changes. Returns whether the
+    /// fullscreen state of the swapchain changed, after which its buffers have to be resized.
+    fn update_fullscreen(&mut self, exclusive: bool) -> bool {
+        // composition swapchains can't be fullscreen, their windows only cover the screen
+        let exclusive = exclusive && self.presentation == WindowPresentation::Hwnd;
+        if exclusive != self.exclusive_requested {
+            self.exclusive_requested = exclusive;
+            if let Err(error) = unsafe {
+                self.swapchain
+                    .SetFullscreenState(BOOL::from(exclusive), None::<&IDXGIOutput>)
+            } {
+                warn!("failed to change exclusive fullscreen to {exclusive}: {error}");
             }
-            .ok()
-            .unwrap();
+        }
 
-            unsafe { WaitForSingleObject(self.fence.fence_event.0, INFINITE) };
+        let mut fullscreen = BOOL(0);
+        unsafe {
+            self.swapchain
+                .GetFullscreenState(Some(&mut fullscreen), None)
         }
+        .expect("GetFullscreenState failed");
+        let changed = fullscreen.as_bool() != self.fullscreen;
+        self.fullscreen = fullscreen.as_bool();
+        changed
     }
 
-    fn create_descriptors(&mut self, rtv_heap: &mut DescriptorHeap) {
-        for _ in 0..FRAME_COUNT {
-            self.rtv_handles.push(rtv_heap.cpu_handle());
+    fn update_frame_index(&mut self) {
+        self.swapchain_buffer_index = unsafe { self.swapchain.GetCurrentBackBufferIndex() };
+    }
+
+    fn wait_frames_finished(&self, gpu: &Gpu, timeout: FenceTimeout) {
+        self.fence
+            .wait_for_last(gpu, timeout, "the frames in flight of a window");
+    }
+
+    // Without waiting the latency waitable is still drained, so switching to Mailbox doesn't
+    // start with the presents of earlier frames counted
+    fn wait_frame_latency(&self, wait: bool, timeout: FenceTimeout) {
+        let millis = if wait {
+            timeout.timeout.as_millis().min(u32::MAX as u128 - 1) as u32
+        } else {
+            0
+        };
+        let result = unsafe { WaitForSingleObjectEx(self.frame_latency_waitable.0, millis, true) };
+        if wait && result != WAIT_OBJECT_0 {
+            warn!(
+                "swapchain had no room for a frame within {:?}",
+                timeout.timeout
+            );
         }
     }
 
@@ -178,6 +863,17 @@ impl WindowRenderTarget {
         (0..FRAME_COUNT).for_each(|i| {
             let rtv = unsafe { self.swapchain.GetBuffer::<ID3D12Resource>(i as u32) }.unwrap();
             unsafe { device.CreateRenderTargetView(&rtv, None, self.rtv_handles[i]) };
+            set_debug_name(&rtv, &format!("back buffer {i}"));
+            if let Some(blit_descriptors) = self.blit_descriptors.get(i) {
+                unsafe {
+                    device.CreateUnorderedAccessView(
+                        &rtv,
+                        None,
+                        None,
+                        blit_descriptors.cpu_handle(1),
+                    )
+                };
+            }
 
             if self.rtvs.len() == i {
                 self.rtvs.push(rtv);
@@ -187,12 +883,29 @@ impl WindowRenderTarget {
         });
     }
 
+    fn create_hdr_views(&mut self, device: &ID3D12Device9) {
+        unsafe {
+            device.CreateRenderTargetView(&self.hdr_target, None, self.hdr_rtv_handle);
+            device.CreateShaderResourceView(&self.hdr_target, None, self.hdr_srv.cpu_handle(0));
+            for blit_descriptors in &self.blit_descriptors {
+                device.CreateShaderResourceView(
+                    &self.hdr_target,
+                    None,
+                    blit_descriptors.cpu_handle(0),
+                );
+            }
+        }
+    }
+
+    fn create_depth_view(&self, device: &ID3D12Device9) {
+        unsafe { device.CreateDepthStencilView(&self.depth_target, None, self.dsv_handle) };
+    }
+
     fn handle_resize(
         &mut self,
         device: &ID3D12Device9,
         desc: DXGI_SWAP_CHAIN_DESC1,
-        width: f32,
-        height: f32,
+        surface: &Surface,
     ) {
         self.destroy_resources();
 
@@ -206,34 +919,252 @@ impl WindowRenderTarget {
             )
         }
         .expect("ResizeBuffers failed");
-
-        self.viewport = create_viewport(width, height);
-        self.rect = create_rect(width as i32, height as i32);
+        if surface.format != self.format {
+            set_color_space(&self.swapchain, surface.format);
+            self.format = surface.format;
+        }
 
         self.create_rtvs(device);
     }
 
+    /// Follows the back buffer size, the [`CameraViewport`] and the [`AccumulationPrecision`],
+    /// the HDR target is recreated when its size or format changes and the depth target when its
+    /// size does. The replaced targets are retired, frames in flight may still draw into them.
+    fn update_layout(
+        &mut self,
+        gpu: &Gpu,
+        surface: &Surface,
+        camera_viewport: &CameraViewport,
+        precision: AccumulationPrecision,
+    ) {
+        let layout = ViewportLayout::new(
+            surface.physical_width,
+            surface.physical_height,
+            camera_viewport,
+            precision,
+        );
+        if layout == self.layout {
+            return;
+        }
+
+        let device = &gpu.device;
+        if layout.hdr_size != self.layout.hdr_size || layout.hdr_format != self.layout.hdr_format {
+            let hdr_target = create_hdr_target(device, layout.hdr_size, layout.hdr_format);
+            gpu.retired
+                .retire(std::mem::replace(&mut self.hdr_target, hdr_target));
+            self.create_hdr_views(device);
+        }
+        if layout.hdr_size != self.layout.hdr_size {
+            let depth_target = create_depth_target(device, layout.hdr_size);
+            gpu.retired
+                .retire(std::mem::replace(&mut self.depth_target, depth_target));
+            self.create_depth_view(device);
+        }
+        self.layout = layout;
+        self.accumulated_frames = 0;
+    }
+
+    /// Follows the [`AovRegistry`] and the size of the HDR target, recreating the AOVs
+    /// restarts accumulation.
+    fn update_aovs(&mut self, gpu: &Gpu, registry: &AovRegistry) {
+        if self.aovs.update(gpu, registry, self.layout.hdr_size) {
+            self.accumulated_frames = 0;
+        }
+    }
+
     fn destroy_resources(&mut self) {
         self.rtvs.clear();
+        // recreated in the size and format of the new back buffers
+        self.msaa_target = None;
     }
 }
 
-fn create_swapchain_desc(window: &Window) -> DXGI_SWAP_CHAIN_DESC1 {
-    DXGI_SWAP_CHAIN_DESC1 {
-        Width: window.physical_width(),
-        Height: window.physical_height(),
-        Format: DXGI_FORMAT_R8G8B8A8_UNORM,
+fn create_swapchain_desc(
+    surface: &Surface,
+    usage: DXGI_USAGE,
+    allow_tearing: bool,
+    configure: Option<&ConfigureSwapchain>,
+) -> DXGI_SWAP_CHAIN_DESC1 {
+    let mut flags = DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT.0 as u32;
+    if allow_tearing {
+        flags |= DXGI_SWAP_CHAIN_FLAG_ALLOW_TEARING.0 as u32;
+    }
+    let mut desc = DXGI_SWAP_CHAIN_DESC1 {
+        Width: surface.physical_width,
+        Height: surface.physical_height,
+        Format: surface.format.dxgi_format(),
         SampleDesc: DXGI_SAMPLE_DESC {
             Count: 1,
             ..Default::default()
         },
-        BufferUsage: DXGI_USAGE_RENDER_TARGET_OUTPUT,
+        BufferUsage: usage,
         BufferCount: FRAME_COUNT as u32,
         SwapEffect: DXGI_SWAP_EFFECT_FLIP_DISCARD,
-        AlphaMode: DXGI_ALPHA_MODE_IGNORE,
-        Flags: DXGI_SWAP_CHAIN_FLAG_FRAME_LATENCY_WAITABLE_OBJECT.0 as u32,
+        AlphaMode: if surface.transparent {
+            DXGI_ALPHA_MODE_PREMULTIPLIED
+        } else {
+            DXGI_ALPHA_MODE_IGNORE
+        },
+        Flags: flags,
         ..Default::default()
+    };
+    if let Some(ConfigureSwapchain(configure)) = configure {
+        configure(&mut desc);
+        desc.BufferUsage = usage;
     }
+    desc
+}
+
+fn set_color_space(swapchain: &IDXGISwapChain4, format: BackBufferFormat) {
+    unsafe { swapchain.SetColorSpace1(format.color_space()) }
+        .expect("failed to set swapchain color space");
+}
+
+fn create_composition(hwnd: HWND, swapchain: &IDXGISwapChain1) -> Composition {
+    let device: IDCompositionDesktopDevice =
+        unsafe { DCompositionCreateDevice2(None) }.expect("failed to create composition device");
+    let target = unsafe { device.CreateTargetForHwnd(hwnd, true) }
+        .expect("failed to create composition target");
+    let visual = unsafe { device.CreateVisual() }.expect("failed to create composition visual");
+    unsafe {
+        visual
+            .SetContent(swapchain)
+            .expect("failed to set swapchain as visual content");
+        target.SetRoot(&visual).expect("failed to set root visual");
+        device.Commit().expect("failed to commit composition");
+    }
+    Composition {
+        _device: device,
+        _target: target,
+        _visual: visual,
+    }
+}
+
+pub(super) fn create_hdr_target(
+    device: &ID3D12Device9,
+    size: UVec2,
+    format: DXGI_FORMAT,
+) -> ID3D12Resource {
+    let mut hdr_target: Option<ID3D12Resource> = None;
+    unsafe {
+        device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_DEFAULT,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                Width: size.x as u64,
+                Height: size.y,
+                DepthOrArraySize: 1,
+                MipLevels: 1,
+                Format: format,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+                Flags: D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+                ..Default::default()
+            },
+            D3D12_RESOURCE_STATE_ALL_SHADER_RESOURCE,
+            Some(&D3D12_CLEAR_VALUE {
+                Format: format,
+                Anonymous: D3D12_CLEAR_VALUE_0 {
+                    Color: [0.0, 0.0, 0.0, 1.0],
+                },
+            }),
+            &mut hdr_target,
+        )
+    }
+    .expect("failed to create HDR render target");
+    let hdr_target = hdr_target.unwrap();
+    set_debug_name(&hdr_target, "HDR target");
+    hdr_target
+}
+
+pub(super) fn create_depth_target(device: &ID3D12Device9, size: UVec2) -> ID3D12Resource {
+    let mut depth_target: Option<ID3D12Resource> = None;
+    unsafe {
+        device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_DEFAULT,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                Width: size.x as u64,
+                Height: size.y,
+                DepthOrArraySize: 1,
+                MipLevels: 1,
+                Format: DEPTH_FORMAT,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: 1,
+                    Quality: 0,
+                },
+                Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+                Flags: D3D12_RESOURCE_FLAG_ALLOW_DEPTH_STENCIL
+                    | D3D12_RESOURCE_FLAG_DENY_SHADER_RESOURCE,
+                ..Default::default()
+            },
+            D3D12_RESOURCE_STATE_DEPTH_WRITE,
+            Some(&D3D12_CLEAR_VALUE {
+                Format: DEPTH_FORMAT,
+                Anonymous: D3D12_CLEAR_VALUE_0 {
+                    DepthStencil: D3D12_DEPTH_STENCIL_VALUE {
+                        Depth: 0.0,
+                        Stencil: 0,
+                    },
+                },
+            }),
+            &mut depth_target,
+        )
+    }
+    .expect("failed to create depth target");
+    let depth_target = depth_target.unwrap();
+    set_debug_name(&depth_target, "depth target");
+    depth_target
+}
+
+fn create_msaa_target(
+    device: &ID3D12Device9,
+    back_buffer: &D3D12_RESOURCE_DESC,
+    samples: u32,
+) -> ID3D12Resource {
+    let mut msaa_target: Option<ID3D12Resource> = None;
+    unsafe {
+        device.CreateCommittedResource(
+            &D3D12_HEAP_PROPERTIES {
+                Type: D3D12_HEAP_TYPE_DEFAULT,
+                ..Default::default()
+            },
+            D3D12_HEAP_FLAG_NONE,
+            &D3D12_RESOURCE_DESC {
+                Dimension: D3D12_RESOURCE_DIMENSION_TEXTURE2D,
+                Width: back_buffer.Width,
+                Height: back_buffer.Height,
+                DepthOrArraySize: 1,
+                MipLevels: 1,
+                Format: back_buffer.Format,
+                SampleDesc: DXGI_SAMPLE_DESC {
+                    Count: samples,
+                    Quality: 0,
+                },
+                Layout: D3D12_TEXTURE_LAYOUT_UNKNOWN,
+                Flags: D3D12_RESOURCE_FLAG_ALLOW_RENDER_TARGET,
+                ..Default::default()
+            },
+            D3D12_RESOURCE_STATE_RESOLVE_SOURCE,
+            None,
+            &mut msaa_target,
+        )
+    }
+    .expect("failed to create MSAA render target");
+    let msaa_target = msaa_target.unwrap();
+    set_debug_name(&msaa_target, "MSAA target");
+    msaa_target
 }
 
 fn get_hwnd(window_handle: &RawHandleWrapperHolder) -> HWND {
@@ -252,7 +1183,7 @@ fn get_hwnd(window_handle: &RawHandleWrapperHolder) -> HWND {
     }
 }
 
-fn create_viewport(width: f32, height: f32) -> D3D12_VIEWPORT {
+pub(super) fn create_viewport(width: f32, height: f32) -> D3D12_VIEWPORT {
     D3D12_VIEWPORT {
         TopLeftX: 0.0,
         TopLeftY: 0.0,
@@ -263,7 +1194,7 @@ fn create_viewport(width: f32, height: f32) -> D3D12_VIEWPORT {
     }
 }
 
-fn create_rect(width: i32, height: i32) -> RECT {
+pub(super) fn create_rect(width: i32, height: i32) -> RECT {
     RECT {
         left: 0,
         top: 0,
@@ -272,16 +1203,28 @@ fn create_rect(width: i32, height: i32) -> RECT {
     }
 }
 
-fn create_fence(gpu: &Gpu) -> Fence {
-    let fence = unsafe { gpu.device.CreateFence(0, D3D12_FENCE_FLAG_NONE) }
-        .expect("failed to create fence");
-    let fence_value = 0;
-    let fence_event =
-        unsafe { CreateEventA(None, false, false, None).expect("Failed to create event") };
+fn offset_viewport(position: Vec2, size: Vec2) -> D3D12_VIEWPORT {
+    D3D12_VIEWPORT {
+        TopLeftX: position.x,
+        TopLeftY: position.y,
+        ..create_viewport(size.x, size.y)
+    }
+}
 
-    Fence {
-        fence,
-        fence_value,
-        fence_event: WinHandle(fence_event),
+fn pixel_rect(min: UVec2, max: UVec2) -> RECT {
+    RECT {
+        left: min.x as i32,
+        top: min.y as i32,
+        right: max.x as i32,
+        bottom: max.y as i32,
     }
 }
+
+fn camera_viewport(cameras: &Query<Option<&CameraViewport>, With<Camera>>) -> CameraViewport {
+    cameras
+        .get_single()
+        .ok()
+        .flatten()
+        .copied()
+        .unwrap_or_default()
+}
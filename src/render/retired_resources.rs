@@ -0,0 +1,64 @@
+//! GPU objects replaced while a frame is recorded, kept alive until the GPU is done with them.
+
+use std::sync::Mutex;
+
+use super::render_target::FRAME_COUNT;
+
+/// Holds on to whatever is retired, like render targets replaced by bigger ones, until the
+/// frames in flight that may still read it are finished. Passes retire what they replace
+/// instead of waiting for the GPU to become idle.
+///
+/// What is retired while a frame slot is recorded is dropped once that slot begins again, the
+/// GPU finished every frame submitted before by then.
+#[derive(Default)]
+pub struct RetiredResources(Mutex<RetiredSlots>);
+
+impl RetiredResources {
+    /// Drops `value` once the frames in flight are finished.
+    pub fn retire(&self, value: impl Send + 'static) {
+        self.0.lock().unwrap().retire(Box::new(value));
+    }
+
+    /// Drops what was retired the last time `frame_slot` was recorded. Called when a frame
+    /// begins, after waiting for the GPU to finish the frame of the same slot.
+    pub fn begin_frame(&self, frame_slot: usize) {
+        self.0.lock().unwrap().begin_frame(frame_slot);
+    }
+}
+
+#[derive(Default)]
+struct RetiredSlots {
+    retired: [Vec<Box<dyn Send>>; FRAME_COUNT],
+    frame_slot: usize,
+}
+
+impl RetiredSlots {
+    fn retire(&mut self, value: Box<dyn Send>) {
+        self.retired[self.frame_slot].push(value);
+    }
+
+    fn begin_frame(&mut self, frame_slot: usize) {
+        self.retired[frame_slot].clear();
+        self.frame_slot = frame_slot;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::RetiredResources;
+
+    #[test]
+    fn retired_values_are_dropped_once_their_frame_slot_begins_again() {
+        let retired = RetiredResources::default();
+        let value = Arc::new(());
+        retired.begin_frame(0);
+        retired.retire(value.clone());
+        retired.begin_frame(1);
+        // the frame of slot 0 may still read it
+        assert_eq!(Arc::strong_count(&value), 2);
+        retired.begin_frame(0);
+        assert_eq!(Arc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,78 @@
+use bevy::{
+    prelude::*,
+    scene::{SceneInstance, SceneSpawner},
+};
+
+use super::{RenderSchedule, RenderSet, UploadQueue};
+
+/// How far a spawned scene is from rendering without placeholders or hitches.
+///
+/// Inserted automatically on every entity with a `Handle<Scene>`. Once it reaches
+/// [`ScenePrepState::Ready`] a [`SceneRenderReady`] event is sent, which is a good moment to
+/// hide a loading screen.
+#[derive(Component, Reflect, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[reflect(Component, Default)]
+pub enum ScenePrepState {
+    /// The scene asset or its dependencies are still loading, or the scene isn't spawned yet.
+    #[default]
+    Loading,
+    /// The scene is spawned and its geometry is waiting for its share of the upload budget.
+    Uploading,
+    /// Everything the scene needs is on the GPU.
+    Ready,
+}
+
+/// Sent once when the scene spawned by `entity` becomes [`ScenePrepState::Ready`].
+#[derive(Event, Debug, Clone, Copy)]
+pub struct SceneRenderReady {
+    pub entity: Entity,
+}
+
+pub struct ScenePrepPlugin;
+
+impl Plugin for ScenePrepPlugin {
+    fn build(&self, app: &mut App) {
+        app.register_type::<ScenePrepState>()
+            .add_event::<SceneRenderReady>()
+            .add_systems(Update, track_new_scenes)
+            .add_systems(
+                RenderSchedule,
+                update_scene_prep_state.after(RenderSet::Present),
+            );
+    }
+}
+
+fn track_new_scenes(
+    mut commands: Commands,
+    scenes: Query<Entity, (With<Handle<Scene>>, Without<ScenePrepState>)>,
+) {
+    for entity in &scenes {
+        commands.entity(entity).insert(ScenePrepState::Loading);
+    }
+}
+
+/// Runs after the frame is submitted, so uploads recorded this frame count as done.
+fn update_scene_prep_state(
+    mut scenes: Query<(Entity, &mut ScenePrepState, Option<&SceneInstance>)>,
+    scene_spawner: Res<SceneSpawner>,
+    uploads: Res<UploadQueue>,
+    mut ready_events: EventWriter<SceneRenderReady>,
+) {
+    for (entity, mut state, instance) in &mut scenes {
+        if *state == ScenePrepState::Loading {
+            // spawned entities were extracted into the mesh data and queued for upload earlier
+            // in this frame
+            let spawned =
+                instance.is_some_and(|instance| scene_spawner.instance_is_ready(**instance));
+            if !spawned {
+                continue;
+            }
+            *state = ScenePrepState::Uploading;
+        }
+
+        if *state == ScenePrepState::Uploading && uploads.is_empty() {
+            *state = ScenePrepState::Ready;
+            ready_events.send(SceneRenderReady { entity });
+        }
+    }
+}
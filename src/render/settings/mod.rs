@@ -0,0 +1,100 @@
+use bevy::{
+    asset::{io::Reader, AssetLoader, LoadContext},
+    prelude::*,
+};
+use serde::Deserialize;
+use thiserror::Error;
+
+use super::{AccumulationPrecision, Dithering, PathTracerSettings, Tonemapping};
+
+/// Renderer configuration loaded from a `.render.ron` file.
+///
+/// Point [`RenderSettingsHandle`] at one to apply it. The settings are applied again whenever the
+/// asset changes, so with bevy's `file_watcher` feature (the `hot_reload` feature of this crate)
+/// edits to the file show up while the app is running. Fields missing from the file keep their
+/// default value.
+#[derive(Asset, TypePath, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct RenderSettings {
+    pub path_tracer: PathTracerSettings,
+    pub tonemapping: Tonemapping,
+    pub dithering: Dithering,
+    pub accumulation_precision: AccumulationPrecision,
+}
+
+/// Render settings asset currently applied to the renderer.
+#[derive(Resource, Deref, DerefMut)]
+pub struct RenderSettingsHandle(pub Handle<RenderSettings>);
+
+pub struct RenderSettingsPlugin;
+
+impl Plugin for RenderSettingsPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_asset::<RenderSettings>()
+            .register_asset_loader(RenderSettingsLoader)
+            .add_systems(Update, apply_render_settings);
+    }
+}
+
+struct RenderSettingsLoader;
+
+#[derive(Error, Debug)]
+pub enum RenderSettingsError {
+    #[error("failed to load file: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("failed to parse render settings: {0}")]
+    Ron(#[from] ron::error::SpannedError),
+}
+
+impl AssetLoader for RenderSettingsLoader {
+    type Asset = RenderSettings;
+    type Settings = ();
+    type Error = RenderSettingsError;
+    async fn load<'a>(
+        &'a self,
+        reader: &'a mut dyn Reader,
+        _settings: &'a (),
+        _load_context: &'a mut LoadContext<'_>,
+    ) -> Result<RenderSettings, Self::Error> {
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes).await?;
+        Ok(ron::de::from_bytes(&bytes)?)
+    }
+
+    fn extensions(&self) -> &[&str] {
+        &["render.ron"]
+    }
+}
+
+fn apply_render_settings(
+    handle: Option<Res<RenderSettingsHandle>>,
+    mut events: EventReader<AssetEvent<RenderSettings>>,
+    assets: Res<Assets<RenderSettings>>,
+    mut path_tracer_settings: ResMut<PathTracerSettings>,
+    mut tonemapping: ResMut<Tonemapping>,
+    mut dithering: ResMut<Dithering>,
+    mut accumulation_precision: ResMut<AccumulationPrecision>,
+) {
+    let Some(handle) = handle else {
+        events.clear();
+        return;
+    };
+
+    let changed = events.read().any(|event| match event {
+        AssetEvent::Added { id } | AssetEvent::Modified { id } => *id == handle.id(),
+        _ => false,
+    });
+    if !changed && !handle.is_changed() {
+        return;
+    }
+
+    let Some(settings) = assets.get(&handle.0) else {
+        return;
+    };
+    info!("Applying render settings {:?}", handle.path());
+    *path_tracer_settings = settings.path_tracer;
+    *tonemapping = settings.tonemapping;
+    *dithering = settings.dithering;
+    accumulation_precision.set_if_neq(settings.accumulation_precision);
+}
@@ -0,0 +1,193 @@
+use std::ops::Range;
+
+use windows::Win32::Graphics::{
+    Direct3D12::*,
+    Dxgi::Common::{DXGI_FORMAT_R32_UINT, DXGI_FORMAT_UNKNOWN, DXGI_SAMPLE_DESC},
+};
+
+use super::{
+    d3d::write_buffer,
+    set_debug_name,
+    upload::{UploadPriority, UploadQueue},
+    Gpu,
+};
+
+/// Default-heap buffer of `T` elements read through an SRV, filled through its own upload buffer.
+pub struct StructuredBuffer<T> {
+    gpu_buffer: ID3D12Resource,
+    upload_buffer: ID3D12Resource,
+    capacity: usize,
+    _type: std::marker::PhantomData<T>,
+}
+
+impl<T> StructuredBuffer<T> {
+    #[track_caller]
+    pub fn new(gpu: &Gpu, capacity: usize) -> Self {
+        let default_heap_properties = D3D12_HEAP_PROPERTIES {
+            Type: D3D12_HEAP_TYPE_DEFAULT,
+            CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+            MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+            CreationNodeMask: 0,
+            VisibleNodeMask: 0,
+        };
+
+        let upload_heap_properties = D3D12_HEAP_PROPERTIES {
+            Type: D3D12_HEAP_TYPE_UPLOAD,
+            CPUPageProperty: D3D12_CPU_PAGE_PROPERTY_UNKNOWN,
+            MemoryPoolPreference: D3D12_MEMORY_POOL_UNKNOWN,
+            CreationNodeMask: 0,
+            VisibleNodeMask: 0,
+        };
+
+        let size = (capacity * std::mem::size_of::<T>()) as u64;
+        let gpu_buffer = create_buffer(
+            gpu,
+            &default_heap_properties,
+            size,
+            D3D12_RESOURCE_STATE_COMMON,
+        )
+        .expect("Could not create GPU structured buffer");
+        let upload_buffer = create_buffer(
+            gpu,
+            &upload_heap_properties,
+            size,
+            D3D12_RESOURCE_STATE_GENERIC_READ,
+        )
+        .expect("Could not create upload structured buffer");
+        let type_name = std::any::type_name::<T>();
+        set_debug_name(&gpu_buffer, &format!("structured buffer of {type_name}"));
+        set_debug_name(&upload_buffer, &format!("upload buffer of {type_name}"));
+        Self {
+            gpu_buffer,
+            upload_buffer,
+            capacity,
+            _type: std::marker::PhantomData,
+        }
+    }
+
+    /// Writes `data` to the upload buffer. Takes effect on the GPU once [`Self::upload`] is
+    /// recorded.
+    pub fn write(&self, data: &[T]) {
+        self.write_range(0, data);
+    }
+
+    /// Writes `data` to the upload buffer starting at element `offset`. Takes effect on the GPU
+    /// once [`Self::upload_range`] is recorded.
+    pub fn write_range(&self, offset: usize, data: &[T]) {
+        assert!(
+            offset + data.len() <= self.capacity,
+            "structured buffer overflow: {} elements at {offset}, capacity is {}",
+            data.len(),
+            self.capacity
+        );
+        write_buffer(&self.upload_buffer, offset * std::mem::size_of::<T>(), data);
+    }
+
+    #[cfg(test)]
+    pub(crate) fn gpu_buffer(&self) -> &ID3D12Resource {
+        &self.gpu_buffer
+    }
+
+    /// Queues a copy of the first `len` elements from the upload buffer to the GPU buffer,
+    /// replacing copies of this buffer that are still queued.
+    pub fn upload(&self, queue: &mut UploadQueue, priority: UploadPriority, len: usize) {
+        queue.cancel(&self.gpu_buffer);
+        self.upload_range(queue, priority, 0..len);
+    }
+
+    /// Queues a copy of elements in `range` from the upload buffer to the GPU buffer.
+    pub fn upload_range(
+        &self,
+        queue: &mut UploadQueue,
+        priority: UploadPriority,
+        range: Range<usize>,
+    ) {
+        let element_size = std::mem::size_of::<T>() as u64;
+        queue.push(
+            priority,
+            &self.gpu_buffer,
+            &self.upload_buffer,
+            range.start as u64 * element_size,
+            range.len() as u64 * element_size,
+        );
+    }
+
+    /// The GPU buffer as vertex data, one `T` per vertex.
+    pub fn vertex_buffer_view(&self) -> D3D12_VERTEX_BUFFER_VIEW {
+        D3D12_VERTEX_BUFFER_VIEW {
+            BufferLocation: unsafe { self.gpu_buffer.GetGPUVirtualAddress() },
+            SizeInBytes: (self.capacity * std::mem::size_of::<T>()) as u32,
+            StrideInBytes: std::mem::size_of::<T>() as u32,
+        }
+    }
+
+    /// Writes a SRV of the whole buffer to `descriptor`.
+    pub fn write_srv(&self, gpu: &Gpu, descriptor: D3D12_CPU_DESCRIPTOR_HANDLE) {
+        let srv_desc = D3D12_SHADER_RESOURCE_VIEW_DESC {
+            Format: DXGI_FORMAT_UNKNOWN,
+            ViewDimension: D3D12_SRV_DIMENSION_BUFFER,
+            Shader4ComponentMapping: D3D12_DEFAULT_SHADER_4_COMPONENT_MAPPING,
+            Anonymous: D3D12_SHADER_RESOURCE_VIEW_DESC_0 {
+                Buffer: D3D12_BUFFER_SRV {
+                    FirstElement: 0,
+                    NumElements: self.capacity as u32,
+                    StructureByteStride: std::mem::size_of::<T>() as u32,
+                    Flags: D3D12_BUFFER_SRV_FLAG_NONE,
+                },
+            },
+        };
+        unsafe {
+            gpu.device
+                .CreateShaderResourceView(&self.gpu_buffer, Some(&srv_desc), descriptor)
+        };
+    }
+}
+
+impl StructuredBuffer<u32> {
+    /// The GPU buffer as 32 bit indices.
+    pub fn index_buffer_view(&self) -> D3D12_INDEX_BUFFER_VIEW {
+        D3D12_INDEX_BUFFER_VIEW {
+            BufferLocation: unsafe { self.gpu_buffer.GetGPUVirtualAddress() },
+            SizeInBytes: (self.capacity * std::mem::size_of::<u32>()) as u32,
+            Format: DXGI_FORMAT_R32_UINT,
+        }
+    }
+}
+
+pub(super) fn create_buffer(
+    gpu: &Gpu,
+    heap_properties: &D3D12_HEAP_PROPERTIES,
+    size: u64,
+    initial_state: D3D12_RESOURCE_STATES,
+) -> Option<ID3D12Resource> {
+    let desc = D3D12_RESOURCE_DESC {
+        Alignment: 0,
+        Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
+        Width: size,
+        Height: 1,
+        DepthOrArraySize: 1,
+        MipLevels: 1,
+        Format: DXGI_FORMAT_UNKNOWN,
+        SampleDesc: DXGI_SAMPLE_DESC {
+            Count: 1,
+            ..Default::default()
+        },
+        Layout: D3D12_TEXTURE_LAYOUT_ROW_MAJOR,
+        Flags: D3D12_RESOURCE_FLAG_NONE,
+    };
+
+    let mut buffer: Option<ID3D12Resource> = None;
+    unsafe {
+        gpu.device
+            .CreateCommittedResource(
+                heap_properties,
+                D3D12_HEAP_FLAG_NONE,
+                &desc,
+                initial_state,
+                None,
+                &mut buffer,
+            )
+            .ok()?;
+    }
+    buffer
+}
@@ -0,0 +1,68 @@
+//! GPU helpers for unit tests. They run on the WARP software adapter, so they don't need a GPU
+//! or a window.
+
+use windows::{
+    core::Interface,
+    Win32::Graphics::Direct3D12::{
+        ID3D12CommandAllocator, ID3D12GraphicsCommandList, ID3D12Resource,
+        D3D12_COMMAND_LIST_TYPE_DIRECT, D3D12_HEAP_PROPERTIES, D3D12_HEAP_TYPE_READBACK,
+        D3D12_RESOURCE_STATE_COPY_DEST,
+    },
+};
+
+use super::{
+    d3d::read_buffer, leak_report::wait_for_idle, structured_buffer::create_buffer, Gpu,
+    GpuSettings,
+};
+
+pub(crate) fn warp_gpu() -> Gpu {
+    unsafe { Gpu::new(true, GpuSettings::default()) }.expect("failed to create WARP device")
+}
+
+/// Records commands with `record`, executes them and waits for the GPU to finish them.
+pub(crate) fn execute(gpu: &Gpu, record: impl FnOnce(&ID3D12GraphicsCommandList)) {
+    let command_allocator: ID3D12CommandAllocator = unsafe {
+        gpu.device
+            .CreateCommandAllocator(D3D12_COMMAND_LIST_TYPE_DIRECT)
+    }
+    .expect("CreateCommandAllocator failed");
+    let command_list: ID3D12GraphicsCommandList = unsafe {
+        gpu.device
+            .CreateCommandList(0, D3D12_COMMAND_LIST_TYPE_DIRECT, &command_allocator, None)
+    }
+    .expect("CreateCommandList failed");
+    record(&command_list);
+    unsafe {
+        command_list.Close().expect("Failed to close command list");
+        gpu.queue.ExecuteCommandLists(&[command_list.cast().ok()]);
+    }
+    wait_for_idle(gpu);
+}
+
+/// Copies the first `size` bytes of `buffer` back to the CPU. Buffers are copied from the common
+/// state they decay to between command lists.
+pub(crate) fn read_back(gpu: &Gpu, buffer: &ID3D12Resource, size: usize) -> Vec<u8> {
+    let readback_buffer = create_buffer(
+        gpu,
+        &D3D12_HEAP_PROPERTIES {
+            Type: D3D12_HEAP_TYPE_READBACK,
+            ..Default::default()
+        },
+        size as u64,
+        D3D12_RESOURCE_STATE_COPY_DEST,
+    )
+    .expect("Could not create test readback buffer");
+    execute(gpu, |command_list| unsafe {
+        command_list.CopyBufferRegion(&readback_buffer, 0, buffer, 0, size as u64)
+    });
+    read_buffer(&readback_buffer, 0..size, |bytes| bytes.to_vec())
+}
+
+/// Bytes of `data`.
+///
+/// # Safety
+///
+/// `T` must not have implicit padding, padding bytes are uninitialized.
+pub(crate) unsafe fn as_bytes<T: Copy>(data: &[T]) -> &[u8] {
+    std::slice::from_raw_parts(data.as_ptr() as *const u8, std::mem::size_of_val(data))
+}
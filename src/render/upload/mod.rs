@@ -0,0 +1,263 @@
+use std::collections::VecDeque;
+
+use bevy::prelude::*;
+use windows::Win32::Graphics::Direct3D12::*;
+
+use super::{
+    d3d::{footprint_location, subresource_location, transition_barrier},
+    render_target::FRAME_COUNT,
+};
+
+/// Limits how many bytes are copied to GPU buffers per frame.
+///
+/// Uploads that don't fit are continued in the next frames, so a big scene finishing loading
+/// shows up over a few frames instead of stalling one of them.
+#[derive(Resource, Reflect, Debug, Clone, Copy)]
+#[reflect(Resource, Default)]
+pub struct UploadBudget {
+    pub bytes_per_frame: u64,
+}
+
+impl Default for UploadBudget {
+    fn default() -> Self {
+        Self {
+            bytes_per_frame: 16 * 1024 * 1024,
+        }
+    }
+}
+
+/// Order in which queued uploads are copied, higher priority first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum UploadPriority {
+    /// Small data that should show up right away, like lights.
+    High = 0,
+    /// Scene geometry.
+    Normal = 1,
+    /// Textures, a placeholder is shown until they arrive.
+    Low = 2,
+}
+
+const PRIORITY_COUNT: usize = 3;
+
+enum CopyRegion {
+    /// `size` bytes at `offset`, the same place in both buffers.
+    Buffer { offset: u64, size: u64 },
+    /// Whole first subresource of a texture, left in `state_after`.
+    Texture {
+        footprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT,
+        size: u64,
+        state_after: D3D12_RESOURCE_STATES,
+    },
+}
+
+struct PendingCopy {
+    destination: ID3D12Resource,
+    source: ID3D12Resource,
+    region: CopyRegion,
+}
+
+impl PendingCopy {
+    fn size(&self) -> u64 {
+        match self.region {
+            CopyRegion::Buffer { size, .. } | CopyRegion::Texture { size, .. } => size,
+        }
+    }
+}
+
+/// Copies from upload buffers to GPU resources waiting for their share of the [`UploadBudget`].
+#[derive(Resource, Default)]
+pub struct UploadQueue {
+    queues: [VecDeque<PendingCopy>; PRIORITY_COUNT],
+    pending_bytes: u64,
+    // sources of the copies of every frame in flight, kept alive until the GPU finished them
+    in_flight: [Vec<ID3D12Resource>; FRAME_COUNT],
+}
+
+impl UploadQueue {
+    /// Queues a copy of `size` bytes at `offset` from `source` to the same place in `destination`.
+    pub(crate) fn push(
+        &mut self,
+        priority: UploadPriority,
+        destination: &ID3D12Resource,
+        source: &ID3D12Resource,
+        offset: u64,
+        size: u64,
+    ) {
+        if size == 0 {
+            return;
+        }
+        self.push_copy(
+            priority,
+            PendingCopy {
+                destination: destination.clone(),
+                source: source.clone(),
+                region: CopyRegion::Buffer { offset, size },
+            },
+        );
+    }
+
+    /// Queues a copy of a texture laid out as `footprint` in `source`. `destination` must be in
+    /// the copy destination state and is moved to `state_after`. Textures are never split
+    /// between frames.
+    pub(crate) fn push_texture(
+        &mut self,
+        priority: UploadPriority,
+        destination: &ID3D12Resource,
+        source: &ID3D12Resource,
+        footprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT,
+        state_after: D3D12_RESOURCE_STATES,
+    ) {
+        let size = footprint.Footprint.RowPitch as u64 * footprint.Footprint.Height as u64;
+        self.push_copy(
+            priority,
+            PendingCopy {
+                destination: destination.clone(),
+                source: source.clone(),
+                region: CopyRegion::Texture {
+                    footprint,
+                    size,
+                    state_after,
+                },
+            },
+        );
+    }
+
+    fn push_copy(&mut self, priority: UploadPriority, copy: PendingCopy) {
+        self.pending_bytes += copy.size();
+        self.queues[priority as usize].push_back(copy);
+    }
+
+    /// Drops queued copies into `destination`, used when its whole content is uploaded again.
+    pub(crate) fn cancel(&mut self, destination: &ID3D12Resource) {
+        for queue in &mut self.queues {
+            queue.retain(|copy| {
+                let keep = &copy.destination != destination;
+                if !keep {
+                    self.pending_bytes -= copy.size();
+                }
+                keep
+            });
+        }
+    }
+
+    /// Whether copies into `destination` are still waiting to be recorded.
+    pub fn is_queued(&self, destination: &ID3D12Resource) -> bool {
+        self.queues
+            .iter()
+            .flatten()
+            .any(|copy| &copy.destination == destination)
+    }
+
+    /// Bytes still waiting to be copied.
+    pub fn pending_bytes(&self) -> u64 {
+        self.pending_bytes
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending_bytes == 0
+    }
+
+    /// Records queued copies until `budget` bytes are used, splitting the last buffer copy if
+    /// needed. The frame last recorded in `frame_slot` must be finished on the GPU.
+    pub(crate) fn record(
+        &mut self,
+        command_list: &ID3D12GraphicsCommandList,
+        budget: u64,
+        frame_slot: usize,
+    ) {
+        let in_flight = &mut self.in_flight[frame_slot];
+        in_flight.clear();
+
+        let mut remaining = budget;
+        for queue in &mut self.queues {
+            while remaining > 0 {
+                let Some(copy) = queue.front_mut() else {
+                    break;
+                };
+                let recorded = match &mut copy.region {
+                    CopyRegion::Buffer { offset, size } => {
+                        let copy_size = (*size).min(remaining);
+                        record_buffer_copy(
+                            command_list,
+                            &copy.destination,
+                            &copy.source,
+                            *offset,
+                            copy_size,
+                        );
+                        *offset += copy_size;
+                        *size -= copy_size;
+                        copy_size
+                    }
+                    CopyRegion::Texture {
+                        footprint,
+                        size,
+                        state_after,
+                    } => {
+                        // a texture bigger than the whole budget still goes through on its own
+                        if *size > remaining && remaining < budget {
+                            remaining = 0;
+                            break;
+                        }
+                        record_texture_copy(
+                            command_list,
+                            &copy.destination,
+                            &copy.source,
+                            *footprint,
+                            *state_after,
+                        );
+                        let copy_size = *size;
+                        *size = 0;
+                        copy_size
+                    }
+                };
+                remaining = remaining.saturating_sub(recorded);
+                self.pending_bytes -= recorded;
+                if copy.size() == 0 {
+                    let copy = queue.pop_front().unwrap();
+                    in_flight.push(copy.source);
+                }
+            }
+        }
+    }
+}
+
+fn record_buffer_copy(
+    command_list: &ID3D12GraphicsCommandList,
+    destination: &ID3D12Resource,
+    source: &ID3D12Resource,
+    offset: u64,
+    size: u64,
+) {
+    unsafe {
+        command_list.ResourceBarrier(&[transition_barrier(
+            destination,
+            D3D12_RESOURCE_STATE_GENERIC_READ,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+        )]);
+        command_list.CopyBufferRegion(destination, offset, source, offset, size);
+        command_list.ResourceBarrier(&[transition_barrier(
+            destination,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+            D3D12_RESOURCE_STATE_GENERIC_READ,
+        )]);
+    }
+}
+
+fn record_texture_copy(
+    command_list: &ID3D12GraphicsCommandList,
+    destination: &ID3D12Resource,
+    source: &ID3D12Resource,
+    footprint: D3D12_PLACED_SUBRESOURCE_FOOTPRINT,
+    state_after: D3D12_RESOURCE_STATES,
+) {
+    let destination_location = subresource_location(destination, 0);
+    let source_location = footprint_location(source, footprint);
+    unsafe {
+        command_list.CopyTextureRegion(&destination_location, 0, 0, 0, &source_location, None);
+        command_list.ResourceBarrier(&[transition_barrier(
+            destination,
+            D3D12_RESOURCE_STATE_COPY_DEST,
+            state_after,
+        )]);
+    }
+}
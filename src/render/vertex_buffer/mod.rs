@@ -7,9 +7,10 @@ use windows::Win32::Graphics::{
     Dxgi::Common::DXGI_SAMPLE_DESC,
 };
 
-use crate::render::Gpu;
+use super::{d3d::write_buffer, set_debug_name, Gpu};
 
 #[repr(C)]
+#[derive(Clone, Copy)]
 struct Vertex {
     position: [f32; 3],
     uv: [f32; 2],
@@ -43,7 +44,7 @@ const FULLSCREEN_QUAD_VERTICES: [Vertex; 6] = [
 ];
 
 pub struct VertexBuffer {
-    _buffer: ID3D12Resource,
+    buffer: ID3D12Resource,
     view: D3D12_VERTEX_BUFFER_VIEW,
 }
 
@@ -53,6 +54,16 @@ impl VertexBuffer {
     }
 
     pub fn fullscreen_quad(gpu: &Gpu) -> Self {
+        let mut vertex_buffer =
+            Self::with_capacity::<Vertex>(gpu, FULLSCREEN_QUAD_VERTICES.len(), "fullscreen quad");
+        vertex_buffer.write(&FULLSCREEN_QUAD_VERTICES);
+        vertex_buffer
+    }
+
+    /// Empty buffer with room for `capacity` vertices of type `T`, filled with
+    /// [`VertexBuffer::write`].
+    pub fn with_capacity<T>(gpu: &Gpu, capacity: usize, name: &str) -> Self {
+        let size = (std::mem::size_of::<T>() * capacity) as u64;
         let mut vertex_buffer: Option<ID3D12Resource> = None;
         unsafe {
             gpu.device
@@ -64,7 +75,7 @@ impl VertexBuffer {
                     D3D12_HEAP_FLAG_NONE,
                     &D3D12_RESOURCE_DESC {
                         Dimension: D3D12_RESOURCE_DIMENSION_BUFFER,
-                        Width: std::mem::size_of_val(&FULLSCREEN_QUAD_VERTICES) as u64,
+                        Width: size,
                         Height: 1,
                         DepthOrArraySize: 1,
                         MipLevels: 1,
@@ -82,29 +93,24 @@ impl VertexBuffer {
                 .expect("Could not create vertex buffer");
         };
         let vertex_buffer = vertex_buffer.unwrap();
-
-        unsafe {
-            let mut data = std::ptr::null_mut();
-            vertex_buffer
-                .Map(0, None, Some(&mut data))
-                .expect("failed to map vertex buffer");
-            std::ptr::copy_nonoverlapping(
-                FULLSCREEN_QUAD_VERTICES.as_ptr(),
-                data as *mut Vertex,
-                FULLSCREEN_QUAD_VERTICES.len(),
-            );
-            vertex_buffer.Unmap(0, None);
-        }
+        set_debug_name(&vertex_buffer, name);
 
         let vbv = D3D12_VERTEX_BUFFER_VIEW {
             BufferLocation: unsafe { vertex_buffer.GetGPUVirtualAddress() },
-            StrideInBytes: std::mem::size_of::<Vertex>() as u32,
-            SizeInBytes: std::mem::size_of_val(&FULLSCREEN_QUAD_VERTICES) as u32,
+            StrideInBytes: std::mem::size_of::<T>() as u32,
+            SizeInBytes: 0,
         };
 
         VertexBuffer {
-            _buffer: vertex_buffer,
+            buffer: vertex_buffer,
             view: vbv,
         }
     }
+
+    /// Replaces the contents with `vertices`, the view covers only them. The GPU must be done
+    /// with the previous contents. Panics if they don't fit.
+    pub fn write<T>(&mut self, vertices: &[T]) {
+        write_buffer(&self.buffer, 0, vertices);
+        self.view.SizeInBytes = std::mem::size_of_val(vertices) as u32;
+    }
 }
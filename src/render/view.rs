@@ -0,0 +1,197 @@
+//! View math shared by the CPU side and the path tracer's primary ray generation.
+//!
+//! Conventions:
+//! - World space is right-handed with +Y up, same as the rest of bevy.
+//! - A camera sits at its [`GlobalTransform`] translation and looks down its local -Z
+//!   ([`GlobalTransform::forward`]). Local +X is right and local +Y is up on screen.
+//! - Viewport positions are normalized: `(0, 0)` is the top left corner and `(1, 1)` the bottom
+//!   right one, same as the uvs of the fullscreen quad.
+//! - Scale of the camera transform is ignored.
+
+use bevy::prelude::*;
+
+use crate::core::Camera;
+
+/// Camera placement and projection, as seen by the renderer.
+#[derive(Debug, Clone, Copy)]
+pub struct View {
+    inverse_view_matrix: Mat4,
+    fov: f32,
+    aspect_ratio: f32,
+}
+
+impl View {
+    pub fn new(transform: &GlobalTransform, camera: &Camera) -> Self {
+        let view_matrix = Mat4::look_to_rh(
+            transform.translation(),
+            *transform.forward(),
+            *transform.up(),
+        );
+        Self {
+            inverse_view_matrix: view_matrix.inverse(),
+            fov: camera.fov,
+            aspect_ratio: camera.aspect_ratio,
+        }
+    }
+
+    /// World to camera space. Camera space looks down -Z.
+    pub fn view_matrix(&self) -> Mat4 {
+        self.inverse_view_matrix.inverse()
+    }
+
+    /// Camera to world space, this is what the shader receives.
+    pub fn inverse_view_matrix(&self) -> Mat4 {
+        self.inverse_view_matrix
+    }
+
+    pub fn origin(&self) -> Vec3 {
+        self.inverse_view_matrix.w_axis.truncate()
+    }
+
+    /// Primary ray through `viewport_position`. Has to stay in sync with `PSMain` in `demo.hlsl`.
+    pub fn ray(&self, viewport_position: Vec2) -> Ray3d {
+        let ndc = Vec2::new(
+            2.0 * viewport_position.x - 1.0,
+            1.0 - 2.0 * viewport_position.y,
+        );
+        let scale = (self.fov * 0.5).tan();
+        let direction_camera_space =
+            Vec3::new(ndc.x * self.aspect_ratio * scale, ndc.y * scale, -1.0);
+
+        Ray3d {
+            origin: self.origin(),
+            direction: Dir3::new(
+                self.inverse_view_matrix
+                    .transform_vector3(direction_camera_space),
+            )
+            .expect("view matrix must be invertible"),
+        }
+    }
+
+    /// Viewport position `world_position` is seen at, inverse of [`View::ray`].
+    ///
+    /// Returns `None` for points behind the camera. Points outside of the view frustum give
+    /// positions outside of `[0, 1]`.
+    pub fn project(&self, world_position: Vec3) -> Option<Vec2> {
+        let camera_space_position = self.view_matrix().transform_point3(world_position);
+        if camera_space_position.z >= 0.0 {
+            return None;
+        }
+
+        let scale = (self.fov * 0.5).tan();
+        let projected = camera_space_position.xy() / (-camera_space_position.z * scale);
+        let ndc = Vec2::new(projected.x / self.aspect_ratio, projected.y);
+        Some(Vec2::new((ndc.x + 1.0) * 0.5, (1.0 - ndc.y) * 0.5))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::f32::consts::FRAC_PI_2;
+
+    use super::*;
+
+    const EPSILON: f32 = 1e-5;
+
+    fn camera(fov: f32, aspect_ratio: f32) -> Camera {
+        Camera { fov, aspect_ratio }
+    }
+
+    /// Direction built straight from the transform basis, independent of the view matrix.
+    fn reference_direction(
+        transform: &GlobalTransform,
+        camera: &Camera,
+        viewport_position: Vec2,
+    ) -> Vec3 {
+        let scale = (camera.fov * 0.5).tan();
+        let x = (2.0 * viewport_position.x - 1.0) * scale * camera.aspect_ratio;
+        let y = (1.0 - 2.0 * viewport_position.y) * scale;
+        (*transform.forward() + *transform.right() * x + *transform.up() * y).normalize()
+    }
+
+    fn transforms() -> Vec<GlobalTransform> {
+        vec![
+            GlobalTransform::IDENTITY,
+            Transform::from_xyz(1.0, 2.0, 3.0)
+                .looking_at(Vec3::new(-4.0, 0.5, 2.0), Vec3::Y)
+                .into(),
+            Transform::from_xyz(-3.0, 0.0, 7.0)
+                .with_rotation(Quat::from_euler(EulerRot::YXZ, 2.1, -0.4, 0.3))
+                .into(),
+            Transform::from_xyz(0.5, -1.0, 0.0)
+                .looking_at(Vec3::ZERO, Vec3::Y)
+                .with_scale(Vec3::splat(3.0))
+                .into(),
+        ]
+    }
+
+    #[test]
+    fn origin_is_camera_translation() {
+        for transform in transforms() {
+            let view = View::new(&transform, &camera(FRAC_PI_2, 1.0));
+            assert!(view.origin().abs_diff_eq(transform.translation(), EPSILON));
+        }
+    }
+
+    #[test]
+    fn center_ray_looks_forward() {
+        for transform in transforms() {
+            let view = View::new(&transform, &camera(1.0, 16.0 / 9.0));
+            let ray = view.ray(Vec2::splat(0.5));
+            assert!(ray.direction.abs_diff_eq(*transform.forward(), EPSILON));
+        }
+    }
+
+    #[test]
+    fn rays_match_reference() {
+        let viewport_positions = [
+            Vec2::ZERO,
+            Vec2::ONE,
+            Vec2::new(1.0, 0.0),
+            Vec2::new(0.25, 0.8),
+            Vec2::new(0.9, 0.1),
+        ];
+        for transform in transforms() {
+            let camera = camera(0.8, 16.0 / 9.0);
+            let view = View::new(&transform, &camera);
+            for viewport_position in viewport_positions {
+                let expected = reference_direction(&transform, &camera, viewport_position);
+                let ray = view.ray(viewport_position);
+                assert!(
+                    ray.direction.abs_diff_eq(expected, EPSILON),
+                    "{viewport_position}: {} != {expected}",
+                    *ray.direction
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn top_of_viewport_is_up() {
+        let view = View::new(&GlobalTransform::IDENTITY, &camera(FRAC_PI_2, 1.0));
+        assert!(view.ray(Vec2::new(0.5, 0.0)).direction.y > 0.0);
+        assert!(view.ray(Vec2::new(1.0, 0.5)).direction.x > 0.0);
+    }
+
+    #[test]
+    fn project_inverts_ray() {
+        for transform in transforms() {
+            let view = View::new(&transform, &camera(1.2, 4.0 / 3.0));
+            for viewport_position in [Vec2::new(0.3, 0.6), Vec2::new(0.0, 1.0), Vec2::splat(2.0)] {
+                let point = view.ray(viewport_position).get_point(4.5);
+                let projected = view
+                    .project(point)
+                    .expect("point is in front of the camera");
+                assert!(projected.abs_diff_eq(viewport_position, EPSILON));
+            }
+        }
+    }
+
+    #[test]
+    fn project_rejects_points_behind() {
+        let transform = GlobalTransform::from(Transform::from_xyz(0.0, 1.0, 0.0));
+        let view = View::new(&transform, &camera(FRAC_PI_2, 1.0));
+        assert!(view.project(Vec3::new(0.0, 1.0, 1.0)).is_none());
+        assert!(view.project(Vec3::new(0.0, 1.0, -1.0)).is_some());
+    }
+}